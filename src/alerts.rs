@@ -0,0 +1,150 @@
+use serde_json::{json, Value};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// A saved query checked whenever the index is updated.
+#[derive(Clone, Debug)]
+pub struct Alert {
+	/// The search terms.
+	pub query: Vec<String>,
+	/// The files matching the query when it was last checked.
+	pub matches: Vec<String>,
+}
+
+/// A command managing saved alerts.
+#[derive(Debug)]
+pub enum AlertCommand {
+	/// Save a query, recording the files it already matches.
+	Add(Vec<String>),
+	/// Update the index and check every alert.
+	Check,
+	/// List the saved alerts.
+	List,
+	/// Remove the alert with the given number, counting from `1`.
+	Remove(usize),
+}
+
+/// Represents an error reading or writing the alerts file.
+#[derive(Debug)]
+pub enum AlertError {
+	Invalid(String),
+	Io(std::io::Error),
+	Parse(serde_json::Error),
+}
+
+impl Display for AlertError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			AlertError::Invalid(msg) => write!(f, "invalid alerts file: {msg}"),
+			AlertError::Io(e) => write!(f, "alerts file error: {e}"),
+			AlertError::Parse(e) => write!(f, "invalid alerts file: {e}"),
+		}
+	}
+}
+
+impl std::error::Error for AlertError {}
+
+impl Alert {
+	/// Returns the query as typed, with multi-word terms quoted.
+	pub fn query_string(&self) -> String {
+		self.query
+			.iter()
+			.map(|t| {
+				if t.contains(' ') {
+					format!("\"{t}\"")
+				} else {
+					t.clone()
+				}
+			})
+			.collect::<Vec<String>>()
+			.join(" ")
+	}
+}
+
+/// Returns the path of the alerts file kept alongside the index at `index`.
+pub fn path_for(index: &Path) -> PathBuf {
+	index.with_extension("alerts")
+}
+
+/// Reads the alerts saved at `path`. A missing file has no alerts.
+pub fn load(path: &Path) -> Result<Vec<Alert>, AlertError> {
+	let contents = match std::fs::read_to_string(path) {
+		Ok(v) => v,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+		Err(e) => return Err(AlertError::Io(e)),
+	};
+
+	let value = serde_json::from_str::<Value>(&contents).map_err(AlertError::Parse)?;
+	let invalid = |msg: &str| AlertError::Invalid(String::from(msg));
+	let strings = |value: Option<&Value>| -> Result<Vec<String>, AlertError> {
+		value
+			.and_then(Value::as_array)
+			.ok_or_else(|| invalid("expected an array of strings"))?
+			.iter()
+			.map(|v| v.as_str().map(String::from))
+			.collect::<Option<Vec<String>>>()
+			.ok_or_else(|| invalid("expected an array of strings"))
+	};
+
+	let mut alerts = Vec::new();
+	for alert in value
+		.get("alerts")
+		.and_then(Value::as_array)
+		.ok_or_else(|| invalid("missing alerts array"))?
+	{
+		alerts.push(Alert {
+			query: strings(alert.get("query"))?,
+			matches: strings(alert.get("matches"))?,
+		});
+	}
+
+	Ok(alerts)
+}
+
+/// Writes `alerts` to `path`, replacing any saved alerts.
+pub fn save(path: &Path, alerts: &[Alert]) -> Result<(), AlertError> {
+	let alerts = alerts
+		.iter()
+		.map(|alert| json!({ "query": alert.query, "matches": alert.matches }))
+		.collect::<Vec<Value>>();
+
+	let contents =
+		serde_json::to_string_pretty(&json!({ "alerts": alerts })).map_err(AlertError::Parse)?;
+
+	std::fs::write(path, contents).map_err(AlertError::Io)
+}
+
+/// Reports files newly matching `alert`. Runs `command` if one is
+/// configured, with the message as its last argument, and otherwise prints
+/// the message to stderr.
+pub fn notify(command: Option<&[String]>, alert: &Alert, new_matches: &[String]) {
+	let message = format!(
+		"New match for {}: {}",
+		alert.query_string(),
+		new_matches.join(", ")
+	);
+
+	let (program, args) = match command.and_then(|c| c.split_first()) {
+		Some(v) => v,
+		None => {
+			eprintln!("Alert: {message}");
+			return;
+		}
+	};
+
+	let status = Command::new(program)
+		.args(args)
+		.arg(&message)
+		.env("CODESEARCH_ALERT_QUERY", alert.query_string())
+		.env("CODESEARCH_ALERT_MATCHES", new_matches.join("\n"))
+		.stdin(Stdio::null())
+		.stdout(Stdio::from(std::io::stderr()))
+		.status();
+
+	match status {
+		Ok(status) if status.success() => {}
+		Ok(status) => eprintln!("Warning: alert command failed with {status}"),
+		Err(e) => eprintln!("Warning: failed to run alert command: {e}"),
+	}
+}
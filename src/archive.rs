@@ -0,0 +1,179 @@
+use flate2::read::GzDecoder;
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// What separates the path of an archive from the path of an entry in it,
+/// e.g. `lib.jar!/com/Foo.java`.
+const SEPARATOR: &[u8] = b"!/";
+
+/// The kinds of archive whose entries are indexed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+	/// A zip file, including Java archives.
+	Zip,
+	/// A gzip-compressed tar file.
+	TarGz,
+}
+
+impl Kind {
+	/// Returns the kind of the archive at `path` from its extension.
+	fn from_path(path: &Path) -> Option<Self> {
+		let name = path.file_name()?.to_str()?.to_lowercase();
+		if name.ends_with(".zip") || name.ends_with(".jar") {
+			Some(Kind::Zip)
+		} else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+			Some(Kind::TarGz)
+		} else {
+			None
+		}
+	}
+}
+
+/// Returns `true` if `path` names an archive whose entries are indexed
+/// instead of the archive itself.
+pub fn is_archive(path: &Path) -> bool {
+	Kind::from_path(path).is_some()
+}
+
+/// Returns the path of the entry `name` of the archive at `archive`.
+fn entry_path(archive: &Path, name: &str) -> PathBuf {
+	let mut path = archive.as_os_str().to_owned();
+	path.push(OsString::from(format!("!/{name}")));
+	PathBuf::from(path)
+}
+
+/// Splits the path of an archive entry into the path of the archive and the
+/// name of the entry, or returns `None` if `path` isn't in an archive.
+pub fn split(path: &Path) -> Option<(PathBuf, String)> {
+	let bytes = path.as_os_str().as_encoded_bytes();
+	let mut start = 0;
+	while let Some(i) = bytes[start..]
+		.windows(SEPARATOR.len())
+		.position(|w| w == SEPARATOR)
+	{
+		let end = start + i;
+		// SAFETY: the bytes come from an `OsStr` and are split before an
+		// ASCII character, which keeps both halves valid.
+		let archive = unsafe { OsStr::from_encoded_bytes_unchecked(&bytes[..end]) };
+		let archive = PathBuf::from(archive);
+		if is_archive(&archive) {
+			let name = String::from_utf8_lossy(&bytes[end + SEPARATOR.len()..]);
+			return Some((archive, name.into_owned()));
+		}
+
+		start = end + SEPARATOR.len();
+	}
+
+	None
+}
+
+/// Lists the files in the archive at `path` as entry paths, with their
+/// uncompressed sizes. Directories and links are left out.
+pub fn entries(path: &Path) -> io::Result<Vec<(PathBuf, u64)>> {
+	let mut entries = Vec::new();
+	match Kind::from_path(path) {
+		Some(Kind::Zip) => {
+			let mut zip = zip::ZipArchive::new(BufReader::new(File::open(path)?))
+				.map_err(io::Error::other)?;
+
+			for i in 0..zip.len() {
+				let file = zip.by_index(i).map_err(io::Error::other)?;
+				if file.is_file() {
+					entries.push((entry_path(path, file.name()), file.size()));
+				}
+			}
+		}
+		Some(Kind::TarGz) => {
+			let mut tar = tar::Archive::new(GzDecoder::new(BufReader::new(File::open(path)?)));
+			for entry in tar.entries()? {
+				let entry = entry?;
+				if entry.header().entry_type().is_file() {
+					let name = entry.path()?.to_string_lossy().into_owned();
+					entries.push((entry_path(path, &name), entry.size()));
+				}
+			}
+		}
+		None => {}
+	}
+
+	Ok(entries)
+}
+
+/// Reads the entry of an archive at `path`, an entry path as listed by
+/// `entries`. Returns `None` once its contents pass `limit` bytes, like
+/// `compression::read`.
+pub fn read(path: &Path, limit: u64) -> io::Result<Option<Vec<u8>>> {
+	let not_found = || io::Error::new(io::ErrorKind::NotFound, "no such archive entry");
+	let (archive, name) = split(path).ok_or_else(not_found)?;
+	match Kind::from_path(&archive) {
+		Some(Kind::Zip) => {
+			let mut zip = zip::ZipArchive::new(BufReader::new(File::open(&archive)?))
+				.map_err(io::Error::other)?;
+
+			let file = zip.by_name(&name).map_err(|_| not_found())?;
+			read_limited(file, limit)
+		}
+		Some(Kind::TarGz) => {
+			// Tar files have no directory, so entries are found by reading
+			// up to them
+			let mut tar = tar::Archive::new(GzDecoder::new(BufReader::new(File::open(&archive)?)));
+			let entry = tar
+				.entries()?
+				.filter_map(Result::ok)
+				.find(|entry| entry.path().is_ok_and(|p| p.to_string_lossy() == name))
+				.ok_or_else(not_found)?;
+
+			read_limited(entry, limit)
+		}
+		None => Err(not_found()),
+	}
+}
+
+/// Reads the files in the archive at `path` in one pass over it, calling
+/// `f` with the entry path and contents of each. Reading entries one at a
+/// time with `read` would decompress a tar.gz from the start for each.
+pub fn for_each_entry<F>(path: &Path, mut f: F) -> io::Result<()>
+where
+	F: FnMut(PathBuf, &mut dyn Read) -> io::Result<()>,
+{
+	match Kind::from_path(path) {
+		Some(Kind::Zip) => {
+			let mut zip = zip::ZipArchive::new(BufReader::new(File::open(path)?))
+				.map_err(io::Error::other)?;
+
+			for i in 0..zip.len() {
+				let mut file = zip.by_index(i).map_err(io::Error::other)?;
+				if file.is_file() {
+					let entry = entry_path(path, file.name());
+					f(entry, &mut file)?;
+				}
+			}
+		}
+		Some(Kind::TarGz) => {
+			let mut tar = tar::Archive::new(GzDecoder::new(BufReader::new(File::open(path)?)));
+			for entry in tar.entries()? {
+				let mut entry = entry?;
+				if entry.header().entry_type().is_file() {
+					let name = entry.path()?.to_string_lossy().into_owned();
+					f(entry_path(path, &name), &mut entry)?;
+				}
+			}
+		}
+		None => {}
+	}
+
+	Ok(())
+}
+
+/// Reads `reader` to the end, or returns `None` once it passes `limit`
+/// bytes.
+pub fn read_limited<R: Read>(reader: R, limit: u64) -> io::Result<Option<Vec<u8>>> {
+	let mut contents = Vec::new();
+	reader
+		.take(limit.saturating_add(1))
+		.read_to_end(&mut contents)?;
+
+	Ok((contents.len() as u64 <= limit).then_some(contents))
+}
@@ -0,0 +1,1244 @@
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::alerts::AlertCommand;
+use crate::completions::Shell;
+use crate::language::Language;
+use crate::log::Level;
+use crate::ngram;
+use crate::progress::ProgressFormat;
+use crate::result_set::SetOperation;
+use crate::template::{Template, TemplateError};
+
+/// The number of candidate documents ranked when none is specified.
+pub const DEFAULT_CANDIDATES: usize = 100;
+
+/// The command to run.
+#[derive(Debug, Default)]
+pub enum Command {
+	/// Search the current directory for the search terms.
+	#[default]
+	Search,
+	/// Combine saved result files with a set operation.
+	Set(SetOperation, Vec<PathBuf>),
+	/// Manage queries checked whenever the index is updated.
+	Alert(AlertCommand),
+	/// Check the index for corruption, rebuilding it if `repair` is set.
+	Verify { repair: bool },
+	/// Create or update the index without searching it, or only list what
+	/// would be indexed if `dry_run` is set.
+	Index { dry_run: bool },
+	/// Explain why a file is or isn't among the results of the search.
+	WhyNot(PathBuf),
+	/// Show the substitutions of `replacement` for `pattern` in the files
+	/// the index finds, applying them if `write` is set and keeping copies
+	/// of the files first if `backup` is set.
+	Replace {
+		pattern: String,
+		replacement: String,
+		write: bool,
+		backup: bool,
+	},
+	/// Print the size of the index, and how it grew with each write if
+	/// `history` is set.
+	Stats { history: bool },
+	/// Save the search with the given arguments under a name.
+	Save(String, Vec<String>),
+	/// Run the search saved under a name with any extra arguments, or list
+	/// the saved searches if no name is given.
+	Run(Option<String>, Vec<String>),
+	/// List the recent searches and their top results.
+	History,
+	/// Time building the index and running each search term as a query.
+	Bench,
+	/// Find the commits that added or removed lines matching the search
+	/// terms.
+	Log,
+	/// Write the index to a bundle file, compressed with zstd if `zstd` is
+	/// set.
+	Export { path: PathBuf, zstd: bool },
+	/// Replace the index with the one in a bundle file.
+	Import(PathBuf),
+	/// Answer JSON-RPC requests on stdin, for editor integrations, framed
+	/// as in the Language Server Protocol and cancelling superseded queries
+	/// if `lsp_proxy` is set.
+	Serve { lsp_proxy: bool },
+	/// Print a completion script for a shell.
+	Completions(Shell),
+	/// Print the help of the named subcommand, or of codesearch if none.
+	Help(Option<&'static str>),
+	/// Print the manual page.
+	Man,
+}
+
+/// How search results are written to stdout.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+	/// The top results with previews, for people to read.
+	#[default]
+	Text,
+	/// Every result as a result file, for other programs and `set`.
+	Json,
+	/// The path of every file with a matching line, for piping into other
+	/// tools, ended with a newline or, with `--print0`, a NUL byte.
+	Paths,
+	/// Every occurrence of the search terms in files with a matching line,
+	/// with its path and line, for piping into other tools.
+	Occurrences,
+	/// The directories with the best matching files, ranked by their
+	/// files' combined ranks.
+	Directories,
+	/// Each matching line laid out by the user's `--template`.
+	Template,
+	/// Each matching line as comma-separated values, for spreadsheets.
+	Csv,
+	/// Each matching line as tab-separated values, for `awk` and `cut`.
+	Tsv,
+	/// A SARIF log with a result for each matching line, for code scanning.
+	Sarif,
+}
+
+/// Whether output is styled with colors.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+	/// Style output written to a terminal, unless `NO_COLOR` is set.
+	#[default]
+	Auto,
+	Always,
+	Never,
+}
+
+impl ColorChoice {
+	/// Returns the color choice with the given name.
+	pub fn from_name(name: &str) -> Option<Self> {
+		match name {
+			"auto" => Some(ColorChoice::Auto),
+			"always" => Some(ColorChoice::Always),
+			"never" => Some(ColorChoice::Never),
+			_ => None,
+		}
+	}
+}
+
+/// Options parsed from the command line.
+#[derive(Debug, Default)]
+pub struct Options {
+	/// Terms the search terms expand to through the project's aliases. They
+	/// match like search terms, but not as part of a phrase.
+	pub aliases: Vec<String>,
+	/// Find documents containing any of the terms, instead of only those
+	/// containing every term.
+	pub any: bool,
+	/// Only verify documents matching rare terms when common terms add no selectivity.
+	pub auto_narrow: bool,
+	/// The most candidate documents to rank, if given. `0` ranks every candidate.
+	pub candidates: Option<usize>,
+	/// Index files that look binary as text instead of skipping them.
+	pub binary: bool,
+	/// Annotate each preview line with who last changed it and when.
+	pub blame: bool,
+	/// Only count matches in code, not in comments or string literals.
+	pub code_only: bool,
+	/// Whether output is styled with colors.
+	pub color: ColorChoice,
+	/// The command to run.
+	pub command: Command,
+	/// Build the index in memory for this search instead of keeping it.
+	pub ephemeral: bool,
+	/// Terms that results must not contain, given as `-term` or `!term`.
+	pub excluded: Vec<String>,
+	/// Only index the files listed in this file, or stdin if it is `-`.
+	pub files_from: Option<PathBuf>,
+	/// Index through symbolic links to directories.
+	pub follow: bool,
+	/// How search results are written to stdout.
+	pub format: OutputFormat,
+	/// Start CSV and TSV output with a row naming the columns.
+	pub header: bool,
+	/// Index minified and generated files instead of skipping them.
+	pub include_generated: bool,
+	/// Keep the index at this path instead of in the data directory.
+	pub index_path: Option<PathBuf>,
+	/// Only index the files git tracks instead of walking the directory.
+	pub git_tracked: bool,
+	/// Run the previous search again, with any other arguments added.
+	pub last: bool,
+	/// Record the target of each file indexed through a symbolic link.
+	pub link_targets: bool,
+	/// Split terms on whitespace, so a term spanning lines, e.g. a pasted
+	/// function signature, matches whatever whitespace separates its words.
+	pub multiline: bool,
+	/// The most previews shown for each text result, if given. `0` shows
+	/// every matching line.
+	pub max_previews: Option<usize>,
+	/// Leave out results less relevant than this, from 0 to 100.
+	pub min_score: Option<usize>,
+	/// The n-gram size to build the index with, if given.
+	pub ngram_size: Option<u8>,
+	/// Include files and lines excluded by suppression annotations.
+	pub no_suppressions: bool,
+	/// Open the result at this 1-based position in an editor instead of
+	/// printing the results, if given.
+	pub open: Option<usize>,
+	/// Only search files in these paths. Empty means all files.
+	pub paths: Vec<PathBuf>,
+	/// How indexing progress is reported.
+	pub progress: ProgressFormat,
+	/// End each path written with `--files-with-matches` with a NUL byte
+	/// instead of a newline.
+	pub print0: bool,
+	/// How many times `bench` runs each query, if given.
+	pub runs: Option<usize>,
+	/// Index the Cargo workspace and resolve `crate::`-style terms.
+	pub rust: bool,
+	/// Split the index into one shard per top-level directory.
+	pub shard: bool,
+	/// Search the index published at this URL instead of one built here.
+	pub remote: Option<String>,
+	/// Only search the documents of these previous results, read from stdin
+	/// with `--refine`.
+	pub refine: Option<HashSet<PathBuf>>,
+	/// Search the files as of this git commit instead of those on disk.
+	/// Replaced by the commit's full hash once it is resolved.
+	pub rev: Option<String>,
+	/// Search the index as is, without updating or rewriting it, so older
+	/// versions of codesearch can keep reading it.
+	pub read_compat: bool,
+	/// Also index and search the stems of words, so other forms of a search
+	/// term match it.
+	pub stem: bool,
+	/// Rank symbol definitions above plain text matches.
+	pub symbols: bool,
+	/// The terms to search for.
+	pub terms: Vec<String>,
+	/// How each matching line is written with `--template`, if given.
+	pub template: Option<Template>,
+	/// Stop ranking after this long and show the best results found, if
+	/// given.
+	pub timeout: Option<Duration>,
+	/// Only search documents in these languages. Empty means all languages.
+	pub types: Vec<Language>,
+	/// How much is written to stderr besides warnings and errors.
+	pub verbosity: Level,
+	/// Update an outdated index before searching it, instead of searching
+	/// it as is while it is updated in the background.
+	pub wait: bool,
+}
+
+/// Represents an error encountered while parsing arguments.
+#[derive(Debug)]
+pub enum ArgsError {
+	/// Two arguments that can't be given together.
+	Conflict(String, String),
+	InvalidValue(String, String),
+	MissingValue(String),
+	/// An invalid `--template`.
+	Template(TemplateError),
+	/// An argument where none is taken, e.g. search terms for `index`.
+	Unexpected(String),
+	UnknownFlag(String),
+}
+
+impl Display for ArgsError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ArgsError::Conflict(a, b) => write!(f, "{a} can't be used with {b}"),
+			ArgsError::InvalidValue(flag, value) => {
+				write!(f, "Invalid value {value} for {flag}")?;
+				match expected(flag) {
+					Some(expected) => write!(f, "; expected {expected}"),
+					None => Ok(()),
+				}
+			}
+			ArgsError::MissingValue(flag) => write!(f, "Missing value for {flag}"),
+			ArgsError::Template(e) => write!(f, "Invalid value for --template: {e}"),
+			ArgsError::Unexpected(arg) => write!(f, "Unexpected argument {arg}"),
+			ArgsError::UnknownFlag(flag) => {
+				write!(f, "Unknown flag {flag}")?;
+				if let Some(command) = SUBCOMMANDS
+					.iter()
+					.find(|command| command.flags.iter().any(|f| f.names.contains(&&**flag)))
+				{
+					return write!(f, "; only {} takes it", command.name);
+				}
+
+				match suggest(flag) {
+					Some(name) if name == flag => write!(f, "; this command doesn't take it"),
+					Some(name) => write!(f, "; did you mean {name}?"),
+					None => Ok(()),
+				}
+			}
+		}
+	}
+}
+
+impl std::error::Error for ArgsError {}
+
+impl Options {
+	/// Parses options from `args`, which should not include the program name.
+	/// Anything following a bare `--` is treated as a search term.
+	pub fn parse<I: Iterator<Item = String>>(args: I) -> Result<Self, ArgsError> {
+		let mut options = Self::default();
+		let args = args.collect::<Vec<String>>();
+
+		// Help is for the subcommand given, wherever `--help` is among flags
+		if args
+			.iter()
+			.take_while(|arg| *arg != "--")
+			.any(|arg| arg == "--help" || arg == "-h")
+		{
+			let command = args.first().and_then(|arg| subcommand(arg));
+			options.command = Command::Help(command.map(|command| command.name));
+			return Ok(options);
+		}
+
+		let mut args = args.into_iter().peekable();
+		if args.next_if(|arg| arg == "set").is_some() {
+			options.command = parse_set(args)?;
+			return Ok(options);
+		}
+
+		if args.next_if(|arg| arg == "save").is_some() {
+			let name = args
+				.next()
+				.ok_or(ArgsError::MissingValue(String::from("save")))?;
+
+			// Only searches are saved, and only if they parse
+			let query = args.collect::<Vec<String>>();
+			let saved = Self::parse(query.iter().cloned())?;
+			if !matches!(saved.command, Command::Search) || saved.terms.is_empty() {
+				return Err(ArgsError::InvalidValue(
+					String::from("save"),
+					query.join(" "),
+				));
+			}
+
+			options.command = Command::Save(name, query);
+			return Ok(options);
+		}
+
+		if args.next_if(|arg| arg == "run").is_some() {
+			options.command = Command::Run(args.next(), args.collect());
+			return Ok(options);
+		}
+
+		if args.next_if(|arg| arg == "completions").is_some() {
+			let completions = String::from("completions");
+			let name = args
+				.next()
+				.ok_or(ArgsError::MissingValue(completions.clone()))?;
+			let shell =
+				Shell::from_name(&name).ok_or(ArgsError::InvalidValue(completions, name))?;
+			if let Some(arg) = args.next() {
+				return Err(unexpected(arg));
+			}
+
+			options.command = Command::Completions(shell);
+			return Ok(options);
+		}
+
+		if args.next_if(|arg| arg == "history").is_some() {
+			if let Some(arg) = args.next() {
+				return Err(unexpected(arg));
+			}
+
+			options.command = Command::History;
+			return Ok(options);
+		}
+
+		if args.next_if(|arg| arg == "man").is_some() {
+			if let Some(arg) = args.next() {
+				return Err(unexpected(arg));
+			}
+
+			options.command = Command::Man;
+			return Ok(options);
+		}
+
+		if args.next_if(|arg| arg == "stats").is_some() {
+			let history = match args.next() {
+				None => false,
+				Some(arg) if arg == "--history" => true,
+				Some(arg) => return Err(unexpected(arg)),
+			};
+
+			options.command = Command::Stats { history };
+			return Ok(options);
+		}
+
+		if args.next_if(|arg| arg == "alert").is_some() {
+			options.command = parse_alert(args)?;
+			return Ok(options);
+		}
+
+		if args.next_if(|arg| arg == "index").is_some() {
+			options.command = Command::Index { dry_run: false };
+		}
+
+		// Repairs take the flags indexes are built with, like `index`
+		if args.next_if(|arg| arg == "verify").is_some() {
+			options.command = Command::Verify { repair: false };
+		}
+
+		if args.next_if(|arg| arg == "serve").is_some() {
+			options.command = Command::Serve { lsp_proxy: false };
+		}
+
+		if args.next_if(|arg| arg == "bench").is_some() {
+			options.command = Command::Bench;
+		}
+
+		if args.next_if(|arg| arg == "log").is_some() {
+			options.command = Command::Log;
+		}
+
+		if args.next_if(|arg| arg == "why-not").is_some() {
+			let path = args
+				.next()
+				.ok_or(ArgsError::MissingValue(String::from("why-not")))?;
+
+			options.command = Command::WhyNot(PathBuf::from(path));
+		}
+
+		if args.next_if(|arg| arg == "replace").is_some() {
+			let replace = String::from("replace");
+			let pattern = args
+				.next()
+				.ok_or(ArgsError::MissingValue(replace.clone()))?;
+			let replacement = args
+				.next()
+				.ok_or(ArgsError::MissingValue(replace.clone()))?;
+
+			// Substitutions are made within lines, so diffs are line for line
+			if pattern.is_empty() || pattern.contains('\n') {
+				return Err(ArgsError::InvalidValue(replace, pattern));
+			}
+
+			if replacement.contains('\n') {
+				return Err(ArgsError::InvalidValue(replace, replacement));
+			}
+
+			options.command = Command::Replace {
+				pattern,
+				replacement,
+				write: false,
+				backup: false,
+			};
+		}
+
+		if args.next_if(|arg| arg == "export").is_some() {
+			// `--zstd` may come before the file, or with the other flags
+			let zstd = args.next_if(|arg| arg == "--zstd").is_some();
+			let path = args
+				.next()
+				.ok_or(ArgsError::MissingValue(String::from("export")))?;
+
+			options.command = Command::Export {
+				path: PathBuf::from(path),
+				zstd,
+			};
+		}
+
+		if args.next_if(|arg| arg == "import").is_some() {
+			let path = args
+				.next()
+				.ok_or(ArgsError::MissingValue(String::from("import")))?;
+
+			options.command = Command::Import(PathBuf::from(path));
+		}
+
+		let mut terms_only = false;
+		while let Some(arg) = args.next() {
+			if !terms_only && arg.len() > 1 && arg.starts_with('!') {
+				options.excluded.push(arg[1..].to_string());
+				continue;
+			}
+
+			if terms_only || !arg.starts_with('-') || arg == "-" {
+				options.terms.push(arg);
+				continue;
+			}
+
+			match arg.as_str() {
+				"--" => terms_only = true,
+				"--any" => options.any = true,
+				"--auto-narrow" => options.auto_narrow = true,
+				"--backup" => match &mut options.command {
+					Command::Replace { backup, .. } => *backup = true,
+					_ => return Err(ArgsError::UnknownFlag(arg)),
+				},
+				"--binary" => options.binary = true,
+				"--blame" => options.blame = true,
+				"--code-only" => options.code_only = true,
+				"--color" => {
+					let value = args.next().ok_or(ArgsError::MissingValue(arg.clone()))?;
+					options.color = ColorChoice::from_name(&value)
+						.ok_or(ArgsError::InvalidValue(arg, value))?;
+				}
+				"--candidates" => {
+					let value = args.next().ok_or(ArgsError::MissingValue(arg.clone()))?;
+					let candidates = value
+						.parse()
+						.map_err(|_| ArgsError::InvalidValue(arg, value))?;
+
+					options.candidates = Some(candidates);
+				}
+				"--ephemeral" | "--no-save" => options.ephemeral = true,
+				"--dry-run" if matches!(options.command, Command::Index { .. }) => {
+					options.command = Command::Index { dry_run: true }
+				}
+				"--files-from" if matches!(options.command, Command::Index { .. }) => {
+					let value = args.next().ok_or(ArgsError::MissingValue(arg.clone()))?;
+					options.files_from = Some(PathBuf::from(value));
+				}
+				"--follow" => options.follow = true,
+				"--format" => {
+					let value = args.next().ok_or(ArgsError::MissingValue(arg.clone()))?;
+					options.format = match value.as_str() {
+						"csv" => OutputFormat::Csv,
+						"json" => OutputFormat::Json,
+						"sarif" => OutputFormat::Sarif,
+						"text" => OutputFormat::Text,
+						"tsv" => OutputFormat::Tsv,
+						_ => return Err(ArgsError::InvalidValue(arg, value)),
+					};
+				}
+				"--files-with-matches" | "-l" => options.format = OutputFormat::Paths,
+				"--group-by-dir" => options.format = OutputFormat::Directories,
+				"--header" => options.header = true,
+				"--git-tracked" => options.git_tracked = true,
+				"--include-generated" => options.include_generated = true,
+				"--index-path" => {
+					let value = args.next().ok_or(ArgsError::MissingValue(arg.clone()))?;
+					options.index_path = Some(PathBuf::from(value));
+				}
+				"--last" => options.last = true,
+				"--link-targets" => options.link_targets = true,
+				"--lsp-proxy" if matches!(options.command, Command::Serve { .. }) => {
+					options.command = Command::Serve { lsp_proxy: true }
+				}
+				"--max-previews" => {
+					let value = args.next().ok_or(ArgsError::MissingValue(arg.clone()))?;
+					let max_previews = value
+						.parse()
+						.map_err(|_| ArgsError::InvalidValue(arg, value))?;
+
+					options.max_previews = Some(max_previews);
+				}
+				"--min-score" => {
+					let value = args.next().ok_or(ArgsError::MissingValue(arg.clone()))?;
+					let min_score = value
+						.parse()
+						.ok()
+						.filter(|v| *v <= 100)
+						.ok_or(ArgsError::InvalidValue(arg, value))?;
+
+					options.min_score = Some(min_score);
+				}
+				"--multiline" => options.multiline = true,
+				"--ngram-size" => {
+					let value = args.next().ok_or(ArgsError::MissingValue(arg.clone()))?;
+					let size = value
+						.parse()
+						.ok()
+						.filter(|v| ngram::is_supported_size(*v))
+						.ok_or(ArgsError::InvalidValue(arg, value))?;
+
+					options.ngram_size = Some(size);
+				}
+				"--no-suppressions" => options.no_suppressions = true,
+				"--only-matching" | "-o" => options.format = OutputFormat::Occurrences,
+				"--open" => {
+					let value = args.next().ok_or(ArgsError::MissingValue(arg.clone()))?;
+					let position = value
+						.parse()
+						.ok()
+						.filter(|v| *v > 0)
+						.ok_or(ArgsError::InvalidValue(arg, value))?;
+
+					options.open = Some(position);
+				}
+				"--path" => {
+					let value = args.next().ok_or(ArgsError::MissingValue(arg.clone()))?;
+					options.paths.push(PathBuf::from(value));
+				}
+				"--print0" => {
+					options.format = OutputFormat::Paths;
+					options.print0 = true;
+				}
+				"--progress" => {
+					let value = args.next().ok_or(ArgsError::MissingValue(arg.clone()))?;
+					options.progress = ProgressFormat::from_name(&value)
+						.ok_or(ArgsError::InvalidValue(arg, value))?;
+				}
+				"--quiet" | "-q" => options.verbosity = Level::Quiet,
+				"--read-compat" => options.read_compat = true,
+				"--refine" => options.refine = Some(HashSet::new()),
+				"--repair" if matches!(options.command, Command::Verify { .. }) => {
+					options.command = Command::Verify { repair: true }
+				}
+				"--remote" => {
+					let value = args.next().ok_or(ArgsError::MissingValue(arg.clone()))?;
+					options.remote = Some(value);
+				}
+				"--runs" => {
+					let value = args.next().ok_or(ArgsError::MissingValue(arg.clone()))?;
+					let runs = value
+						.parse()
+						.ok()
+						.filter(|v| *v > 0)
+						.ok_or(ArgsError::InvalidValue(arg, value))?;
+
+					options.runs = Some(runs);
+				}
+				"--rev" => {
+					let value = args.next().ok_or(ArgsError::MissingValue(arg.clone()))?;
+					options.rev = Some(value);
+				}
+				"--rust" => options.rust = true,
+				"--shard" => options.shard = true,
+				"--stem" => options.stem = true,
+				"--symbols" => options.symbols = true,
+				"--template" => {
+					let value = args.next().ok_or(ArgsError::MissingValue(arg.clone()))?;
+					let template = Template::parse(&value).map_err(ArgsError::Template)?;
+					options.format = OutputFormat::Template;
+					options.template = Some(template);
+				}
+				"--timeout" => {
+					let value = args.next().ok_or(ArgsError::MissingValue(arg.clone()))?;
+					let timeout = value
+						.parse::<f64>()
+						.ok()
+						.filter(|v| v.is_finite() && *v > 0.0)
+						.ok_or(ArgsError::InvalidValue(arg, value))?;
+
+					options.timeout = Some(Duration::from_secs_f64(timeout));
+				}
+				"--type" | "-t" => {
+					let value = args.next().ok_or(ArgsError::MissingValue(arg.clone()))?;
+					let language =
+						Language::from_name(&value).ok_or(ArgsError::InvalidValue(arg, value))?;
+
+					options.types.push(language);
+				}
+				"--verbose" | "-v" => {
+					options.verbosity = Level::from_verbosity(options.verbosity.verbosity() + 1)
+				}
+				"-vv" => options.verbosity = Level::Trace,
+				"--wait" => options.wait = true,
+				"--write" => match &mut options.command {
+					Command::Replace { write, .. } => *write = true,
+					_ => return Err(ArgsError::UnknownFlag(arg)),
+				},
+				"--zstd" => match &mut options.command {
+					Command::Export { zstd, .. } => *zstd = true,
+					_ => return Err(ArgsError::UnknownFlag(arg)),
+				},
+				// Any other single dash excludes a term, e.g. `-retry`
+				_ if !arg.starts_with("--") => options.excluded.push(arg[1..].to_string()),
+				_ => return Err(ArgsError::UnknownFlag(arg)),
+			}
+		}
+
+		let command = match options.command {
+			Command::Verify { .. } => Some("verify"),
+			Command::Index { .. } => Some("index"),
+			Command::Export { .. } => Some("export"),
+			Command::Import(_) => Some("import"),
+			Command::Serve { .. } => Some("serve"),
+			Command::Replace { .. } => Some("replace"),
+			_ => None,
+		};
+
+		if let (Some(command), Some(term)) = (command, options.terms.first()) {
+			return Err(ArgsError::Unexpected(format!("{term} for {command}")));
+		}
+
+		// The words of a multiline term are searched as a phrase, which
+		// matches across any whitespace
+		if options.multiline {
+			options.terms = options
+				.terms
+				.iter()
+				.flat_map(|term| term.split_whitespace())
+				.map(String::from)
+				.collect();
+		}
+
+		// Every file containing the pattern is a candidate for replacement
+		if let Command::Replace { pattern, .. } = &options.command {
+			options.terms = vec![pattern.clone()];
+			options.candidates = Some(0);
+		}
+
+		// Path lists and formats for other programs list every matching
+		// file, so every candidate is ranked unless --candidates says not to
+		let lists_every_file = matches!(
+			options.format,
+			OutputFormat::Json
+				| OutputFormat::Paths
+				| OutputFormat::Occurrences
+				| OutputFormat::Template
+				| OutputFormat::Csv
+				| OutputFormat::Tsv
+				| OutputFormat::Sarif
+		);
+
+		if lists_every_file && options.candidates.is_none() {
+			options.candidates = Some(0);
+		}
+
+		if let (Command::Index { .. }, true) = (&options.command, options.read_compat) {
+			return Err(ArgsError::Conflict(
+				String::from("index"),
+				String::from("--read-compat"),
+			));
+		}
+
+		// Ephemeral indexes are only searched, never kept or compared
+		let conflict = match options.command {
+			Command::Verify { .. } => Some("verify"),
+			Command::Index { .. } => Some("index"),
+			Command::Bench => Some("bench"),
+			Command::Log => Some("log"),
+			Command::Export { .. } => Some("export"),
+			Command::Import(_) => Some("import"),
+			Command::Serve { .. } => Some("serve"),
+			_ if options.read_compat => Some("--read-compat"),
+			_ => None,
+		};
+
+		if let (true, Some(conflict)) = (options.ephemeral, conflict) {
+			return Err(ArgsError::Conflict(
+				String::from(conflict),
+				String::from("--ephemeral"),
+			));
+		}
+
+		// Remote indexes are only searched as they were published
+		let conflict = match options.command {
+			Command::Verify { .. } => Some("verify"),
+			Command::Index { .. } => Some("index"),
+			Command::Import(_) => Some("import"),
+			Command::Replace { .. } => Some("replace"),
+			_ if options.ephemeral => Some("--ephemeral"),
+			_ if options.index_path.is_some() => Some("--index-path"),
+			_ if options.rev.is_some() => Some("--rev"),
+			_ => None,
+		};
+
+		if let (Some(_), Some(conflict)) = (&options.remote, conflict) {
+			return Err(ArgsError::Conflict(
+				String::from(conflict),
+				String::from("--remote"),
+			));
+		}
+
+		// Replacements are made in the files on disk, not a commit's
+		if let (Command::Replace { .. }, Some(_)) = (&options.command, &options.rev) {
+			return Err(ArgsError::Conflict(
+				String::from("replace"),
+				String::from("--rev"),
+			));
+		}
+
+		// A commit's files replace any other list of files
+		if options.rev.is_some() && (options.git_tracked || options.files_from.is_some()) {
+			let flag = if options.git_tracked {
+				"--git-tracked"
+			} else {
+				"--files-from"
+			};
+
+			return Err(ArgsError::Conflict(
+				String::from("--rev"),
+				String::from(flag),
+			));
+		}
+
+		Ok(options)
+	}
+}
+
+/// Parses the arguments of the `set` command: an operation followed by at
+/// least two result files.
+fn parse_set<I: Iterator<Item = String>>(mut args: I) -> Result<Command, ArgsError> {
+	let name = args
+		.next()
+		.ok_or(ArgsError::MissingValue(String::from("set")))?;
+
+	let operation =
+		SetOperation::from_name(&name).ok_or(ArgsError::InvalidValue(String::from("set"), name))?;
+
+	let files = args.map(PathBuf::from).collect::<Vec<PathBuf>>();
+	if files.len() < 2 {
+		return Err(ArgsError::MissingValue(String::from("set")));
+	}
+
+	Ok(Command::Set(operation, files))
+}
+
+/// Parses the arguments of the `alert` command: `add` followed by a query,
+/// `check`, `list`, or `remove` followed by an alert number.
+fn parse_alert<I: Iterator<Item = String>>(mut args: I) -> Result<Command, ArgsError> {
+	let alert = String::from("alert");
+	let name = args.next().ok_or(ArgsError::MissingValue(alert.clone()))?;
+	let command = match name.as_str() {
+		"add" => {
+			let query = args.collect::<Vec<String>>();
+			if query.is_empty() {
+				return Err(ArgsError::MissingValue(name));
+			}
+
+			AlertCommand::Add(query)
+		}
+		"check" => AlertCommand::Check,
+		"list" => AlertCommand::List,
+		"remove" => {
+			let value = args.next().ok_or(ArgsError::MissingValue(name.clone()))?;
+			let number = value
+				.parse()
+				.ok()
+				.filter(|n| *n > 0)
+				.ok_or(ArgsError::InvalidValue(name, value))?;
+
+			AlertCommand::Remove(number)
+		}
+		_ => return Err(ArgsError::InvalidValue(alert, name)),
+	};
+
+	Ok(Command::Alert(command))
+}
+
+/// Returns the subcommand named `name`, if there is one.
+pub fn subcommand(name: &str) -> Option<&'static Subcommand> {
+	SUBCOMMANDS.iter().find(|command| command.name == name)
+}
+
+/// Returns the error for an argument given after everything a subcommand
+/// takes: an unknown flag if it looks like one.
+fn unexpected(arg: String) -> ArgsError {
+	if arg.starts_with('-') {
+		ArgsError::UnknownFlag(arg)
+	} else {
+		ArgsError::Unexpected(arg)
+	}
+}
+
+/// Returns every flag, those of searches and of each subcommand.
+fn all_flags() -> impl Iterator<Item = &'static Flag> {
+	FLAGS
+		.iter()
+		.chain(SUBCOMMANDS.iter().flat_map(|command| command.flags))
+}
+
+/// Returns the values `flag` or a subcommand takes, e.g. `text or json`, if
+/// it takes one of a few.
+fn expected(flag: &str) -> Option<String> {
+	let choices = match flag {
+		"alert" => vec!["add", "check", "list", "remove"],
+		"completions" => Shell::ALL.iter().map(|shell| shell.name()).collect(),
+		"set" => vec!["and", "or", "subtract", "xor"],
+		_ => match all_flags().find(|f| f.names.contains(&flag))?.value? {
+			"language" => Language::ALL
+				.iter()
+				.map(|language| language.name())
+				.collect(),
+			value if value.contains('|') => value.split('|').collect(),
+			_ => return None,
+		},
+	};
+
+	Some(match choices.as_slice() {
+		[a, b] => format!("{a} or {b}"),
+		_ => format!("one of {}", choices.join(", ")),
+	})
+}
+
+/// Returns the long flag most like `flag`, if it's close enough that
+/// `flag` is likely a typo of it.
+fn suggest(flag: &str) -> Option<&'static str> {
+	all_flags()
+		.flat_map(|f| f.names.iter().copied())
+		.filter(|name| name.starts_with("--"))
+		.map(|name| (distance(flag, name), name))
+		.filter(|(distance, _)| *distance <= 2)
+		.min()
+		.map(|(_, name)| name)
+}
+
+/// Returns the Levenshtein distance between `a` and `b`: how many characters
+/// must be inserted, removed, or replaced to turn one into the other.
+fn distance(a: &str, b: &str) -> usize {
+	let b = b.chars().collect::<Vec<char>>();
+	let mut previous = (0..=b.len()).collect::<Vec<usize>>();
+	for (i, a) in a.chars().enumerate() {
+		let mut current = vec![i + 1];
+		for (j, b) in b.iter().enumerate() {
+			let replace = previous[j] + usize::from(a != *b);
+			current.push(replace.min(previous[j + 1] + 1).min(current[j] + 1));
+		}
+
+		previous = current;
+	}
+
+	previous[b.len()]
+}
+
+/// A command-line flag, as documented by `--help` and offered by shell
+/// completions.
+pub struct Flag {
+	/// The flag's names, long name first, e.g. `--type` and `-t`.
+	pub names: &'static [&'static str],
+	/// What the flag's value is if it takes one: `|`-separated choices,
+	/// e.g. `text|json`, `language`, `file`, or a placeholder like `n`.
+	pub value: Option<&'static str>,
+	/// What the flag does, in a few words.
+	pub help: &'static str,
+}
+
+/// A subcommand, with the flags only it takes.
+pub struct Subcommand {
+	pub name: &'static str,
+	/// The arguments it takes after its name, as shown by `--help`.
+	pub usage: &'static str,
+	/// What the subcommand does, in a sentence or two.
+	pub about: &'static str,
+	/// What the subcommand does, in a few words.
+	pub help: &'static str,
+	pub flags: &'static [Flag],
+	/// Whether it takes the flags of searches too.
+	pub search_flags: bool,
+	/// Whether its arguments are files, e.g. the result files of `set`.
+	pub takes_files: bool,
+}
+
+/// Builds a flag, to keep the tables below short.
+const fn flag(
+	names: &'static [&'static str],
+	value: Option<&'static str>,
+	help: &'static str,
+) -> Flag {
+	Flag { names, value, help }
+}
+
+/// The flags of searches, which most subcommands take too.
+pub const FLAGS: &[Flag] = &[
+	flag(&["--any"], None, "Find files containing any term"),
+	flag(
+		&["--auto-narrow"],
+		None,
+		"Skip common terms that do not narrow the search",
+	),
+	flag(&["--binary"], None, "Index files that look binary as text"),
+	flag(&["--blame"], None, "Annotate previews with git blame"),
+	flag(
+		&["--candidates"],
+		Some("n"),
+		"Rank only the n best candidates",
+	),
+	flag(&["--code-only"], None, "Only count matches in code"),
+	flag(
+		&["--color"],
+		Some("auto|always|never"),
+		"Whether output is styled",
+	),
+	flag(
+		&["--ephemeral", "--no-save"],
+		None,
+		"Search an index kept in memory",
+	),
+	flag(
+		&["--files-with-matches", "-l"],
+		None,
+		"Write only matching paths",
+	),
+	flag(&["--follow"], None, "Index through symbolic links"),
+	flag(
+		&["--format"],
+		Some("text|json|csv|tsv|sarif"),
+		"How results are written",
+	),
+	flag(&["--git-tracked"], None, "Index only files git tracks"),
+	flag(
+		&["--group-by-dir"],
+		None,
+		"Rank directories instead of files",
+	),
+	flag(
+		&["--header"],
+		None,
+		"Start CSV and TSV output with column names",
+	),
+	flag(&["--help", "-h"], None, "Show help for the command"),
+	flag(
+		&["--include-generated"],
+		None,
+		"Index generated and minified files",
+	),
+	flag(
+		&["--index-path"],
+		Some("file"),
+		"Keep the index at this path",
+	),
+	flag(&["--last"], None, "Repeat the last search"),
+	flag(
+		&["--link-targets"],
+		None,
+		"Record where linked files really are",
+	),
+	flag(
+		&["--max-previews"],
+		Some("n"),
+		"Preview at most n lines per file",
+	),
+	flag(
+		&["--min-score"],
+		Some("0-100"),
+		"Leave out less relevant results",
+	),
+	flag(&["--multiline"], None, "Search terms spanning lines"),
+	flag(
+		&["--ngram-size"],
+		Some("2|3|4"),
+		"Build the index from n-grams of this size",
+	),
+	flag(
+		&["--no-suppressions"],
+		None,
+		"Include suppressed files and lines",
+	),
+	flag(
+		&["--only-matching", "-o"],
+		None,
+		"Write only each occurrence",
+	),
+	flag(&["--open"], Some("n"), "Open the nth result in an editor"),
+	flag(&["--path"], Some("path"), "Only search files in this path"),
+	flag(&["--print0"], None, "Write matching paths ended by NUL"),
+	flag(
+		&["--progress"],
+		Some("bar|json"),
+		"How indexing progress is reported",
+	),
+	flag(
+		&["--quiet", "-q"],
+		None,
+		"Print nothing and stop at the first match",
+	),
+	flag(
+		&["--read-compat"],
+		None,
+		"Search the index without rewriting it",
+	),
+	flag(&["--refine"], None, "Only search the previous results"),
+	flag(
+		&["--remote"],
+		Some("url"),
+		"Search an index published at a URL",
+	),
+	flag(
+		&["--rev"],
+		Some("commit"),
+		"Search the files as of a commit",
+	),
+	flag(
+		&["--rust"],
+		None,
+		"Search a Cargo workspace and its dependencies",
+	),
+	flag(&["--shard"], None, "Split the index by top-level directory"),
+	flag(&["--stem"], None, "Also search the stems of terms"),
+	flag(&["--symbols"], None, "Rank symbol definitions first"),
+	flag(
+		&["--template"],
+		Some("template"),
+		"Write each matching line in this layout",
+	),
+	flag(
+		&["--timeout"],
+		Some("seconds"),
+		"Stop ranking after this long",
+	),
+	flag(
+		&["--type", "-t"],
+		Some("language"),
+		"Only search files in a language",
+	),
+	flag(
+		&["--verbose", "-v"],
+		None,
+		"Log more, given twice to log everything",
+	),
+	flag(&["--wait"], None, "Update the index before searching"),
+];
+
+/// Every subcommand. Searches have none.
+pub const SUBCOMMANDS: &[Subcommand] = &[
+	Subcommand {
+		name: "alert",
+		usage: "add <search term>... | check | list | remove <number>",
+		about: "Manage searches that are checked whenever the index is updated. `add` saves one, `check` updates the index and checks them now, `list` numbers them, and `remove` deletes one by its number.",
+		help: "Manage searches checked when the index changes",
+		flags: &[],
+		search_flags: false,
+		takes_files: false,
+	},
+	Subcommand {
+		name: "bench",
+		usage: "[--runs n] <query>...",
+		about: "Time building the index, then run each search term as a query and time it.",
+		help: "Time indexing and searches",
+		flags: &[flag(&["--runs"], Some("n"), "Run each search n times")],
+		search_flags: true,
+		takes_files: false,
+	},
+	Subcommand {
+		name: "completions",
+		usage: "bash|zsh|fish|powershell",
+		about: "Print a completion script for a shell, to load from its startup file.",
+		help: "Print a shell completion script",
+		flags: &[],
+		search_flags: false,
+		takes_files: false,
+	},
+	Subcommand {
+		name: "export",
+		usage: "[--zstd] <file>",
+		about: "Write the index to a bundle file that `import` reads on another machine.",
+		help: "Write the index to a bundle file",
+		flags: &[flag(&["--zstd"], None, "Compress the bundle with zstd")],
+		search_flags: true,
+		takes_files: true,
+	},
+	Subcommand {
+		name: "history",
+		usage: "",
+		about: "List the recent searches and their top results.",
+		help: "List recent searches",
+		flags: &[],
+		search_flags: false,
+		takes_files: false,
+	},
+	Subcommand {
+		name: "import",
+		usage: "<file>",
+		about: "Replace the index with the one in a bundle file written by `export`.",
+		help: "Replace the index with a bundle file",
+		flags: &[],
+		search_flags: true,
+		takes_files: true,
+	},
+	Subcommand {
+		name: "index",
+		usage: "[--dry-run] [--files-from file|-]",
+		about: "Create or update the index without searching it.",
+		help: "Create or update the index",
+		flags: &[
+			flag(&["--dry-run"], None, "List what would be indexed"),
+			flag(
+				&["--files-from"],
+				Some("file"),
+				"Index the files listed in a file",
+			),
+		],
+		search_flags: true,
+		takes_files: false,
+	},
+	Subcommand {
+		name: "log",
+		usage: "<search term>...",
+		about: "Find the commits that added or removed lines matching the search terms.",
+		help: "Find commits that changed matching lines",
+		flags: &[],
+		search_flags: true,
+		takes_files: false,
+	},
+	Subcommand {
+		name: "man",
+		usage: "",
+		about: "Print the manual page of codesearch, in roff, e.g. for `man -l -`.",
+		help: "Print the manual page",
+		flags: &[],
+		search_flags: false,
+		takes_files: false,
+	},
+	Subcommand {
+		name: "replace",
+		usage: "<pattern> <replacement> [--write] [--backup]",
+		about: "Show the substitutions of the replacement for the pattern in every file containing it, as a diff, and write them with `--write`.",
+		help: "Replace a pattern in every file",
+		flags: &[
+			flag(&["--backup"], None, "Keep a copy of each changed file"),
+			flag(&["--write"], None, "Write the replacements"),
+		],
+		search_flags: true,
+		takes_files: false,
+	},
+	Subcommand {
+		name: "run",
+		usage: "[name] [argument]...",
+		about: "Run the search saved under a name, with any extra arguments added, or list the saved searches if no name is given.",
+		help: "Run a saved search, or list them",
+		flags: &[],
+		search_flags: false,
+		takes_files: false,
+	},
+	Subcommand {
+		name: "save",
+		usage: "<name> <search term>...",
+		about: "Save a search with its flags under a name, for `run`.",
+		help: "Save a search under a name",
+		flags: &[],
+		search_flags: false,
+		takes_files: false,
+	},
+	Subcommand {
+		name: "serve",
+		usage: "[--lsp-proxy]",
+		about: "Answer JSON-RPC requests on stdin, for editor integrations.",
+		help: "Answer JSON-RPC requests on stdin",
+		flags: &[flag(&["--lsp-proxy"], None, "Frame messages as in LSP")],
+		search_flags: true,
+		takes_files: false,
+	},
+	Subcommand {
+		name: "set",
+		usage: "and|or|subtract|xor <result file> <result file>...",
+		about: "Combine result files written with `--format json` and write the result file of the combination.",
+		help: "Combine result files",
+		flags: &[],
+		search_flags: false,
+		takes_files: true,
+	},
+	Subcommand {
+		name: "stats",
+		usage: "[--history]",
+		about: "Print the size of the index.",
+		help: "Print the size of the index",
+		flags: &[flag(&["--history"], None, "Show how the index grew")],
+		search_flags: false,
+		takes_files: false,
+	},
+	Subcommand {
+		name: "verify",
+		usage: "[--repair]",
+		about: "Check the index for corruption. Repairs keep the n-gram size the index was built with; give the other flags it was built with, like `--stem`, to keep those too.",
+		help: "Check the index for corruption",
+		flags: &[flag(&["--repair"], None, "Rebuild a corrupt index")],
+		search_flags: true,
+		takes_files: false,
+	},
+	Subcommand {
+		name: "why-not",
+		usage: "<path> <search term>...",
+		about: "Explain why a file is or is not among the results of a search.",
+		help: "Explain why a file is or is not found",
+		flags: &[],
+		search_flags: true,
+		takes_files: true,
+	},
+];
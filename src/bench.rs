@@ -0,0 +1,133 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::bitmap::BitMap;
+use crate::error::CodesearchError;
+use crate::hooks::Hooks;
+use crate::index::{Index, IndexOptions};
+use crate::lock;
+use crate::ngram;
+use crate::progress::ProgressFormat;
+
+/// How many times each query is run when no count is given.
+pub const DEFAULT_RUNS: usize = 20;
+
+/// The latencies of one benchmarked operation.
+pub struct Samples {
+	/// What was measured.
+	pub name: &'static str,
+	/// How long each run took, sorted.
+	durations: Vec<Duration>,
+	/// How many items, e.g. n-grams or files, all runs processed together.
+	items: u64,
+	/// What the items are, for throughput.
+	unit: &'static str,
+}
+
+/// The results of a benchmark.
+pub struct Report {
+	/// The number of documents in the index built.
+	pub documents: u32,
+	/// How long building the index took.
+	pub build: Duration,
+	/// The latencies of each measured phase of searching.
+	pub samples: Vec<Samples>,
+}
+
+impl Samples {
+	fn new(name: &'static str, unit: &'static str) -> Self {
+		Self {
+			name,
+			durations: Vec::new(),
+			items: 0,
+			unit,
+		}
+	}
+
+	/// Returns the latency that fraction `p` of runs took at most.
+	pub fn percentile(&self, p: f64) -> Duration {
+		let i = ((self.durations.len() as f64 * p).ceil() as usize).saturating_sub(1);
+		self.durations.get(i).copied().unwrap_or_default()
+	}
+
+	/// Returns the items processed per second, with their unit.
+	pub fn throughput(&self) -> (f64, &'static str) {
+		let total = self.durations.iter().sum::<Duration>().as_secs_f64();
+		let rate = if total > 0.0 {
+			self.items as f64 / total
+		} else {
+			0.0
+		};
+
+		(rate, self.unit)
+	}
+}
+
+/// Builds an index of the current directory at `path` with `options`, then
+/// runs each of `queries` `runs` times, timing the n-gram lookup on its own
+/// and the whole search with `search`, which returns how many results it
+/// ranked. The index is deleted afterwards.
+pub fn run<F>(
+	path: &Path,
+	options: &IndexOptions,
+	queries: &[String],
+	runs: usize,
+	mut search: F,
+) -> Result<Report, CodesearchError>
+where
+	F: FnMut(&mut Index, &str) -> Result<usize, CodesearchError>,
+{
+	// Hooks would be timed too, and progress isn't wanted in the output
+	let options = IndexOptions {
+		hooks: Hooks::default(),
+		progress: ProgressFormat::Hidden,
+		shard: None,
+		..options.clone()
+	};
+
+	let started = Instant::now();
+	let result = Index::create(path, &options);
+	let build = started.elapsed();
+	let result = result.map_err(CodesearchError::from).and_then(|mut index| {
+		let mut lookup = Samples::new("lookup", "n-grams");
+		let mut searching = Samples::new("search", "results");
+		let mut ranking = Samples::new("ranking", "results");
+		for query in queries {
+			let mut ngrams = Vec::new();
+			ngram::get_ngrams(query, index.ngram_size(), &mut ngrams);
+			for _ in 0..runs {
+				let started = Instant::now();
+				let mut candidates = BitMap::new(index.document_count() as usize);
+				for gram in &ngrams {
+					if let Some(v) = index.find_ngram_ref(*gram)? {
+						candidates |= v;
+					}
+				}
+
+				let looked_up = started.elapsed();
+				lookup.durations.push(looked_up);
+				lookup.items += ngrams.len() as u64;
+
+				let started = Instant::now();
+				let results = search(&mut index, query)? as u64;
+				let searched = started.elapsed();
+				searching.durations.push(searched);
+				searching.items += results;
+				ranking.durations.push(searched.saturating_sub(looked_up));
+				ranking.items += results;
+			}
+		}
+
+		let mut samples = vec![lookup, ranking, searching];
+		samples.iter_mut().for_each(|s| s.durations.sort());
+		Ok(Report {
+			documents: index.document_count(),
+			build,
+			samples,
+		})
+	});
+
+	let _ = std::fs::remove_file(path);
+	let _ = std::fs::remove_file(lock::path_for(path));
+	result
+}
@@ -1,20 +1,60 @@
 use std::{
 	fmt::Display,
 	ops::{
-		BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Shl, ShlAssign, Shr,
-		ShrAssign,
+		BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Not, Shl, ShlAssign, Shr,
+		ShrAssign, Sub, SubAssign,
 	},
 };
 
 /// A variable-length bitmap.
-/// Allows various operations such as bitwise AND, OR, XOR, shifts, etc.
+/// Allows various operations such as bitwise AND, OR, XOR, NOT, shifts, etc.
+/// Bits are stored in 64-bit words, bit `i` being bit `i % 64` of word
+/// `i / 64`. Bits past the bitmap's length in its last word are always `0`,
+/// so operations like NOT never set bits for documents that don't exist.
 #[derive(Clone, Debug)]
-pub struct BitMap(Vec<u8>);
+pub struct BitMap {
+	words: Vec<u64>,
+	len: usize,
+}
+
+/// A borrowed view of a bitmap in its on-disk form, e.g. a posting read
+/// into a reused buffer. Can be combined into a `BitMap` without first
+/// being copied into one.
+#[derive(Clone, Copy, Debug)]
+pub struct BitMapRef<'a> {
+	bytes: &'a [u8],
+	len: usize,
+}
+
+/// The number of bits in each word of a bitmap.
+const WORD_BITS: usize = u64::BITS as usize;
+
+/// An auxiliary index over a bitmap that translates between bit positions
+/// and the ordinals of set bits, e.g. between document IDs and positions
+/// within a filtered subset of documents.
+pub struct RankSelect<'a> {
+	bitmap: &'a BitMap,
+	/// The number of set bits before each word, followed by the total.
+	blocks: Vec<usize>,
+}
+
+/// An iterator over the indices of the set bits in a bitmap.
+pub struct OnesIterator<I> {
+	/// The remaining words of the bitmap.
+	words: I,
+	/// The bit index of the start of `word`.
+	base: usize,
+	/// The bit index of the start of the next word.
+	next: usize,
+	/// The set bits of the current word not yet returned.
+	word: u64,
+}
 
 /// An iterator over a bitmap.
 pub struct BitMapIterator {
+	len: usize,
 	pos: usize,
-	vec: Vec<u8>,
+	words: Vec<u64>,
 }
 
 impl BitMap {
@@ -24,23 +64,158 @@ impl BitMap {
 	/// # Returns
 	/// A new bitmap, with all bits initialized to `0`/`false`.
 	pub fn new(len: usize) -> Self {
-		let bytes = (len as f64 / 8.0).ceil() as usize;
-		Self(vec![0; bytes])
+		Self {
+			words: vec![0; len.div_ceil(WORD_BITS)],
+			len,
+		}
+	}
+
+	/// Creates a bitmap of `len` bits from its on-disk form, as written by
+	/// `to_bytes`. Missing bytes are `0`, and bits past `len` are ignored.
+	pub fn from_be_bytes(bytes: &[u8], len: usize) -> Self {
+		let mut words = bytes
+			.chunks(WORD_BITS / 8)
+			.map(to_word)
+			.collect::<Vec<u64>>();
+
+		words.resize(len.div_ceil(WORD_BITS), 0);
+		let mut bitmap = Self { words, len };
+		bitmap.clear_tail();
+		bitmap
+	}
+
+	/// Returns the on-disk form of this bitmap: bit `i` is bit `i % 8` of
+	/// byte `i / 8`, with just enough bytes to hold every bit.
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut bytes = self
+			.words
+			.iter()
+			.flat_map(|w| w.to_le_bytes())
+			.collect::<Vec<u8>>();
+
+		bytes.truncate(self.byte_len());
+		bytes
+	}
+
+	/// Decodes a bitmap of `len` bits from its run-length encoding, as
+	/// written by `to_rle`. Returns `None` if the encoding is invalid.
+	pub fn from_rle(mut bytes: &[u8], len: usize) -> Option<Self> {
+		let mut bitmap = Self::new(len);
+		let mut pos = 0;
+		while !bytes.is_empty() {
+			pos += read_varint(&mut bytes)?;
+			let ones = read_varint(&mut bytes)?;
+			if ones > len.checked_sub(pos)? {
+				return None;
+			}
+
+			(pos..pos + ones).for_each(|i| bitmap.set(i, true));
+			pos += ones;
+		}
+
+		Some(bitmap)
+	}
+
+	/// Encodes this bitmap as alternating runs of unset and set bits, each
+	/// run length written as a variable-length integer. Trailing unset bits
+	/// are implied by the bitmap's length. Bitmaps made of a few long runs,
+	/// e.g. n-grams found in nearly every document, encode much smaller than
+	/// their bytes.
+	pub fn to_rle(&self) -> Vec<u8> {
+		let mut out = Vec::new();
+		let mut end = 0;
+		let mut run: Option<(usize, usize)> = None;
+		for i in self.iter_ones() {
+			run = match run {
+				Some((start, run_end)) if run_end == i => Some((start, i + 1)),
+				Some((start, run_end)) => {
+					write_varint(&mut out, start - end);
+					write_varint(&mut out, run_end - start);
+					end = run_end;
+					Some((i, i + 1))
+				}
+				None => Some((i, i + 1)),
+			};
+		}
+
+		if let Some((start, run_end)) = run {
+			write_varint(&mut out, start - end);
+			write_varint(&mut out, run_end - start);
+		}
+
+		out
+	}
+
+	/// Returns the length of this bitmap in bits.
+	pub fn len(&self) -> usize {
+		self.len
+	}
+
+	/// Returns the length of the on-disk form of this bitmap in bytes.
+	pub fn byte_len(&self) -> usize {
+		self.len.div_ceil(8)
+	}
+
+	/// Returns the number of bits set to `1`/`true`.
+	pub fn count_ones(&self) -> usize {
+		self.words.iter().map(|w| w.count_ones() as usize).sum()
+	}
+
+	/// Returns `true` if no bits are set.
+	pub fn is_empty(&self) -> bool {
+		self.words.iter().all(|w| *w == 0)
+	}
+
+	/// Returns an iterator over the indices of the set bits, in order.
+	/// Skips a whole word at a time where no bits are set.
+	pub fn iter_ones(&self) -> OnesIterator<impl Iterator<Item = u64> + '_> {
+		OnesIterator {
+			words: self.words.iter().copied(),
+			base: 0,
+			next: 0,
+			word: 0,
+		}
+	}
+
+	/// Builds an index answering rank and select queries on this bitmap.
+	pub fn rank_select(&self) -> RankSelect<'_> {
+		let mut blocks = Vec::with_capacity(self.words.len() + 1);
+		let mut ones = 0;
+		for word in &self.words {
+			blocks.push(ones);
+			ones += word.count_ones() as usize;
+		}
+
+		blocks.push(ones);
+		RankSelect {
+			bitmap: self,
+			blocks,
+		}
+	}
+
+	/// Clears any bits past the length of this bitmap in its last word.
+	fn clear_tail(&mut self) {
+		let used = self.len % WORD_BITS;
+		if used != 0 {
+			if let Some(last) = self.words.last_mut() {
+				*last &= (1 << used) - 1;
+			}
+		}
 	}
 
-	/// Returns this bitmap as a byte slice.
-	pub fn as_bytes(&self) -> &[u8] {
-		return &self.0;
+	/// Resizes this bitmap to at least `len` bits, filling with `0`.
+	fn grow(&mut self, len: usize) {
+		if len > self.len {
+			self.len = len;
+			self.words.resize(len.div_ceil(WORD_BITS), 0);
+		}
 	}
 
 	/// Gets the value at the specified bit.
 	/// Panics if `i` is less than `0` or greater than
 	/// the bitmap's length.
 	pub fn get(&self, i: usize) -> bool {
-		let byte = i / 8;
-		let bit = i % 8;
-		let mask = (1 << bit) as u8;
-		self.0[byte] & mask != 0
+		self.words[i / WORD_BITS] & (1 << (i % WORD_BITS)) != 0
 	}
 
 	/// Sets the specified bit to the given value.
@@ -50,21 +225,142 @@ impl BitMap {
 	/// Panics if `i` is less than `0` or greater than
 	/// the bitmap's length.
 	pub fn set(&mut self, i: usize, v: bool) {
-		let byte = i / 8;
-		let bit = i % 8;
-		let mask = (1 << bit) as u8;
+		let mask = 1 << (i % WORD_BITS);
 		if v {
-			self.0[byte] |= mask;
+			self.words[i / WORD_BITS] |= mask;
 		} else {
-			self.0[byte] &= !mask;
+			self.words[i / WORD_BITS] &= !mask;
+		}
+	}
+}
+
+impl<'a> BitMapRef<'a> {
+	/// Creates a view of the `len`-bit bitmap whose on-disk form is `bytes`.
+	/// Missing bytes are `0`.
+	pub fn new(bytes: &'a [u8], len: usize) -> Self {
+		Self {
+			bytes: &bytes[..usize::min(bytes.len(), len.div_ceil(8))],
+			len,
+		}
+	}
+
+	/// Returns the words of this bitmap, with bits past its length cleared.
+	fn words(self) -> impl Iterator<Item = u64> + 'a {
+		let len = self.len;
+		self.bytes
+			.chunks(WORD_BITS / 8)
+			.enumerate()
+			.map(move |(i, chunk)| {
+				let word = to_word(chunk);
+				match len - i * WORD_BITS {
+					used if used < WORD_BITS => word & ((1 << used) - 1),
+					_ => word,
+				}
+			})
+	}
+
+	/// Returns an iterator over the indices of the set bits, in order.
+	pub fn iter_ones(self) -> OnesIterator<impl Iterator<Item = u64> + 'a> {
+		OnesIterator {
+			words: self.words(),
+			base: 0,
+			next: 0,
+			word: 0,
+		}
+	}
+
+	/// Copies this view into a new bitmap.
+	pub fn to_bitmap(self) -> BitMap {
+		BitMap::from_be_bytes(self.bytes, self.len)
+	}
+}
+
+impl RankSelect<'_> {
+	/// Returns the number of bits set before bit `i`, in constant time.
+	/// Bits past the end of the bitmap count as unset.
+	pub fn rank(&self, i: usize) -> usize {
+		let block = i / WORD_BITS;
+		if block >= self.bitmap.words.len() {
+			return self.blocks[self.blocks.len() - 1];
+		}
+
+		let mask = (1u64 << (i % WORD_BITS)) - 1;
+		self.blocks[block] + (self.bitmap.words[block] & mask).count_ones() as usize
+	}
+
+	/// Returns the position of the set bit with ordinal `n`, counting from
+	/// `0`, or `None` if fewer bits are set. Finds the word by binary search,
+	/// so this takes time logarithmic in the length of the bitmap.
+	#[cfg(test)]
+	pub fn select(&self, n: usize) -> Option<usize> {
+		if n >= *self.blocks.last()? {
+			return None;
+		}
+
+		// The last word whose preceding count is at most `n` holds the bit
+		let block = self.blocks.partition_point(|ones| *ones <= n) - 1;
+		let mut word = self.bitmap.words[block];
+		for _ in 0..n - self.blocks[block] {
+			word &= word - 1;
+		}
+
+		Some(block * WORD_BITS + word.trailing_zeros() as usize)
+	}
+}
+
+/// Reads up to 8 bytes of a bitmap's on-disk form as a little-endian word,
+/// so that bit `i` of the word is bit `i` of the bytes.
+fn to_word(bytes: &[u8]) -> u64 {
+	let mut word = [0; 8];
+	word[..bytes.len()].copy_from_slice(bytes);
+	u64::from_le_bytes(word)
+}
+
+/// Writes `value` as a LEB128 variable-length integer.
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+	while value >= 0x80 {
+		out.push(value as u8 | 0x80);
+		value >>= 7;
+	}
+
+	out.push(value as u8);
+}
+
+/// Reads a LEB128 variable-length integer from the front of `bytes`.
+fn read_varint(bytes: &mut &[u8]) -> Option<usize> {
+	let mut value = 0usize;
+	for shift in (0..usize::BITS).step_by(7) {
+		let (byte, rest) = bytes.split_first()?;
+		*bytes = rest;
+		value |= ((byte & 0x7f) as usize).checked_shl(shift)?;
+		if byte & 0x80 == 0 {
+			return Some(value);
 		}
 	}
+
+	None
+}
+
+impl<I: Iterator<Item = u64>> Iterator for OnesIterator<I> {
+	type Item = usize;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		while self.word == 0 {
+			self.word = self.words.next()?;
+			self.base = self.next;
+			self.next += WORD_BITS;
+		}
+
+		let bit = self.word.trailing_zeros() as usize;
+		self.word &= self.word - 1;
+		Some(self.base + bit)
+	}
 }
 
 impl Display for BitMap {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-		let mut buf = String::with_capacity(self.0.len() * 8);
-		for b in &self.0 {
+		let mut buf = String::with_capacity(self.byte_len() * 8);
+		for b in self.to_bytes() {
 			buf.push_str(&format!("{:08b}", b));
 		}
 
@@ -72,20 +368,15 @@ impl Display for BitMap {
 	}
 }
 
-impl From<Vec<u8>> for BitMap {
-	fn from(value: Vec<u8>) -> Self {
-		Self(value)
-	}
-}
-
 impl IntoIterator for BitMap {
 	type Item = bool;
 	type IntoIter = BitMapIterator;
 
 	fn into_iter(self) -> Self::IntoIter {
 		BitMapIterator {
+			len: self.len,
 			pos: 0,
-			vec: self.0,
+			words: self.words,
 		}
 	}
 }
@@ -94,172 +385,219 @@ impl Iterator for BitMapIterator {
 	type Item = bool;
 
 	fn next(&mut self) -> Option<Self::Item> {
-		let byte = self.pos / 8;
-		if byte >= self.vec.len() {
+		if self.pos >= self.len {
 			return None;
 		}
 
-		let bit = self.pos % 8;
-
-		let byte = self.vec[byte];
-		let bit = 1 << bit;
+		let word = self.words[self.pos / WORD_BITS];
+		let bit = 1 << (self.pos % WORD_BITS);
 
 		self.pos += 1;
-		Some(byte & bit != 0)
+		Some(word & bit != 0)
 	}
 }
 
 impl BitAnd<&Self> for BitMap {
 	type Output = Self;
 
-	fn bitand(self, rhs: &Self) -> Self::Output {
-		let len = usize::max(self.0.len(), rhs.0.len());
-		let mut res = Self::new(len);
-		for i in 0..len {
-			res.0[i] = self.0.get(i).unwrap_or(&0) & rhs.0.get(i).unwrap_or(&0);
-		}
-
-		res
+	fn bitand(mut self, rhs: &Self) -> Self::Output {
+		self &= rhs;
+		self
 	}
 }
 
 impl BitAndAssign<&Self> for BitMap {
 	fn bitand_assign(&mut self, rhs: &Self) {
-		let len = usize::max(self.0.len(), rhs.0.len());
-		for i in 0..len {
-			if i < self.0.len() {
-				self.0[i] &= rhs.0.get(i).unwrap_or(&0);
-			} else {
-				self.0.push(0);
-			}
-		}
+		self.grow(rhs.len);
+		let shared = usize::min(self.words.len(), rhs.words.len());
+		let (head, tail) = self.words.split_at_mut(shared);
+		head.iter_mut()
+			.zip(&rhs.words[..shared])
+			.for_each(|(a, b)| *a &= b);
+
+		tail.fill(0);
+	}
+}
+
+impl BitAndAssign<BitMapRef<'_>> for BitMap {
+	fn bitand_assign(&mut self, rhs: BitMapRef<'_>) {
+		self.grow(rhs.len);
+		let mut rhs = rhs.words();
+		self.words
+			.iter_mut()
+			.for_each(|a| *a &= rhs.next().unwrap_or(0));
 	}
 }
 
 impl BitOr<&Self> for BitMap {
 	type Output = Self;
 
-	fn bitor(self, rhs: &Self) -> Self::Output {
-		let len = usize::max(self.0.len(), rhs.0.len());
-		let mut res = Self::new(len);
-		for i in 0..len {
-			res.0[i] =
-				self.0.get(i).as_deref().unwrap_or(&0) | rhs.0.get(i).as_deref().unwrap_or(&0);
-		}
-
-		res
+	fn bitor(mut self, rhs: &Self) -> Self::Output {
+		self |= rhs;
+		self
 	}
 }
 
 impl BitOrAssign<&Self> for BitMap {
 	fn bitor_assign(&mut self, rhs: &Self) {
-		let len = usize::max(self.0.len(), rhs.0.len());
-		for i in 0..len {
-			if i < self.0.len() {
-				self.0[i] |= rhs.0.get(i).unwrap_or(&0);
-			} else {
-				self.0.push(rhs.0[i]);
-			}
-		}
+		self.grow(rhs.len);
+		self.words
+			.iter_mut()
+			.zip(&rhs.words)
+			.for_each(|(a, b)| *a |= b);
+	}
+}
+
+impl BitOrAssign<BitMapRef<'_>> for BitMap {
+	fn bitor_assign(&mut self, rhs: BitMapRef<'_>) {
+		self.grow(rhs.len);
+		self.words
+			.iter_mut()
+			.zip(rhs.words())
+			.for_each(|(a, b)| *a |= b);
 	}
 }
 
 impl BitXor<&Self> for BitMap {
 	type Output = Self;
 
-	fn bitxor(self, rhs: &Self) -> Self::Output {
-		let len = usize::max(self.0.len(), rhs.0.len());
-		let mut res = Self::new(len);
-		for i in 0..len {
-			res.0[i] =
-				self.0.get(i).as_deref().unwrap_or(&0) ^ rhs.0.get(i).as_deref().unwrap_or(&0);
-		}
-
-		res
+	fn bitxor(mut self, rhs: &Self) -> Self::Output {
+		self ^= rhs;
+		self
 	}
 }
 
 impl BitXorAssign<&Self> for BitMap {
 	fn bitxor_assign(&mut self, rhs: &Self) {
-		let len = usize::max(self.0.len(), rhs.0.len());
-		for i in 0..len {
-			if i < self.0.len() {
-				self.0[i] ^= rhs.0.get(i).unwrap_or(&0);
-			} else {
-				self.0.push(rhs.0[i]);
-			}
-		}
+		self.grow(rhs.len);
+		self.words
+			.iter_mut()
+			.zip(&rhs.words)
+			.for_each(|(a, b)| *a ^= b);
+	}
+}
+
+impl Not for BitMap {
+	type Output = Self;
+
+	fn not(mut self) -> Self::Output {
+		self.words.iter_mut().for_each(|w| *w = !*w);
+		self.clear_tail();
+		self
+	}
+}
+
+impl Sub<&Self> for BitMap {
+	type Output = Self;
+
+	/// Returns the bits set in this bitmap but not in `rhs`.
+	fn sub(mut self, rhs: &Self) -> Self::Output {
+		self -= rhs;
+		self
+	}
+}
+
+impl SubAssign<&Self> for BitMap {
+	fn sub_assign(&mut self, rhs: &Self) {
+		self.words
+			.iter_mut()
+			.zip(&rhs.words)
+			.for_each(|(a, b)| *a &= !b);
 	}
 }
 
 impl Shl<usize> for BitMap {
 	type Output = Self;
 
-	fn shl(self, rhs: usize) -> Self::Output {
-		let mut res = self.clone();
-		res <<= rhs;
-		res
+	fn shl(mut self, rhs: usize) -> Self::Output {
+		self <<= rhs;
+		self
 	}
 }
 
 impl ShlAssign<usize> for BitMap {
+	/// Moves every bit `rhs` places towards the end of the bitmap, so bit
+	/// `i` becomes bit `i + rhs`. The length is unchanged: bits moved past
+	/// the end are dropped, and the first `rhs` bits become `0`.
 	fn shl_assign(&mut self, rhs: usize) {
-		let byte_shifts = rhs / u8::BITS as usize;
-		let bit_shifts = rhs % u8::BITS as usize;
-		for _ in 0..byte_shifts {
-			for i in 1..self.0.len() {
-				self.0[i - 1] = self.0[i];
+		let word_shifts = rhs / WORD_BITS;
+		let bit_shifts = rhs % WORD_BITS;
+		for i in (0..self.words.len()).rev() {
+			let mut word = 0;
+			if i >= word_shifts {
+				word = self.words[i - word_shifts] << bit_shifts;
+				if bit_shifts > 0 && i > word_shifts {
+					word |= self.words[i - word_shifts - 1] >> (WORD_BITS - bit_shifts);
+				}
 			}
 
-			let end = self.0.len() - 1;
-			self.0[end] = 0;
+			self.words[i] = word;
 		}
 
-		let hi_bits = u8::BITS as usize - bit_shifts;
-		let hi_mask = u8::MAX << hi_bits;
-		let mut hi = 0;
-		for i in 0..self.0.len() {
-			if i > 0 {
-				self.0[i - 1] |= hi;
-			}
-
-			let byte = self.0[i];
-			hi = (byte & hi_mask) >> hi_bits;
-			self.0[i] <<= bit_shifts;
-		}
+		self.clear_tail();
 	}
 }
 
 impl Shr<usize> for BitMap {
 	type Output = Self;
 
-	fn shr(self, rhs: usize) -> Self::Output {
-		let mut res = self.clone();
-		res >>= rhs;
-		res
+	fn shr(mut self, rhs: usize) -> Self::Output {
+		self >>= rhs;
+		self
 	}
 }
 
 impl ShrAssign<usize> for BitMap {
+	/// Moves every bit `rhs` places towards the start of the bitmap, so bit
+	/// `i` becomes bit `i - rhs`. The length is unchanged: the first `rhs`
+	/// bits are dropped, and the last `rhs` bits become `0`.
 	fn shr_assign(&mut self, rhs: usize) {
-		let byte_shifts = rhs / u8::BITS as usize;
-		let bit_shifts = rhs % u8::BITS as usize;
-		for _ in 0..byte_shifts {
-			for i in (0..self.0.len() - 1).rev() {
-				self.0[i + 1] = self.0[i];
+		let word_shifts = rhs / WORD_BITS;
+		let bit_shifts = rhs % WORD_BITS;
+		let len = self.words.len();
+		for i in 0..len {
+			let mut word = 0;
+			if i + word_shifts < len {
+				word = self.words[i + word_shifts] >> bit_shifts;
+				if bit_shifts > 0 && i + word_shifts + 1 < len {
+					word |= self.words[i + word_shifts + 1] << (WORD_BITS - bit_shifts);
+				}
 			}
 
-			self.0[0] = 0;
+			self.words[i] = word;
 		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn select_inverts_rank() {
+		let mut bitmap = BitMap::new(300);
+		let ones = [0, 5, 63, 64, 200, 299];
+		ones.iter().for_each(|i| bitmap.set(*i, true));
 
-		let hi_bits = u8::BITS as usize - bit_shifts;
-		let hi_mask = u8::MAX >> hi_bits;
-		let mut hi = 0;
-		for i in 0..self.0.len() {
-			let byte = self.0[i];
-			self.0[i] >>= bit_shifts;
-			self.0[i] |= hi;
-			hi = (byte & hi_mask) << hi_bits;
+		let index = bitmap.rank_select();
+		for (n, i) in ones.iter().enumerate() {
+			assert_eq!(index.rank(*i), n);
+			assert_eq!(index.select(n), Some(*i));
 		}
+
+		assert_eq!(index.rank(300), ones.len());
+		assert_eq!(index.select(ones.len()), None);
+	}
+
+	#[test]
+	fn select_skips_empty_words() {
+		let mut bitmap = BitMap::new(256);
+		bitmap.set(1, true);
+		bitmap.set(250, true);
+
+		let index = bitmap.rank_select();
+		assert_eq!(index.select(0), Some(1));
+		assert_eq!(index.select(1), Some(250));
+		assert_eq!(BitMap::new(10).rank_select().select(0), None);
 	}
 }
@@ -0,0 +1,109 @@
+use std::path::{Path, PathBuf};
+
+use crate::args::Options;
+
+/// The flags that change which files are indexed and how, saved alongside
+/// the index so that updates, repairs, and background builds index files
+/// the way it was built.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BuildOptions {
+	/// `--binary`: index files that look binary as text.
+	pub binary: bool,
+	/// `--follow`: index through symbolic links to directories.
+	pub follow: bool,
+	/// `--git-tracked`: only index the files git tracks.
+	pub git_tracked: bool,
+	/// `--include-generated`: index minified and generated files.
+	pub include_generated: bool,
+	/// `--link-targets`: record where files reached through links are.
+	pub link_targets: bool,
+	/// `--rust`: index the whole Cargo workspace.
+	pub rust: bool,
+	/// `--stem`: record the stems of identifier subtokens.
+	pub stem: bool,
+}
+
+impl BuildOptions {
+	/// Returns the build options set by `options`.
+	pub fn from_options(options: &Options) -> Self {
+		Self {
+			binary: options.binary,
+			follow: options.follow,
+			git_tracked: options.git_tracked,
+			include_generated: options.include_generated,
+			link_targets: options.link_targets,
+			rust: options.rust,
+			stem: options.stem,
+		}
+	}
+
+	/// Sets the flags of `options` that these build options set.
+	pub fn apply(self, options: &mut Options) {
+		options.binary |= self.binary;
+		options.follow |= self.follow;
+		options.git_tracked |= self.git_tracked;
+		options.include_generated |= self.include_generated;
+		options.link_targets |= self.link_targets;
+		options.rust |= self.rust;
+		options.stem |= self.stem;
+	}
+
+	/// Returns the command-line flags that set these build options.
+	pub fn flags(self) -> Vec<&'static str> {
+		[
+			(self.binary, "--binary"),
+			(self.follow, "--follow"),
+			(self.git_tracked, "--git-tracked"),
+			(self.include_generated, "--include-generated"),
+			(self.link_targets, "--link-targets"),
+			(self.rust, "--rust"),
+			(self.stem, "--stem"),
+		]
+		.into_iter()
+		.filter_map(|(set, flag)| set.then_some(flag))
+		.collect()
+	}
+}
+
+/// Returns the path of the build options kept alongside the index at
+/// `index`.
+pub fn path_for(index: &Path) -> PathBuf {
+	index.with_extension("options")
+}
+
+/// Reads the build options saved at `path`, one flag per line, or `None` if
+/// there are none. Unknown flags are ignored.
+pub fn load(path: &Path) -> std::io::Result<Option<BuildOptions>> {
+	let contents = match std::fs::read_to_string(path) {
+		Ok(v) => v,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+		Err(e) => return Err(e),
+	};
+
+	let mut options = BuildOptions::default();
+	for line in contents.lines() {
+		match line.trim() {
+			"--binary" => options.binary = true,
+			"--follow" => options.follow = true,
+			"--git-tracked" => options.git_tracked = true,
+			"--include-generated" => options.include_generated = true,
+			"--link-targets" => options.link_targets = true,
+			"--rust" => options.rust = true,
+			"--stem" => options.stem = true,
+			_ => {}
+		}
+	}
+
+	Ok(Some(options))
+}
+
+/// Writes `options` to `path`, replacing any saved build options.
+pub fn save(path: &Path, options: BuildOptions) -> std::io::Result<()> {
+	let mut contents = String::new();
+	for flag in options.flags() {
+		contents.push_str(flag);
+		contents.push('\n');
+	}
+
+	std::fs::write(path, contents)
+}
@@ -0,0 +1,254 @@
+use serde_json::{json, Value};
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::build_options;
+use crate::file_list;
+use crate::lock::IndexLock;
+use crate::shard;
+
+/// The version of the bundle format, raised when it changes incompatibly.
+const FORMAT_VERSION: u64 = 1;
+
+/// The name of the bundle entry describing the exported index.
+const METADATA_ENTRY: &str = "codesearch.json";
+
+/// The name of the bundle entry holding the index file itself. Files kept
+/// alongside it are named by their extension, e.g. `index.shards`.
+const INDEX_ENTRY: &str = "index";
+
+/// The magic bytes starting a zstd frame.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// The zstd compression level of exported bundles.
+const ZSTD_LEVEL: i32 = 19;
+
+/// Represents an error exporting or importing an index.
+#[derive(Debug)]
+pub enum BundleError {
+	Invalid(String),
+	Io(io::Error),
+	NoIndex,
+}
+
+impl Display for BundleError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			BundleError::Invalid(msg) => write!(f, "invalid index bundle: {msg}"),
+			BundleError::Io(e) => write!(f, "index bundle error: {e}"),
+			BundleError::NoIndex => write!(f, "there is no index to export yet"),
+		}
+	}
+}
+
+impl std::error::Error for BundleError {}
+
+impl From<io::Error> for BundleError {
+	fn from(value: io::Error) -> Self {
+		BundleError::Io(value)
+	}
+}
+
+/// Describes an exported index.
+#[derive(Debug)]
+pub struct Metadata {
+	/// The directory the index was built in.
+	pub root: PathBuf,
+	/// The version of codesearch that exported it.
+	pub version: String,
+	/// When the index was last written.
+	pub written: SystemTime,
+}
+
+/// Writes the index at `save_path`, with its shards if it is split into the
+/// shards of `shards`, to a bundle at `out`: a tar file, compressed with
+/// zstd if `zstd` is set, that `import` reads back.
+pub fn export(
+	save_path: &Path,
+	shards: Option<&[PathBuf]>,
+	out: &Path,
+	zstd: bool,
+) -> Result<(), BundleError> {
+	let mut files = Vec::new();
+	match shards {
+		Some(dirs) => {
+			files.push(shard::manifest_path(save_path));
+			files.extend(dirs.iter().map(|dir| shard::index_path(save_path, dir)));
+		}
+		None if save_path.exists() => files.push(save_path.to_path_buf()),
+		None => return Err(BundleError::NoIndex),
+	}
+
+	for path in [
+		file_list::path_for(save_path),
+		build_options::path_for(save_path),
+	] {
+		if path.exists() {
+			files.push(path);
+		}
+	}
+
+	// The index isn't rewritten while it is exported
+	let _lock = IndexLock::shared(save_path)?;
+	let written = fs::metadata(files.first().expect("an index has a file"))?.modified()?;
+	let metadata = json!({
+		"format": FORMAT_VERSION,
+		"root": std::env::current_dir()?.to_string_lossy(),
+		"version": env!("CARGO_PKG_VERSION"),
+		"written": written.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+	});
+
+	let file = BufWriter::new(File::create(out)?);
+	let writer: Box<dyn Write> = if zstd {
+		Box::new(zstd::Encoder::new(file, ZSTD_LEVEL)?.auto_finish())
+	} else {
+		Box::new(file)
+	};
+
+	let mut tar = tar::Builder::new(writer);
+	let metadata = serde_json::to_vec_pretty(&metadata).expect("metadata is valid JSON");
+	append(&mut tar, METADATA_ENTRY, &metadata, written)?;
+	for path in &files {
+		append(
+			&mut tar,
+			&entry_name(save_path, path),
+			&fs::read(path)?,
+			written,
+		)?;
+	}
+
+	tar.into_inner()?.flush()?;
+	Ok(())
+}
+
+/// Appends a file named `name` holding `data` to `tar`.
+fn append<W: Write>(
+	tar: &mut tar::Builder<W>,
+	name: &str,
+	data: &[u8],
+	modified: SystemTime,
+) -> io::Result<()> {
+	let mut header = tar::Header::new_gnu();
+	header.set_size(data.len() as u64);
+	header.set_mode(0o644);
+	header.set_mtime(
+		modified
+			.duration_since(UNIX_EPOCH)
+			.unwrap_or_default()
+			.as_secs(),
+	);
+	header.set_cksum();
+	tar.append_data(&mut header, name, data)
+}
+
+/// Returns the name of the bundle entry holding the file at `path`, kept
+/// alongside the index at `save_path`.
+fn entry_name(save_path: &Path, path: &Path) -> String {
+	match path.extension().filter(|_| path != save_path) {
+		Some(extension) => format!("{INDEX_ENTRY}.{}", extension.to_string_lossy()),
+		None => String::from(INDEX_ENTRY),
+	}
+}
+
+/// Replaces the index at `save_path`, and any shards of it, with the index
+/// in the bundle at `input`, and returns its metadata. Each file keeps the
+/// modification time of the exported index, so files changed since are
+/// checked on the next update.
+pub fn import(save_path: &Path, input: &Path) -> Result<Metadata, BundleError> {
+	// Bundles are compressed if they start like a zstd frame
+	let mut reader = BufReader::new(File::open(input)?);
+	let reader: Box<dyn Read> = if reader.fill_buf()?.starts_with(&ZSTD_MAGIC) {
+		Box::new(zstd::Decoder::with_buffer(reader)?)
+	} else {
+		Box::new(reader)
+	};
+
+	let mut tar = tar::Archive::new(reader);
+	let mut metadata = None;
+	let mut files = Vec::new();
+	for entry in tar.entries()? {
+		let mut entry = entry?;
+		let name = entry.path()?.to_string_lossy().into_owned();
+		let mut data = Vec::new();
+		entry.read_to_end(&mut data)?;
+		if name == METADATA_ENTRY {
+			metadata = Some(parse_metadata(&data)?);
+		} else if name == INDEX_ENTRY {
+			files.push((save_path.to_path_buf(), data));
+		} else if let Some(extension) = name.strip_prefix(&format!("{INDEX_ENTRY}.")) {
+			// Entries are only ever written next to the index
+			if extension.is_empty() || extension.contains(['/', '\\']) || extension == ".." {
+				return Err(BundleError::Invalid(format!("unexpected entry {name}")));
+			}
+
+			files.push((save_path.with_extension(extension), data));
+		} else {
+			return Err(BundleError::Invalid(format!("unexpected entry {name}")));
+		}
+	}
+
+	let metadata =
+		metadata.ok_or_else(|| BundleError::Invalid(format!("missing {METADATA_ENTRY}")))?;
+	if files.is_empty() {
+		return Err(BundleError::Invalid(String::from("no index files")));
+	}
+
+	// The files of the index being replaced could outlive it otherwise
+	let _lock = IndexLock::exclusive(save_path)?;
+	if let Ok(Some(dirs)) = shard::load(&shard::manifest_path(save_path)) {
+		for dir in dirs {
+			let _ = fs::remove_file(shard::index_path(save_path, &dir));
+		}
+	}
+
+	for path in [
+		save_path.to_path_buf(),
+		shard::manifest_path(save_path),
+		file_list::path_for(save_path),
+		build_options::path_for(save_path),
+	] {
+		let _ = fs::remove_file(path);
+	}
+
+	for (path, data) in files {
+		fs::write(&path, data)?;
+		File::options()
+			.write(true)
+			.open(&path)?
+			.set_modified(metadata.written)?;
+	}
+
+	Ok(metadata)
+}
+
+/// Parses the metadata entry of a bundle.
+fn parse_metadata(data: &[u8]) -> Result<Metadata, BundleError> {
+	let invalid = |msg: &str| BundleError::Invalid(format!("{METADATA_ENTRY}: {msg}"));
+	let value = serde_json::from_slice::<Value>(data).map_err(|e| invalid(&e.to_string()))?;
+	let format = value.get("format").and_then(Value::as_u64);
+	if format != Some(FORMAT_VERSION) {
+		return Err(invalid("unsupported format version"));
+	}
+
+	let string = |key: &str| {
+		value
+			.get(key)
+			.and_then(Value::as_str)
+			.map(String::from)
+			.ok_or_else(|| invalid(&format!("missing {key}")))
+	};
+
+	let written = value
+		.get("written")
+		.and_then(Value::as_u64)
+		.ok_or_else(|| invalid("missing written"))?;
+
+	Ok(Metadata {
+		root: PathBuf::from(string("root")?),
+		version: string("version")?,
+		written: UNIX_EPOCH + Duration::from_secs(written),
+	})
+}
@@ -0,0 +1,51 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Lets a search be cancelled from another thread, e.g. when a newer query
+/// supersedes it, or stopped once a deadline passes. Searches check it
+/// between documents and ranking steps and stop early once it is cancelled
+/// or expired. Clones share the same state.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+	cancelled: Arc<AtomicBool>,
+	deadline: Option<Instant>,
+}
+
+impl CancelToken {
+	/// Returns a token that expires once `timeout` has passed, if given.
+	pub fn with_timeout(timeout: Option<Duration>) -> Self {
+		Self {
+			deadline: timeout.map(|timeout| Instant::now() + timeout),
+			..Self::default()
+		}
+	}
+
+	/// Cancels the searches holding this token or a clone of it.
+	pub fn cancel(&self) {
+		self.cancelled.store(true, Ordering::Relaxed);
+	}
+
+	/// Returns `true` if the token was cancelled.
+	pub fn is_cancelled(&self) -> bool {
+		self.cancelled.load(Ordering::Relaxed)
+	}
+
+	/// Returns `true` if the token has a deadline, so searches should rank
+	/// their most promising candidates first.
+	pub fn has_deadline(&self) -> bool {
+		self.deadline.is_some()
+	}
+
+	/// Returns `true` if the token's deadline has passed. Searches keep the
+	/// results found so far when it expires.
+	pub fn is_expired(&self) -> bool {
+		self.deadline
+			.is_some_and(|deadline| Instant::now() >= deadline)
+	}
+
+	/// Returns `true` if the token was cancelled or has expired.
+	pub fn should_stop(&self) -> bool {
+		self.is_cancelled() || self.is_expired()
+	}
+}
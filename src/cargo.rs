@@ -0,0 +1,236 @@
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::path::{Component, Path, PathBuf};
+
+use toml::{Table, Value};
+
+/// The name of the Cargo manifest in each crate's directory.
+const MANIFEST_FILE: &str = "Cargo.toml";
+
+/// The dependency tables whose path dependencies are followed.
+const DEPENDENCY_TABLES: [&str; 3] = ["dependencies", "dev-dependencies", "build-dependencies"];
+
+/// A crate in a Cargo workspace, or a path dependency of one.
+#[derive(Debug)]
+pub struct Crate {
+	/// The crate's name as written in paths, with `-` replaced by `_`.
+	pub name: String,
+	/// The crate's directory, relative to the current directory.
+	pub dir: PathBuf,
+}
+
+/// The crates of the Cargo workspace or package in the current directory.
+#[derive(Debug, Default)]
+pub struct Workspace {
+	/// Every workspace member and path dependency.
+	pub crates: Vec<Crate>,
+	/// The name of the package in the current directory, if it is one.
+	pub root: Option<String>,
+}
+
+/// Represents an error reading a Cargo manifest.
+#[derive(Debug)]
+pub enum CargoError {
+	Invalid(String),
+	Io(PathBuf, std::io::Error),
+	Parse(PathBuf, toml::de::Error),
+}
+
+impl Display for CargoError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			CargoError::Invalid(msg) => write!(f, "invalid Cargo manifest: {msg}"),
+			CargoError::Io(path, e) => write!(f, "failed to read {}: {e}", path.display()),
+			CargoError::Parse(path, e) => write!(f, "invalid {}: {e}", path.display()),
+		}
+	}
+}
+
+impl std::error::Error for CargoError {}
+
+impl Workspace {
+	/// Loads the workspace or package whose manifest is in the current
+	/// directory, along with every crate reachable through path dependencies.
+	pub fn load() -> Result<Self, CargoError> {
+		let mut workspace = Self::default();
+		let manifest = read_manifest(Path::new("."))?;
+		workspace.root = package_name(&manifest);
+
+		let mut pending = vec![PathBuf::from(".")];
+		if let Some(Value::Table(ws)) = manifest.get("workspace") {
+			for member in strings(ws, "members")? {
+				pending.extend(expand_member(&member)?);
+			}
+
+			pending.extend(path_dependencies(Path::new("."), ws));
+		}
+
+		let mut seen = HashSet::new();
+		while let Some(dir) = pending.pop() {
+			if !seen.insert(dir.canonicalize().unwrap_or_else(|_| dir.clone())) {
+				continue;
+			}
+
+			let manifest = read_manifest(&dir)?;
+			for table in DEPENDENCY_TABLES {
+				if let Some(Value::Table(deps)) = manifest.get(table) {
+					pending.extend(path_dependencies(&dir, deps));
+				}
+			}
+
+			if let Some(name) = package_name(&manifest) {
+				workspace.crates.push(Crate { name, dir });
+			}
+		}
+
+		workspace.crates.sort_by(|a, b| a.dir.cmp(&b.dir));
+		Ok(workspace)
+	}
+
+	/// Returns the crate directories outside the current directory, which
+	/// aren't found by walking it.
+	pub fn external_roots(&self) -> Vec<PathBuf> {
+		self.crates
+			.iter()
+			.filter(|c| c.dir.starts_with(".."))
+			.map(|c| c.dir.clone())
+			.collect()
+	}
+
+	/// Resolves a `crate::`-style path like `crate::index::Index` or
+	/// `my_crate::io::read` to the files of the deepest module it names and
+	/// the item to search for in them, its last segment. Crates without a
+	/// `lib.rs` or `main.rs` resolve to their whole directory. Returns `None`
+	/// if `term` isn't a path into a crate of this workspace.
+	pub fn resolve_path(&self, term: &str) -> Option<(Vec<PathBuf>, String)> {
+		let segments = term.split("::").collect::<Vec<&str>>();
+		let (first, rest) = segments.split_first()?;
+		let (item, modules) = rest.split_last()?;
+		let crates = self
+			.crates
+			.iter()
+			.filter(|c| match *first {
+				"crate" => self.root.as_ref().is_none_or(|root| &c.name == root),
+				name => c.name == name,
+			})
+			.collect::<Vec<&Crate>>();
+
+		if crates.is_empty() || item.is_empty() {
+			return None;
+		}
+
+		let files = crates
+			.iter()
+			.map(|c| resolve_module(&c.dir.join("src"), modules).unwrap_or_else(|| c.dir.clone()))
+			.collect::<Vec<PathBuf>>();
+
+		Some((files, String::from(*item)))
+	}
+}
+
+/// Returns the file of the module at `modules` under the crate source
+/// directory `src`, stopping at the first module with no file.
+fn resolve_module(src: &Path, modules: &[&str]) -> Option<PathBuf> {
+	let mut file = ["lib.rs", "main.rs"]
+		.iter()
+		.map(|name| src.join(name))
+		.find(|path| path.is_file())?;
+
+	let mut dir = src.to_path_buf();
+	for module in modules {
+		let flat = dir.join(format!("{module}.rs"));
+		let nested = dir.join(module).join("mod.rs");
+		file = match (flat.is_file(), nested.is_file()) {
+			(true, _) => flat,
+			(_, true) => nested,
+			_ => break,
+		};
+
+		dir.push(module);
+	}
+
+	Some(file)
+}
+
+/// Reads and parses the manifest in `dir`.
+fn read_manifest(dir: &Path) -> Result<Table, CargoError> {
+	let path = dir.join(MANIFEST_FILE);
+	let contents = std::fs::read_to_string(&path).map_err(|e| CargoError::Io(path.clone(), e))?;
+
+	contents
+		.parse::<Table>()
+		.map_err(|e| CargoError::Parse(path, e))
+}
+
+/// Returns the package name in `manifest` as written in paths.
+fn package_name(manifest: &Table) -> Option<String> {
+	let name = manifest.get("package")?.get("name")?.as_str()?;
+	Some(name.replace('-', "_"))
+}
+
+/// Returns the array of strings named `key`, which may be missing.
+fn strings(table: &Table, key: &str) -> Result<Vec<String>, CargoError> {
+	let invalid = || CargoError::Invalid(format!("{key} must be an array of strings"));
+	match table.get(key) {
+		None => Ok(Vec::new()),
+		Some(Value::Array(values)) => values
+			.iter()
+			.map(|v| v.as_str().map(String::from).ok_or_else(invalid))
+			.collect(),
+		Some(_) => Err(invalid()),
+	}
+}
+
+/// Expands a workspace member, which may end in a `*` matching every
+/// directory with a manifest, e.g. `crates/*`.
+fn expand_member(member: &str) -> Result<Vec<PathBuf>, CargoError> {
+	let parent = match member.strip_suffix("/*") {
+		Some(parent) => Path::new(".").join(parent),
+		None => return Ok(vec![normalize(&Path::new(".").join(member))]),
+	};
+
+	let entries = std::fs::read_dir(&parent).map_err(|e| CargoError::Io(parent.clone(), e))?;
+	let mut members = Vec::new();
+	for entry in entries {
+		let path = entry.map_err(|e| CargoError::Io(parent.clone(), e))?.path();
+		if path.join(MANIFEST_FILE).is_file() {
+			members.push(normalize(&path));
+		}
+	}
+
+	Ok(members)
+}
+
+/// Returns the directories of the path dependencies in `deps`, resolved
+/// against the crate directory `dir`.
+fn path_dependencies(dir: &Path, deps: &Table) -> Vec<PathBuf> {
+	deps.values()
+		.filter_map(|dep| dep.get("path")?.as_str())
+		.map(|path| normalize(&dir.join(path)))
+		.collect()
+}
+
+/// Resolves `.` and `..` in `path` without touching the file system, so a
+/// path dependency `./a/../../b` becomes `../b`. Paths under the current
+/// directory keep a leading `./`, matching the paths of walked files.
+fn normalize(path: &Path) -> PathBuf {
+	let mut parts = Vec::new();
+	for component in path.components() {
+		match component {
+			Component::CurDir => {}
+			Component::ParentDir => match parts.last() {
+				Some(Component::Normal(_)) => {
+					parts.pop();
+				}
+				_ => parts.push(component),
+			},
+			_ => parts.push(component),
+		}
+	}
+
+	let normalized = parts.iter().collect::<PathBuf>();
+	match parts.first() {
+		Some(Component::Normal(_)) | None => Path::new(".").join(normalized),
+		_ => normalized,
+	}
+}
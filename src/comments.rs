@@ -0,0 +1,157 @@
+use std::ops::Range;
+
+use crate::language::Language;
+
+/// Where a byte of a document is: in code, a comment, or a string literal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Region {
+	Code,
+	Comment,
+	String,
+}
+
+/// The comment and string literal syntax of a language.
+struct Syntax {
+	line_comments: &'static [&'static str],
+	block_comment: Option<(&'static str, &'static str)>,
+	/// Longest first where one starts another, e.g. `"""` before `"`.
+	quotes: &'static [&'static str],
+	/// Line comments only start a line or follow whitespace, as `#` does in
+	/// shells, where `$#` isn't a comment.
+	spaced_comments: bool,
+}
+
+/// The comments and string literals of a document, found by a lightweight
+/// scan that knows each language's delimiters but not its grammar.
+#[derive(Debug, Default)]
+pub struct Regions(Vec<(Range<usize>, Region)>);
+
+impl Regions {
+	/// Finds the comments and string literals in `text`, written in
+	/// `language`. Text in other languages, or none, is all code.
+	pub fn find(text: &str, language: Option<Language>) -> Self {
+		let mut regions = Vec::new();
+		let Some(syntax) = language.and_then(syntax) else {
+			return Self(regions);
+		};
+
+		let bytes = text.as_bytes();
+		let mut i = 0;
+		while i < bytes.len() {
+			let rest = &bytes[i..];
+			if let Some((open, close)) = syntax
+				.block_comment
+				.filter(|(open, _)| rest.starts_with(open.as_bytes()))
+			{
+				let end = find(bytes, i + open.len(), close)
+					.map(|j| j + close.len())
+					.unwrap_or(bytes.len());
+				regions.push((i..end, Region::Comment));
+				i = end;
+				continue;
+			}
+
+			let is_comment = syntax
+				.line_comments
+				.iter()
+				.any(|marker| rest.starts_with(marker.as_bytes()));
+			if is_comment
+				&& (!syntax.spaced_comments || i == 0 || bytes[i - 1].is_ascii_whitespace())
+			{
+				let end = find(bytes, i, "\n").unwrap_or(bytes.len());
+				regions.push((i..end, Region::Comment));
+				i = end;
+				continue;
+			}
+
+			if let Some(quote) = syntax
+				.quotes
+				.iter()
+				.find(|quote| rest.starts_with(quote.as_bytes()))
+			{
+				let end = string_end(bytes, i + quote.len(), quote);
+				regions.push((i..end, Region::String));
+				i = end;
+				continue;
+			}
+
+			i += 1;
+		}
+
+		Self(regions)
+	}
+
+	/// Returns the region the byte at `offset` is in.
+	pub fn at(&self, offset: usize) -> Region {
+		let i = self.0.partition_point(|(range, _)| range.end <= offset);
+		match self.0.get(i) {
+			Some((range, region)) if range.contains(&offset) => *region,
+			_ => Region::Code,
+		}
+	}
+}
+
+/// Returns the comment and string syntax of `language`, or `None` if it has
+/// no code to tell them apart from.
+fn syntax(language: Language) -> Option<Syntax> {
+	let c_like = |quotes| Syntax {
+		line_comments: &["//"],
+		block_comment: Some(("/*", "*/")),
+		quotes,
+		spaced_comments: false,
+	};
+
+	let hash = |quotes, spaced_comments| Syntax {
+		line_comments: &["#"],
+		block_comment: None,
+		quotes,
+		spaced_comments,
+	};
+
+	Some(match language {
+		Language::C | Language::Cpp | Language::CSharp | Language::Java => c_like(&["\"", "'"]),
+		Language::Go => c_like(&["\"", "'", "`"]),
+		Language::JavaScript | Language::TypeScript => c_like(&["`", "\"", "'"]),
+		// `'` also starts lifetimes, so only `"` starts a string
+		Language::Rust => c_like(&["\""]),
+		Language::Python => hash(&["\"\"\"", "'''", "\"", "'"], false),
+		Language::Ruby => hash(&["\"", "'"], false),
+		Language::Shell => hash(&["\"", "'"], true),
+		Language::Markdown => return None,
+	})
+}
+
+/// Returns where the string literal opened by `quote` before `start` ends,
+/// after its closing quote. Backslashes escape the next byte, and strings
+/// opened by a single `"` or `'` end at the end of their line if unclosed.
+fn string_end(bytes: &[u8], start: usize, quote: &str) -> usize {
+	let single_line = quote == "\"" || quote == "'";
+	let mut i = start;
+	while i < bytes.len() {
+		if bytes[i] == b'\\' {
+			i += 2;
+			continue;
+		}
+
+		if bytes[i..].starts_with(quote.as_bytes()) {
+			return i + quote.len();
+		}
+
+		if single_line && bytes[i] == b'\n' {
+			return i;
+		}
+
+		i += 1;
+	}
+
+	bytes.len()
+}
+
+/// Returns the offset of the first `needle` in `bytes` at or after `start`.
+fn find(bytes: &[u8], start: usize, needle: &str) -> Option<usize> {
+	bytes
+		.get(start..)?
+		.windows(needle.len())
+		.position(|window| window == needle.as_bytes())
+		.map(|i| start + i)
+}
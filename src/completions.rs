@@ -0,0 +1,436 @@
+use crate::args::{Flag, FLAGS, SUBCOMMANDS};
+use crate::language::Language;
+
+/// A shell that completion scripts can be generated for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shell {
+	Bash,
+	Zsh,
+	Fish,
+	Powershell,
+}
+
+impl Shell {
+	/// Every supported shell.
+	pub const ALL: [Shell; 4] = [Shell::Bash, Shell::Zsh, Shell::Fish, Shell::Powershell];
+
+	/// Looks up a shell by the name given to `completions`.
+	pub fn from_name(name: &str) -> Option<Self> {
+		Self::ALL.into_iter().find(|shell| shell.name() == name)
+	}
+
+	/// Returns the name of this shell.
+	pub fn name(self) -> &'static str {
+		match self {
+			Shell::Bash => "bash",
+			Shell::Zsh => "zsh",
+			Shell::Fish => "fish",
+			Shell::Powershell => "powershell",
+		}
+	}
+}
+
+/// Returns the completion script for `shell`. Saved search names are
+/// completed after `run` by listing them with `codesearch run`.
+pub fn generate(shell: Shell) -> String {
+	match shell {
+		Shell::Bash => bash(),
+		Shell::Zsh => zsh(),
+		Shell::Fish => fish(),
+		Shell::Powershell => powershell(),
+	}
+}
+
+/// The values a flag's value can be completed with: its choices, or every
+/// language name for `--type`. Other values can't be completed.
+fn choices(value: &str) -> Vec<String> {
+	if value == "language" {
+		return Language::ALL
+			.iter()
+			.map(|language| language.name().to_string())
+			.collect();
+	}
+
+	if !value.contains('|') {
+		return Vec::new();
+	}
+
+	value.split('|').map(String::from).collect()
+}
+
+/// Returns `true` if a flag's value is a path, completed with files.
+fn is_path(value: &str) -> bool {
+	value == "file" || value == "path"
+}
+
+/// Returns the names of the shells, for completing `completions`.
+fn shell_names() -> String {
+	Shell::ALL.map(Shell::name).join(" ")
+}
+
+/// Returns `text` quoted for a shell, in single quotes.
+fn quote(text: &str) -> String {
+	format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+fn bash() -> String {
+	let mut values = String::new();
+	for flag in FLAGS
+		.iter()
+		.chain(SUBCOMMANDS.iter().flat_map(|command| command.flags))
+	{
+		let Some(value) = flag.value else {
+			continue;
+		};
+
+		let reply = if is_path(value) {
+			String::from(r#"COMPREPLY=($(compgen -f -- "$cur"))"#)
+		} else {
+			let words = choices(value).join(" ");
+			format!(r#"COMPREPLY=($(compgen -W "{words}" -- "$cur"))"#)
+		};
+
+		values.push_str(&format!(
+			"\t\t{})\n\t\t\t{reply}\n\t\t\treturn ;;\n",
+			flag.names.join("|")
+		));
+	}
+
+	let mut command_flags = String::new();
+	let mut file_commands = Vec::new();
+	for command in SUBCOMMANDS {
+		if command.takes_files {
+			file_commands.push(command.name);
+		}
+
+		if command.flags.is_empty() {
+			continue;
+		}
+
+		let names = command
+			.flags
+			.iter()
+			.flat_map(|flag| flag.names)
+			.copied()
+			.collect::<Vec<&str>>()
+			.join(" ");
+		command_flags.push_str(&format!(
+			"\t\t{}) flags=\"$flags {names}\" ;;\n",
+			command.name
+		));
+	}
+
+	let flags = all_names(FLAGS).join(" ");
+	let commands = SUBCOMMANDS
+		.iter()
+		.map(|c| c.name)
+		.collect::<Vec<&str>>()
+		.join(" ");
+	let file_commands = file_commands.join("|");
+	let shells = shell_names();
+	format!(
+		r#"# bash completion for codesearch
+_codesearch() {{
+	local cur="${{COMP_WORDS[COMP_CWORD]}}"
+	local prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+	local command="${{COMP_WORDS[1]}}"
+	case "$prev" in
+{values}	esac
+
+	if [[ $COMP_CWORD -eq 2 && $command == run ]]; then
+		COMPREPLY=($(compgen -W "$(codesearch run 2>/dev/null | cut -f1)" -- "$cur"))
+		return
+	fi
+
+	if [[ $COMP_CWORD -eq 2 && $command == completions ]]; then
+		COMPREPLY=($(compgen -W "{shells}" -- "$cur"))
+		return
+	fi
+
+	local flags="{flags}"
+	case "$command" in
+{command_flags}	esac
+
+	if [[ $cur == -* ]]; then
+		COMPREPLY=($(compgen -W "$flags" -- "$cur"))
+	elif [[ $COMP_CWORD -eq 1 ]]; then
+		COMPREPLY=($(compgen -W "{commands}" -- "$cur"))
+	elif [[ $command == @({file_commands}) ]]; then
+		COMPREPLY=($(compgen -f -- "$cur"))
+	fi
+}}
+
+complete -F _codesearch codesearch
+"#
+	)
+}
+
+fn zsh() -> String {
+	// Help is shown in brackets, and values follow colons
+	let escape = |text: &str| {
+		text.replace('\'', r"'\''")
+			.replace('[', r"\[")
+			.replace(']', r"\]")
+			.replace(':', r"\:")
+	};
+
+	let spec = |flag: &Flag, indent: &str| {
+		flag.names
+			.iter()
+			.map(|name| {
+				let value = match flag.value {
+					None => String::new(),
+					Some(value) if is_path(value) => format!(":{value}:_files"),
+					Some(value) => format!(":{value}:({})", choices(value).join(" ")),
+				};
+
+				format!("{indent}'{name}[{}]{value}'\n", escape(flag.help))
+			})
+			.collect::<String>()
+	};
+
+	let flags = FLAGS
+		.iter()
+		.map(|flag| spec(flag, "\t\t"))
+		.collect::<String>();
+	let commands = SUBCOMMANDS
+		.iter()
+		.map(|command| format!("\t\t'{}:{}'\n", command.name, escape(command.help)))
+		.collect::<String>();
+
+	let mut command_flags = String::new();
+	for command in SUBCOMMANDS {
+		let rest = if command.takes_files {
+			"'*:file:_files'"
+		} else {
+			"'*:term: '"
+		};
+
+		let specs = command
+			.flags
+			.iter()
+			.map(|flag| spec(flag, "\t\t\t\t"))
+			.collect::<String>();
+		command_flags.push_str(&format!(
+			"\t\t{})\n\t\t\tflags+=(\n{specs}\t\t\t\t{rest}\n\t\t\t) ;;\n",
+			command.name
+		));
+	}
+
+	let shells = shell_names();
+	format!(
+		r#"#compdef codesearch
+
+_codesearch() {{
+	local -a commands flags saved
+	commands=(
+{commands}	)
+
+	flags=(
+{flags}	)
+
+	case $words[2] in
+		run)
+			if (( CURRENT == 3 )); then
+				saved=(${{(f)"$(codesearch run 2>/dev/null | cut -f1)"}})
+				compadd -a saved
+				return
+			fi ;;
+		completions)
+			if (( CURRENT == 3 )); then
+				compadd {shells}
+				return
+			fi ;;
+	esac
+
+	case $words[2] in
+{command_flags}		*) flags+=('*:term: ') ;;
+	esac
+
+	if (( CURRENT == 2 )) && [[ $PREFIX != -* ]]; then
+		_describe 'command' commands
+	else
+		_arguments -s $flags
+	fi
+}}
+
+_codesearch "$@"
+"#
+	)
+}
+
+fn fish() -> String {
+	let line = |flag: &Flag, condition: Option<String>| {
+		let mut line = String::from("complete -c codesearch");
+		if let Some(condition) = condition {
+			line.push_str(&format!(" -n {}", quote(&condition)));
+		}
+
+		for name in flag.names {
+			match name.strip_prefix("--") {
+				Some(long) => line.push_str(&format!(" -l {long}")),
+				None => line.push_str(&format!(" -s {}", &name[1..])),
+			}
+		}
+
+		match flag.value {
+			None => {}
+			Some(value) if is_path(value) => line.push_str(" -r -F"),
+			Some(value) => {
+				line.push_str(" -x");
+				let choices = choices(value);
+				if !choices.is_empty() {
+					line.push_str(&format!(" -a {}", quote(&choices.join(" "))));
+				}
+			}
+		}
+
+		line.push_str(&format!(" -d {}\n", quote(flag.help)));
+		line
+	};
+
+	let mut script = String::from("# fish completion for codesearch\ncomplete -c codesearch -f\n");
+	for command in SUBCOMMANDS {
+		script.push_str(&format!(
+			"complete -c codesearch -n __fish_use_subcommand -a {} -d {}\n",
+			command.name,
+			quote(command.help)
+		));
+	}
+
+	script.extend(FLAGS.iter().map(|flag| line(flag, None)));
+	for command in SUBCOMMANDS {
+		let condition = format!("__fish_seen_subcommand_from {}", command.name);
+		script.extend(
+			command
+				.flags
+				.iter()
+				.map(|flag| line(flag, Some(condition.clone()))),
+		);
+
+		if command.takes_files {
+			script.push_str(&format!(
+				"complete -c codesearch -n {} -F\n",
+				quote(&condition)
+			));
+		}
+	}
+
+	let second = "test (count (commandline -opc)) -eq 2";
+	script.push_str(&format!(
+		"complete -c codesearch -n {} -x -a {}\n",
+		quote(&format!("__fish_seen_subcommand_from run; and {second}")),
+		quote("(codesearch run 2>/dev/null | string split -f1 \\t)")
+	));
+	script.push_str(&format!(
+		"complete -c codesearch -n {} -x -a {}\n",
+		quote(&format!(
+			"__fish_seen_subcommand_from completions; and {second}"
+		)),
+		quote(&shell_names())
+	));
+
+	script
+}
+
+fn powershell() -> String {
+	let list = |words: &[String]| {
+		words
+			.iter()
+			.map(|word| quote(word))
+			.collect::<Vec<String>>()
+			.join(", ")
+	};
+
+	let mut values = String::new();
+	for flag in FLAGS
+		.iter()
+		.chain(SUBCOMMANDS.iter().flat_map(|command| command.flags))
+	{
+		let Some(value) = flag.value else {
+			continue;
+		};
+
+		let reply = if is_path(value) {
+			String::from("return")
+		} else {
+			format!("$candidates = @({})", list(&choices(value)))
+		};
+
+		let names = flag
+			.names
+			.iter()
+			.map(|name| quote(name))
+			.collect::<Vec<String>>();
+		values.push_str(&format!(
+			"\t\t{{ $_ -in {} }} {{ {reply} }}\n",
+			names.join(", ")
+		));
+	}
+
+	let mut command_flags = String::new();
+	for command in SUBCOMMANDS
+		.iter()
+		.filter(|command| !command.flags.is_empty())
+	{
+		let names = all_names(command.flags)
+			.into_iter()
+			.map(String::from)
+			.collect::<Vec<String>>();
+		command_flags.push_str(&format!(
+			"\t\t\t\t\t{} {{ $candidates += @({}) }}\n",
+			quote(command.name),
+			list(&names)
+		));
+	}
+
+	let flags = all_names(FLAGS)
+		.into_iter()
+		.map(String::from)
+		.collect::<Vec<String>>();
+	let commands = SUBCOMMANDS
+		.iter()
+		.map(|command| command.name.to_string())
+		.collect::<Vec<String>>();
+	let shells = Shell::ALL.map(|shell| shell.name().to_string());
+	let (flags, commands, shells) = (list(&flags), list(&commands), list(&shells));
+	format!(
+		r#"# PowerShell completion for codesearch
+Register-ArgumentCompleter -Native -CommandName codesearch -ScriptBlock {{
+	param($wordToComplete, $commandAst, $cursorPosition)
+	$words = @($commandAst.CommandElements | ForEach-Object {{ $_.ToString() }})
+	if ($wordToComplete) {{
+		$words = $words[0..($words.Count - 2)]
+	}}
+
+	$previous = $words[-1]
+	$command = if ($words.Count -gt 1) {{ $words[1] }} else {{ '' }}
+	$candidates = @()
+	switch ($previous) {{
+{values}		{{ $words.Count -eq 2 -and $_ -eq 'run' }} {{
+			$candidates = @(codesearch run 2>$null | ForEach-Object {{ ($_ -split "`t")[0] }})
+		}}
+		{{ $words.Count -eq 2 -and $_ -eq 'completions' }} {{ $candidates = @({shells}) }}
+		default {{
+			if ($words.Count -eq 1 -and -not $wordToComplete.StartsWith('-')) {{
+				$candidates = @({commands})
+			}} else {{
+				$candidates = @({flags})
+				switch ($command) {{
+{command_flags}				}}
+			}}
+		}}
+	}}
+
+	$candidates | Where-Object {{ $_ -like "$wordToComplete*" }} | ForEach-Object {{
+		[System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)
+	}}
+}}
+"#
+	)
+}
+
+/// Returns every name of `flags`.
+fn all_names(flags: &[Flag]) -> Vec<&'static str> {
+	flags.iter().flat_map(|flag| flag.names).copied().collect()
+}
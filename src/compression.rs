@@ -0,0 +1,55 @@
+use flate2::read::GzDecoder;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// The first two bytes of every gzip stream.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Returns `true` if the file at `path` is gzip-compressed.
+/// The file must have a `.gz` extension and start with the gzip magic bytes.
+pub fn is_compressed(path: &Path) -> bool {
+	if path.extension().and_then(|e| e.to_str()) != Some("gz") {
+		return false;
+	}
+
+	let mut magic = [0; 2];
+	File::open(path)
+		.and_then(|mut f| f.read_exact(&mut magic))
+		.is_ok()
+		&& magic == GZIP_MAGIC
+}
+
+/// Reads the contents of the file at `path`, decompressing them if
+/// `compressed` is set. Returns `None` once they pass `limit` bytes, so a
+/// huge file or gzip stream is never read whole.
+pub fn read(path: &Path, compressed: bool, limit: u64) -> io::Result<Option<Vec<u8>>> {
+	let mut contents = Vec::new();
+	open(path, compressed)?
+		.take(limit.saturating_add(1))
+		.read_to_end(&mut contents)?;
+
+	Ok((contents.len() as u64 <= limit).then_some(contents))
+}
+
+/// Opens the file at `path` to be read as a stream, decompressing it if
+/// `compressed` is set.
+pub fn open(path: &Path, compressed: bool) -> io::Result<Box<dyn Read>> {
+	let file = File::open(path)?;
+	Ok(if compressed {
+		Box::new(GzDecoder::new(file))
+	} else {
+		Box::new(file)
+	})
+}
+
+/// Returns the path a compressed file would have once decompressed,
+/// e.g. `fixtures/data.json.gz` becomes `fixtures/data.json`.
+/// This is used to detect the language of a compressed file.
+pub fn logical_path(path: &Path, compressed: bool) -> PathBuf {
+	if compressed {
+		path.with_extension("")
+	} else {
+		path.to_path_buf()
+	}
+}
@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use toml::{Table, Value};
+
+use crate::extractor::{ExternalExtractor, Limits};
+use crate::hooks::Hooks;
+use crate::search_rank::{CommentScoring, PathScoring};
+
+/// The name of the project configuration file, read from the indexed directory.
+pub const CONFIG_FILE: &str = ".codesearch.toml";
+
+/// Project configuration.
+#[derive(Debug, Default)]
+pub struct Config {
+	/// The command run when an alert finds new matches, if any.
+	pub alert_command: Option<Vec<String>>,
+	/// The terms each lowercase search term also matches, e.g. a service's
+	/// nickname and the identifiers used for it in code.
+	pub aliases: HashMap<String, Vec<String>>,
+	/// How matches in comments and string literals affect ranking.
+	pub comment_scoring: CommentScoring,
+	/// The arguments that open a file at a line in each editor, by program
+	/// name, in place of the built-in ones.
+	pub editors: HashMap<String, Vec<String>>,
+	/// External commands used to extract text from non-text files.
+	pub extractors: Vec<ExternalExtractor>,
+	/// Commands run before and after the index is written.
+	pub hooks: Hooks,
+	/// How file paths affect ranking.
+	pub path_scoring: PathScoring,
+	/// Files and directories whose matching files are always listed first.
+	pub pinned: Vec<PathBuf>,
+}
+
+/// Represents an error in the project configuration.
+#[derive(Debug)]
+pub enum ConfigError {
+	Invalid(String),
+	Io(std::io::Error),
+	Parse(toml::de::Error),
+}
+
+impl Display for ConfigError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ConfigError::Invalid(msg) => write!(f, "config error: {msg}"),
+			ConfigError::Io(e) => write!(f, "config error: {e}"),
+			ConfigError::Parse(e) => write!(f, "config error: {e}"),
+		}
+	}
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+	/// Loads the configuration file at `path`.
+	/// Returns the default configuration if the file does not exist.
+	pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+		let contents = match std::fs::read_to_string(path) {
+			Ok(v) => v,
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+			Err(e) => return Err(ConfigError::Io(e)),
+		};
+
+		let table = contents.parse::<Table>().map_err(ConfigError::Parse)?;
+		let mut config = Self::default();
+		for extractor in get_tables(&table, "extractor")? {
+			config.extractors.push(parse_extractor(extractor)?);
+		}
+
+		if let Some(alerts) = get_table(&table, "alerts")? {
+			config.alert_command =
+				Some(get_strings(alerts, "alerts.command")?).filter(|c| !c.is_empty());
+		}
+
+		if let Some(aliases) = get_table(&table, "aliases")? {
+			config.aliases = parse_aliases(aliases)?;
+		}
+
+		if let Some(editors) = get_table(&table, "editors")? {
+			config.editors = parse_editors(editors)?;
+		}
+
+		if let Some(hooks) = get_table(&table, "hooks")? {
+			config.hooks = parse_hooks(hooks)?;
+		}
+
+		config.pinned = get_strings(&table, "pinned")?
+			.into_iter()
+			.map(PathBuf::from)
+			.collect();
+
+		if let Some(path_scoring) = get_table(&table, "path_scoring")? {
+			config.path_scoring = parse_path_scoring(path_scoring)?;
+		}
+
+		if let Some(comment_scoring) = get_table(&table, "comment_scoring")? {
+			config.comment_scoring = parse_comment_scoring(comment_scoring)?;
+		}
+
+		Ok(config)
+	}
+}
+
+/// Parses an `[[extractor]]` entry:
+/// ```toml
+/// [[extractor]]
+/// extensions = ["pdf"]
+/// command = ["pdftotext", "{}", "-"]
+/// timeout = 10 # seconds
+/// max_memory = 512 # MiB
+/// max_output = 16 # MiB
+/// ```
+fn parse_extractor(table: &Table) -> Result<ExternalExtractor, ConfigError> {
+	let command = get_strings(table, "extractor.command")?;
+	if command.is_empty() {
+		return Err(ConfigError::Invalid(String::from(
+			"extractor.command must not be empty",
+		)));
+	}
+
+	let defaults = Limits::default();
+	let limits = Limits {
+		timeout: get_integer(table, "extractor.timeout")?
+			.map(Duration::from_secs)
+			.unwrap_or(defaults.timeout),
+		max_memory: get_integer(table, "extractor.max_memory")?
+			.map(|v| v * 1024 * 1024)
+			.unwrap_or(defaults.max_memory),
+		max_output: get_integer(table, "extractor.max_output")?
+			.map(|v| v as usize * 1024 * 1024)
+			.unwrap_or(defaults.max_output),
+	};
+
+	Ok(ExternalExtractor {
+		command,
+		extensions: get_strings(table, "extractor.extensions")?,
+		limits,
+	})
+}
+
+/// Parses the `[aliases]` table, keyed by lowercase term:
+/// ```toml
+/// [aliases]
+/// db = ["database", "sqlx", "postgres"]
+/// ```
+fn parse_aliases(table: &Table) -> Result<HashMap<String, Vec<String>>, ConfigError> {
+	table
+		.iter()
+		.map(|(term, aliases)| {
+			let aliases = aliases
+				.as_array()
+				.and_then(|values| {
+					values
+						.iter()
+						.map(|v| v.as_str().map(String::from))
+						.collect::<Option<Vec<String>>>()
+				})
+				.ok_or_else(|| {
+					ConfigError::Invalid(format!("aliases.{term} must be an array of strings"))
+				})?;
+
+			Ok((term.to_lowercase(), aliases))
+		})
+		.collect()
+}
+
+/// Parses the `[editors]` table, keyed by the editor's program name. `{file}`,
+/// `{line}`, `{column}` in characters, and `{byte_column}` are replaced with
+/// where to open:
+/// ```toml
+/// [editors]
+/// code = ["-g", "{file}:{line}:{column}"]
+/// vim = ["+call cursor({line}, {byte_column})", "{file}"]
+/// ```
+fn parse_editors(table: &Table) -> Result<HashMap<String, Vec<String>>, ConfigError> {
+	table
+		.iter()
+		.map(|(name, args)| {
+			let args = args
+				.as_array()
+				.and_then(|values| {
+					values
+						.iter()
+						.map(|v| v.as_str().map(String::from))
+						.collect::<Option<Vec<String>>>()
+				})
+				.ok_or_else(|| {
+					ConfigError::Invalid(format!("editors.{name} must be an array of strings"))
+				})?;
+
+			Ok((name.clone(), args))
+		})
+		.collect()
+}
+
+/// Parses the `[hooks]` table:
+/// ```toml
+/// [hooks]
+/// pre_update = ["make", "proto"]
+/// post_update = ["./notify.sh"]
+/// ```
+fn parse_hooks(table: &Table) -> Result<Hooks, ConfigError> {
+	let command = |key: &str| -> Result<Option<Vec<String>>, ConfigError> {
+		let command = get_strings(table, key)?;
+		Ok(Some(command).filter(|c| !c.is_empty()))
+	};
+
+	Ok(Hooks {
+		pre_update: command("hooks.pre_update")?,
+		post_update: command("hooks.post_update")?,
+	})
+}
+
+/// Parses the `[path_scoring]` table. Missing keys keep their defaults:
+/// ```toml
+/// [path_scoring]
+/// basename = 200 # rank per byte of a term in a file name
+/// directory = 20 # rank per byte of a term in a directory name
+/// demote = ["vendor", "tests", ".min."]
+/// demote_factor = 4
+/// ```
+fn parse_path_scoring(table: &Table) -> Result<PathScoring, ConfigError> {
+	let defaults = PathScoring::default();
+	let demote = match table.get("demote") {
+		Some(_) => get_strings(table, "path_scoring.demote")?,
+		None => defaults.demote,
+	};
+
+	Ok(PathScoring {
+		basename_rank: get_integer(table, "path_scoring.basename")?
+			.map(|v| v as usize)
+			.unwrap_or(defaults.basename_rank),
+		directory_rank: get_integer(table, "path_scoring.directory")?
+			.map(|v| v as usize)
+			.unwrap_or(defaults.directory_rank),
+		demote,
+		demote_factor: get_integer(table, "path_scoring.demote_factor")?
+			.map(|v| v as usize)
+			.unwrap_or(defaults.demote_factor),
+	})
+}
+
+/// Parses the `[comment_scoring]` table, as percentages of rank kept:
+/// ```toml
+/// [comment_scoring]
+/// comment = 50
+/// string = 100
+/// ```
+fn parse_comment_scoring(table: &Table) -> Result<CommentScoring, ConfigError> {
+	let defaults = CommentScoring::default();
+	Ok(CommentScoring {
+		comment: get_integer(table, "comment_scoring.comment")?
+			.map(|v| v as usize)
+			.unwrap_or(defaults.comment),
+		string: get_integer(table, "comment_scoring.string")?
+			.map(|v| v as usize)
+			.unwrap_or(defaults.string),
+	})
+}
+
+/// Returns the table named `key`, which may be missing.
+fn get_table<'a>(table: &'a Table, key: &str) -> Result<Option<&'a Table>, ConfigError> {
+	match table.get(key) {
+		None => Ok(None),
+		Some(Value::Table(v)) => Ok(Some(v)),
+		Some(_) => Err(ConfigError::Invalid(format!("{key} must be a table"))),
+	}
+}
+
+/// Returns the array of tables named `key`, which may be missing.
+fn get_tables<'a>(table: &'a Table, key: &str) -> Result<Vec<&'a Table>, ConfigError> {
+	match table.get(key) {
+		None => Ok(Vec::new()),
+		Some(Value::Array(values)) => values
+			.iter()
+			.map(|v| {
+				v.as_table().ok_or_else(|| {
+					ConfigError::Invalid(format!("{key} must be an array of tables"))
+				})
+			})
+			.collect(),
+		Some(_) => Err(ConfigError::Invalid(format!(
+			"{key} must be an array of tables"
+		))),
+	}
+}
+
+/// Returns the array of strings at the last component of the dotted `key`.
+fn get_strings(table: &Table, key: &str) -> Result<Vec<String>, ConfigError> {
+	let name = key.rsplit('.').next().unwrap_or(key);
+	let invalid = || ConfigError::Invalid(format!("{key} must be an array of strings"));
+	match table.get(name) {
+		None => Ok(Vec::new()),
+		Some(Value::Array(values)) => values
+			.iter()
+			.map(|v| v.as_str().map(String::from).ok_or_else(invalid))
+			.collect(),
+		Some(_) => Err(invalid()),
+	}
+}
+
+/// Returns the non-negative integer at the last component of the dotted `key`.
+fn get_integer(table: &Table, key: &str) -> Result<Option<u64>, ConfigError> {
+	let name = key.rsplit('.').next().unwrap_or(key);
+	match table.get(name) {
+		None => Ok(None),
+		Some(Value::Integer(v)) if *v >= 0 => Ok(Some(*v as u64)),
+		Some(_) => Err(ConfigError::Invalid(format!(
+			"{key} must be a non-negative integer"
+		))),
+	}
+}
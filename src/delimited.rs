@@ -0,0 +1,54 @@
+use std::borrow::Cow;
+use std::io::Write;
+
+/// The columns of each row, one row per matching line, as named by the
+/// header row.
+pub const HEADER: [&str; 5] = ["path", "line", "column", "score", "text"];
+
+/// What separates the fields of a row.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Delimiter {
+	/// Comma-separated values, quoted as in RFC 4180 for spreadsheets.
+	Comma,
+	/// Tab-separated values, escaped with backslashes for `awk` and `cut`.
+	Tab,
+}
+
+/// Writes a row of `fields` to `out`, each escaped for `delimiter`.
+pub fn write_row<W: Write>(
+	out: &mut W,
+	delimiter: Delimiter,
+	fields: &[&str],
+) -> std::io::Result<()> {
+	let separator = match delimiter {
+		Delimiter::Comma => ",",
+		Delimiter::Tab => "\t",
+	};
+
+	let fields = fields
+		.iter()
+		.map(|field| escape(field, delimiter))
+		.collect::<Vec<Cow<str>>>();
+
+	writeln!(out, "{}", fields.join(separator))
+}
+
+/// Escapes `field` for `delimiter`. CSV fields containing a comma, quote, or
+/// line break are quoted, with quotes doubled. TSV fields have backslashes,
+/// tabs, and line breaks written as `\\`, `\t`, `\n`, and `\r`, since they
+/// can't be quoted.
+fn escape(field: &str, delimiter: Delimiter) -> Cow<'_, str> {
+	match delimiter {
+		Delimiter::Comma if field.contains([',', '"', '\n', '\r']) => {
+			Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+		}
+		Delimiter::Tab if field.contains(['\\', '\t', '\n', '\r']) => Cow::Owned(
+			field
+				.replace('\\', "\\\\")
+				.replace('\t', "\\t")
+				.replace('\n', "\\n")
+				.replace('\r', "\\r"),
+		),
+		_ => Cow::Borrowed(field),
+	}
+}
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::Path;
+use std::process::Command;
+
+/// The editor run when neither `$VISUAL` nor `$EDITOR` is set.
+const DEFAULT_EDITOR: &str = "vi";
+
+/// Represents an error opening a result in an editor.
+#[derive(Debug)]
+pub enum EditorError {
+	Failed(Option<i32>),
+	NoResult(usize),
+	NotOnDisk(String),
+	Spawn(std::io::Error),
+}
+
+impl Display for EditorError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			EditorError::Failed(Some(code)) => write!(f, "editor exited with code {code}"),
+			EditorError::Failed(None) => write!(f, "editor was terminated"),
+			EditorError::NoResult(position) => write!(f, "there is no result {position}"),
+			EditorError::NotOnDisk(path) => write!(f, "{path} isn't a file on disk"),
+			EditorError::Spawn(e) => write!(f, "failed to run editor: {e}"),
+		}
+	}
+}
+
+impl std::error::Error for EditorError {}
+
+/// Opens `path` at `line` and character `column`, both 1-based, in the
+/// user's editor and waits for it to exit. Templates may use the 1-based
+/// `byte_column` instead, e.g. for Vim's `cursor()`. The editor is `$VISUAL` or
+/// `$EDITOR`, which may hold arguments of its own, and is told where to open
+/// by the template in `templates` named after its program, or else a
+/// built-in one.
+pub fn open(
+	path: &Path,
+	line: usize,
+	column: usize,
+	byte_column: usize,
+	templates: &HashMap<String, Vec<String>>,
+) -> Result<(), EditorError> {
+	let editor = ["VISUAL", "EDITOR"]
+		.iter()
+		.filter_map(|var| std::env::var(var).ok())
+		.find(|editor| !editor.trim().is_empty())
+		.unwrap_or_else(|| String::from(DEFAULT_EDITOR));
+
+	let mut words = editor.split_whitespace();
+	let program = words.next().unwrap_or(DEFAULT_EDITOR);
+	let name = Path::new(program)
+		.file_stem()
+		.map(|v| v.to_string_lossy().into_owned())
+		.unwrap_or_default();
+
+	let file = path.to_string_lossy();
+	let fill = |arg: &str| {
+		arg.replace("{file}", &file)
+			.replace("{line}", &line.to_string())
+			.replace("{column}", &column.to_string())
+			.replace("{byte_column}", &byte_column.to_string())
+	};
+
+	let args = match templates.get(&name) {
+		Some(template) => template
+			.iter()
+			.map(|arg| fill(arg))
+			.collect::<Vec<String>>(),
+		None => builtin_template(&name)
+			.iter()
+			.map(|arg| fill(arg))
+			.collect(),
+	};
+
+	let status = Command::new(program)
+		.args(words)
+		.args(args)
+		.status()
+		.map_err(EditorError::Spawn)?;
+
+	if !status.success() {
+		return Err(EditorError::Failed(status.code()));
+	}
+
+	Ok(())
+}
+
+/// Returns the 1-based character column of the 1-based byte `column` of the
+/// 1-based `line` of `text`. Columns past the end of the line are clamped.
+pub fn char_column(text: &str, line: usize, column: usize) -> usize {
+	let line = text.lines().nth(line.saturating_sub(1)).unwrap_or("");
+	let mut end = column.saturating_sub(1).min(line.len());
+	while !line.is_char_boundary(end) {
+		end -= 1;
+	}
+
+	line[..end].chars().count() + 1
+}
+
+/// Returns the arguments that open `{file}` at `{line}` and `{column}` in
+/// the editor with the program name `editor`. Unknown editors are given
+/// `+{line}`, which most terminal editors understand.
+fn builtin_template(editor: &str) -> &'static [&'static str] {
+	match editor {
+		"code" | "code-insiders" | "codium" | "cursor" => &["-g", "{file}:{line}:{column}"],
+		"subl" | "hx" | "zed" => &["{file}:{line}:{column}"],
+		"emacs" | "emacsclient" | "kak" | "micro" => &["+{line}:{column}", "{file}"],
+		"nano" => &["+{line},{column}", "{file}"],
+		_ => &["+{line}", "{file}"],
+	}
+}
@@ -1,37 +1,101 @@
 use std::ffi::{OsStr, OsString};
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf, Prefix};
 
 const HEX_CHARS: [char; 16] = [
 	'0', '1', '2', '3', '4', '5', '6', '7', '8', '9', 'a', 'b', 'c', 'd', 'e', 'f',
 ];
 
-/// Returns `true` if and only if the bytes in `s` are between the ranges
-/// `0x09` (ASCII HT, Horizontal Tab) to `0x0d` (ASCII CR, Carriage Return)
-/// and `0x20` (ASCII Space) to `0x7e` (ASCII ~, Tilde).
-/// These are both printable ranges.
-pub fn is_printable(s: &[u8]) -> bool {
-	s.iter()
-		.all(|b| (*b > 0x08 && *b < 0x0e) || (*b >= 0x20 && *b < 0x7f))
+/// How many bytes at the start of a file are checked to decide whether it
+/// is binary.
+const BINARY_CHECK_LEN: usize = 8192;
+
+/// Lowercased text, with where each of its bytes came from in the original
+/// text, so matches found ignoring case can be located in the original.
+pub struct Folded<'a> {
+	pub original: &'a str,
+	pub text: String,
+	/// The offset in the original text of the character each byte of
+	/// `text` lowercases, or `None` while every character keeps its length.
+	offsets: Option<Vec<u32>>,
 }
 
-/// Returns true if the bytes in s *could be* part of a valid
-/// Unicode code point. This does not mean s is a valid UTF-8
-/// string.
-pub fn is_utf8(s: &[u8]) -> bool {
-	/*
-		UTF-8 bytes are prefixed with any of:
-		0XXXXXXX
-		10XXXXXX
-		110XXXXX
-		1110XXXX
-		11110XXX
-	*/
-	s.iter().all(|b| {
-		(b & 0x80 == 0)
-			|| (b & 0xc0 == 0x80)
-			|| (b & 0xe0 == 0xc0)
-			|| (b & 0xf0 == 0xe0)
-			|| (b & 0xf8 == 0xf0)
-	})
+impl<'a> Folded<'a> {
+	/// Lowercases `original` character by character.
+	pub fn new(original: &'a str) -> Self {
+		let mut text = String::with_capacity(original.len());
+		let mut offsets: Option<Vec<u32>> = None;
+		for (offset, c) in original.char_indices() {
+			let start = text.len();
+			text.extend(c.to_lowercase());
+
+			// Most text keeps its byte offsets, so they're only recorded
+			// once a character changes length
+			if offsets.is_none() && text.len() - start != c.len_utf8() {
+				offsets = Some((0..start as u32).collect());
+			}
+
+			if let Some(offsets) = &mut offsets {
+				offsets.resize(text.len(), offset as u32);
+			}
+		}
+
+		Self {
+			original,
+			text,
+			offsets,
+		}
+	}
+
+	/// Returns the bytes of the original text that the bytes `range` of the
+	/// lowercased text come from. Characters partly in the range are wholly
+	/// in the result.
+	pub fn original_range(&self, range: Range<usize>) -> Range<usize> {
+		let Some(offsets) = &self.offsets else {
+			return range;
+		};
+
+		let start = offsets
+			.get(range.start)
+			.map_or(self.original.len(), |v| *v as usize);
+
+		let end = match range.end.checked_sub(1).and_then(|last| offsets.get(last)) {
+			Some(&last) => {
+				let last = last as usize;
+				last + self.original[last..]
+					.chars()
+					.next()
+					.map_or(0, char::len_utf8)
+			}
+			None => start,
+		};
+
+		start..end.max(start)
+	}
+}
+
+/// Returns `true` if `bytes` look like a binary file rather than text: the
+/// first few kilobytes contain a NUL byte or aren't valid UTF-8. A
+/// character cut off by the end of the checked chunk doesn't count.
+pub fn is_binary(bytes: &[u8]) -> bool {
+	let chunk = &bytes[..bytes.len().min(BINARY_CHECK_LEN)];
+	if chunk.contains(&0) {
+		return true;
+	}
+
+	std::str::from_utf8(chunk).is_err_and(|e| e.error_len().is_some())
+}
+
+/// Converts `bytes` into text, replacing any invalid UTF-8 sequences with
+/// U+FFFD. Valid text isn't copied.
+pub fn decode(bytes: Vec<u8>) -> String {
+	String::from_utf8(bytes).unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned())
+}
+
+/// Returns `true` if `text` holds U+FFFD, which `decode` puts in place of
+/// bytes that aren't valid UTF-8, so it may not show the file's real text.
+pub fn is_lossy(text: &str) -> bool {
+	text.contains(char::REPLACEMENT_CHARACTER)
 }
 
 /// Converts `s` into a hexadecimal string.
@@ -82,12 +146,150 @@ pub fn bytes_to_os_string(b: Vec<u8>) -> OsString {
 		panic!("invalid number of bytes for a UTF-16 string");
 	}
 
-	let mut wide = Vec::with_capacity(b.len() / 2);
-	let mut buf = [0; 2];
-	for i in (0..b.len()).step_by(2) {
-		buf.copy_from_slice(&b[i..i + 2]);
-		wide.push(u16::from_be_bytes(buf));
-	}
+	let wide = b
+		.chunks_exact(2)
+		.map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+		.collect::<Vec<u16>>();
 
 	OsString::from_wide(&wide)
 }
+
+/// Removes the verbatim prefix from a Windows path like `\\?\C:\src` or
+/// `\\?\UNC\server\share`, as returned by `canonicalize`, so it compares
+/// equal to the same path written normally. Long paths still work without
+/// it, since the standard library adds it back when it is needed. Other
+/// paths are returned as they are.
+pub fn strip_verbatim(path: &Path) -> PathBuf {
+	let mut components = path.components();
+	let mut stripped = match components.next() {
+		Some(Component::Prefix(prefix)) => match prefix.kind() {
+			Prefix::VerbatimDisk(disk) => PathBuf::from(format!("{}:", disk as char)),
+			Prefix::VerbatimUNC(server, share) => {
+				let mut unc = OsString::from(r"\\");
+				unc.push(server);
+				unc.push(r"\");
+				unc.push(share);
+				PathBuf::from(unc)
+			}
+			_ => return path.to_path_buf(),
+		},
+		_ => return path.to_path_buf(),
+	};
+
+	stripped.extend(components);
+	stripped
+}
+
+/// Returns `path` in the case the file system compares paths in: lowercase
+/// on Windows, whose file systems ignore case, and as it is elsewhere.
+#[cfg(target_family = "windows")]
+pub fn fold_case(path: &Path) -> PathBuf {
+	match path.to_str() {
+		Some(s) => PathBuf::from(s.to_lowercase()),
+		None => path.to_path_buf(),
+	}
+}
+
+/// Returns `path` in the case the file system compares paths in: lowercase
+/// on Windows, whose file systems ignore case, and as it is elsewhere.
+#[cfg(not(target_family = "windows"))]
+pub fn fold_case(path: &Path) -> PathBuf {
+	path.to_path_buf()
+}
+
+/// Returns `path` relative to `base`, or `None` if it isn't in `base`.
+/// Paths are compared by component the way the file system would, ignoring
+/// verbatim prefixes and, on Windows, case.
+pub fn strip_path_prefix(path: &Path, base: &Path) -> Option<PathBuf> {
+	let path = strip_verbatim(path);
+	let base = strip_verbatim(base);
+	let mut components = path.components();
+	for expected in base.components() {
+		let component = components.next()?;
+		if fold_case(Path::new(&component)) != fold_case(Path::new(&expected)) {
+			return None;
+		}
+	}
+
+	Some(components.as_path().to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn os_strings_round_trip() {
+		for s in ["", "src/main.rs", "naïve/файл.txt", "emoji 😀.md"] {
+			let bytes = os_str_to_bytes(OsStr::new(s));
+			assert_eq!(bytes_to_os_string(bytes.into_vec()), OsString::from(s));
+		}
+	}
+
+	#[cfg(target_family = "unix")]
+	#[test]
+	fn invalid_utf8_round_trips() {
+		let bytes = vec![b'a', 0xff, 0xfe, b'/', b'b'];
+		let s = bytes_to_os_string(bytes.clone());
+		assert_eq!(os_str_to_bytes(&s).into_vec(), bytes);
+	}
+
+	#[cfg(target_family = "windows")]
+	#[test]
+	fn utf16_is_big_endian() {
+		let bytes = os_str_to_bytes(OsStr::new("a😀"));
+		assert_eq!(&bytes[..], &[0x00, 0x61, 0xd8, 0x3d, 0xde, 0x00]);
+	}
+
+	#[cfg(target_family = "windows")]
+	#[test]
+	fn unpaired_surrogates_round_trip() {
+		use std::os::windows::ffi::OsStringExt;
+
+		let s = OsString::from_wide(&[0x61, 0xd800, 0x62]);
+		let bytes = os_str_to_bytes(&s);
+		assert_eq!(&bytes[..], &[0x00, 0x61, 0xd8, 0x00, 0x00, 0x62]);
+		assert_eq!(bytes_to_os_string(bytes.into_vec()), s);
+	}
+
+	#[cfg(target_family = "windows")]
+	#[test]
+	fn verbatim_prefixes_are_stripped() {
+		assert_eq!(
+			strip_verbatim(Path::new(r"\\?\C:\src\main.rs")),
+			PathBuf::from(r"C:\src\main.rs")
+		);
+		assert_eq!(
+			strip_verbatim(Path::new(r"\\?\UNC\server\share\a.rs")),
+			PathBuf::from(r"\\server\share\a.rs")
+		);
+		assert_eq!(
+			strip_path_prefix(Path::new(r"\\?\C:\Src\Main.rs"), Path::new(r"c:\src")),
+			Some(PathBuf::from("Main.rs"))
+		);
+	}
+
+	#[test]
+	fn path_prefixes_are_stripped_by_component() {
+		let strip = |path: &str, base: &str| strip_path_prefix(Path::new(path), Path::new(base));
+		assert_eq!(
+			strip("src/server/a.rs", "src"),
+			Some(PathBuf::from("server/a.rs"))
+		);
+		assert_eq!(
+			strip("src/server/a.rs", "src/server/a.rs"),
+			Some(PathBuf::new())
+		);
+		assert_eq!(strip("src/serverless/a.rs", "src/server"), None);
+		assert_eq!(strip("src", "src/server"), None);
+	}
+
+	#[cfg(target_family = "unix")]
+	#[test]
+	fn unix_paths_are_case_sensitive() {
+		assert_eq!(
+			strip_path_prefix(Path::new("Src/a.rs"), Path::new("src")),
+			None
+		);
+	}
+}
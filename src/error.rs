@@ -0,0 +1,247 @@
+use std::fmt::Display;
+use std::string::FromUtf8Error;
+
+use crate::alerts::AlertError;
+use crate::args::ArgsError;
+use crate::bundle::BundleError;
+use crate::cargo::CargoError;
+use crate::config::ConfigError;
+use crate::editor::EditorError;
+use crate::git::GitError;
+use crate::history::HistoryError;
+use crate::index::IndexError;
+use crate::queries::QueryError;
+use crate::remote::RemoteError;
+use crate::result_set::ResultSetError;
+use crate::saved::SavedError;
+use crate::shard::ShardError;
+use crate::synonyms::SynonymsError;
+
+/// Any error codesearch exits with, wrapping the error of the module it
+/// came from.
+#[derive(Debug)]
+pub enum CodesearchError {
+	Alert(AlertError),
+	Args(ArgsError),
+	Bundle(BundleError),
+	Cancelled,
+	Cargo(CargoError),
+	Config(ConfigError),
+	Editor(EditorError),
+	Git(GitError),
+	History(HistoryError),
+	Index(IndexError),
+	Io(std::io::Error),
+	NoDataDir(&'static str),
+	Query(QueryError),
+	Remote(RemoteError),
+	ResultSet(ResultSetError),
+	Saved(SavedError),
+	Shard(ShardError),
+	Synonyms(SynonymsError),
+	Utf8(FromUtf8Error),
+	Walk(ignore::Error),
+}
+
+/// The kind of an error, which decides the exit code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Category {
+	/// The command line or a query couldn't be parsed.
+	Query,
+	/// A file couldn't be read or written, or a command couldn't be run.
+	Io,
+	/// An index, configuration, or data file is malformed.
+	Format,
+	/// The directory tree couldn't be walked.
+	Walk,
+}
+
+impl Category {
+	/// Returns the exit code for errors in this category. These are stable,
+	/// so scripts can rely on them.
+	pub fn exit_code(self) -> i32 {
+		match self {
+			Category::Query => 2,
+			Category::Io => 3,
+			Category::Format => 4,
+			Category::Walk => 5,
+		}
+	}
+}
+
+impl CodesearchError {
+	/// Returns the kind of this error.
+	pub fn category(&self) -> Category {
+		use Category::{Format, Io, Query, Walk};
+		match self {
+			CodesearchError::Alert(AlertError::Io(_))
+			| CodesearchError::Bundle(BundleError::Io(_) | BundleError::NoIndex)
+			| CodesearchError::Cargo(CargoError::Io(..))
+			| CodesearchError::Config(ConfigError::Io(_))
+			| CodesearchError::Editor(EditorError::Failed(_) | EditorError::Spawn(_))
+			| CodesearchError::Git(_)
+			| CodesearchError::History(HistoryError::Io(_))
+			| CodesearchError::Index(
+				IndexError::Extract(_) | IndexError::Git(_) | IndexError::Io(_),
+			)
+			| CodesearchError::Io(_)
+			| CodesearchError::NoDataDir(_)
+			| CodesearchError::Query(QueryError::Io(_))
+			| CodesearchError::Remote(RemoteError::Http(_) | RemoteError::Io(_))
+			| CodesearchError::ResultSet(ResultSetError::Io(_))
+			| CodesearchError::Saved(SavedError::Io(_))
+			| CodesearchError::Shard(ShardError::Io(_))
+			| CodesearchError::Synonyms(SynonymsError::Io(_)) => Io,
+			CodesearchError::Args(_)
+			| CodesearchError::Cancelled
+			| CodesearchError::Editor(EditorError::NoResult(_) | EditorError::NotOnDisk(_))
+			| CodesearchError::Remote(RemoteError::UnsupportedUrl(_)) => Query,
+			CodesearchError::Index(IndexError::Walk(_))
+			| CodesearchError::Shard(ShardError::Walk(_))
+			| CodesearchError::Walk(_) => Walk,
+			_ => Format,
+		}
+	}
+
+	/// Returns the exit code for this error.
+	pub fn exit_code(&self) -> i32 {
+		self.category().exit_code()
+	}
+}
+
+impl Display for CodesearchError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			CodesearchError::Alert(e) => e.fmt(f),
+			CodesearchError::Args(e) => e.fmt(f),
+			CodesearchError::Bundle(e) => e.fmt(f),
+			CodesearchError::Cancelled => write!(f, "search cancelled"),
+			CodesearchError::Cargo(e) => e.fmt(f),
+			CodesearchError::Config(e) => e.fmt(f),
+			CodesearchError::Editor(e) => e.fmt(f),
+			CodesearchError::Git(e) => e.fmt(f),
+			CodesearchError::History(e) => e.fmt(f),
+			CodesearchError::Index(e) => e.fmt(f),
+			CodesearchError::Io(e) => e.fmt(f),
+			CodesearchError::NoDataDir(var) => write!(f, "{var} isn't set"),
+			CodesearchError::Query(e) => e.fmt(f),
+			CodesearchError::Remote(e) => e.fmt(f),
+			CodesearchError::ResultSet(e) => e.fmt(f),
+			CodesearchError::Saved(e) => e.fmt(f),
+			CodesearchError::Shard(e) => e.fmt(f),
+			CodesearchError::Synonyms(e) => e.fmt(f),
+			CodesearchError::Utf8(e) => e.fmt(f),
+			CodesearchError::Walk(e) => e.fmt(f),
+		}
+	}
+}
+
+impl std::error::Error for CodesearchError {}
+
+impl From<AlertError> for CodesearchError {
+	fn from(value: AlertError) -> Self {
+		CodesearchError::Alert(value)
+	}
+}
+
+impl From<ArgsError> for CodesearchError {
+	fn from(value: ArgsError) -> Self {
+		CodesearchError::Args(value)
+	}
+}
+
+impl From<BundleError> for CodesearchError {
+	fn from(value: BundleError) -> Self {
+		CodesearchError::Bundle(value)
+	}
+}
+
+impl From<CargoError> for CodesearchError {
+	fn from(value: CargoError) -> Self {
+		CodesearchError::Cargo(value)
+	}
+}
+
+impl From<ConfigError> for CodesearchError {
+	fn from(value: ConfigError) -> Self {
+		CodesearchError::Config(value)
+	}
+}
+
+impl From<EditorError> for CodesearchError {
+	fn from(value: EditorError) -> Self {
+		CodesearchError::Editor(value)
+	}
+}
+
+impl From<GitError> for CodesearchError {
+	fn from(value: GitError) -> Self {
+		CodesearchError::Git(value)
+	}
+}
+
+impl From<HistoryError> for CodesearchError {
+	fn from(value: HistoryError) -> Self {
+		CodesearchError::History(value)
+	}
+}
+
+impl From<IndexError> for CodesearchError {
+	fn from(value: IndexError) -> Self {
+		CodesearchError::Index(value)
+	}
+}
+
+impl From<std::io::Error> for CodesearchError {
+	fn from(value: std::io::Error) -> Self {
+		CodesearchError::Io(value)
+	}
+}
+
+impl From<QueryError> for CodesearchError {
+	fn from(value: QueryError) -> Self {
+		CodesearchError::Query(value)
+	}
+}
+
+impl From<RemoteError> for CodesearchError {
+	fn from(value: RemoteError) -> Self {
+		CodesearchError::Remote(value)
+	}
+}
+
+impl From<ResultSetError> for CodesearchError {
+	fn from(value: ResultSetError) -> Self {
+		CodesearchError::ResultSet(value)
+	}
+}
+
+impl From<SavedError> for CodesearchError {
+	fn from(value: SavedError) -> Self {
+		CodesearchError::Saved(value)
+	}
+}
+
+impl From<ShardError> for CodesearchError {
+	fn from(value: ShardError) -> Self {
+		CodesearchError::Shard(value)
+	}
+}
+
+impl From<SynonymsError> for CodesearchError {
+	fn from(value: SynonymsError) -> Self {
+		CodesearchError::Synonyms(value)
+	}
+}
+
+impl From<FromUtf8Error> for CodesearchError {
+	fn from(value: FromUtf8Error) -> Self {
+		CodesearchError::Utf8(value)
+	}
+}
+
+impl From<ignore::Error> for CodesearchError {
+	fn from(value: ignore::Error) -> Self {
+		CodesearchError::Walk(value)
+	}
+}
@@ -0,0 +1,232 @@
+use std::fmt::{Debug, Display};
+use std::io::Read;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::front_matter::FrontMatterExtractor;
+use crate::notebook::NotebookExtractor;
+
+/// How often a running extractor is checked for completion.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Extracts searchable text from the files with the extensions it handles,
+/// which are indexed and searched as that text instead of their contents.
+pub trait Extractor: Debug + Send + Sync {
+	/// Returns the file extensions this extractor handles, without the
+	/// leading `.`.
+	fn extensions(&self) -> Vec<&str>;
+
+	/// Returns the text of the file at `path`.
+	fn extract(&self, path: &Path) -> Result<String, ExtractError>;
+
+	/// Describes where the 1-based `line` of the text extracted from the
+	/// file at `path` is in the file, e.g. `cell 2:5`, for extractors whose
+	/// text doesn't keep the file's line numbers.
+	fn locate(&self, _path: &Path, _line: usize) -> Option<String> {
+		None
+	}
+
+	/// Returns `true` if this extractor handles the file at `path`.
+	fn matches(&self, path: &Path) -> bool {
+		path.extension().and_then(|e| e.to_str()).is_some_and(|e| {
+			self.extensions()
+				.iter()
+				.any(|ext| ext.eq_ignore_ascii_case(e))
+		})
+	}
+}
+
+/// Returns the `configured` extractors followed by the built-in ones, so
+/// configured extractors take precedence for the same extensions.
+pub fn with_builtins(configured: Vec<ExternalExtractor>) -> Vec<Arc<dyn Extractor>> {
+	let mut extractors = configured
+		.into_iter()
+		.map(|e| Arc::new(e) as Arc<dyn Extractor>)
+		.collect::<Vec<Arc<dyn Extractor>>>();
+
+	extractors.push(Arc::new(NotebookExtractor));
+	extractors.push(Arc::new(FrontMatterExtractor));
+	extractors
+}
+
+/// Resource limits applied to an external extractor command.
+#[derive(Clone, Debug)]
+pub struct Limits {
+	/// How long the command may run before it is killed.
+	pub timeout: Duration,
+	/// The maximum address space of the command, in bytes.
+	/// Only enforced on Unix.
+	pub max_memory: u64,
+	/// The maximum number of bytes of text the command may output.
+	pub max_output: usize,
+}
+
+/// An external command that extracts searchable text from files it
+/// can't otherwise be read as text, e.g. `pdftotext {} -`.
+#[derive(Clone, Debug)]
+pub struct ExternalExtractor {
+	/// The program and its arguments. `{}` is replaced with the file path.
+	pub command: Vec<String>,
+	/// The file extensions this extractor handles, without the leading `.`.
+	pub extensions: Vec<String>,
+	pub limits: Limits,
+}
+
+/// Represents a failed extraction.
+#[derive(Debug)]
+pub enum ExtractError {
+	Failed(Option<i32>, String),
+	/// The file couldn't be parsed, with why.
+	Invalid(String),
+	InvalidOutput,
+	Io(std::io::Error),
+	OutputTooLarge(usize),
+	Spawn(std::io::Error),
+	Timeout(Duration),
+}
+
+impl Default for Limits {
+	fn default() -> Self {
+		Self {
+			timeout: Duration::from_secs(30),
+			max_memory: 1024 * 1024 * 1024,
+			max_output: 16 * 1024 * 1024,
+		}
+	}
+}
+
+impl Display for ExtractError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ExtractError::Failed(Some(code), stderr) => {
+				write!(f, "extractor exited with code {code}: {}", stderr.trim())
+			}
+			ExtractError::Failed(None, stderr) => {
+				write!(f, "extractor was terminated: {}", stderr.trim())
+			}
+			ExtractError::Invalid(msg) => write!(f, "failed to extract text: {msg}"),
+			ExtractError::InvalidOutput => write!(f, "extractor output was not valid UTF-8"),
+			ExtractError::Io(e) => write!(f, "failed to extract text: {e}"),
+			ExtractError::OutputTooLarge(max) => {
+				write!(f, "extractor output exceeded {max} bytes")
+			}
+			ExtractError::Spawn(e) => write!(f, "failed to run extractor: {e}"),
+			ExtractError::Timeout(timeout) => {
+				write!(f, "extractor timed out after {}s", timeout.as_secs_f64())
+			}
+		}
+	}
+}
+
+impl std::error::Error for ExtractError {}
+
+impl Extractor for ExternalExtractor {
+	fn extensions(&self) -> Vec<&str> {
+		self.extensions.iter().map(String::as_str).collect()
+	}
+
+	/// Runs the extractor on the file at `path` within its limits and
+	/// returns the extracted text. The command is killed if it runs for
+	/// too long or produces too much output.
+	fn extract(&self, path: &Path) -> Result<String, ExtractError> {
+		let path = path.to_string_lossy();
+		let mut args = self.command.iter().map(|arg| arg.replace("{}", &path));
+		let program = args.next().ok_or(ExtractError::Failed(
+			None,
+			String::from("no command configured"),
+		))?;
+
+		let mut command = Command::new(program);
+		command
+			.args(args)
+			.stdin(Stdio::null())
+			.stdout(Stdio::piped())
+			.stderr(Stdio::piped());
+
+		limit_memory(&mut command, self.limits.max_memory);
+		let mut child = command.spawn().map_err(ExtractError::Spawn)?;
+
+		// Read output on separate threads so a full pipe can't block the command
+		let max_output = self.limits.max_output;
+		let stdout = child.stdout.take().map(|out| {
+			thread::spawn(move || {
+				let mut buf = Vec::new();
+				let _ = out.take(max_output as u64 + 1).read_to_end(&mut buf);
+				buf
+			})
+		});
+
+		let stderr = child.stderr.take().map(|err| {
+			thread::spawn(move || {
+				let mut buf = Vec::new();
+				let _ = err.take(4096).read_to_end(&mut buf);
+				buf
+			})
+		});
+
+		let status = wait_timeout(&mut child, self.limits.timeout)?;
+		let stdout = stdout.and_then(|t| t.join().ok()).unwrap_or_default();
+		let stderr = stderr.and_then(|t| t.join().ok()).unwrap_or_default();
+		if !status.success() {
+			let stderr = String::from_utf8_lossy(&stderr).into_owned();
+			return Err(ExtractError::Failed(status.code(), stderr));
+		}
+
+		if stdout.len() > max_output {
+			return Err(ExtractError::OutputTooLarge(max_output));
+		}
+
+		String::from_utf8(stdout).map_err(|_| ExtractError::InvalidOutput)
+	}
+}
+
+/// Waits for `child` to exit, killing it if it runs longer than `timeout`.
+fn wait_timeout(
+	child: &mut Child,
+	timeout: Duration,
+) -> Result<std::process::ExitStatus, ExtractError> {
+	let start = Instant::now();
+	loop {
+		if let Some(status) = child.try_wait().map_err(ExtractError::Spawn)? {
+			return Ok(status);
+		}
+
+		if start.elapsed() >= timeout {
+			let _ = child.kill();
+			let _ = child.wait();
+			return Err(ExtractError::Timeout(timeout));
+		}
+
+		thread::sleep(POLL_INTERVAL);
+	}
+}
+
+/// Limits the address space of the process spawned by `command`.
+#[cfg(target_family = "unix")]
+fn limit_memory(command: &mut Command, max_memory: u64) {
+	use std::os::unix::process::CommandExt;
+
+	let limit = libc::rlimit {
+		rlim_cur: max_memory as libc::rlim_t,
+		rlim_max: max_memory as libc::rlim_t,
+	};
+
+	// SAFETY: setrlimit is async-signal-safe and only affects the child process.
+	unsafe {
+		command.pre_exec(move || {
+			if libc::setrlimit(libc::RLIMIT_AS, &limit) != 0 {
+				return Err(std::io::Error::last_os_error());
+			}
+
+			Ok(())
+		});
+	}
+}
+
+/// Limits the address space of the process spawned by `command`.
+/// Memory limits are not supported on this platform.
+#[cfg(not(target_family = "unix"))]
+fn limit_memory(_command: &mut Command, _max_memory: u64) {}
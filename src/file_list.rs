@@ -0,0 +1,58 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::index;
+
+/// Returns the path of the file list kept alongside the index at `index`,
+/// which replaces walking the current directory while it exists.
+pub fn path_for(index: &Path) -> PathBuf {
+	index.with_extension("files")
+}
+
+/// Reads a list of files, one path per line, from `source`: a file, or
+/// stdin if it is `-`. Blank lines are skipped, and paths are stored the
+/// way the index stores them.
+pub fn read(source: &Path) -> std::io::Result<Vec<PathBuf>> {
+	let mut contents = String::new();
+	if source == Path::new("-") {
+		std::io::stdin().read_to_string(&mut contents)?;
+	} else {
+		contents = std::fs::read_to_string(source)?;
+	}
+
+	Ok(parse(&contents))
+}
+
+/// Reads the file list saved at `path`, or `None` if there is none.
+pub fn load(path: &Path) -> std::io::Result<Option<Vec<PathBuf>>> {
+	match std::fs::read_to_string(path) {
+		Ok(v) => Ok(Some(parse(&v))),
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+		Err(e) => Err(e),
+	}
+}
+
+/// Writes `files` to `path`, replacing any saved file list.
+pub fn save(path: &Path, files: &[PathBuf]) -> std::io::Result<()> {
+	let mut contents = String::new();
+	for file in files {
+		contents.push_str(&file.to_string_lossy());
+		contents.push('\n');
+	}
+
+	std::fs::write(path, contents)
+}
+
+/// Parses a list of files, one path per line.
+fn parse(contents: &str) -> Vec<PathBuf> {
+	let mut files = contents
+		.lines()
+		.map(str::trim_end)
+		.filter(|line| !line.is_empty())
+		.map(|line| index::document_path(Path::new(line)))
+		.collect::<Vec<PathBuf>>();
+
+	files.sort();
+	files.dedup();
+	files
+}
@@ -0,0 +1,203 @@
+use std::path::{Path, PathBuf};
+use std::process::{self, Stdio};
+use std::sync::Mutex;
+use std::{env, fs};
+
+use crate::args::Options;
+use crate::build_options;
+use crate::fail;
+use crate::file_list;
+use crate::index::{Index, IndexError, IndexOptions};
+use crate::lock::{self, IndexLock};
+use crate::log;
+use crate::progress::{Progress, ProgressFormat};
+use crate::shard;
+
+/// Whether an opened index matches the files on disk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Freshness {
+	/// The index already matched the files.
+	Current,
+	/// The index was rewritten to match the files.
+	Updated,
+	/// Files changed, but updating the index was deferred.
+	Outdated,
+}
+
+/// Loads and updates the index at `path`, creating it if it can't be read
+/// or rebuilding it if `ngram_size` differs from its n-gram size. If `defer`
+/// is set, an outdated index is returned as is instead of being updated.
+pub fn open_index(
+	path: &Path,
+	options: &IndexOptions,
+	ngram_size: Option<u8>,
+	defer: bool,
+) -> Result<(Index, Freshness), IndexError> {
+	let mut freshness = Freshness::Updated;
+	let index = Index::load(path)
+		.and_then(|mut i| {
+			if ngram_size.is_some_and(|size| size != i.ngram_size()) {
+				drop(i);
+				log::info(format_args!(
+					"Rebuilding index with {}-byte n-grams...",
+					options.ngram_size
+				));
+				return Index::create(path, options);
+			}
+
+			freshness = if defer && i.is_outdated(options)? {
+				Freshness::Outdated
+			} else if i.update(options)? {
+				Freshness::Updated
+			} else {
+				Freshness::Current
+			};
+
+			Ok(i)
+		})
+		.or_else(|e| {
+			// A first search with --wait has no index to read yet
+			if path.exists() {
+				eprintln!("Failed to read index: {e}");
+			}
+
+			Index::create(path, options)
+		})?;
+
+	Ok((index, freshness))
+}
+
+/// Opens every shard of the index at `save_path` in parallel, creating or
+/// updating them as needed, and writes the shard manifest. Shards of the
+/// `previous` directories that no longer exist are deleted, as is any
+/// unsharded index. Returns the shards and the freshness of the stalest.
+pub fn open_shards(
+	save_path: &Path,
+	previous: &[PathBuf],
+	options: &IndexOptions,
+	ngram_size: Option<u8>,
+	defer: bool,
+) -> (Vec<Index>, Freshness) {
+	let dirs = match shard::discover(&options.roots)
+		.and_then(|dirs| shard::save(&shard::manifest_path(save_path), &dirs).map(|_| dirs))
+	{
+		Ok(v) => v,
+		Err(e) => fail("Failed to write shards", e),
+	};
+
+	let mut freshness = Freshness::Current;
+	if fs::remove_file(save_path).is_ok() {
+		freshness = Freshness::Updated;
+	}
+
+	for dir in previous.iter().filter(|dir| !dirs.contains(dir)) {
+		let path = shard::index_path(save_path, dir);
+		if fs::remove_file(&path).is_ok() {
+			freshness = Freshness::Updated;
+		}
+
+		let _ = fs::remove_file(lock::path_for(&path));
+	}
+
+	let progress = Mutex::new(Progress::new(
+		options.progress,
+		"sharding",
+		dirs.len() as u64,
+	));
+
+	let results = shard::parallel_map(dirs, |dir| {
+		progress.lock().expect("progress poisoned").inc(Some(&dir));
+		let shard_options = IndexOptions {
+			progress: ProgressFormat::Hidden,
+			shard: Some(dir.clone()),
+			..options.clone()
+		};
+
+		open_index(
+			&shard::index_path(save_path, &dir),
+			&shard_options,
+			ngram_size,
+			defer,
+		)
+		.map_err(|e| (dir, e))
+	});
+
+	progress.into_inner().expect("progress poisoned").finish();
+	let mut indexes = Vec::with_capacity(results.len());
+	for result in results {
+		match result {
+			Ok((index, shard_freshness)) => {
+				indexes.push(index);
+				freshness = freshness.max(shard_freshness);
+			}
+			Err((dir, e)) => fail(
+				&format!("Index creation failed: shard {}", dir.to_string_lossy()),
+				e,
+			),
+		}
+	}
+
+	(indexes, freshness)
+}
+
+/// Starts building the index at `save_path` in a background process,
+/// unless another process is already building it. Returns whether the
+/// index is being built.
+pub fn start_background_build(save_path: &Path, options: &Options) -> bool {
+	match IndexLock::building(save_path) {
+		Ok(Some(_)) => {}
+		Ok(None) => return true,
+		Err(e) => {
+			eprintln!("Warning: failed to lock index: {e}");
+			return false;
+		}
+	}
+
+	let mut args = vec![String::from("index")];
+	if let Some(path) = &options.index_path {
+		args.extend([
+			String::from("--index-path"),
+			path.to_string_lossy().into_owned(),
+		]);
+	}
+
+	if let Some(size) = options.ngram_size {
+		args.extend([String::from("--ngram-size"), size.to_string()]);
+	}
+
+	if options.shard {
+		args.push(String::from("--shard"));
+	}
+
+	// `index` saves the flags it is given, so it is given the saved ones
+	let saved = build_options::load(&build_options::path_for(save_path));
+	if let Ok(Some(saved)) = saved {
+		args.extend(saved.flags().into_iter().map(String::from));
+	}
+
+	// A plain `index` would stop using the file list
+	let files_path = file_list::path_for(save_path);
+	if files_path.exists() {
+		args.extend([
+			String::from("--files-from"),
+			files_path.to_string_lossy().into_owned(),
+		]);
+	}
+
+	let child = env::current_exe().and_then(|exe| {
+		process::Command::new(exe)
+			.args(&args)
+			.stdin(Stdio::null())
+			.stdout(Stdio::null())
+			.stderr(Stdio::null())
+			.spawn()
+	});
+
+	match child {
+		Ok(_) => true,
+		Err(e) => {
+			eprintln!("Warning: failed to build the index in the background: {e}");
+			false
+		}
+	}
+}
@@ -0,0 +1,70 @@
+use std::path::Path;
+
+use crate::extractor::{ExtractError, Extractor};
+
+/// Extracts the text of markdown files with the fields of any YAML (`---`)
+/// or TOML (`+++`) front matter as plain `key: value` lines, without the
+/// quotes and brackets around their values. Every line stays on the same
+/// line number.
+#[derive(Debug)]
+pub struct FrontMatterExtractor;
+
+impl Extractor for FrontMatterExtractor {
+	fn extensions(&self) -> Vec<&str> {
+		vec!["md", "markdown"]
+	}
+
+	fn extract(&self, path: &Path) -> Result<String, ExtractError> {
+		let contents = std::fs::read(path).map_err(ExtractError::Io)?;
+		let text = String::from_utf8(contents).map_err(|_| ExtractError::InvalidOutput)?;
+		Ok(extract_front_matter(&text))
+	}
+}
+
+/// Rewrites the front matter of `text`, if it has any.
+fn extract_front_matter(text: &str) -> String {
+	let lines = text.split('\n').collect::<Vec<&str>>();
+	let (fence, separator) = match lines.first().map(|l| l.trim_end()) {
+		Some("---") => ("---", ':'),
+		Some("+++") => ("+++", '='),
+		_ => return text.to_string(),
+	};
+
+	let end = match lines
+		.iter()
+		.skip(1)
+		.position(|line| line.trim_end() == fence)
+	{
+		Some(i) => i + 1,
+		None => return text.to_string(),
+	};
+
+	let mut out = Vec::with_capacity(lines.len());
+	out.push(String::new());
+	for line in &lines[1..end] {
+		out.push(match line.split_once(separator) {
+			Some((key, value)) => format!("{}: {}", key.trim(), unquote(value)),
+			None => unquote(line.trim_start().trim_start_matches("- ")),
+		});
+	}
+
+	out.push(String::new());
+	out.extend(lines[end + 1..].iter().map(|line| line.to_string()));
+	out.join("\n")
+}
+
+/// Removes the brackets around a list value and the quotes around each of
+/// its items, e.g. `["a", 'b']` becomes `a, b`.
+fn unquote(value: &str) -> String {
+	let value = value.trim();
+	let value = value
+		.strip_prefix('[')
+		.and_then(|v| v.strip_suffix(']'))
+		.unwrap_or(value);
+
+	value
+		.split(',')
+		.map(|item| item.trim().trim_matches(|c| c == '"' || c == '\''))
+		.collect::<Vec<&str>>()
+		.join(", ")
+}
@@ -0,0 +1,46 @@
+use std::path::Path;
+
+/// Suffixes of the names of minified files.
+const MINIFIED_SUFFIXES: [&str; 3] = [".min.js", ".min.mjs", ".min.css"];
+
+/// How many lines at the start of a file are checked for a marker saying
+/// it is generated.
+const MARKER_LINES: usize = 10;
+
+/// The average line length in bytes above which a file is taken to be
+/// minified.
+const MINIFIED_LINE_LEN: usize = 500;
+
+/// How long a file must be before its line lengths are checked, so short
+/// one-line files aren't taken to be minified.
+const MINIFIED_MIN_LEN: usize = 4096;
+
+/// Returns `true` if the file at `path` with the given `text` looks
+/// minified or generated: its name ends in e.g. `.min.js`, its first lines
+/// have an `@generated` or `Code generated ... DO NOT EDIT` marker, or its
+/// lines are very long on average.
+pub fn is_generated(path: &Path, text: &str) -> bool {
+	let name = path
+		.file_name()
+		.map(|name| name.to_string_lossy().to_lowercase())
+		.unwrap_or_default();
+
+	if MINIFIED_SUFFIXES
+		.iter()
+		.any(|suffix| name.ends_with(suffix))
+	{
+		return true;
+	}
+
+	// Split so this file doesn't mark itself as generated
+	let marker = concat!("@", "generated");
+	let marked = text.lines().take(MARKER_LINES).any(|line| {
+		line.contains(marker) || (line.contains("Code generated") && line.contains("DO NOT EDIT"))
+	});
+
+	if marked {
+		return true;
+	}
+
+	text.len() >= MINIFIED_MIN_LEN && text.len() / text.lines().count().max(1) > MINIFIED_LINE_LEN
+}
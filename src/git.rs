@@ -0,0 +1,281 @@
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::encoding;
+use crate::index;
+
+/// Represents an error running git.
+#[derive(Debug)]
+pub enum GitError {
+	/// git ran but failed, with what it printed to stderr.
+	Failed(String),
+	Io(std::io::Error),
+}
+
+impl Display for GitError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			GitError::Failed(msg) => write!(f, "git failed: {msg}"),
+			GitError::Io(e) => write!(f, "failed to run git: {e}"),
+		}
+	}
+}
+
+impl std::error::Error for GitError {}
+
+/// A commit and the lines it changed.
+pub struct Commit {
+	/// The full hash of the commit.
+	pub hash: String,
+	/// The date it was authored, e.g. `2024-01-31`.
+	pub date: String,
+	/// The first line of its message.
+	pub subject: String,
+	/// The lines it added or removed, in diff order.
+	pub changes: Vec<Change>,
+}
+
+/// A line a commit added or removed.
+pub struct Change {
+	/// The file the line is in, relative to the current directory.
+	pub path: PathBuf,
+	/// Whether the line was added rather than removed.
+	pub added: bool,
+	/// The line, without its `+` or `-`.
+	pub line: String,
+}
+
+/// Who last changed a line, and when.
+#[derive(Clone, Default)]
+pub struct Blame {
+	/// The author of the commit that last changed the line.
+	pub author: String,
+	/// When the commit was authored, in seconds since the Unix epoch.
+	pub time: u64,
+}
+
+/// Lists the files git tracks in the current directory and below, as the
+/// index stores paths. Tracked files deleted from the working tree are
+/// included.
+pub fn tracked_files() -> Result<Vec<PathBuf>, GitError> {
+	let output = run(["ls-files", "-z"])?;
+	Ok(output
+		.split(|b| *b == 0)
+		.filter(|path| !path.is_empty())
+		.map(|path| index::document_path(Path::new(&encoding::bytes_to_os_string(path.to_vec()))))
+		.collect())
+}
+
+/// Returns the full hash of the commit `rev` names, e.g. a tag or branch.
+pub fn resolve_commit(rev: &str) -> Result<String, GitError> {
+	let spec = format!("{rev}^{{commit}}");
+	let output = run([
+		"rev-parse",
+		"--verify",
+		"--quiet",
+		"--end-of-options",
+		spec.as_str(),
+	])
+	.map_err(|e| match e {
+		GitError::Failed(msg) if msg.is_empty() => {
+			GitError::Failed(format!("{rev} isn't a commit"))
+		}
+		e => e,
+	})?;
+
+	Ok(String::from_utf8_lossy(&output).trim().to_string())
+}
+
+/// Lists the files in the current directory and below as of `commit`, as
+/// the index stores paths. Submodules are left out.
+pub fn revision_files(commit: &str) -> Result<Vec<PathBuf>, GitError> {
+	let output = run(["ls-tree", "-r", "-z", commit])?;
+	Ok(output
+		.split(|b| *b == 0)
+		.filter_map(|entry| {
+			// Each entry is `<mode> <type> <object>\t<path>`
+			let tab = entry.iter().position(|b| *b == b'\t')?;
+			let kind = entry[..tab].split(|b| *b == b' ').nth(1)?;
+			if kind != b"blob" {
+				return None;
+			}
+
+			let path = encoding::bytes_to_os_string(entry[tab + 1..].to_vec());
+			Some(index::document_path(Path::new(&path)))
+		})
+		.collect())
+}
+
+/// Reads the contents of the file at `path`, relative to the current
+/// directory, as of `commit`.
+pub fn read_blob(commit: &str, path: &Path) -> Result<Vec<u8>, GitError> {
+	let mut spec = OsString::from(format!("{commit}:"));
+	spec.push(path.as_os_str());
+	run([OsStr::new("cat-file"), OsStr::new("blob"), &spec])
+}
+
+/// Returns whether `ancestor` is `commit` or one of its ancestors.
+pub fn is_ancestor(ancestor: &str, commit: &str) -> Result<bool, GitError> {
+	let output = Command::new("git")
+		.args(["merge-base", "--is-ancestor", ancestor, commit])
+		.output()
+		.map_err(GitError::Io)?;
+
+	// It exits with 1 if it isn't an ancestor, and anything else on errors
+	match output.status.code() {
+		Some(0) => Ok(true),
+		Some(1) => Ok(false),
+		_ => {
+			let stderr = String::from_utf8_lossy(&output.stderr);
+			Err(GitError::Failed(stderr.trim().to_string()))
+		}
+	}
+}
+
+/// Lists the commits `revs` selects, e.g. `a..b`, newest first, with the
+/// lines each changed in the current directory and below. Merge commits
+/// change nothing, as with `git log`.
+pub fn commits(revs: &[&str]) -> Result<Vec<Commit>, GitError> {
+	let mut args = vec![
+		"log",
+		"--patch",
+		"--unified=0",
+		"--no-color",
+		"--no-ext-diff",
+		"--no-textconv",
+		"--relative",
+		"--date=short",
+		"--format=%x00%H%x00%ad%x00%s",
+	];
+
+	args.extend(revs);
+	args.extend(["--", "."]);
+	let output = run(args)?;
+	Ok(parse_log(&String::from_utf8_lossy(&output)))
+}
+
+/// Parses the output of `git log` in the format `commits` asks for.
+fn parse_log(log: &str) -> Vec<Commit> {
+	let mut commits = Vec::new();
+	for record in log.split('\0').skip(1).collect::<Vec<&str>>().chunks(3) {
+		let [hash, date, rest] = record else {
+			break;
+		};
+
+		let (subject, patch) = rest.split_once('\n').unwrap_or((rest, ""));
+		let mut changes = Vec::new();
+		let mut path = PathBuf::new();
+		let mut in_hunk = false;
+		for line in patch.lines() {
+			if line.starts_with("diff --git ") {
+				in_hunk = false;
+			} else if line.starts_with("@@") {
+				in_hunk = true;
+			} else if let (false, Some(new)) = (in_hunk, line.strip_prefix("+++ ")) {
+				// Deleted files only name their old path
+				if new != "/dev/null" {
+					path = index::document_path(Path::new(new.strip_prefix("b/").unwrap_or(new)));
+				}
+			} else if let (false, Some(old)) = (in_hunk, line.strip_prefix("--- ")) {
+				path = index::document_path(Path::new(old.strip_prefix("a/").unwrap_or(old)));
+			} else if in_hunk && (line.starts_with('+') || line.starts_with('-')) {
+				changes.push(Change {
+					path: path.clone(),
+					added: line.starts_with('+'),
+					line: line[1..].to_string(),
+				});
+			}
+		}
+
+		commits.push(Commit {
+			hash: hash.to_string(),
+			date: date.to_string(),
+			subject: subject.to_string(),
+			changes,
+		});
+	}
+
+	commits
+}
+
+/// Finds who last changed each of the 1-based `lines` of the file at `path`,
+/// as of `revision` if given, or in the working tree otherwise.
+pub fn blame(
+	path: &Path,
+	lines: &[usize],
+	revision: Option<&str>,
+) -> Result<HashMap<usize, Blame>, GitError> {
+	let mut args = vec![OsString::from("blame"), OsString::from("--porcelain")];
+	for line in lines {
+		args.push(OsString::from(format!("-L{line},{line}")));
+	}
+
+	args.extend(revision.map(OsString::from));
+	args.extend([OsString::from("--"), path.as_os_str().to_owned()]);
+	let output = run(args)?;
+
+	// Each line starts with a header naming its commit, whose details are
+	// only given the first time it appears
+	let mut authors = HashMap::new();
+	let mut commits = HashMap::new();
+	let mut current: Option<(String, usize)> = None;
+	for line in String::from_utf8_lossy(&output).lines() {
+		if line.starts_with('\t') {
+			current = None;
+			continue;
+		}
+
+		let Some((commit, _)) = &current else {
+			let mut fields = line.split(' ');
+			let commit = fields.next().unwrap_or_default().to_string();
+			let number = fields.nth(1).and_then(|v| v.parse().ok()).unwrap_or(0);
+			commits.insert(number, commit.clone());
+			current = Some((commit, number));
+			continue;
+		};
+
+		let entry = authors.entry(commit.clone()).or_insert_with(Blame::default);
+
+		if let Some(author) = line.strip_prefix("author ") {
+			entry.author = author.to_string();
+		} else if let Some(time) = line.strip_prefix("author-time ") {
+			entry.time = time.parse().unwrap_or(0);
+		}
+	}
+
+	Ok(commits
+		.into_iter()
+		.filter_map(|(number, commit)| Some((number, authors.get(&commit)?.clone())))
+		.collect())
+}
+
+/// Returns the path of the index of `commit` alongside the index at
+/// `index`. It has no extension, so its data files don't clash with those
+/// of `index`.
+pub fn index_path(index: &Path, commit: &str) -> PathBuf {
+	let mut path = index.as_os_str().to_owned();
+	path.push(format!("-{commit}"));
+	PathBuf::from(path)
+}
+
+/// Runs git with `args` in the current directory, returning its stdout.
+fn run<I, S>(args: I) -> Result<Vec<u8>, GitError>
+where
+	I: IntoIterator<Item = S>,
+	S: AsRef<OsStr>,
+{
+	let output = Command::new("git")
+		.args(args)
+		.output()
+		.map_err(GitError::Io)?;
+
+	if !output.status.success() {
+		let stderr = String::from_utf8_lossy(&output.stderr);
+		return Err(GitError::Failed(stderr.trim().to_string()));
+	}
+
+	Ok(output.stdout)
+}
@@ -0,0 +1,141 @@
+use crate::args::{Flag, Subcommand, FLAGS, SUBCOMMANDS};
+
+/// Returns the help of codesearch run as `program`: how to search, its
+/// subcommands, and the flags of searches.
+pub fn general(program: &str) -> String {
+	let mut help = format!(
+		"Usage: {program} [flags] <search term>... [-term|!term]\n       {program} <command> [arguments]\n\nSearch the current directory for files containing the search terms, best\nmatches first, keeping an index of it up to date.\n\nCommands:\n"
+	);
+
+	let rows = SUBCOMMANDS
+		.iter()
+		.map(|command| (command.name.to_string(), command.help))
+		.collect::<Vec<(String, &str)>>();
+	help.push_str(&table(&rows));
+
+	help.push_str("\nFlags:\n");
+	help.push_str(&table(&flag_rows(FLAGS)));
+	help.push_str(&format!(
+		"\nRun `{program} <command> --help` for the arguments of a command.\n"
+	));
+
+	help
+}
+
+/// Returns the help of `command`, run as `program`: its usage, what it does,
+/// and its flags.
+pub fn command(program: &str, command: &Subcommand) -> String {
+	let usage = match command.search_flags {
+		true => format!("{} [flags]", command.usage),
+		false => command.usage.to_string(),
+	};
+
+	let mut help = format!(
+		"Usage: {program} {} {}\n\n{}\n",
+		command.name,
+		usage.trim(),
+		command.about
+	);
+
+	if !command.flags.is_empty() {
+		help.push_str("\nFlags:\n");
+		help.push_str(&table(&flag_rows(command.flags)));
+	}
+
+	if command.search_flags {
+		help.push_str(&format!(
+			"\nThe flags of searches are taken too; run `{program} --help` to list them.\n"
+		));
+	}
+
+	help
+}
+
+/// Returns the manual page of codesearch, in roff.
+pub fn man_page() -> String {
+	let mut page = String::from(
+		".TH CODESEARCH 1\n.SH NAME\ncodesearch \\- search code with an n\\-gram index\n.SH SYNOPSIS\n.B codesearch\n[\\fIflags\\fR] \\fIsearch term\\fR... [\\fB\\-\\fIterm\\fR|\\fB!\\fIterm\\fR]\n.br\n.B codesearch\n\\fIcommand\\fR [\\fIarguments\\fR]\n.SH DESCRIPTION\nSearch the current directory for files containing the search terms, best\nmatches first, keeping an index of it up to date. Terms prefixed with\n\\fB\\-\\fR or \\fB!\\fR leave out files containing them.\n.SH COMMANDS\n",
+	);
+
+	for command in SUBCOMMANDS {
+		page.push_str(&format!(
+			".TP\n.B {} {}\n{}\n",
+			roff(command.name),
+			roff(command.usage),
+			roff(command.about)
+		));
+
+		if !command.flags.is_empty() {
+			page.push_str(".RS\n");
+			for flag in command.flags {
+				page.push_str(&man_flag(flag));
+			}
+
+			page.push_str(".RE\n");
+		}
+	}
+
+	page.push_str(".SH OPTIONS\n");
+	for flag in FLAGS {
+		page.push_str(&man_flag(flag));
+	}
+
+	page.push_str(".SH EXIT STATUS\nA search exits with 0 if any file has a matching line and 1 if none do.\nOther commands exit with 0 on success. Errors exit with 2 for an invalid\ncommand line or query, 3 for a file that couldn't be read or written, 4\nfor a malformed index or configuration, and 5 if the directory tree\ncouldn't be walked.\n");
+	page
+}
+
+/// Returns the rows of a table of `flags`: their names and value, and what
+/// they do.
+fn flag_rows(flags: &[Flag]) -> Vec<(String, &str)> {
+	flags
+		.iter()
+		.map(|flag| {
+			let mut names = flag.names.join(", ");
+			if let Some(value) = flag.value {
+				names.push(' ');
+				names.push_str(value);
+			}
+
+			(names, flag.help)
+		})
+		.collect()
+}
+
+/// Lays out `rows` in two indented columns.
+fn table(rows: &[(String, &str)]) -> String {
+	let width = rows.iter().map(|(left, _)| left.len()).max().unwrap_or(0);
+	rows.iter()
+		.map(|(left, right)| format!("  {left:width$}  {right}\n"))
+		.collect()
+}
+
+/// Returns the manual page entry of `flag`.
+fn man_flag(flag: &Flag) -> String {
+	let names = flag
+		.names
+		.iter()
+		.map(|name| format!("\\fB{}\\fR", roff(name)))
+		.collect::<Vec<String>>()
+		.join(", ");
+
+	let value = match flag.value {
+		Some(value) => format!(" \\fI{}\\fR", roff(value)),
+		None => String::new(),
+	};
+
+	format!(".TP\n{names}{value}\n{}.\n", roff(flag.help))
+}
+
+/// Escapes `text` for roff, where `-` is a hyphen rather than a minus and
+/// `\` starts an escape, and sets what's quoted in backticks in bold.
+fn roff(text: &str) -> String {
+	text.replace('\\', "\\e")
+		.replace('-', "\\-")
+		.split('`')
+		.enumerate()
+		.map(|(i, part)| match i % 2 {
+			0 => part.to_string(),
+			_ => format!("\\fB{part}\\fR"),
+		})
+		.collect()
+}
@@ -0,0 +1,171 @@
+use serde_json::{json, Value};
+use std::fmt::Display;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// The most writes kept in the history. Older writes are dropped.
+const MAX_ENTRIES: usize = 1000;
+
+/// One write of the index, recorded for `stats --history`.
+#[derive(Clone, Copy, Debug)]
+pub struct Entry {
+	/// When the index was written, in seconds since the Unix epoch.
+	pub time: u64,
+	/// The number of documents in the index.
+	pub documents: u64,
+	/// The size of the index on disk, in bytes.
+	pub bytes: u64,
+	/// How long creating or updating the index took, in milliseconds.
+	pub duration: u64,
+}
+
+/// Represents an error reading or writing the history file.
+#[derive(Debug)]
+pub enum HistoryError {
+	Invalid(String),
+	Io(std::io::Error),
+	Parse(serde_json::Error),
+}
+
+impl Display for HistoryError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			HistoryError::Invalid(msg) => write!(f, "invalid history file: {msg}"),
+			HistoryError::Io(e) => write!(f, "history file error: {e}"),
+			HistoryError::Parse(e) => write!(f, "invalid history file: {e}"),
+		}
+	}
+}
+
+impl std::error::Error for HistoryError {}
+
+impl Entry {
+	/// Records a write finishing now that took `duration`.
+	pub fn new(documents: u64, bytes: u64, duration: Duration) -> Self {
+		let time = SystemTime::now()
+			.duration_since(SystemTime::UNIX_EPOCH)
+			.unwrap_or_default();
+
+		Self {
+			time: time.as_secs(),
+			documents,
+			bytes,
+			duration: duration.as_millis() as u64,
+		}
+	}
+
+	/// Returns the entry as one line of the history file.
+	fn to_json(self) -> Value {
+		json!({
+			"time": self.time,
+			"documents": self.documents,
+			"bytes": self.bytes,
+			"duration": self.duration,
+		})
+	}
+
+	/// Parses one line of the history file.
+	fn from_json(value: &Value) -> Option<Self> {
+		let field = |name: &str| value.get(name).and_then(Value::as_u64);
+		Some(Self {
+			time: field("time")?,
+			documents: field("documents")?,
+			bytes: field("bytes")?,
+			duration: field("duration")?,
+		})
+	}
+}
+
+/// Returns the path of the history file kept alongside the index at `index`.
+pub fn path_for(index: &Path) -> PathBuf {
+	index.with_extension("history")
+}
+
+/// Reads the writes recorded at `path`, oldest first. A missing file has
+/// no history.
+pub fn load(path: &Path) -> Result<Vec<Entry>, HistoryError> {
+	let contents = match std::fs::read_to_string(path) {
+		Ok(v) => v,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+		Err(e) => return Err(HistoryError::Io(e)),
+	};
+
+	contents
+		.lines()
+		.filter(|line| !line.trim().is_empty())
+		.map(|line| {
+			let value = serde_json::from_str::<Value>(line).map_err(HistoryError::Parse)?;
+			Entry::from_json(&value)
+				.ok_or_else(|| HistoryError::Invalid(format!("malformed entry {line}")))
+		})
+		.collect()
+}
+
+/// Appends `entry` to the history at `path`, one JSON object per line,
+/// dropping the oldest entries once there are too many.
+pub fn append(path: &Path, entry: Entry) -> Result<(), HistoryError> {
+	let mut entries = load(path)?;
+	if entries.len() < MAX_ENTRIES {
+		let mut file = File::options()
+			.create(true)
+			.append(true)
+			.open(path)
+			.map_err(HistoryError::Io)?;
+
+		return writeln!(file, "{}", entry.to_json()).map_err(HistoryError::Io);
+	}
+
+	entries.drain(..=entries.len() - MAX_ENTRIES);
+	entries.push(entry);
+	let contents = entries
+		.iter()
+		.map(|entry| format!("{}\n", entry.to_json()))
+		.collect::<String>();
+
+	std::fs::write(path, contents).map_err(HistoryError::Io)
+}
+
+/// Formats `time`, in seconds since the Unix epoch, as a UTC date and time.
+pub fn format_time(time: u64) -> String {
+	// Converts days since the epoch to a civil date, after Howard Hinnant
+	let days = (time / 86_400) as i64 + 719_468;
+	let era = days.div_euclid(146_097);
+	let day_of_era = days.rem_euclid(146_097);
+	let year_of_era =
+		(day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+	let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+	let month_index = (5 * day_of_year + 2) / 153;
+	let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+	let month = if month_index < 10 {
+		month_index + 3
+	} else {
+		month_index - 9
+	};
+
+	let year = year_of_era + era * 400 + i64::from(month <= 2);
+	let seconds = time % 86_400;
+	format!(
+		"{year:04}-{month:02}-{day:02} {:02}:{:02}",
+		seconds / 3600,
+		seconds / 60 % 60
+	)
+}
+
+/// Formats `bytes` with a binary unit, e.g. `1.5 MiB`.
+pub fn format_bytes(bytes: u64) -> String {
+	const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+	if bytes < 1024 {
+		return format!("{bytes} B");
+	}
+
+	let mut size = bytes as f64 / 1024.0;
+	let mut unit = 0;
+	while size >= 1024.0 && unit < UNITS.len() - 1 {
+		size /= 1024.0;
+		unit += 1;
+	}
+
+	format!("{size:.1} {}", UNITS[unit])
+}
@@ -0,0 +1,110 @@
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Commands run around index creation and updates.
+#[derive(Clone, Debug, Default)]
+pub struct Hooks {
+	/// Run before files are scanned, e.g. to regenerate sources.
+	pub pre_update: Option<Vec<String>>,
+	/// Run after the index has been written, e.g. to notify a dashboard.
+	pub post_update: Option<Vec<String>>,
+}
+
+/// What an index operation changed, as told to post-update hooks.
+#[derive(Debug, Default)]
+pub struct Changes {
+	/// Files that were indexed or reindexed.
+	pub changed: Vec<PathBuf>,
+	/// The number of documents in the written index.
+	pub documents: u32,
+	/// Files removed from the index.
+	pub removed: Vec<PathBuf>,
+}
+
+/// Represents a hook command that failed.
+#[derive(Debug)]
+pub enum HookError {
+	Failed(Option<i32>),
+	Spawn(std::io::Error),
+}
+
+impl Display for HookError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			HookError::Failed(Some(code)) => write!(f, "hook exited with code {code}"),
+			HookError::Failed(None) => write!(f, "hook was terminated"),
+			HookError::Spawn(e) => write!(f, "failed to run hook: {e}"),
+		}
+	}
+}
+
+impl std::error::Error for HookError {}
+
+impl Hooks {
+	/// Runs the pre-update hook, if there is one. `event` is `create` or `update`.
+	pub fn pre_update(&self, event: &str, index: &Path) {
+		if let Some(command) = &self.pre_update {
+			report(run(command, &environment(event, "pre", index)));
+		}
+	}
+
+	/// Runs the post-update hook with `changes`, if there is one.
+	pub fn post_update(&self, event: &str, index: &Path, changes: &Changes) {
+		if let Some(command) = &self.post_update {
+			let mut env = environment(event, "post", index);
+			env.push(("CODESEARCH_CHANGED", join_paths(&changes.changed)));
+			env.push(("CODESEARCH_DOCUMENTS", changes.documents.to_string()));
+			env.push(("CODESEARCH_REMOVED", join_paths(&changes.removed)));
+			report(run(command, &env));
+		}
+	}
+}
+
+/// Returns the environment variables given to every hook.
+fn environment(event: &str, phase: &str, index: &Path) -> Vec<(&'static str, String)> {
+	vec![
+		("CODESEARCH_EVENT", String::from(event)),
+		("CODESEARCH_INDEX", index.to_string_lossy().into_owned()),
+		("CODESEARCH_PHASE", String::from(phase)),
+	]
+}
+
+/// Joins `paths` with newlines.
+fn join_paths(paths: &[PathBuf]) -> String {
+	paths
+		.iter()
+		.map(|p| p.to_string_lossy())
+		.collect::<Vec<_>>()
+		.join("\n")
+}
+
+/// Runs `command` with `env` and waits for it to exit. The command's output
+/// goes to stderr so it can't be mistaken for search results.
+fn run(command: &[String], env: &[(&str, String)]) -> Result<(), HookError> {
+	let (program, args) = match command.split_first() {
+		Some(v) => v,
+		None => return Ok(()),
+	};
+
+	let status = Command::new(program)
+		.args(args)
+		.envs(env.iter().map(|(k, v)| (k, v)))
+		.stdin(Stdio::null())
+		.stdout(Stdio::from(std::io::stderr()))
+		.status()
+		.map_err(HookError::Spawn)?;
+
+	if !status.success() {
+		return Err(HookError::Failed(status.code()));
+	}
+
+	Ok(())
+}
+
+/// Warns about a failed hook. Hooks never stop indexing.
+fn report(result: Result<(), HookError>) {
+	if let Err(e) = result {
+		eprintln!("Warning: {e}");
+	}
+}
@@ -1,33 +1,302 @@
-use indicatif::ProgressBar;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::ffi::OsString;
 use std::fmt::Display;
 use std::fs::File;
-use std::io::{BufReader, Read, Seek, SeekFrom, Write};
-use std::path::{Path, PathBuf};
+use std::io::{BufRead, BufReader, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::time::SystemTime;
 
-use crate::bitmap::BitMap;
+use crate::archive;
+use crate::bitmap::{BitMap, BitMapRef};
+use crate::compression;
 use crate::encoding;
+use crate::extractor::{ExtractError, Extractor};
+use crate::generated;
+use crate::git::{self, GitError};
+use crate::hooks::{Changes, Hooks};
+use crate::language::Language;
+use crate::lock::IndexLock;
+use crate::log::{self, Level};
+use crate::lru::Lru;
+use crate::ngram::{self, NGram};
+use crate::normalize::Normalizer;
+use crate::progress::{Progress, ProgressFormat};
+use crate::shard;
+use crate::symbols::{self, Symbol};
+use crate::tokenize;
 
 const HEADER_LEN: u64 = 12;
 
+/// Trailing bytes identifying an index with a section directory.
+const FOOTER_MAGIC: [u8; 4] = *b"KCSX";
+const FOOTER_LEN: u64 = 8;
+const SECTION_ENTRY_LEN: u64 = 20;
+
+/// Section holding the bitmap of gzip-compressed documents.
+const COMPRESSED_SECTION: [u8; 4] = *b"COMP";
+
+/// Section holding a document bitmap, encoded like a posting, for each
+/// directory containing documents.
+const DIRECTORY_SECTION: [u8; 4] = *b"DIRS";
+
+/// Section holding checksums: the SHA-256 hash of every byte of the index
+/// before this section, then the tag and SHA-256 hash of every other section.
+const CHECKSUM_SECTION: [u8; 4] = *b"SUMS";
+const CHECKSUM_LEN: usize = 32;
+
+/// Section holding the number of documents containing each n-gram, in
+/// record order.
+const DOCUMENT_FREQUENCY_SECTION: [u8; 4] = *b"DFRQ";
+
+/// Section holding the n-gram occurrence counts of every document.
+const FREQUENCY_SECTION: [u8; 4] = *b"FREQ";
+
+/// Section holding the size and content hash of every document.
+const FINGERPRINT_SECTION: [u8; 4] = *b"HASH";
+const FINGERPRINT_LEN: usize = 40;
+
+/// Section holding a document bitmap for each language class.
+const LANGUAGE_SECTION: [u8; 4] = *b"LANG";
+
+/// Section holding the target of every document reached through a symbolic
+/// link, or an empty path for other documents.
+const LINK_SECTION: [u8; 4] = *b"LINK";
+
+/// Section holding the encoded bitmap of every n-gram record. Indexes with
+/// this section store an offset and length into it in place of each record's
+/// bitmap.
+const POSTING_SECTION: [u8; 4] = *b"POST";
+const POSTING_ENTRY_LEN: usize = 8;
+
+/// Flag bytes marking how a posting's bitmap is encoded.
+const POSTING_RAW: u8 = 0;
+const POSTING_RLE: u8 = 1;
+
+/// Section holding the paths of the files walked but not indexed, e.g.
+/// because they are binary or empty.
+const SKIPPED_SECTION: [u8; 4] = *b"SKIP";
+
+/// Section holding the symbol definitions of every document.
+const SYMBOL_SECTION: [u8; 4] = *b"SYMB";
+
+/// Section holding a document bitmap for each identifier subtoken.
+const TOKEN_SECTION: [u8; 4] = *b"TOKN";
+
+/// The occurrence counts of an n-gram, keyed by document.
+type PostingCounts = HashMap<usize, u32>;
+
+/// The most bytes of text a file may have to be indexed. Larger files, e.g.
+/// data dumps or gzip bombs, are skipped rather than read into memory.
+const MAX_INDEXED_BYTES: u64 = 64 << 20;
+
+/// The most bytes of a document read to rank it and preview its matches.
+const MAX_RANKED_BYTES: usize = 8 << 20;
+
+/// The most bytes of a line ranked and previewed. The rest of a longer
+/// line, e.g. in minified code, is skipped without being kept in memory.
+const MAX_RANKED_LINE_LEN: usize = 4096;
+
+/// The most n-gram bitmaps an `IndexCache` keeps.
+const CACHED_NGRAMS: usize = 1024;
+
+/// The most document paths an `IndexCache` keeps.
+const CACHED_DOCUMENTS: usize = 4096;
+
 /// Represents a search index.
 pub struct Index {
+	/// Keeps the bitmaps and paths looked up most recently, if set.
+	cache: Option<Arc<IndexCache>>,
 	document_count: u32,
+	len: u64,
+	/// The shared lock held while the index is open, released while it is
+	/// rewritten by `update`.
+	lock: Option<IndexLock>,
 	modified: SystemTime,
 	ngram_count: u32,
-	source: BufReader<File>,
+	ngram_size: u8,
+	path: PathBuf,
+	/// Holds the bitmap of the last n-gram looked up.
+	scratch: Vec<u8>,
+	sections: HashMap<[u8; 4], (u64, u64)>,
+	source: Source,
+}
+
+/// Keeps the decoded bitmaps of the n-grams and the paths of the documents
+/// looked up most recently in an index, so processes that search it again
+/// and again, e.g. `serve`, skip reading them each time. A cache shared by
+/// each load of an index is emptied when the index was rewritten since.
+#[derive(Debug)]
+pub struct IndexCache {
+	documents: Mutex<Lru<u32, OsString>>,
+	/// When the index the cache holds parts of was written, and its length.
+	generation: Mutex<Option<(SystemTime, u64)>>,
+	/// The record number and on-disk bitmap of each n-gram.
+	ngrams: Mutex<Lru<NGram, (u32, Vec<u8>)>>,
+}
+
+impl Default for IndexCache {
+	fn default() -> Self {
+		Self {
+			documents: Mutex::new(Lru::new(CACHED_DOCUMENTS)),
+			generation: Mutex::new(None),
+			ngrams: Mutex::new(Lru::new(CACHED_NGRAMS)),
+		}
+	}
+}
+
+/// Where the bytes of an index are read from.
+enum Source {
+	/// An index file.
+	File(BufReader<File>),
+	/// An index built in memory, which is never written to disk.
+	Memory(Cursor<Vec<u8>>),
+}
+
+impl Source {
+	/// Seeks `offset` bytes from the current position, keeping any buffered
+	/// bytes of a file.
+	fn seek_relative(&mut self, offset: i64) -> std::io::Result<()> {
+		match self {
+			Source::File(reader) => reader.seek_relative(offset),
+			Source::Memory(cursor) => cursor.seek(SeekFrom::Current(offset)).map(|_| ()),
+		}
+	}
+}
+
+impl Read for Source {
+	fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+		match self {
+			Source::File(reader) => reader.read(buf),
+			Source::Memory(cursor) => cursor.read(buf),
+		}
+	}
+}
+
+impl Seek for Source {
+	fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+		match self {
+			Source::File(reader) => reader.seek(pos),
+			Source::Memory(cursor) => cursor.seek(pos),
+		}
+	}
+}
+
+/// The outcome of verifying an index.
+#[derive(Debug, Default)]
+pub struct Verification {
+	/// Whether the index has checksums to verify its contents against.
+	pub checksummed: bool,
+	/// A description of each problem found.
+	pub problems: Vec<String>,
+}
+
+/// Settings used when building or updating an index.
+#[derive(Clone, Debug)]
+pub struct IndexOptions {
+	/// External commands used to extract text from non-text files.
+	pub extractors: Vec<Arc<dyn Extractor>>,
+	/// Commands run before and after the index is written.
+	pub hooks: Hooks,
+	/// The size in bytes of the n-grams to index. Updates keep the
+	/// n-gram size the index was created with.
+	pub ngram_size: u8,
+	/// How progress is reported while indexing.
+	pub progress: ProgressFormat,
+	/// Directories indexed alongside the current directory, such as path
+	/// dependencies outside it.
+	pub roots: Vec<PathBuf>,
+	/// Follow symbolic links while walking the indexed directories. Each
+	/// directory is walked once, however many links lead to it.
+	pub follow_links: bool,
+	/// Record the target of each document reached through a symbolic link
+	/// alongside its path.
+	pub link_targets: bool,
+	/// Index files that look binary as text instead of skipping them.
+	pub binary: bool,
+	/// Index minified and generated files instead of skipping them.
+	pub generated: bool,
+	/// Only index these files instead of walking any directories.
+	pub files: Option<Vec<PathBuf>>,
+	/// Read the `files` as of this commit from git instead of from disk.
+	/// Commits never change, so neither does their index.
+	pub revision: Option<String>,
+	/// Only index this directory, or only the files directly in the current
+	/// directory if it is `.`, instead of the current directory and `roots`.
+	pub shard: Option<PathBuf>,
+	/// Also index the normal forms of identifier subtokens, e.g. their stems.
+	pub normalizer: Option<Arc<dyn Normalizer>>,
+}
+
+/// What indexing would do with a file, as listed by `index --dry-run`.
+#[derive(Debug)]
+pub enum Plan {
+	/// The file is new or changed, so it would be indexed.
+	Index,
+	/// The file is indexed and unchanged, so it would be kept as is.
+	Keep,
+	/// The file would be skipped for the given reason, e.g. it is binary.
+	Skip(String),
+	/// The file or directory is ignored, e.g. by a `.gitignore`, so it isn't
+	/// walked at all.
+	Ignore,
+	/// The file is indexed but no longer exists, so it would be removed.
+	Remove,
+}
+
+/// The size and content hash of a file on disk, used to detect changes
+/// that modification times miss.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Fingerprint {
+	hash: [u8; 32],
+	size: u64,
+}
+
+/// The files found by walking the indexed directories.
+struct Scan {
+	/// Each file's path, modification time, and size.
+	files: Vec<(PathBuf, SystemTime, u64)>,
+	/// Whether any file was modified after the index was written.
+	modified: bool,
+	/// Files changed without their modification times passing the index's.
+	stale: HashSet<PathBuf>,
+	/// Files neither indexed nor skipped when the index was written, e.g.
+	/// new files with older modification times, or newly listed files.
+	unknown: HashSet<PathBuf>,
+}
+
+/// A document along with everything extracted from it during indexing.
+struct Document {
+	compressed: bool,
+	fingerprint: Fingerprint,
+	language: Option<Language>,
+	/// The total number of n-grams in the document, counting repeats.
+	length: u32,
+	/// Each unique n-gram in the document and how many times it occurs.
+	ngrams: Vec<(NGram, u32)>,
+	path: PathBuf,
+	symbols: Vec<Symbol>,
+	/// Where the document really is, if it is reached through a symbolic link.
+	target: Option<PathBuf>,
+	tokens: Vec<String>,
 }
 
 /// Represents an indexing error.
 #[derive(Debug)]
 pub enum IndexError {
 	BinaryFile,
+	Corrupt(&'static str),
+	Extract(ExtractError),
+	GeneratedFile,
+	Git(GitError),
 	InvalidHeader,
+	InvalidSection([u8; 4]),
+	Io(std::io::Error),
+	TooLarge,
 	UnsupportedNGramLength(u8),
-	Other(Box<dyn std::error::Error>),
+	Utf8(std::string::FromUtf8Error),
+	Walk(ignore::Error),
 }
 
 impl Display for IndexError {
@@ -37,32 +306,62 @@ impl Display for IndexError {
 				f,
 				"index error: Given file was binary or used an unrecognized encoding"
 			),
+			IndexError::Corrupt(what) => write!(f, "index error: Corrupt {what}"),
+			IndexError::Extract(e) => write!(f, "index error: {e}"),
+			IndexError::GeneratedFile => {
+				write!(f, "index error: Given file was minified or generated")
+			}
+			IndexError::Git(e) => write!(f, "index error: {e}"),
 			IndexError::InvalidHeader => write!(f, "index error: Invalid header"),
+			IndexError::InvalidSection(tag) => write!(
+				f,
+				"index error: Invalid section {}",
+				String::from_utf8_lossy(tag)
+			),
+			IndexError::Io(e) => write!(f, "index error: {e}"),
+			IndexError::TooLarge => write!(
+				f,
+				"index error: Given file was larger than {} MiB",
+				MAX_INDEXED_BYTES >> 20
+			),
 			IndexError::UnsupportedNGramLength(len) => {
 				write!(f, "index error: Invalid n-gram length {len}")
 			}
-			IndexError::Other(e) => write!(f, "index error: {e}"),
+			IndexError::Utf8(e) => write!(f, "index error: {e}"),
+			IndexError::Walk(e) => write!(f, "index error: {e}"),
 		}
 	}
 }
 
 impl Error for IndexError {}
 
+impl From<ExtractError> for IndexError {
+	fn from(value: ExtractError) -> Self {
+		IndexError::Extract(value)
+	}
+}
+
+impl From<GitError> for IndexError {
+	fn from(value: GitError) -> Self {
+		IndexError::Git(value)
+	}
+}
+
 impl From<ignore::Error> for IndexError {
 	fn from(value: ignore::Error) -> Self {
-		IndexError::Other(value.into())
+		IndexError::Walk(value)
 	}
 }
 
 impl From<std::io::Error> for IndexError {
 	fn from(value: std::io::Error) -> Self {
-		IndexError::Other(value.into())
+		IndexError::Io(value)
 	}
 }
 
 impl From<std::string::FromUtf8Error> for IndexError {
 	fn from(value: std::string::FromUtf8Error) -> Self {
-		IndexError::Other(value.into())
+		IndexError::Utf8(value)
 	}
 }
 
@@ -73,90 +372,193 @@ impl Index {
 		(self.document_count as f64 / 8.0).ceil() as u64
 	}
 
-	/// Creates a new index and writes the contents to the file at `path`.
-	pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, IndexError> {
-		// Create a list of files to index
-		let mut files = Vec::new();
-		for res in ignore::Walk::new(".") {
-			match res {
-				Ok(entry) => files.push(entry.path().to_path_buf()),
-				Err(e) => return Err(e.into()),
-			}
+	/// Returns the length in bytes of an n-gram record.
+	fn record_len(&self) -> u64 {
+		if self.sections.contains_key(&POSTING_SECTION) {
+			self.ngram_size as u64 + POSTING_ENTRY_LEN as u64
+		} else {
+			self.ngram_size as u64 + self.bitmap_len()
 		}
+	}
 
-		// Index all files into documents
-		let progress = ProgressBar::new(files.len() as u64 * 2);
-		progress.println("Creating index...");
+	/// Returns the offset of the document table.
+	fn documents_start(&self) -> u64 {
+		HEADER_LEN + self.record_len() * self.ngram_count as u64
+	}
 
-		let mut documents = Vec::with_capacity(files.len());
-		for file in files {
-			progress.inc(1);
-			let trigrams = match index_file(&file) {
-				Ok(v) => v,
-				Err(e) => {
-					progress.println(format!("Failed to index {}: {}", file.to_string_lossy(), e));
-					continue;
-				}
-			};
+	/// Returns the number of documents in this index.
+	pub fn document_count(&self) -> u32 {
+		self.document_count
+	}
 
-			if trigrams.len() == 0 {
-				continue;
-			}
+	/// Returns when the index was last written.
+	pub fn modified(&self) -> SystemTime {
+		self.modified
+	}
 
-			documents.push((file, trigrams));
-		}
+	/// Returns whether this index is in an older format, storing each
+	/// n-gram's bitmap in its record instead of in the `POST` section. It is
+	/// still read, and written in the current format on its next update.
+	pub fn is_legacy_format(&self) -> bool {
+		!self.sections.contains_key(&POSTING_SECTION)
+	}
 
-		// Put all documents into a search index
-		let mut index = HashMap::new();
-		for (i, trigrams) in documents.iter().map(|v| &v.1).enumerate() {
-			for t in trigrams {
-				if !index.contains_key(t) {
-					index.insert(*t, BitMap::new(documents.len()));
-				}
+	/// Returns the length in bytes of the index file.
+	pub fn file_len(&self) -> u64 {
+		self.len
+	}
 
-				index.get_mut(t).unwrap().set(i, true);
-			}
+	/// Returns the size in bytes of the n-grams in this index.
+	pub fn ngram_size(&self) -> u8 {
+		self.ngram_size
+	}
 
-			progress.inc(1);
+	/// Creates a new index and writes the contents to the file at `path`.
+	pub fn create<P: AsRef<Path>>(path: P, options: &IndexOptions) -> Result<Self, IndexError> {
+		let ngram_size = options.ngram_size;
+		if !ngram::is_supported_size(ngram_size) {
+			return Err(IndexError::UnsupportedNGramLength(ngram_size));
 		}
 
-		// Order index by trigram
-		let mut index = index.into_iter().collect::<Vec<([u8; 3], BitMap)>>();
-		index.sort_by(|a, b| a.0.cmp(&b.0));
-
-		progress.finish();
+		options.hooks.pre_update("create", path.as_ref());
+		let (documents, skipped) = index_files(options)?;
 
+		let lock = IndexLock::exclusive(path.as_ref())?;
 		let file = File::options()
 			.create(true)
 			.write(true)
 			.truncate(true)
 			.open(&path)?;
 
+		let changes = Changes {
+			changed: documents.iter().map(|doc| doc.path.clone()).collect(),
+			documents: documents.len() as u32,
+			removed: Vec::new(),
+		};
+
+		write_index(file, ngram_size, documents, &skipped, options.progress)?;
+		drop(lock);
+		options.hooks.post_update("create", path.as_ref(), &changes);
+		Self::load(path)
+	}
+
+	/// Rebuilds the damaged index at `path` with `options`, keeping the
+	/// n-gram size it was built with if its header is intact.
+	pub fn repair<P: AsRef<Path>>(path: P, options: &IndexOptions) -> Result<Self, IndexError> {
+		let options = IndexOptions {
+			ngram_size: read_ngram_size(path.as_ref()).unwrap_or(options.ngram_size),
+			..options.clone()
+		};
+
+		Self::create(path, &options)
+	}
+
+	/// Creates a new index in memory, without writing anything to disk or
+	/// running hooks. It can't be updated.
+	pub fn create_in_memory(options: &IndexOptions) -> Result<Self, IndexError> {
+		let ngram_size = options.ngram_size;
+		if !ngram::is_supported_size(ngram_size) {
+			return Err(IndexError::UnsupportedNGramLength(ngram_size));
+		}
+
+		let (documents, skipped) = index_files(options)?;
+		let mut bytes = Vec::new();
 		write_index(
-			file,
-			documents
-				.into_iter()
-				.map(|v| v.0.as_os_str().to_os_string())
-				.collect(),
-			index,
+			&mut bytes,
+			ngram_size,
+			documents,
+			&skipped,
+			options.progress,
+		)?;
+		let len = bytes.len() as u64;
+		Self::read(
+			Source::Memory(Cursor::new(bytes)),
+			len,
+			SystemTime::now(),
+			None,
+			PathBuf::new(),
 		)
-		.map_err(IndexError::Other)?;
+	}
+
+	/// Creates an index at `path` of `texts` instead of files, each named
+	/// by a path that needn't exist, e.g. the changes of a commit. Empty
+	/// texts are left out.
+	pub fn create_from_texts<P: AsRef<Path>>(
+		path: P,
+		ngram_size: u8,
+		texts: Vec<(PathBuf, String)>,
+		progress: ProgressFormat,
+	) -> Result<Self, IndexError> {
+		if !ngram::is_supported_size(ngram_size) {
+			return Err(IndexError::UnsupportedNGramLength(ngram_size));
+		}
+
+		let documents = texts
+			.into_iter()
+			.filter_map(|(path, text)| {
+				let ngrams = collect_ngrams(&text, ngram_size);
+				if ngrams.is_empty() {
+					return None;
+				}
+
+				Some(Document {
+					compressed: false,
+					fingerprint: Fingerprint::new(text.as_bytes()),
+					language: None,
+					length: ngrams.iter().map(|(_, count)| count).sum(),
+					ngrams,
+					path,
+					symbols: Vec::new(),
+					target: None,
+					tokens: tokenize::tokens(&text, None),
+				})
+			})
+			.collect();
+
+		let lock = IndexLock::exclusive(path.as_ref())?;
+		let file = File::options()
+			.create(true)
+			.write(true)
+			.truncate(true)
+			.open(&path)?;
+
+		write_index(file, ngram_size, documents, &[], progress)?;
+		drop(lock);
 		Self::load(path)
 	}
 
 	/// Loads an index from the file at `path`.
 	pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, IndexError> {
-		let file = File::open(path)?;
+		let lock = IndexLock::shared(path.as_ref())?;
+		let file = File::open(&path)?;
 		let metadata = file.metadata()?;
-		let mut reader = BufReader::new(file);
+		Self::read(
+			Source::File(BufReader::new(file)),
+			metadata.len(),
+			metadata.modified()?,
+			Some(lock),
+			path.as_ref().to_path_buf(),
+		)
+	}
+
+	/// Reads the header and section directory of the index of `len` bytes
+	/// in `source`, last written at `modified`.
+	fn read(
+		mut source: Source,
+		len: u64,
+		modified: SystemTime,
+		lock: Option<IndexLock>,
+		path: PathBuf,
+	) -> Result<Self, IndexError> {
 		let mut header = [0; 12];
-		reader.read_exact(&mut header)?;
+		source.read_exact(&mut header)?;
 		if !header.starts_with(&[0x4b, 0x43, 0x53]) {
 			return Err(IndexError::InvalidHeader);
 		}
 
-		if header[3] != 3 {
-			return Err(IndexError::UnsupportedNGramLength(header[3]));
+		let ngram_size = header[3];
+		if !ngram::is_supported_size(ngram_size) {
+			return Err(IndexError::UnsupportedNGramLength(ngram_size));
 		}
 
 		let mut document_count = [0; 4];
@@ -167,267 +569,2398 @@ impl Index {
 		ngram_count.copy_from_slice(&header[8..12]);
 		let ngram_count = u32::from_be_bytes(ngram_count);
 
-		Ok(Self {
+		let sections = read_sections(&mut source, len)?;
+
+		let index = Self {
+			cache: None,
 			document_count,
-			modified: metadata.modified()?,
+			len,
+			lock,
+			modified,
 			ngram_count,
-			source: reader,
-		})
+			ngram_size,
+			path,
+			scratch: Vec::new(),
+			sections,
+			source,
+		};
+
+		// Every document needs at least its length in the document table
+		if index.documents_start() + document_count as u64 * 4 > index.len {
+			return Err(IndexError::Corrupt("header, or the index is truncated"));
+		}
+
+		Ok(index)
 	}
 
 	/// Indexes any new or changed files, and removes any indexed but deleted files.
-	pub fn update(&mut self) -> Result<(), IndexError> {
-		// Get list of files
-		let mut files = Vec::with_capacity(self.document_count as usize);
-		let mut needs_reindex = false;
-		for res in ignore::Walk::new(".") {
-			let entry = res?;
-			let path = entry.path().to_path_buf();
-			let modified = entry.metadata()?.modified()?;
-			if modified > self.modified {
-				needs_reindex = true;
-			}
-
-			files.push((path, modified));
+	/// Indexes in an older format are rewritten even if no files changed.
+	/// Returns whether the index was rewritten.
+	pub fn update(&mut self, options: &IndexOptions) -> Result<bool, IndexError> {
+		options.hooks.pre_update("update", &self.path);
+
+		let scan = self.scan(options)?;
+		if !scan.modified
+			&& scan.stale.is_empty()
+			&& scan.unknown.is_empty()
+			&& !self.is_legacy_format()
+		{
+			return Ok(false);
 		}
 
-		if !needs_reindex {
-			return Ok(());
-		}
+		self.rewrite(scan, options)?;
+		Ok(true)
+	}
 
-		// Load index into memory
-		let seek_start = HEADER_LEN;
-		self.source.seek(SeekFrom::Start(seek_start))?;
+	/// Reindexes `files` right away, e.g. a file an editor just saved,
+	/// without walking the indexed directories for other changes. Only files
+	/// the index holds or skipped are reindexed, since others may be
+	/// ignored, and those that no longer exist are removed. Returns whether
+	/// the index was rewritten.
+	pub fn reindex(
+		&mut self,
+		files: &[PathBuf],
+		options: &IndexOptions,
+	) -> Result<bool, IndexError> {
+		let known = self
+			.document_paths()?
+			.into_iter()
+			.chain(self.skipped_paths()?)
+			.collect::<HashSet<PathBuf>>();
 
-		let mut index = Vec::with_capacity(self.ngram_count as usize);
-		let mut trigram_buf = [0; 3];
-		let mut bitmap_buf = vec![0; self.bitmap_len() as usize];
-		for _ in 0..self.ngram_count {
-			self.source.read_exact(&mut trigram_buf)?;
-			self.source.read_exact(&mut bitmap_buf)?;
+		let saved = files
+			.iter()
+			.filter(|file| known.contains(*file))
+			.cloned()
+			.collect::<HashSet<PathBuf>>();
 
-			let bitmap = BitMap::from(bitmap_buf.clone());
-			index.push((trigram_buf, bitmap));
+		if saved.is_empty() {
+			return Ok(false);
 		}
 
+		options.hooks.pre_update("update", &self.path);
+
+		// Every other file is kept as it was indexed
+		let modified = self.modified;
+		let files = known
+			.into_iter()
+			.filter(|path| !saved.contains(path) || path.exists())
+			.map(|path| (path, modified, 0))
+			.collect();
+
+		self.rewrite(
+			Scan {
+				files,
+				modified: true,
+				stale: saved,
+				unknown: HashSet::new(),
+			},
+			options,
+		)?;
+		Ok(true)
+	}
+
+	/// Rewrites the index with the changes `scan` found: files it didn't
+	/// find are removed, and its stale, unknown, and modified files are
+	/// reindexed.
+	fn rewrite(&mut self, scan: Scan, options: &IndexOptions) -> Result<(), IndexError> {
+		let Scan {
+			files,
+			stale,
+			unknown,
+			..
+		} = scan;
+
+		// Load index into memory
+		let index = self.postings()?;
+
+		let paths = self.document_paths()?;
+		let mut fingerprints = self.fingerprints()?;
+		let (lengths, counts) = self.frequencies(&index)?;
+		let compressed = self.compressed()?;
+		let mut languages = self.languages()?;
+		let mut symbols = self.symbols()?;
+		let mut tokens = self.tokens()?;
+		let mut targets = self.link_targets()?;
+		let mut skipped = self
+			.skipped_paths()?
+			.into_iter()
+			.collect::<HashSet<PathBuf>>();
 		let mut documents = HashMap::with_capacity(self.document_count as usize);
-		let mut len_buf = [0; 4];
-		for i in 0..self.document_count as usize {
-			self.source.read_exact(&mut len_buf)?;
-			let len = u32::from_be_bytes(len_buf);
-			let mut buf = vec![0; len as usize];
-			self.source.read_exact(&mut buf)?;
+		let mut changes = Changes::default();
 
-			let doc = PathBuf::from(encoding::bytes_to_os_string(buf));
-			if !files.iter().any(|(path, _)| path == &doc) {
+		// Indexes without fingerprints get them from the files on disk
+		let mut missing = Fingerprint::read_all(
+			paths
+				.iter()
+				.zip(&fingerprints)
+				.filter(|(_, fingerprint)| fingerprint.is_none())
+				.map(|(path, _)| path.as_path()),
+		);
+
+		for (i, doc) in paths.into_iter().enumerate() {
+			if !files.iter().any(|(path, _, _)| path == &doc) {
 				// Filter out files if they no longer exist on disk
+				if log::enabled(Level::Verbose) {
+					eprintln!("Removed deleted file {}", doc.display());
+				}
+
+				changes.removed.push(doc);
 				continue;
 			}
 
-			let trigrams = index
+			let ngrams = index
 				.iter()
-				.filter_map(|(tri, bit)| if bit.get(i) { Some(*tri) } else { None })
-				.collect::<Vec<[u8; 3]>>();
-
-			if trigrams.len() == 0 {
-				documents.remove(&doc);
+				.zip(&counts)
+				.filter_map(|((gram, bit), counts)| {
+					if bit.get(i) {
+						Some((*gram, counts.get(&i).copied().unwrap_or(1)))
+					} else {
+						None
+					}
+				})
+				.collect::<Vec<(NGram, u32)>>();
+
+			if ngrams.is_empty() {
 				continue;
 			}
 
-			documents.insert(doc, trigrams);
-		}
-
-		// Reindex updated files
-		let files = files.into_iter().filter_map(|(path, modified)| {
-			if modified > self.modified {
-				Some(path)
-			} else {
-				None
-			}
-		});
+			let fingerprint = match fingerprints[i].take().or_else(|| missing.remove(&doc)) {
+				Some(v) => v,
+				None => continue,
+			};
 
-		for file in files {
-			let trigrams = match index_file(&file) {
-				Ok(v) => v,
-				Err(e) => {
-					eprintln!("Failed to index file {}: {}", file.to_string_lossy(), e);
-					continue;
-				}
+			let document = Document {
+				compressed: compressed.as_ref().is_some_and(|c| c.get(i)),
+				fingerprint,
+				language: languages[i].take(),
+				length: lengths[i],
+				ngrams,
+				path: doc.clone(),
+				symbols: std::mem::take(&mut symbols[i]),
+				target: targets[i].take(),
+				tokens: std::mem::take(&mut tokens[i]),
 			};
 
-			documents.insert(file, trigrams);
+			documents.insert(doc, document);
 		}
 
-		let mut index = HashMap::new();
-		for (i, tris) in documents.iter().map(|(_, trigrams)| trigrams).enumerate() {
-			tris.iter().for_each(|tri| {
-				if !index.contains_key(tri) {
-					index.insert(*tri, BitMap::new(documents.len()));
+		// Reindex updated files
+		let walked = files
+			.iter()
+			.map(|(path, _, _)| path.clone())
+			.collect::<HashSet<PathBuf>>();
+
+		// Files touched but unchanged, e.g. by a fresh checkout of an
+		// imported index, keep their documents
+		let touched = Fingerprint::read_all(
+			files
+				.iter()
+				.filter(|(path, modified, _)| {
+					*modified > self.modified
+						&& documents.contains_key(path)
+						&& !stale.contains(path)
+						&& !unknown.contains(path)
+				})
+				.map(|(path, _, _)| path.as_path()),
+		);
+
+		let files = files
+			.into_iter()
+			.filter_map(|(path, modified, _)| {
+				if path.is_dir() {
+					None
+				} else if stale.contains(&path) || unknown.contains(&path) {
+					Some(path)
+				} else if modified > self.modified {
+					let unchanged = documents
+						.get(&path)
+						.is_some_and(|doc| touched.get(&path) == Some(&doc.fingerprint));
+
+					(!unchanged).then_some(path)
+				} else {
+					None
 				}
-
-				index.get_mut(tri).unwrap().set(i, true);
 			})
+			.collect::<Vec<PathBuf>>();
+
+		if !files.is_empty() {
+			let mut progress = Progress::new(options.progress, "updating", files.len() as u64);
+			index_each(files, self.ngram_size, options, |file, document| {
+				progress.inc(Some(&file));
+				let document = match document {
+					Ok(v) => v,
+					Err(e) => {
+						log_skipped(&progress, &file, &e);
+						skipped.insert(file);
+						return;
+					}
+				};
+
+				if document.ngrams.is_empty() {
+					progress.log(
+						Level::Verbose,
+						format!("Skipped empty file {}", file.display()),
+					);
+					if documents.remove(&file).is_some() {
+						changes.removed.push(file.clone());
+					}
+
+					skipped.insert(file);
+					return;
+				}
+
+				skipped.remove(&file);
+				progress.log(Level::Trace, format!("Reindexed {}", file.display()));
+				changes.changed.push(file.clone());
+				documents.insert(file, document);
+			});
+
+			progress.finish();
 		}
 
-		let mut index = index.into_iter().collect::<Vec<([u8; 3], BitMap)>>();
-		index.sort_by(|a, b| a.0.cmp(&b.0));
+		// Readers share the lock, so release ours before waiting for them
+		self.lock = None;
+		let lock = IndexLock::exclusive(&self.path)?;
+		let out = File::options()
+			.write(true)
+			.truncate(true)
+			.open(&self.path)?;
 
-		let documents = documents
+		// Forget skipped files that are gone, or indexed after all
+		let mut skipped = skipped
 			.into_iter()
-			.map(|(file, _)| file.into_os_string())
-			.collect();
+			.filter(|path| walked.contains(path) && !documents.contains_key(path))
+			.collect::<Vec<PathBuf>>();
 
-		let out = self.source.get_mut();
-		out.seek(SeekFrom::Start(0))?;
-		write_index(out, documents, index).map_err(IndexError::Other)?;
+		skipped.sort();
+		changes.documents = documents.len() as u32;
+		write_index(
+			out,
+			self.ngram_size,
+			documents.into_values().collect(),
+			&skipped,
+			options.progress,
+		)?;
+		drop(lock);
+		options.hooks.post_update("update", &self.path, &changes);
+		*self = Self::load(&self.path)?;
 		Ok(())
 	}
 
-	/// Finds the document with the given index.
-	pub fn find_document(&mut self, document: u32) -> Result<Option<OsString>, IndexError> {
-		let seek_start = HEADER_LEN + (self.bitmap_len() + 3) * self.ngram_count as u64;
-		self.source.seek(SeekFrom::Start(seek_start))?;
-		let mut buf = [0; 4];
-		for _ in 0..document {
-			self.source.read_exact(&mut buf)?;
-			let len = u32::from_be_bytes(buf) as i64;
-			self.source.seek_relative(len)?;
+	/// Returns whether any files changed since the index was written, or it
+	/// is in an older format, so that `update` would rewrite it.
+	pub fn is_outdated(&mut self, options: &IndexOptions) -> Result<bool, IndexError> {
+		if self.is_legacy_format() {
+			return Ok(true);
 		}
 
-		self.source.read_exact(&mut buf)?;
-		let len = u32::from_be_bytes(buf) as usize;
-		let mut buf = vec![0; len];
-		self.source.read_exact(&mut buf)?;
-
-		let document = encoding::bytes_to_os_string(buf);
-		Ok(Some(document))
+		let scan = self.scan(options)?;
+		Ok(scan.modified || !scan.stale.is_empty() || !scan.unknown.is_empty())
 	}
 
-	/// Finds the given trigram and returns its bitmap.
-	pub fn find_trigram(&mut self, trigram: [u8; 3]) -> Result<Option<BitMap>, IndexError> {
-		let skip = self.bitmap_len() + 3;
-		let seek_start = HEADER_LEN;
+	/// Works out what `update` would do with every file, without writing
+	/// anything.
+	pub fn plan_update(
+		&mut self,
+		options: &IndexOptions,
+	) -> Result<Vec<(PathBuf, Plan)>, IndexError> {
+		let Scan { files, stale, .. } = self.scan(options)?;
+		let walked = files
+			.iter()
+			.map(|(path, _, _)| path.clone())
+			.collect::<HashSet<PathBuf>>();
+
+		let indexed = self
+			.document_paths()?
+			.into_iter()
+			.collect::<HashSet<PathBuf>>();
 
-		// Binary search for the right trigram
-		let mut rec_start = 0;
-		let mut rec_end = self.ngram_count;
-		let mut rec = self.ngram_count / 2 + 1;
-		let mut buf = [0; 3];
-		let mut bitmap_buf = vec![0; self.bitmap_len() as usize];
-		while rec > rec_start && rec < rec_end {
-			self.source
-				.seek(SeekFrom::Start(rec as u64 * skip + seek_start))?;
+		let mut plans = indexed
+			.iter()
+			.filter(|path| !walked.contains(*path))
+			.map(|path| (path.clone(), Plan::Remove))
+			.collect::<Vec<(PathBuf, Plan)>>();
 
-			self.source.read_exact(&mut buf)?;
-			match trigram.cmp(&buf) {
-				std::cmp::Ordering::Less => {
-					rec_end = rec;
-					rec = rec_start + (rec_end - rec_start) / 2;
-				}
-				std::cmp::Ordering::Equal => {
-					self.source.read_exact(&mut bitmap_buf)?;
-					return Ok(Some(bitmap_buf.into()));
-				}
-				std::cmp::Ordering::Greater => {
-					rec_start = rec;
-					rec = rec_start + (rec_end - rec_start) / 2 + 1;
-				}
+		let mut changed = Vec::new();
+		for (path, modified, _) in files {
+			if path.is_dir() {
+				continue;
+			}
+
+			if indexed.contains(&path) && modified <= self.modified && !stale.contains(&path) {
+				plans.push((path, Plan::Keep));
+			} else {
+				changed.push(path);
 			}
 		}
 
-		Ok(None)
-	}
-}
+		index_each(changed, self.ngram_size, options, |file, document| {
+			plans.push((file, plan(document)));
+		});
 
-/// Reads the file at `path` and collects all of its trigrams.
-fn index_file(path: &Path) -> Result<Vec<[u8; 3]>, IndexError> {
-	let file = File::open(path)?;
-	let mut reader = BufReader::new(file);
-	let mut buf = [0; 3];
-	let mut trigrams = Vec::new();
-	'read: while let Ok(()) = reader.read_exact(&mut buf) {
-		reader.seek_relative(-2)?;
+		plans.extend(ignored(options)?);
+		plans.sort_by(|a, b| a.0.cmp(&b.0));
+		Ok(plans)
+	}
 
-		if !encoding::is_utf8(&buf) || !encoding::is_printable(&buf) {
-			return Err(IndexError::BinaryFile);
+	/// Walks the indexed directories, finding the files changed since the
+	/// index was written.
+	fn scan(&mut self, options: &IndexOptions) -> Result<Scan, IndexError> {
+		// The files of a commit are as they were when it was indexed
+		if options.revision.is_some() {
+			let modified = self.modified;
+			return Ok(Scan {
+				files: self
+					.document_paths()?
+					.into_iter()
+					.map(|path| (path, modified, 0))
+					.collect(),
+				modified: false,
+				stale: HashSet::new(),
+				unknown: HashSet::new(),
+			});
 		}
 
-		if let Ok(s) = std::str::from_utf8(&buf) {
-			let mut lower = buf;
-			for (i, c) in s.char_indices() {
-				if !c.is_alphanumeric() {
-					continue 'read;
-				}
+		let now = SystemTime::now();
+		let mut files = Vec::with_capacity(self.document_count as usize);
+		let mut needs_reindex = false;
+		let mut future_count = 0;
+		for res in walk(options) {
+			let entry = res?;
+			let path = entry.path().to_path_buf();
+			let metadata = entry.metadata()?;
+			let modified = metadata.modified()?;
+			if modified > self.modified {
+				needs_reindex = true;
+			}
 
-				if c.is_ascii() {
-					lower[i] = buf[i].to_ascii_lowercase();
-				}
+			if modified > now {
+				future_count += 1;
 			}
 
-			let add = !trigrams.contains(&lower);
-			if add {
-				trigrams.push(lower);
+			// Archive entries change when their archive does
+			for (path, len) in expand_archive(path, metadata.len()) {
+				files.push((path, modified, len));
 			}
 		}
-	}
 
-	Ok(trigrams)
-}
+		if future_count > 0 {
+			eprintln!(
+				"Warning: {future_count} files have modification times in the future. \
+				Check the system clock; these files are reindexed on every search."
+			);
+		}
 
-/// Writes an index out to a stream.
-fn write_index<T: Write>(
-	mut out: T,
-	documents: Vec<OsString>,
-	index: Vec<([u8; 3], BitMap)>,
-) -> Result<(), Box<dyn Error>> {
-	assert!(documents.len() <= u32::MAX as usize);
-	let document_count = (documents.len() as u32).to_be_bytes();
+		// Files can appear or disappear without any directory changing, e.g.
+		// when they are listed, so the walk is compared with the index too
+		let walked = files
+			.iter()
+			.map(|(path, _, _)| path)
+			.collect::<HashSet<&PathBuf>>();
 
-	assert!(index.len() <= u32::MAX as usize);
-	let ngram_count = (index.len() as u32).to_be_bytes();
+		let indexed = self.document_paths()?;
+		if indexed.iter().any(|path| !walked.contains(path)) {
+			needs_reindex = true;
+		}
 
-	// Write header
-	let header: [u8; HEADER_LEN as usize] = [
-		// KCS
-		0x4b,
-		0x43,
-		0x53,
-		// ngram size
-		0x03,
-		// document count
-		document_count[0],
-		document_count[1],
-		document_count[2],
-		document_count[3],
-		// ngram count
-		ngram_count[0],
-		ngram_count[1],
-		ngram_count[2],
-		ngram_count[3],
-	];
+		let known = indexed
+			.into_iter()
+			.chain(self.skipped_paths()?)
+			.collect::<HashSet<PathBuf>>();
+
+		let unknown = files
+			.iter()
+			.map(|(path, _, _)| path)
+			.filter(|path| !known.contains(*path) && !path.is_dir())
+			.cloned()
+			.collect::<HashSet<PathBuf>>();
+
+		// Find files changed without a newer modification time, e.g. by a
+		// checkout that preserved modification times
+		let stale = self.find_stale(&files)?;
+		Ok(Scan {
+			files,
+			modified: needs_reindex,
+			stale,
+			unknown,
+		})
+	}
 
-	out.write_all(&header)?;
+	/// Finds indexed files whose contents changed without their modification
+	/// times passing the index's. Sizes are compared first, and if any differ,
+	/// the contents of every such file are hashed and compared.
+	fn find_stale(
+		&mut self,
+		files: &[(PathBuf, SystemTime, u64)],
+	) -> Result<HashSet<PathBuf>, IndexError> {
+		let mut stale = HashSet::new();
+		if !self.sections.contains_key(&FINGERPRINT_SECTION) {
+			return Ok(stale);
+		}
 
-	// Write index
-	let progress = ProgressBar::new((index.len() + documents.len()) as u64);
-	progress.println("Writing index...");
+		let fingerprints = self
+			.document_paths()?
+			.into_iter()
+			.zip(self.fingerprints()?)
+			.filter_map(|(path, fingerprint)| fingerprint.map(|f| (path, f)))
+			.collect::<HashMap<PathBuf, Fingerprint>>();
+
+		let unchanged = files
+			.iter()
+			.filter(|(_, modified, _)| *modified <= self.modified)
+			.filter_map(|(path, _, size)| fingerprints.get(path).map(|f| (path, *size, f)))
+			.collect::<Vec<_>>();
+
+		let resized = unchanged
+			.iter()
+			.filter(|(_, size, fingerprint)| *size != fingerprint.size)
+			.count();
+
+		if resized == 0 {
+			return Ok(stale);
+		}
+
+		eprintln!(
+			"Warning: {resized} files changed without their modification times changing, \
+			e.g. after a checkout that preserved them. Verifying file contents..."
+		);
+
+		let read = Fingerprint::read_all(unchanged.iter().map(|(path, _, _)| path.as_path()));
+		for (path, _, fingerprint) in unchanged {
+			if read.get(path) != Some(fingerprint) {
+				stale.insert(path.clone());
+			}
+		}
 
-	for (trigram, bitmap) in index {
-		out.write_all(&trigram)?;
-		out.write_all(&bitmap.as_bytes())?;
-		progress.inc(1);
+		Ok(stale)
 	}
 
-	// Write documents
-	for doc in documents {
-		let doc = encoding::os_str_to_bytes(&doc);
-		let len = (doc.len() as u32).to_be_bytes();
-		out.write_all(&len)?;
+	/// Returns the path of every document, in document order.
+	pub fn document_paths(&mut self) -> Result<Vec<PathBuf>, IndexError> {
+		self.source.seek(SeekFrom::Start(self.documents_start()))?;
+
+		let mut paths = Vec::with_capacity(self.document_count as usize);
+		let mut len_buf = [0; 4];
+		for _ in 0..self.document_count {
+			self.source.read_exact(&mut len_buf)?;
+			let len = u32::from_be_bytes(len_buf);
+			if len as u64 > self.len {
+				return Err(IndexError::Corrupt("document table"));
+			}
+
+			let mut buf = vec![0; len as usize];
+			self.source.read_exact(&mut buf)?;
+			paths.push(PathBuf::from(encoding::bytes_to_os_string(buf)));
+		}
+
+		Ok(paths)
+	}
+
+	/// Returns the fingerprint of every document, in document order.
+	/// Indexes without a fingerprint section have none.
+	fn fingerprints(&mut self) -> Result<Vec<Option<Fingerprint>>, IndexError> {
+		let mut fingerprints = vec![None; self.document_count as usize];
+		let section = match self.read_section(FINGERPRINT_SECTION)? {
+			Some(v) => v,
+			None => return Ok(fingerprints),
+		};
+
+		if section.len() != fingerprints.len() * FINGERPRINT_LEN {
+			return Err(IndexError::InvalidSection(FINGERPRINT_SECTION));
+		}
+
+		for (fingerprint, entry) in fingerprints
+			.iter_mut()
+			.zip(section.chunks_exact(FINGERPRINT_LEN))
+		{
+			*fingerprint = Some(Fingerprint {
+				hash: entry[8..].try_into().unwrap(),
+				size: u64::from_be_bytes(entry[..8].try_into().unwrap()),
+			});
+		}
+
+		Ok(fingerprints)
+	}
+
+	/// Keeps the bitmaps and paths this index looks up in `cache`, and
+	/// looks them up there first. The cache is emptied if it held parts of
+	/// an earlier version of the index.
+	pub fn use_cache(&mut self, cache: Arc<IndexCache>) {
+		let generation = Some((self.modified, self.len));
+		let mut cached = cache.generation.lock().expect("cache lock poisoned");
+		if *cached != generation {
+			cache.ngrams.lock().expect("cache lock poisoned").clear();
+			cache.documents.lock().expect("cache lock poisoned").clear();
+			*cached = generation;
+		}
+
+		drop(cached);
+		self.cache = Some(cache);
+	}
+
+	/// Finds the document with the given index.
+	pub fn find_document(&mut self, document: u32) -> Result<Option<OsString>, IndexError> {
+		if let Some(cache) = &self.cache {
+			let mut documents = cache.documents.lock().expect("cache lock poisoned");
+			if let Some(path) = documents.get(&document) {
+				return Ok(Some(path.clone()));
+			}
+		}
+
+		self.source.seek(SeekFrom::Start(self.documents_start()))?;
+		let mut buf = [0; 4];
+		for _ in 0..document {
+			self.source.read_exact(&mut buf)?;
+			let len = u32::from_be_bytes(buf) as i64;
+			self.source.seek_relative(len)?;
+		}
+
+		self.source.read_exact(&mut buf)?;
+		let len = u32::from_be_bytes(buf) as usize;
+		let mut buf = vec![0; len];
+		self.source.read_exact(&mut buf)?;
+
+		let path = encoding::bytes_to_os_string(buf);
+		if let Some(cache) = &self.cache {
+			let mut documents = cache.documents.lock().expect("cache lock poisoned");
+			documents.insert(document, path.clone());
+		}
+
+		Ok(Some(path))
+	}
+
+	/// Finds the given n-gram and returns a view of its bitmap. The bitmap is
+	/// read into a buffer reused across lookups, so combining the postings of
+	/// many n-grams doesn't allocate a bitmap for each.
+	pub fn find_ngram_ref(&mut self, ngram: NGram) -> Result<Option<BitMapRef<'_>>, IndexError> {
+		let document_count = self.document_count as usize;
+		Ok(self
+			.find_ngram_bytes(ngram)?
+			.map(|_| BitMapRef::new(&self.scratch, document_count)))
+	}
+
+	/// Finds the given n-gram and returns its record number and bitmap.
+	fn find_ngram_record(&mut self, ngram: NGram) -> Result<Option<(u32, BitMap)>, IndexError> {
+		let document_count = self.document_count as usize;
+		Ok(self
+			.find_ngram_bytes(ngram)?
+			.map(|rec| (rec, BitMap::from_be_bytes(&self.scratch, document_count))))
+	}
+
+	/// Finds the given n-gram, reads the on-disk form of its bitmap into the
+	/// scratch buffer, and returns its record number.
+	fn find_ngram_bytes(&mut self, ngram: NGram) -> Result<Option<u32>, IndexError> {
+		if ngram.as_bytes().len() != self.ngram_size as usize {
+			return Ok(None);
+		}
+
+		if let Some(cache) = &self.cache {
+			let mut ngrams = cache.ngrams.lock().expect("cache lock poisoned");
+			if let Some((rec, bitmap)) = ngrams.get(&ngram) {
+				// Copying into the scratch buffer reuses its allocation
+				self.scratch.clear();
+				self.scratch.extend_from_slice(bitmap);
+				return Ok(Some(*rec));
+			}
+		}
+
+		let rec = match self.find_record(ngram)? {
+			Some(v) => v,
+			None => return Ok(None),
+		};
+
+		self.read_record_bitmap()?;
+		if let Some(cache) = &self.cache {
+			let mut ngrams = cache.ngrams.lock().expect("cache lock poisoned");
+			ngrams.insert(ngram, (rec, self.scratch.clone()));
+		}
+
+		Ok(Some(rec))
+	}
+
+	/// Finds the record of the given n-gram, leaving the source positioned
+	/// just after its n-gram, and returns its record number.
+	fn find_record(&mut self, ngram: NGram) -> Result<Option<u32>, IndexError> {
+		let skip = self.record_len();
+		let seek_start = HEADER_LEN;
+
+		// Binary search for the right n-gram
+		let mut rec_start = 0;
+		let mut rec_end = self.ngram_count;
+		let mut buf = vec![0; self.ngram_size as usize];
+		while rec_start < rec_end {
+			let rec = rec_start + (rec_end - rec_start) / 2;
+			self.source
+				.seek(SeekFrom::Start(rec as u64 * skip + seek_start))?;
+
+			self.source.read_exact(&mut buf)?;
+			match ngram.as_bytes().cmp(&buf) {
+				std::cmp::Ordering::Less => rec_end = rec,
+				std::cmp::Ordering::Equal => return Ok(Some(rec)),
+				std::cmp::Ordering::Greater => rec_start = rec + 1,
+			}
+		}
+
+		Ok(None)
+	}
+
+	/// Returns the number of documents containing the given n-gram, or
+	/// `None` if it isn't indexed. Indexes without a document frequency
+	/// section count the documents in its bitmap instead.
+	pub fn ngram_document_frequency(&mut self, ngram: NGram) -> Result<Option<u32>, IndexError> {
+		if !self.sections.contains_key(&DOCUMENT_FREQUENCY_SECTION) {
+			return Ok(self
+				.find_ngram_ref(ngram)?
+				.map(|v| v.iter_ones().count() as u32));
+		}
+
+		if ngram.as_bytes().len() != self.ngram_size as usize {
+			return Ok(None);
+		}
+
+		let rec = match self.find_record(ngram)? {
+			Some(v) => v,
+			None => return Ok(None),
+		};
+
+		Ok(self
+			.read_section_range(DOCUMENT_FREQUENCY_SECTION, rec as u64 * 4, 4)?
+			.map(|v| read_u32s(&v)[0]))
+	}
+
+	/// Reads the on-disk form of the bitmap of the n-gram record just read
+	/// into the scratch buffer.
+	fn read_record_bitmap(&mut self) -> Result<(), IndexError> {
+		let (section_offset, section_len) = match self.sections.get(&POSTING_SECTION) {
+			Some(v) => *v,
+			None => {
+				self.scratch.resize(self.bitmap_len() as usize, 0);
+				self.source.read_exact(&mut self.scratch)?;
+				return Ok(());
+			}
+		};
+
+		let mut entry = [0; POSTING_ENTRY_LEN];
+		self.source.read_exact(&mut entry)?;
+		let (offset, len) = read_posting_entry(&entry);
+		if offset + len > section_len {
+			return Err(IndexError::InvalidSection(POSTING_SECTION));
+		}
+
+		self.source.seek(SeekFrom::Start(section_offset + offset))?;
+		self.scratch.resize(len as usize, 0);
+		self.source.read_exact(&mut self.scratch)?;
+		match self.scratch.first() {
+			Some(&POSTING_RAW) => {
+				self.scratch.remove(0);
+			}
+			_ => {
+				let bitmap = decode_posting(&self.scratch, self.document_count as usize)?;
+				self.scratch = bitmap.to_bytes();
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Returns every n-gram record with its bitmap, ordered by n-gram.
+	fn postings(&mut self) -> Result<Vec<(NGram, BitMap)>, IndexError> {
+		let record_len = self.record_len() as usize;
+		let ngram_size = self.ngram_size as usize;
+		let mut records = vec![0; record_len * self.ngram_count as usize];
+		self.source.seek(SeekFrom::Start(HEADER_LEN))?;
+		self.source.read_exact(&mut records)?;
+
+		let section = self.read_section(POSTING_SECTION)?;
+		let document_count = self.document_count as usize;
+		let mut postings = Vec::with_capacity(self.ngram_count as usize);
+		for record in records.chunks_exact(record_len) {
+			let (ngram, entry) = record.split_at(ngram_size);
+			let bitmap = match &section {
+				Some(section) => {
+					let (offset, len) = read_posting_entry(entry);
+					let posting = section
+						.get(offset as usize..(offset + len) as usize)
+						.ok_or(IndexError::InvalidSection(POSTING_SECTION))?;
+
+					decode_posting(posting, document_count)?
+				}
+				None => BitMap::from_be_bytes(entry, document_count),
+			};
+
+			postings.push((NGram::new(ngram), bitmap));
+		}
+
+		Ok(postings)
+	}
+
+	/// Returns each n-gram in this index with the number of documents
+	/// containing it, in n-gram order.
+	pub fn ngram_document_counts(&mut self) -> Result<Vec<(NGram, usize)>, IndexError> {
+		Ok(self
+			.postings()?
+			.into_iter()
+			.map(|(ngram, bitmap)| (ngram, bitmap.count_ones()))
+			.collect())
+	}
+
+	/// Returns the length in bytes of each part of the index file: the
+	/// header, the n-gram records, and each section, by tag. The rest of the
+	/// file holds the document table and the section directory.
+	pub fn part_lengths(&self) -> Vec<(String, u64)> {
+		let mut parts = vec![
+			(String::from("header"), HEADER_LEN),
+			(
+				String::from("records"),
+				self.record_len() * self.ngram_count as u64,
+			),
+		];
+
+		let mut sections = self
+			.sections
+			.iter()
+			.map(|(tag, (_, len))| (String::from_utf8_lossy(tag).into_owned(), *len))
+			.collect::<Vec<(String, u64)>>();
+
+		sections.sort();
+		parts.append(&mut sections);
+		let rest = self
+			.len
+			.saturating_sub(parts.iter().map(|(_, len)| len).sum());
+
+		parts.push((String::from("documents"), rest));
+		parts
+	}
+
+	/// Returns the size on disk of each document when it was indexed, or
+	/// `None` if this index has no fingerprints.
+	pub fn document_sizes(&mut self) -> Result<Option<Vec<u64>>, IndexError> {
+		Ok(self
+			.fingerprints()?
+			.into_iter()
+			.map(|f| f.map(|f| f.size))
+			.collect())
+	}
+
+	/// Checks the structure and checksums of this index without changing it.
+	pub fn verify(&mut self) -> Verification {
+		type Check = fn(&mut Index) -> Result<(), IndexError>;
+		let checks: [(&str, Check); 8] = [
+			("n-gram records", |i| {
+				let postings = i.postings()?;
+				if postings.windows(2).any(|w| w[0].0 >= w[1].0) {
+					return Err(IndexError::Corrupt("n-gram order"));
+				}
+
+				i.frequencies(&postings).map(drop)
+			}),
+			("document table", |i| i.document_paths().map(drop)),
+			("compressed documents", |i| i.compressed().map(drop)),
+			("fingerprints", |i| i.fingerprints().map(drop)),
+			("frequencies", |i| i.document_lengths().map(drop)),
+			("languages", |i| i.languages().map(drop)),
+			("symbols", |i| i.symbols().map(drop)),
+			("tokens", |i| i.tokens().map(drop)),
+		];
+
+		let mut verification = Verification::default();
+		for (name, check) in checks {
+			if let Err(e) = check(self) {
+				verification.problems.push(format!("{name}: {e}"));
+			}
+		}
+
+		match self.verify_checksums() {
+			Ok(Some(problems)) => {
+				verification.checksummed = true;
+				verification.problems.extend(problems);
+			}
+			Ok(None) => {}
+			Err(e) => verification.problems.push(format!("checksums: {e}")),
+		}
+
+		verification
+	}
+
+	/// Compares the checksums in the checksum section against the contents of
+	/// the index, returning a description of each mismatch. Returns `None` if
+	/// this index has no checksum section.
+	fn verify_checksums(&mut self) -> Result<Option<Vec<String>>, IndexError> {
+		let sums = match self.read_section(CHECKSUM_SECTION)? {
+			Some(v) => v,
+			None => return Ok(None),
+		};
+
+		let entry_len = 4 + CHECKSUM_LEN;
+		if sums.len() < CHECKSUM_LEN || !(sums.len() - CHECKSUM_LEN).is_multiple_of(entry_len) {
+			return Err(IndexError::InvalidSection(CHECKSUM_SECTION));
+		}
+
+		let mut problems = Vec::new();
+		for entry in sums[CHECKSUM_LEN..].chunks_exact(entry_len) {
+			let tag: [u8; 4] = entry[..4].try_into().unwrap();
+			let name = String::from_utf8_lossy(&tag).into_owned();
+			match self.read_section(tag)? {
+				Some(data) if hmac_sha256::Hash::hash(&data) == entry[4..] => {}
+				Some(_) => problems.push(format!("section {name} does not match its checksum")),
+				None => problems.push(format!("section {name} is missing")),
+			}
+		}
+
+		// Hash everything before the checksum section
+		let (end, _) = self.sections[&CHECKSUM_SECTION];
+		let mut hash = hmac_sha256::Hash::new();
+		let mut buf = vec![0; 64 * 1024];
+		let mut pos = 0;
+		self.source.seek(SeekFrom::Start(0))?;
+		while pos < end {
+			let len = usize::min(buf.len(), (end - pos) as usize);
+			self.source.read_exact(&mut buf[..len])?;
+			hash.update(&buf[..len]);
+			pos += len as u64;
+		}
+
+		// A changed section also changes the file checksum, so only report
+		// the file checksum when no section explains it
+		if hash.finalize() != sums[..CHECKSUM_LEN] && problems.is_empty() {
+			problems.push(String::from(
+				"the header, n-gram records, or document table do not match their checksum",
+			));
+		}
+
+		Ok(Some(problems))
+	}
+
+	/// Reads the raw contents of the section tagged `tag`, if this index has one.
+	pub fn read_section(&mut self, tag: [u8; 4]) -> Result<Option<Vec<u8>>, IndexError> {
+		let len = match self.sections.get(&tag) {
+			Some((_, len)) => *len,
+			None => return Ok(None),
+		};
+
+		self.read_section_range(tag, 0, len)
+	}
+
+	/// Reads `len` bytes starting `start` bytes into the section tagged `tag`,
+	/// if this index has one.
+	fn read_section_range(
+		&mut self,
+		tag: [u8; 4],
+		start: u64,
+		len: u64,
+	) -> Result<Option<Vec<u8>>, IndexError> {
+		let (offset, section_len) = match self.sections.get(&tag) {
+			Some(v) => *v,
+			None => return Ok(None),
+		};
+
+		if start + len > section_len {
+			return Err(IndexError::InvalidSection(tag));
+		}
+
+		self.source.seek(SeekFrom::Start(offset + start))?;
+		let mut buf = vec![0; len as usize];
+		self.source.read_exact(&mut buf)?;
+		Ok(Some(buf))
+	}
+
+	/// Returns the total number of n-grams in every document, counting repeats.
+	/// Returns `None` if this index has no frequency section.
+	pub fn document_lengths(&mut self) -> Result<Option<Vec<u32>>, IndexError> {
+		let len = self.document_count as u64 * 4;
+		let section = match self.read_section_range(FREQUENCY_SECTION, 0, len)? {
+			Some(v) => v,
+			None => return Ok(None),
+		};
+
+		Ok(Some(read_u32s(&section)))
+	}
+
+	/// Finds the given n-gram and returns how many times it occurs in every
+	/// document. Returns `None` if the n-gram isn't indexed or this index has
+	/// no frequency section.
+	pub fn ngram_frequencies(&mut self, ngram: NGram) -> Result<Option<Vec<u32>>, IndexError> {
+		if !self.sections.contains_key(&FREQUENCY_SECTION) {
+			return Ok(None);
+		}
+
+		let (rec, bitmap) = match self.find_ngram_record(ngram)? {
+			Some(v) => v,
+			None => return Ok(None),
+		};
+
+		let offsets_start = self.document_count as u64 * 4;
+		let counts_start = offsets_start + self.ngram_count as u64 * 4;
+		let offset = self
+			.read_section_range(FREQUENCY_SECTION, offsets_start + rec as u64 * 4, 4)?
+			.map(|v| read_u32s(&v)[0] as u64)
+			.unwrap_or(0);
+
+		let counts = self
+			.read_section_range(
+				FREQUENCY_SECTION,
+				counts_start + offset * 2,
+				bitmap.count_ones() as u64 * 2,
+			)?
+			.unwrap_or_default();
+
+		let counts = counts
+			.chunks_exact(2)
+			.map(|c| u16::from_be_bytes([c[0], c[1]]) as u32);
+
+		// Counts are stored in document order, so a document's count is at
+		// its rank among the documents containing the n-gram
+		let counts = counts.collect::<Vec<u32>>();
+		let ranks = bitmap.rank_select();
+		let mut frequencies = vec![0; self.document_count as usize];
+		for (doc_id, frequency) in frequencies.iter_mut().enumerate() {
+			if bitmap.get(doc_id) {
+				*frequency = counts.get(ranks.rank(doc_id)).copied().unwrap_or(1);
+			}
+		}
+
+		Ok(Some(frequencies))
+	}
+
+	/// Returns the length of every document and, for each n-gram posting in
+	/// `postings`, the occurrence counts keyed by document. Indexes without a
+	/// frequency section count every n-gram once.
+	fn frequencies(
+		&mut self,
+		postings: &[(NGram, BitMap)],
+	) -> Result<(Vec<u32>, Vec<PostingCounts>), IndexError> {
+		let section = match self.read_section(FREQUENCY_SECTION)? {
+			Some(v) => v,
+			None => {
+				let lengths = (0..self.document_count as usize)
+					.map(|i| postings.iter().filter(|(_, bit)| bit.get(i)).count() as u32)
+					.collect();
+
+				return Ok((lengths, vec![HashMap::new(); postings.len()]));
+			}
+		};
+
+		let document_count = self.document_count as usize;
+		let counts_start = (document_count + postings.len()) * 4;
+		if section.len() < counts_start {
+			return Err(IndexError::InvalidSection(FREQUENCY_SECTION));
+		}
+
+		let lengths = read_u32s(&section[..document_count * 4]);
+		let offsets = read_u32s(&section[document_count * 4..counts_start]);
+		let mut counts = Vec::with_capacity(postings.len());
+		for ((_, bitmap), offset) in postings.iter().zip(offsets) {
+			let mut pos = counts_start + offset as usize * 2;
+			let mut doc_counts = HashMap::new();
+			for doc_id in bitmap.iter_ones() {
+				let count = section
+					.get(pos..pos + 2)
+					.ok_or(IndexError::InvalidSection(FREQUENCY_SECTION))?;
+
+				doc_counts.insert(doc_id, u16::from_be_bytes([count[0], count[1]]) as u32);
+				pos += 2;
+			}
+
+			counts.push(doc_counts);
+		}
+
+		Ok((lengths, counts))
+	}
+
+	/// Returns the bitmap of documents stored gzip-compressed on disk.
+	/// Returns `None` if this index has no compressed documents.
+	pub fn compressed(&mut self) -> Result<Option<BitMap>, IndexError> {
+		let document_count = self.document_count as usize;
+		Ok(self
+			.read_section(COMPRESSED_SECTION)?
+			.map(|bytes| BitMap::from_be_bytes(&bytes, document_count)))
+	}
+
+	/// Returns the bitmap of documents in the directory `dir` or any directory
+	/// in it. Returns `None` if this index has no directory section, or no
+	/// documents in `dir`, e.g. because it is a file.
+	pub fn directory_bitmap(&mut self, dir: &Path) -> Result<Option<BitMap>, IndexError> {
+		let section = match self.read_section(DIRECTORY_SECTION)? {
+			Some(v) => v,
+			None => return Ok(None),
+		};
+
+		let invalid = || IndexError::InvalidSection(DIRECTORY_SECTION);
+		let dir = encoding::os_str_to_bytes(directory_key(dir).as_os_str());
+		let mut pos = 0;
+		while pos < section.len() {
+			let path_len = read_len(&section, pos).ok_or_else(invalid)?;
+			let path = section
+				.get(pos + 4..pos + 4 + path_len)
+				.ok_or_else(invalid)?;
+
+			pos += 4 + path_len;
+			let posting_len = read_len(&section, pos).ok_or_else(invalid)?;
+			let posting = section
+				.get(pos + 4..pos + 4 + posting_len)
+				.ok_or_else(invalid)?;
+
+			if path == &dir[..] {
+				return decode_posting(posting, self.document_count as usize)
+					.map(Some)
+					.map_err(|_| invalid());
+			}
+
+			pos += 4 + posting_len;
+		}
+
+		Ok(None)
+	}
+
+	/// Returns the bitmap of documents in the given language class.
+	/// Returns `None` if this index has no language section.
+	pub fn language_bitmap(&mut self, language: Language) -> Result<Option<BitMap>, IndexError> {
+		let section = match self.read_section(LANGUAGE_SECTION)? {
+			Some(v) => v,
+			None => return Ok(None),
+		};
+
+		let bitmap_len = self.bitmap_len() as usize;
+		for entry in section.chunks(bitmap_len + 1) {
+			if entry.len() != bitmap_len + 1 {
+				return Err(IndexError::InvalidSection(LANGUAGE_SECTION));
+			}
+
+			if entry[0] == language.id() {
+				return Ok(Some(BitMap::from_be_bytes(
+					&entry[1..],
+					self.document_count as usize,
+				)));
+			}
+		}
+
+		Ok(Some(BitMap::new(self.document_count as usize)))
+	}
+
+	/// Returns the language class of every document, in document order.
+	pub fn languages(&mut self) -> Result<Vec<Option<Language>>, IndexError> {
+		let mut languages = vec![None; self.document_count as usize];
+		let section = match self.read_section(LANGUAGE_SECTION)? {
+			Some(v) => v,
+			None => return Ok(languages),
+		};
+
+		let bitmap_len = self.bitmap_len() as usize;
+		for entry in section.chunks(bitmap_len + 1) {
+			let language = Language::from_id(entry[0])
+				.filter(|_| entry.len() == bitmap_len + 1)
+				.ok_or(IndexError::InvalidSection(LANGUAGE_SECTION))?;
+
+			let bitmap = BitMap::from_be_bytes(&entry[1..], self.document_count as usize);
+			for doc_id in bitmap.iter_ones() {
+				languages[doc_id] = Some(language);
+			}
+		}
+
+		Ok(languages)
+	}
+
+	/// Returns the target of every document reached through a symbolic link,
+	/// in document order. Every target is `None` if this index has no link
+	/// section.
+	pub fn link_targets(&mut self) -> Result<Vec<Option<PathBuf>>, IndexError> {
+		let section = match self.read_section(LINK_SECTION)? {
+			Some(v) => v,
+			None => return Ok(vec![None; self.document_count as usize]),
+		};
+
+		let targets = decode_paths(&section, LINK_SECTION)?;
+		if targets.len() != self.document_count as usize {
+			return Err(IndexError::InvalidSection(LINK_SECTION));
+		}
+
+		Ok(targets
+			.into_iter()
+			.map(|target| Some(target).filter(|t| !t.as_os_str().is_empty()))
+			.collect())
+	}
+
+	/// Returns the paths of the files walked but not indexed when the index
+	/// was written. Indexes without a skipped files section have none.
+	pub fn skipped_paths(&mut self) -> Result<Vec<PathBuf>, IndexError> {
+		match self.read_section(SKIPPED_SECTION)? {
+			Some(section) => decode_paths(&section, SKIPPED_SECTION),
+			None => Ok(Vec::new()),
+		}
+	}
+
+	/// Finds the given identifier subtoken and returns its bitmap.
+	pub fn find_token(&mut self, token: &str) -> Result<Option<BitMap>, IndexError> {
+		let bitmap_len = self.bitmap_len() as usize;
+		let section = match self.read_section(TOKEN_SECTION)? {
+			Some(v) => v,
+			None => return Ok(None),
+		};
+
+		let mut pos = 0;
+		while pos < section.len() {
+			let len = section[pos] as usize;
+			let entry = section
+				.get(pos + 1..pos + 1 + len + bitmap_len)
+				.ok_or(IndexError::InvalidSection(TOKEN_SECTION))?;
+
+			if &entry[..len] == token.as_bytes() {
+				return Ok(Some(BitMap::from_be_bytes(
+					&entry[len..],
+					self.document_count as usize,
+				)));
+			}
+
+			pos += 1 + len + bitmap_len;
+		}
+
+		Ok(None)
+	}
+
+	/// Returns the identifier subtokens of every document, in document order.
+	pub fn tokens(&mut self) -> Result<Vec<Vec<String>>, IndexError> {
+		let bitmap_len = self.bitmap_len() as usize;
+		let mut tokens = vec![Vec::new(); self.document_count as usize];
+		let section = match self.read_section(TOKEN_SECTION)? {
+			Some(v) => v,
+			None => return Ok(tokens),
+		};
+
+		let mut pos = 0;
+		while pos < section.len() {
+			let len = section[pos] as usize;
+			let entry = section
+				.get(pos + 1..pos + 1 + len + bitmap_len)
+				.ok_or(IndexError::InvalidSection(TOKEN_SECTION))?;
+
+			let token = String::from_utf8(entry[..len].to_vec())?;
+			let bitmap = BitMap::from_be_bytes(&entry[len..], self.document_count as usize);
+			for doc_id in bitmap.iter_ones() {
+				tokens[doc_id].push(token.clone());
+			}
+
+			pos += 1 + len + bitmap_len;
+		}
+
+		Ok(tokens)
+	}
+
+	/// Returns the symbol definitions of every document, in document order.
+	/// Indexes built without symbol support have no definitions.
+	pub fn symbols(&mut self) -> Result<Vec<Vec<Symbol>>, IndexError> {
+		let mut symbols = vec![Vec::new(); self.document_count as usize];
+		let section = match self.read_section(SYMBOL_SECTION)? {
+			Some(v) => v,
+			None => return Ok(symbols),
+		};
+
+		let mut pos = 0;
+		for doc_symbols in symbols.iter_mut() {
+			let count = section
+				.get(pos..pos + 4)
+				.ok_or(IndexError::InvalidSection(SYMBOL_SECTION))?;
+
+			let count = u32::from_be_bytes(count.try_into().unwrap());
+			pos += 4;
+			for _ in 0..count {
+				let (symbol, len) = Symbol::read_from(&section[pos..])
+					.ok_or(IndexError::InvalidSection(SYMBOL_SECTION))?;
+
+				doc_symbols.push(symbol);
+				pos += len;
+			}
+		}
+
+		Ok(symbols)
+	}
+}
+
+impl Fingerprint {
+	/// Fingerprints the file contents `bytes`.
+	fn new(bytes: &[u8]) -> Self {
+		Self {
+			hash: hmac_sha256::Hash::hash(bytes),
+			size: bytes.len() as u64,
+		}
+	}
+
+	/// Fingerprints the file on disk at `path`.
+	fn read(path: &Path) -> std::io::Result<Self> {
+		Self::from_reader(File::open(path)?)
+	}
+
+	/// Fingerprints each of `paths`, reading each archive in one pass for
+	/// all of its entries. Files that can't be read are left out.
+	fn read_all<'a, I: IntoIterator<Item = &'a Path>>(paths: I) -> HashMap<PathBuf, Self> {
+		let mut fingerprints = HashMap::new();
+		let mut archives = HashMap::<PathBuf, HashSet<PathBuf>>::new();
+		for path in paths {
+			match archive::split(path) {
+				Some((archive, _)) => {
+					archives
+						.entry(archive)
+						.or_default()
+						.insert(path.to_path_buf());
+				}
+				None => {
+					if let Ok(v) = Self::read(path) {
+						fingerprints.insert(path.to_path_buf(), v);
+					}
+				}
+			}
+		}
+
+		for (archive, entries) in archives {
+			let _ = archive::for_each_entry(&archive, |entry, reader| {
+				if entries.contains(&entry) {
+					fingerprints.insert(entry, Self::from_reader(reader)?);
+				}
+
+				Ok(())
+			});
+		}
+
+		fingerprints
+	}
+
+	/// Fingerprints the contents of `reader`, hashing them as they are read
+	/// rather than reading them whole.
+	fn from_reader<R: Read>(reader: R) -> std::io::Result<Self> {
+		let mut reader = BufReader::new(reader);
+		let mut hash = hmac_sha256::Hash::new();
+		let mut size = 0;
+		loop {
+			let buf = reader.fill_buf()?;
+			if buf.is_empty() {
+				break;
+			}
+
+			hash.update(buf);
+			size += buf.len() as u64;
+			let len = buf.len();
+			reader.consume(len);
+		}
+
+		Ok(Self {
+			hash: hash.finalize(),
+			size,
+		})
+	}
+}
+
+/// Walks the indexed directories, or reads the listed files, and indexes
+/// each file into a document. Returns the documents and the paths of the
+/// files skipped, sorted.
+fn index_files(options: &IndexOptions) -> Result<(Vec<Document>, Vec<PathBuf>), IndexError> {
+	let ngram_size = options.ngram_size;
+
+	// Create a list of files to index
+	let mut files = Vec::new();
+	if options.revision.is_some() {
+		files = options.files.clone().unwrap_or_default();
+	} else {
+		for res in walk(options) {
+			match res {
+				Ok(entry) if entry.path().is_dir() => {}
+				Ok(entry) => {
+					let expanded = expand_archive(entry.path().to_path_buf(), 0);
+					files.extend(expanded.into_iter().map(|(path, _)| path));
+				}
+				Err(e) => return Err(e.into()),
+			}
+		}
+	}
+
+	// Index all files into documents
+	let mut progress = Progress::new(options.progress, "indexing", files.len() as u64);
+	progress.println("Creating index...");
+
+	let mut documents = Vec::with_capacity(files.len());
+	let mut skipped = Vec::new();
+	index_each(files, ngram_size, options, |file, document| {
+		progress.inc(Some(&file));
+		let document = match document {
+			Ok(v) => v,
+			Err(e) => {
+				log_skipped(&progress, &file, &e);
+				skipped.push(file);
+				return;
+			}
+		};
+
+		if document.ngrams.is_empty() {
+			progress.log(
+				Level::Verbose,
+				format!("Skipped empty file {}", file.display()),
+			);
+			skipped.push(file);
+			return;
+		}
+
+		progress.log(Level::Trace, format!("Indexed {}", file.display()));
+		documents.push(document);
+	});
+
+	progress.finish();
+	skipped.sort();
+	Ok((documents, skipped))
+}
+
+/// Reads the text of the document at `path`. The text comes from the first
+/// extractor that handles the file if there is one. Otherwise, the file
+/// is read directly and decompressed if `compressed` is set. Fails with
+/// `IndexError::TooLarge` if the file or its text is over
+/// `MAX_INDEXED_BYTES`.
+pub fn read_document(
+	path: &Path,
+	compressed: bool,
+	extractors: &[Arc<dyn Extractor>],
+	revision: Option<&str>,
+) -> Result<Vec<u8>, IndexError> {
+	let limited = |contents: Vec<u8>| match contents.len() as u64 > MAX_INDEXED_BYTES {
+		true => Err(IndexError::TooLarge),
+		false => Ok(contents),
+	};
+
+	if let Some(commit) = revision {
+		return limited(git::read_blob(commit, path)?);
+	}
+
+	if archive::split(path).is_some() {
+		return archive::read(path, MAX_INDEXED_BYTES)?.ok_or(IndexError::TooLarge);
+	}
+
+	if let Some(extractor) = extractors.iter().find(|e| e.matches(path)) {
+		if std::fs::metadata(path)?.len() > MAX_INDEXED_BYTES {
+			return Err(IndexError::TooLarge);
+		}
+
+		return limited(extractor.extract(path)?.into_bytes());
+	}
+
+	compression::read(path, compressed, MAX_INDEXED_BYTES)?.ok_or(IndexError::TooLarge)
+}
+
+/// Reads the text of the document at `path` like `read_document`, but
+/// scans it line by line, keeping at most `MAX_RANKED_BYTES` of whole lines
+/// and `MAX_RANKED_LINE_LEN` bytes of each line, so enormous files and
+/// files with multi-megabyte lines are ranked in bounded memory and time.
+/// Returns the text and whether any of it was left out.
+pub fn read_ranked_document(
+	path: &Path,
+	compressed: bool,
+	extractors: &[Arc<dyn Extractor>],
+	revision: Option<&str>,
+) -> Result<(Vec<u8>, bool), IndexError> {
+	// Only plain files can be streamed; other text is produced whole
+	let streamed = revision.is_none()
+		&& archive::split(path).is_none()
+		&& !extractors.iter().any(|e| e.matches(path));
+
+	if !streamed {
+		let contents = read_document(path, compressed, extractors, revision)?;
+		return Ok(scan_lines(contents.as_slice())?);
+	}
+
+	let reader = BufReader::new(compression::open(path, compressed)?);
+	Ok(scan_lines(reader)?)
+}
+
+/// Copies the lines of `reader` up to `MAX_RANKED_BYTES`, ending at the
+/// last whole line, cutting each line to `MAX_RANKED_LINE_LEN` bytes at a
+/// character boundary. Returns the text and whether any of it was cut.
+fn scan_lines<R: BufRead>(mut reader: R) -> std::io::Result<(Vec<u8>, bool)> {
+	let mut text = Vec::new();
+	let mut line_len = 0;
+	let mut cut = false;
+	loop {
+		let buf = reader.fill_buf()?;
+		if buf.is_empty() {
+			return Ok((text, cut));
+		}
+
+		let (chunk, ends_line) = match buf.iter().position(|b| *b == b'\n') {
+			Some(i) => (&buf[..i], true),
+			None => (buf, false),
+		};
+
+		let room = MAX_RANKED_LINE_LEN - line_len;
+		if chunk.len() > room {
+			// Keep the start of the line, but never half a character
+			let kept = char_boundary(&chunk[..room]);
+			text.extend_from_slice(&chunk[..kept]);
+			line_len = MAX_RANKED_LINE_LEN;
+			cut = true;
+		} else {
+			text.extend_from_slice(chunk);
+			line_len += chunk.len();
+		}
+
+		if ends_line {
+			text.push(b'\n');
+			line_len = 0;
+		}
+
+		let consumed = chunk.len() + ends_line as usize;
+		reader.consume(consumed);
+
+		if text.len() > MAX_RANKED_BYTES {
+			let end = text[..MAX_RANKED_BYTES]
+				.iter()
+				.rposition(|b| *b == b'\n')
+				.map_or(0, |i| i + 1);
+
+			text.truncate(end);
+			return Ok((text, true));
+		}
+	}
+}
+
+/// Returns the length of the longest prefix of `bytes` that doesn't end
+/// partway through a UTF-8 character.
+fn char_boundary(bytes: &[u8]) -> usize {
+	// The last character starts at the last byte that isn't a continuation
+	// byte, and its first byte tells how long it is
+	let start = match bytes.iter().rposition(|b| b & 0xc0 != 0x80) {
+		Some(v) => v,
+		None => return 0,
+	};
+
+	let len = match bytes[start] {
+		b if b >= 0xf0 => 4,
+		b if b >= 0xe0 => 3,
+		b if b >= 0xc0 => 2,
+		_ => 1,
+	};
+
+	if start + len > bytes.len() {
+		start
+	} else {
+		bytes.len()
+	}
+}
+
+/// Returns the entries of the archive at `path`, with their sizes, or
+/// `path` itself and its size `len` if it isn't an archive. Archives that
+/// can't be read are returned as they are too, to be skipped when indexed.
+pub fn expand_archive(path: PathBuf, len: u64) -> Vec<(PathBuf, u64)> {
+	if !archive::is_archive(&path) {
+		return vec![(path, len)];
+	}
+
+	match archive::entries(&path) {
+		Ok(v) => v,
+		Err(e) => {
+			if log::enabled(Level::Verbose) {
+				eprintln!("Failed to read archive {}: {e}", path.display());
+			}
+
+			vec![(path, len)]
+		}
+	}
+}
+
+/// Logs why `file` was skipped while indexing. Binary, generated, and too
+/// large files are expected, so they are only logged with `--verbose`.
+fn log_skipped(progress: &Progress, file: &Path, error: &IndexError) {
+	match error {
+		IndexError::BinaryFile => progress.log(
+			Level::Verbose,
+			format!("Skipped binary file {}", file.display()),
+		),
+		IndexError::GeneratedFile => progress.log(
+			Level::Verbose,
+			format!("Skipped generated file {}", file.display()),
+		),
+		IndexError::TooLarge => progress.log(
+			Level::Verbose,
+			format!(
+				"Skipped file {}: too large, over {} MiB",
+				file.display(),
+				MAX_INDEXED_BYTES >> 20
+			),
+		),
+		e => progress.log(
+			Level::Normal,
+			format!("Failed to index {}: {e}", file.display()),
+		),
+	}
+}
+
+/// Works out what `create` would do with every file, without writing
+/// anything.
+pub fn plan_create(options: &IndexOptions) -> Result<Vec<(PathBuf, Plan)>, IndexError> {
+	let mut files = Vec::new();
+	if let (Some(_), Some(listed)) = (&options.revision, &options.files) {
+		files.clone_from(listed);
+	} else {
+		for res in walk(options) {
+			let entry = res?;
+			if !entry.path().is_dir() {
+				let expanded = expand_archive(entry.into_path(), 0);
+				files.extend(expanded.into_iter().map(|(path, _)| path));
+			}
+		}
+	}
+
+	let mut plans = Vec::new();
+	index_each(files, options.ngram_size, options, |file, document| {
+		plans.push((file, plan(document)));
+	});
+
+	plans.extend(ignored(options)?);
+	plans.sort_by(|a, b| a.0.cmp(&b.0));
+	Ok(plans)
+}
+
+/// Works out whether a file would be indexed or skipped from the result of
+/// indexing it.
+fn plan(document: Result<Document, IndexError>) -> Plan {
+	match document {
+		Ok(document) if document.ngrams.is_empty() => Plan::Skip(String::from("empty")),
+		Ok(_) => Plan::Index,
+		Err(IndexError::BinaryFile) => Plan::Skip(String::from("binary")),
+		Err(IndexError::GeneratedFile) => Plan::Skip(String::from("generated")),
+		Err(IndexError::TooLarge) => Plan::Skip(String::from("too large")),
+		Err(e) => Plan::Skip(e.to_string()),
+	}
+}
+
+/// Finds the files and directories `walk` leaves out because they are
+/// ignored or hidden, or listed but missing. Directories are listed without
+/// their contents.
+fn ignored(options: &IndexOptions) -> Result<Vec<(PathBuf, Plan)>, IndexError> {
+	if options.revision.is_some() {
+		return Ok(Vec::new());
+	}
+
+	if let Some(files) = &options.files {
+		return Ok(files
+			.iter()
+			.filter(|file| !file.exists())
+			.map(|file| (file.clone(), Plan::Skip(String::from("doesn't exist"))))
+			.collect());
+	}
+
+	let walked = walk(options)
+		.map(|res| res.map(|entry| entry.into_path()))
+		.collect::<Result<HashSet<PathBuf>, ignore::Error>>()?;
+
+	let ignored = Arc::new(Mutex::new(Vec::new()));
+	let found = Arc::clone(&ignored);
+	let mut builder = walk_builder(options);
+	builder.standard_filters(false).filter_entry(move |entry| {
+		if walked.contains(entry.path()) {
+			return true;
+		}
+
+		found
+			.lock()
+			.expect("ignored paths poisoned")
+			.push((entry.path().to_path_buf(), Plan::Ignore));
+
+		false
+	});
+
+	// Walking fills the list through the filter
+	for res in skip_loops(builder.build()) {
+		res?;
+	}
+
+	let ignored = std::mem::take(&mut *ignored.lock().expect("ignored paths poisoned"));
+	Ok(ignored)
+}
+
+/// Indexes each of `files`, calling `f` with each file and the result of
+/// indexing it. The entries of an archive are all read in one pass over it,
+/// when its first entry comes up.
+fn index_each<F>(files: Vec<PathBuf>, ngram_size: u8, options: &IndexOptions, mut f: F)
+where
+	F: FnMut(PathBuf, Result<Document, IndexError>),
+{
+	let mut archives = HashMap::<PathBuf, HashSet<PathBuf>>::new();
+	for file in &files {
+		if let Some((archive, _)) = archive::split(file) {
+			archives.entry(archive).or_default().insert(file.clone());
+		}
+	}
+
+	for file in files {
+		let Some((archive, _)) = archive::split(&file) else {
+			let document = index_file(&file, ngram_size, options);
+			f(file, document);
+			continue;
+		};
+
+		let Some(mut entries) = archives.remove(&archive) else {
+			continue;
+		};
+
+		let res = archive::for_each_entry(&archive, |entry, reader| {
+			if entries.remove(&entry) {
+				let document = match archive::read_limited(reader, MAX_INDEXED_BYTES)? {
+					Some(contents) => {
+						index_contents(&entry, contents, false, None, ngram_size, options)
+					}
+					None => Err(IndexError::TooLarge),
+				};
+
+				f(entry, document);
+			}
+
+			Ok(())
+		});
+
+		// Entries not read were removed, or come after where reading failed
+		let mut entries = entries.into_iter().collect::<Vec<PathBuf>>();
+		entries.sort();
+		for entry in entries {
+			let error = match &res {
+				Ok(()) => {
+					std::io::Error::new(std::io::ErrorKind::NotFound, "no such archive entry")
+				}
+				Err(e) => std::io::Error::new(e.kind(), e.to_string()),
+			};
+
+			f(entry, Err(error.into()));
+		}
+	}
+}
+
+/// Reads the file at `path` and collects all of its n-grams, identifier
+/// subtokens and symbols. Gzip-compressed files are decompressed first.
+fn index_file(path: &Path, ngram_size: u8, options: &IndexOptions) -> Result<Document, IndexError> {
+	// Files of a commit or in an archive are read as they are, since
+	// extractors and decompression only work on files on disk
+	let revision = options.revision.as_deref();
+	let on_disk = revision.is_none() && archive::split(path).is_none();
+	let extractors = if on_disk {
+		&options.extractors[..]
+	} else {
+		&[][..]
+	};
+
+	let compressed = on_disk && compression::is_compressed(path);
+	let contents = read_document(path, compressed, extractors, revision)?;
+
+	// Fingerprint the file on disk, not the text read from it
+	let fingerprint = if compressed || extractors.iter().any(|e| e.matches(path)) {
+		Some(Fingerprint::read(path)?)
+	} else {
+		None
+	};
+
+	index_contents(path, contents, compressed, fingerprint, ngram_size, options)
+}
+
+/// Collects the n-grams, identifier subtokens and symbols of `contents`,
+/// the text of the file at `path`, decompressed if `compressed` is set. The
+/// file is fingerprinted from `contents` unless its `fingerprint` is given.
+fn index_contents(
+	path: &Path,
+	contents: Vec<u8>,
+	compressed: bool,
+	fingerprint: Option<Fingerprint>,
+	ngram_size: u8,
+	options: &IndexOptions,
+) -> Result<Document, IndexError> {
+	let revision = options.revision.as_deref();
+	let fingerprint = fingerprint.unwrap_or_else(|| Fingerprint::new(&contents));
+	if !options.binary && encoding::is_binary(&contents) {
+		return Err(IndexError::BinaryFile);
+	}
+
+	let text = encoding::decode(contents);
+	let logical_path = compression::logical_path(path, compressed);
+	if !options.generated && generated::is_generated(&logical_path, &text) {
+		return Err(IndexError::GeneratedFile);
+	}
+
+	let ngrams = collect_ngrams(&text, ngram_size);
+	let target = if options.link_targets && revision.is_none() {
+		link_target(path)
+	} else {
+		None
+	};
+
+	let length = ngrams.iter().map(|(_, count)| count).sum();
+
+	// Tokenize identifiers and extract symbols from text files
+	let (symbols, tokens) = if ngrams.is_empty() {
+		(Vec::new(), Vec::new())
+	} else {
+		(
+			symbols::extract(&logical_path, &text),
+			tokenize::tokens(&text, options.normalizer.as_deref()),
+		)
+	};
+
+	Ok(Document {
+		compressed,
+		fingerprint,
+		language: Language::detect(&logical_path, &text),
+		length,
+		ngrams,
+		path: path.to_path_buf(),
+		symbols,
+		target,
+		tokens,
+	})
+}
+
+/// Returns where `path` really is if any part of it is a symbolic link,
+/// relative to the current directory if it is within it.
+fn link_target(path: &Path) -> Option<PathBuf> {
+	let target = encoding::strip_verbatim(&std::fs::canonicalize(path).ok()?);
+	let cwd = encoding::strip_verbatim(&std::fs::canonicalize(".").ok()?);
+	let mut lexical = cwd.clone();
+	for component in path.components() {
+		match component {
+			Component::CurDir => {}
+			Component::ParentDir => {
+				lexical.pop();
+			}
+			c => lexical.push(c),
+		}
+	}
+
+	if target == lexical {
+		return None;
+	}
+
+	match target.strip_prefix(&cwd) {
+		Ok(relative) => Some(Path::new(".").join(relative)),
+		Err(_) => Some(target),
+	}
+}
+
+/// Collects all of the unique n-grams of `ngram_size` bytes in `text`,
+/// along with how many times each occurs, sorted by n-gram.
+fn collect_ngrams(text: &str, ngram_size: u8) -> Vec<(NGram, u32)> {
+	let mut all = Vec::new();
+	ngram::get_ngrams(text, ngram_size, &mut all);
+
+	let mut counts: HashMap<NGram, u32> = HashMap::new();
+	for ngram in all {
+		*counts.entry(ngram).or_insert(0) += 1;
+	}
+
+	let mut ngrams = counts.into_iter().collect::<Vec<(NGram, u32)>>();
+	ngrams.sort_unstable_by_key(|(ngram, _)| *ngram);
+	ngrams
+}
+
+/// Encodes each of `paths` as a `u32` length followed by its bytes.
+fn encode_paths<'a, I: Iterator<Item = &'a Path>>(paths: I) -> Vec<u8> {
+	let mut out = Vec::new();
+	for path in paths {
+		let bytes = encoding::os_str_to_bytes(path.as_os_str());
+		out.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+		out.extend_from_slice(&bytes);
+	}
+
+	out
+}
+
+/// Reads the big-endian `u32` length at `pos` in `section`.
+fn read_len(section: &[u8], pos: usize) -> Option<usize> {
+	let bytes = section.get(pos..pos + 4)?;
+	Some(u32::from_be_bytes(bytes.try_into().expect("slice is 4 bytes")) as usize)
+}
+
+/// Returns `path` as directories are keyed in the directory section,
+/// without a leading `./`.
+fn directory_key(path: &Path) -> &Path {
+	path.strip_prefix(".").unwrap_or(path)
+}
+
+/// Encodes the bitmap of documents in each directory containing any, as
+/// entries of a `u32` length and path followed by a `u32` length and
+/// posting, sorted by path.
+fn encode_directories(documents: &[Document]) -> Vec<u8> {
+	let mut directories = HashMap::new();
+	for (i, doc) in documents.iter().enumerate() {
+		for dir in directory_key(&doc.path).ancestors().skip(1) {
+			if dir.as_os_str().is_empty() {
+				break;
+			}
+
+			directories
+				.entry(dir)
+				.or_insert_with(|| BitMap::new(documents.len()))
+				.set(i, true);
+		}
+	}
+
+	let mut directories = directories.into_iter().collect::<Vec<_>>();
+	directories.sort_by_key(|v| v.0);
+	let mut out = Vec::new();
+	for (dir, bitmap) in directories {
+		let path = encoding::os_str_to_bytes(dir.as_os_str());
+		let posting = encode_posting(&bitmap);
+		out.extend_from_slice(&(path.len() as u32).to_be_bytes());
+		out.extend_from_slice(&path);
+		out.extend_from_slice(&(posting.len() as u32).to_be_bytes());
+		out.extend_from_slice(&posting);
+	}
+
+	out
+}
+
+/// Decodes the paths encoded by `encode_paths` in the section `tag`.
+fn decode_paths(section: &[u8], tag: [u8; 4]) -> Result<Vec<PathBuf>, IndexError> {
+	let mut paths = Vec::new();
+	let mut pos = 0;
+	while pos < section.len() {
+		let len = section
+			.get(pos..pos + 4)
+			.ok_or(IndexError::InvalidSection(tag))?;
+
+		let len = u32::from_be_bytes(len.try_into().expect("slice is 4 bytes")) as usize;
+		let bytes = section
+			.get(pos + 4..pos + 4 + len)
+			.ok_or(IndexError::InvalidSection(tag))?;
+
+		paths.push(PathBuf::from(encoding::bytes_to_os_string(bytes.to_vec())));
+		pos += 4 + len;
+	}
+
+	Ok(paths)
+}
+
+/// Reads the n-gram size from the header of the index at `path`, or `None`
+/// if the header can't be read or is invalid.
+fn read_ngram_size(path: &Path) -> Option<u8> {
+	let mut header = [0; HEADER_LEN as usize];
+	File::open(path).ok()?.read_exact(&mut header).ok()?;
+	let ngram_size = header[3];
+	(header.starts_with(&[0x4b, 0x43, 0x53]) && ngram::is_supported_size(ngram_size))
+		.then_some(ngram_size)
+}
+
+/// Reads the section directory from the end of an index, if it has one.
+/// Returns a map of section tags to their offset and length.
+fn read_sections<T: Read + Seek>(
+	source: &mut T,
+	file_len: u64,
+) -> Result<HashMap<[u8; 4], (u64, u64)>, IndexError> {
+	let mut sections = HashMap::new();
+	if file_len < HEADER_LEN + FOOTER_LEN {
+		return Ok(sections);
+	}
+
+	let mut footer = [0; FOOTER_LEN as usize];
+	source.seek(SeekFrom::End(-(FOOTER_LEN as i64)))?;
+	source.read_exact(&mut footer)?;
+	if footer[4..] != FOOTER_MAGIC {
+		return Ok(sections);
+	}
+
+	let count = u32::from_be_bytes(footer[..4].try_into().unwrap()) as u64;
+	let directory_len = count * SECTION_ENTRY_LEN;
+	if directory_len + FOOTER_LEN + HEADER_LEN > file_len {
+		return Err(IndexError::InvalidHeader);
+	}
+
+	source.seek(SeekFrom::End(-((directory_len + FOOTER_LEN) as i64)))?;
+	let mut entry = [0; SECTION_ENTRY_LEN as usize];
+	for _ in 0..count {
+		source.read_exact(&mut entry)?;
+		let tag = entry[..4].try_into().unwrap();
+		let offset = u64::from_be_bytes(entry[4..12].try_into().unwrap());
+		let len = u64::from_be_bytes(entry[12..20].try_into().unwrap());
+		if offset + len > file_len {
+			return Err(IndexError::InvalidSection(tag));
+		}
+
+		sections.insert(tag, (offset, len));
+	}
+
+	Ok(sections)
+}
+
+/// Builds the n-gram postings for a set of documents, ordered by n-gram.
+/// Each posting holds the occurrence counts of its documents in document order.
+fn build_postings(documents: &[Document]) -> Vec<(NGram, BitMap, Vec<u16>)> {
+	let mut index = HashMap::new();
+	for (i, doc) in documents.iter().enumerate() {
+		for (t, count) in &doc.ngrams {
+			let (bitmap, counts) = index
+				.entry(*t)
+				.or_insert_with(|| (BitMap::new(documents.len()), Vec::new()));
+
+			bitmap.set(i, true);
+			counts.push((*count).min(u16::MAX as u32) as u16);
+		}
+	}
+
+	let mut index = index
+		.into_iter()
+		.map(|(ngram, (bitmap, counts))| (ngram, bitmap, counts))
+		.collect::<Vec<_>>();
+
+	index.sort_by_key(|v| v.0);
+	index
+}
+
+/// Encodes a posting's bitmap with a flag byte, run-length encoding it if
+/// that is smaller than its raw bytes.
+fn encode_posting(bitmap: &BitMap) -> Vec<u8> {
+	let rle = bitmap.to_rle();
+	let (flag, data) = if rle.len() < bitmap.byte_len() {
+		(POSTING_RLE, rle)
+	} else {
+		(POSTING_RAW, bitmap.to_bytes())
+	};
+
+	let mut posting = Vec::with_capacity(data.len() + 1);
+	posting.push(flag);
+	posting.extend_from_slice(&data);
+	posting
+}
+
+/// Decodes a posting written by `encode_posting` into a bitmap of
+/// `document_count` bits.
+fn decode_posting(posting: &[u8], document_count: usize) -> Result<BitMap, IndexError> {
+	let invalid = IndexError::InvalidSection(POSTING_SECTION);
+	match posting.split_first() {
+		Some((&POSTING_RAW, data)) => Ok(BitMap::from_be_bytes(data, document_count)),
+		Some((&POSTING_RLE, data)) => BitMap::from_rle(data, document_count).ok_or(invalid),
+		_ => Err(invalid),
+	}
+}
+
+/// Reads the offset and length of a posting from an n-gram record.
+fn read_posting_entry(entry: &[u8]) -> (u64, u64) {
+	let entry = read_u32s(entry);
+	(entry[0] as u64, entry[1] as u64)
+}
+
+/// Passes writes through to a writer, hashing everything written.
+struct HashingWriter<T> {
+	hash: hmac_sha256::Hash,
+	inner: T,
+}
+
+impl<T: Write> Write for HashingWriter<T> {
+	fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+		let len = self.inner.write(buf)?;
+		self.hash.update(&buf[..len]);
+		Ok(len)
+	}
+
+	fn flush(&mut self) -> std::io::Result<()> {
+		self.inner.flush()
+	}
+}
+
+/// Converts big-endian bytes into `u32`s, ignoring any trailing bytes.
+fn read_u32s(bytes: &[u8]) -> Vec<u32> {
+	bytes
+		.chunks_exact(4)
+		.map(|v| u32::from_be_bytes(v.try_into().unwrap()))
+		.collect()
+}
+
+/// Writes an index out to a stream. Documents are numbered in path order,
+/// so indexing the same files always gives the same index.
+fn write_index<T: Write>(
+	out: T,
+	ngram_size: u8,
+	mut documents: Vec<Document>,
+	skipped: &[PathBuf],
+	progress_format: ProgressFormat,
+) -> std::io::Result<()> {
+	documents.sort_by(|a, b| a.path.cmp(&b.path));
+	let index = build_postings(&documents);
+	let mut out = HashingWriter {
+		hash: hmac_sha256::Hash::new(),
+		inner: out,
+	};
+
+	assert!(documents.len() <= u32::MAX as usize);
+	let document_count = (documents.len() as u32).to_be_bytes();
+
+	assert!(index.len() <= u32::MAX as usize);
+	let ngram_count = (index.len() as u32).to_be_bytes();
+
+	// Write header
+	let header: [u8; HEADER_LEN as usize] = [
+		// KCS
+		0x4b,
+		0x43,
+		0x53,
+		// ngram size
+		ngram_size,
+		// document count
+		document_count[0],
+		document_count[1],
+		document_count[2],
+		document_count[3],
+		// ngram count
+		ngram_count[0],
+		ngram_count[1],
+		ngram_count[2],
+		ngram_count[3],
+	];
+
+	out.write_all(&header)?;
+	let mut written = HEADER_LEN;
+
+	// Write index
+	let mut progress = Progress::new(
+		progress_format,
+		"writing",
+		(index.len() + documents.len()) as u64,
+	);
+
+	progress.println("Writing index...");
+
+	let mut postings = Vec::new();
+	for (ngram, bitmap, _) in &index {
+		let posting = encode_posting(bitmap);
+		assert!(postings.len() + posting.len() <= u32::MAX as usize);
+		out.write_all(ngram.as_bytes())?;
+		out.write_all(&(postings.len() as u32).to_be_bytes())?;
+		out.write_all(&(posting.len() as u32).to_be_bytes())?;
+		written += (ngram.as_bytes().len() + POSTING_ENTRY_LEN) as u64;
+		postings.extend_from_slice(&posting);
+		progress.inc(None);
+	}
+
+	// Write documents
+	for doc in &documents {
+		let doc = encoding::os_str_to_bytes(doc.path.as_os_str());
+		let len = (doc.len() as u32).to_be_bytes();
+		out.write_all(&len)?;
 		out.write_all(&doc)?;
-		progress.inc(1);
+		written += (len.len() + doc.len()) as u64;
+		progress.inc(None);
+	}
+
+	// Write sections
+	let mut document_frequencies = Vec::with_capacity(index.len() * 4);
+	for (_, bitmap, _) in &index {
+		document_frequencies.extend_from_slice(&(bitmap.count_ones() as u32).to_be_bytes());
+	}
+
+	let mut frequencies = Vec::new();
+	for doc in &documents {
+		frequencies.extend_from_slice(&doc.length.to_be_bytes());
+	}
+
+	let mut offset = 0u32;
+	for (_, _, counts) in &index {
+		frequencies.extend_from_slice(&offset.to_be_bytes());
+		offset += counts.len() as u32;
+	}
+
+	for (_, _, counts) in &index {
+		counts
+			.iter()
+			.for_each(|c| frequencies.extend_from_slice(&c.to_be_bytes()));
 	}
 
+	let mut fingerprints = Vec::with_capacity(documents.len() * FINGERPRINT_LEN);
+	for doc in &documents {
+		fingerprints.extend_from_slice(&doc.fingerprint.size.to_be_bytes());
+		fingerprints.extend_from_slice(&doc.fingerprint.hash);
+	}
+
+	let mut symbols = Vec::new();
+	for doc in &documents {
+		symbols.extend_from_slice(&(doc.symbols.len() as u32).to_be_bytes());
+		doc.symbols.iter().for_each(|s| s.write_to(&mut symbols));
+	}
+
+	let mut compressed = BitMap::new(documents.len());
+	for (i, doc) in documents.iter().enumerate() {
+		compressed.set(i, doc.compressed);
+	}
+
+	let mut languages = HashMap::new();
+	for (i, doc) in documents.iter().enumerate() {
+		if let Some(language) = doc.language {
+			languages
+				.entry(language)
+				.or_insert_with(|| BitMap::new(documents.len()))
+				.set(i, true);
+		}
+	}
+
+	let mut languages = languages.into_iter().collect::<Vec<_>>();
+	languages.sort_by_key(|v| v.0);
+	let languages = languages
+		.into_iter()
+		.flat_map(|(language, bitmap)| {
+			let mut entry = vec![language.id()];
+			entry.extend_from_slice(&bitmap.to_bytes());
+			entry
+		})
+		.collect();
+
+	let mut tokens = HashMap::new();
+	for (i, doc) in documents.iter().enumerate() {
+		for token in &doc.tokens {
+			tokens
+				.entry(token.as_str())
+				.or_insert_with(|| BitMap::new(documents.len()))
+				.set(i, true);
+		}
+	}
+
+	let mut tokens = tokens.into_iter().collect::<Vec<_>>();
+	tokens.sort_by_key(|v| v.0);
+	let tokens = tokens
+		.into_iter()
+		.flat_map(|(token, bitmap)| {
+			let mut entry = vec![token.len() as u8];
+			entry.extend_from_slice(token.as_bytes());
+			entry.extend_from_slice(&bitmap.to_bytes());
+			entry
+		})
+		.collect();
+
+	let mut sections = vec![
+		(DIRECTORY_SECTION, encode_directories(&documents)),
+		(DOCUMENT_FREQUENCY_SECTION, document_frequencies),
+		(FINGERPRINT_SECTION, fingerprints),
+		(FREQUENCY_SECTION, frequencies),
+		(LANGUAGE_SECTION, languages),
+		(POSTING_SECTION, postings),
+		(SYMBOL_SECTION, symbols),
+		(TOKEN_SECTION, tokens),
+	];
+
+	if documents.iter().any(|doc| doc.compressed) {
+		sections.push((COMPRESSED_SECTION, compressed.to_bytes()));
+	}
+
+	if documents.iter().any(|doc| doc.target.is_some()) {
+		let targets = documents
+			.iter()
+			.map(|doc| doc.target.as_deref().unwrap_or(Path::new("")));
+
+		sections.push((LINK_SECTION, encode_paths(targets)));
+	}
+
+	if !skipped.is_empty() {
+		sections.push((
+			SKIPPED_SECTION,
+			encode_paths(skipped.iter().map(PathBuf::as_path)),
+		));
+	}
+
+	let mut directory = Vec::with_capacity((sections.len() + 1) * SECTION_ENTRY_LEN as usize);
+	let mut sums = Vec::with_capacity(CHECKSUM_LEN + sections.len() * (4 + CHECKSUM_LEN));
+	for (tag, data) in &sections {
+		out.write_all(data)?;
+		directory.extend_from_slice(tag);
+		directory.extend_from_slice(&written.to_be_bytes());
+		directory.extend_from_slice(&(data.len() as u64).to_be_bytes());
+		written += data.len() as u64;
+		sums.extend_from_slice(tag);
+		sums.extend_from_slice(&hmac_sha256::Hash::hash(data));
+	}
+
+	// The checksum section comes last so it can cover everything before it
+	sums.splice(0..0, out.hash.finalize());
+	out.write_all(&sums)?;
+	directory.extend_from_slice(&CHECKSUM_SECTION);
+	directory.extend_from_slice(&written.to_be_bytes());
+	directory.extend_from_slice(&(sums.len() as u64).to_be_bytes());
+	sections.push((CHECKSUM_SECTION, sums));
+
+	// Write section directory and footer
+	out.write_all(&directory)?;
+	out.write_all(&(sections.len() as u32).to_be_bytes())?;
+	out.write_all(&FOOTER_MAGIC)?;
+
 	progress.finish();
 
 	Ok(())
 }
+
+/// Returns `path` as the index stores it: relative to the current
+/// directory and starting with `.`.
+pub fn document_path(path: &Path) -> PathBuf {
+	let relative = std::env::current_dir()
+		.ok()
+		.filter(|_| path.is_absolute())
+		.and_then(|cwd| encoding::strip_path_prefix(path, &cwd));
+
+	let path = relative.as_deref().unwrap_or(path);
+	if path.starts_with(".") || path.starts_with("..") || path.is_absolute() {
+		path.to_path_buf()
+	} else {
+		Path::new(".").join(path)
+	}
+}
+
+/// Walks the current directory and any extra roots in `options`, or only
+/// its shard, skipping ignored files. If `options` lists the files to index,
+/// only those that exist are walked instead.
+pub fn walk(
+	options: &IndexOptions,
+) -> impl Iterator<Item = Result<ignore::DirEntry, ignore::Error>> {
+	skip_loops(walk_builder(options).build())
+}
+
+/// Leaves out the errors of `walk` for symbolic links leading back to a
+/// directory being walked, logging them with `--verbose`.
+fn skip_loops(walk: ignore::Walk) -> impl Iterator<Item = Result<ignore::DirEntry, ignore::Error>> {
+	walk.filter(|res| match res {
+		Err(e) if is_loop(e) => {
+			if log::enabled(Level::Verbose) {
+				eprintln!("Skipped symbolic link loop: {e}");
+			}
+
+			false
+		}
+		_ => true,
+	})
+}
+
+/// Returns whether `error` is a symbolic link leading back to a directory
+/// being walked.
+fn is_loop(error: &ignore::Error) -> bool {
+	match error {
+		ignore::Error::Loop { .. } => true,
+		ignore::Error::WithDepth { err, .. }
+		| ignore::Error::WithLineNumber { err, .. }
+		| ignore::Error::WithPath { err, .. } => is_loop(err),
+		_ => false,
+	}
+}
+
+/// Returns an ID unique to the file or directory `metadata` describes.
+#[cfg(target_family = "unix")]
+fn file_id(metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+	use std::os::unix::fs::MetadataExt;
+	Some((metadata.dev(), metadata.ino()))
+}
+
+/// Returns an ID unique to the file or directory `metadata` describes.
+#[cfg(not(target_family = "unix"))]
+fn file_id(_metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+	None
+}
+
+/// Configures a walk of the directories indexed with `options`.
+fn walk_builder(options: &IndexOptions) -> ignore::WalkBuilder {
+	if let Some(files) = &options.files {
+		// Listed files are indexed whatever ignore files say
+		let mut files = files.iter().filter(|file| file.exists());
+		let mut builder =
+			ignore::WalkBuilder::new(files.next().map_or(Path::new("."), PathBuf::as_path));
+		files.for_each(|file| {
+			builder.add(file);
+		});
+
+		builder.standard_filters(false).max_depth(Some(0));
+		return builder;
+	}
+
+	let mut builder = match options.shard.as_deref() {
+		Some(dir) if dir == Path::new(shard::ROOT_SHARD) => {
+			let mut builder = ignore::WalkBuilder::new(dir);
+			builder.max_depth(Some(1));
+			builder
+		}
+		Some(dir) => ignore::WalkBuilder::new(dir),
+		None => {
+			let mut builder = ignore::WalkBuilder::new(".");
+			options.roots.iter().for_each(|root| {
+				builder.add(root);
+			});
+
+			builder
+		}
+	};
+
+	if options.follow_links {
+		// Links into the walked directories are walked through their real
+		// paths instead, and other links can lead to the same directory
+		// more than once
+		let roots = std::iter::once(Path::new("."))
+			.chain(options.roots.iter().map(PathBuf::as_path))
+			.filter_map(|root| std::fs::canonicalize(root).ok())
+			.collect::<Vec<PathBuf>>();
+
+		let visited = Mutex::new(HashSet::new());
+		builder.follow_links(true).filter_entry(move |entry| {
+			if !entry.file_type().is_some_and(|t| t.is_dir()) {
+				return true;
+			}
+
+			if entry.depth() > 0
+				&& entry.path_is_symlink()
+				&& std::fs::canonicalize(entry.path())
+					.is_ok_and(|target| roots.iter().any(|root| target.starts_with(root)))
+			{
+				return false;
+			}
+
+			match std::fs::metadata(entry.path())
+				.ok()
+				.as_ref()
+				.and_then(file_id)
+			{
+				Some(id) => visited
+					.lock()
+					.expect("visited directories poisoned")
+					.insert(id),
+				None => true,
+			}
+		});
+	}
+
+	builder
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn repair_keeps_ngram_size() {
+		let dir = std::env::temp_dir().join(format!("codesearch-repair-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let file = dir.join("parser.rs");
+		std::fs::write(&file, "fn parse_header(bytes: &[u8]) -> u32 { 0 }\n").unwrap();
+
+		let options = |ngram_size| IndexOptions {
+			extractors: Vec::new(),
+			hooks: Hooks::default(),
+			ngram_size,
+			progress: ProgressFormat::Hidden,
+			roots: Vec::new(),
+			follow_links: false,
+			link_targets: false,
+			binary: false,
+			generated: false,
+			files: Some(vec![file.clone()]),
+			revision: None,
+			shard: None,
+			normalizer: None,
+		};
+
+		let path = dir.join("index");
+		let ngram_count = Index::create(&path, &options(4)).unwrap().ngram_count;
+
+		// Damage everything after the header
+		let mut contents = std::fs::read(&path).unwrap();
+		contents[HEADER_LEN as usize..].fill(0xff);
+		std::fs::write(&path, contents).unwrap();
+		assert!(!Index::load(&path).is_ok_and(|mut index| index.verify().problems.is_empty()));
+
+		let mut index = Index::repair(&path, &options(ngram::DEFAULT_NGRAM_SIZE)).unwrap();
+		let verification = index.verify();
+		std::fs::remove_dir_all(&dir).unwrap();
+
+		assert_eq!(index.ngram_size(), 4);
+		assert_eq!(index.ngram_count, ngram_count);
+		assert!(verification.problems.is_empty());
+	}
+}
@@ -0,0 +1,196 @@
+use std::fmt::Display;
+use std::path::Path;
+
+/// The number of lines at each end of a file searched for a modeline.
+const MODELINE_LINES: usize = 5;
+
+/// A source language recognized by the index.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Language {
+	C,
+	Cpp,
+	CSharp,
+	Go,
+	Java,
+	JavaScript,
+	Markdown,
+	Python,
+	Ruby,
+	Rust,
+	Shell,
+	TypeScript,
+}
+
+impl Language {
+	/// Every recognized language, in ID order.
+	pub const ALL: [Language; 12] = [
+		Language::C,
+		Language::Cpp,
+		Language::CSharp,
+		Language::Go,
+		Language::Java,
+		Language::JavaScript,
+		Language::Markdown,
+		Language::Python,
+		Language::Ruby,
+		Language::Rust,
+		Language::Shell,
+		Language::TypeScript,
+	];
+
+	/// Detects the language of the file at `path` with the text `text`: from
+	/// an editor modeline, which overrides the extension, then the extension,
+	/// then the interpreter named by a shebang line.
+	pub fn detect(path: &Path, text: &str) -> Option<Self> {
+		Self::from_modeline(text)
+			.or_else(|| Self::from_path(path))
+			.or_else(|| Self::from_shebang(text))
+	}
+
+	/// Detects the language of the file at `path` from its extension.
+	pub fn from_path(path: &Path) -> Option<Self> {
+		let extension = path.extension()?.to_str()?;
+		Some(match extension {
+			"c" | "h" => Language::C,
+			"cc" | "cpp" | "cxx" | "hpp" | "hh" | "hxx" => Language::Cpp,
+			"cs" => Language::CSharp,
+			"go" => Language::Go,
+			"java" => Language::Java,
+			"js" | "jsx" | "mjs" | "cjs" => Language::JavaScript,
+			"md" | "markdown" => Language::Markdown,
+			"py" | "pyi" => Language::Python,
+			"rb" => Language::Ruby,
+			"rs" => Language::Rust,
+			"sh" | "bash" | "zsh" => Language::Shell,
+			"ts" | "tsx" | "mts" | "cts" => Language::TypeScript,
+			_ => return None,
+		})
+	}
+
+	/// Detects the language of a script from the interpreter on its shebang
+	/// line, e.g. `#!/bin/sh` or `#!/usr/bin/env python3`.
+	fn from_shebang(text: &str) -> Option<Self> {
+		let line = text.lines().next()?.strip_prefix("#!")?;
+		let mut words = line.split_whitespace();
+		let mut program = words.next()?.rsplit('/').next()?;
+		if program == "env" {
+			program = words.find(|word| !word.starts_with('-'))?;
+		}
+
+		// Versioned interpreters like `python3.12` are named for the language
+		let name = program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+		Some(match name {
+			"python" | "pypy" => Language::Python,
+			"ruby" => Language::Ruby,
+			"node" | "nodejs" | "deno" | "bun" => Language::JavaScript,
+			"ts-node" | "tsx" => Language::TypeScript,
+			"sh" | "bash" | "zsh" | "dash" | "ksh" => Language::Shell,
+			_ => return None,
+		})
+	}
+
+	/// Detects the language named by a Vim (`vim: set ft=python:`) or Emacs
+	/// (`-*- mode: ruby -*-`) modeline near the start or end of `text`.
+	fn from_modeline(text: &str) -> Option<Self> {
+		let lines = text.lines().collect::<Vec<&str>>();
+		let tail = lines
+			.len()
+			.saturating_sub(MODELINE_LINES)
+			.max(MODELINE_LINES);
+		lines
+			.iter()
+			.take(MODELINE_LINES)
+			.chain(lines.iter().skip(tail))
+			.find_map(|line| {
+				let name = match line.split_once("-*-") {
+					Some((_, rest)) => emacs_mode(rest)?,
+					None => vim_filetype(line)?,
+				};
+
+				match name.to_ascii_lowercase().as_str() {
+					"c++" => Some(Language::Cpp),
+					"shell-script" => Some(Language::Shell),
+					name => Self::from_name(name.strip_suffix("-ts").unwrap_or(name)),
+				}
+			})
+	}
+
+	/// Looks up a language by the name given to `--type`.
+	/// Common extensions are accepted as aliases.
+	pub fn from_name(name: &str) -> Option<Self> {
+		let name = name.to_ascii_lowercase();
+		Self::ALL
+			.into_iter()
+			.find(|l| l.name() == name)
+			.or_else(|| Self::from_path(Path::new(&format!("_.{name}"))))
+	}
+
+	/// Returns the ID this language is stored as in the index.
+	pub fn id(self) -> u8 {
+		self as u8
+	}
+
+	/// Returns the language with the given index ID.
+	pub fn from_id(id: u8) -> Option<Self> {
+		Self::ALL.get(id as usize).copied()
+	}
+
+	/// Returns the canonical name of this language.
+	pub fn name(self) -> &'static str {
+		match self {
+			Language::C => "c",
+			Language::Cpp => "cpp",
+			Language::CSharp => "csharp",
+			Language::Go => "go",
+			Language::Java => "java",
+			Language::JavaScript => "javascript",
+			Language::Markdown => "markdown",
+			Language::Python => "python",
+			Language::Ruby => "ruby",
+			Language::Rust => "rust",
+			Language::Shell => "shell",
+			Language::TypeScript => "typescript",
+		}
+	}
+}
+
+impl Display for Language {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", self.name())
+	}
+}
+
+/// Returns the mode in the rest of an Emacs modeline after its opening
+/// `-*-`: the whole of `-*- python -*-`, or the `mode` variable.
+fn emacs_mode(rest: &str) -> Option<&str> {
+	let (inner, _) = rest.split_once("-*-")?;
+	if !inner.contains(':') {
+		return Some(inner.trim()).filter(|v| !v.is_empty());
+	}
+
+	inner.split(';').find_map(|variable| {
+		let (name, value) = variable.split_once(':')?;
+		name.trim()
+			.eq_ignore_ascii_case("mode")
+			.then(|| value.trim())
+	})
+}
+
+/// Returns the filetype set by a Vim modeline in `line`, e.g.
+/// `# vim: set ft=python:` or `// vi: filetype=go`.
+fn vim_filetype(line: &str) -> Option<&str> {
+	// Markers start the line or follow whitespace, so `regex:` isn't one
+	let (_, options) = ["vim:", "vi:", "ex:"].iter().find_map(|marker| {
+		line.split_once(marker)
+			.filter(|(before, _)| before.is_empty() || before.ends_with(char::is_whitespace))
+	})?;
+
+	options
+		.split([' ', ':', '\t'])
+		.find_map(|option| {
+			option
+				.strip_prefix("ft=")
+				.or_else(|| option.strip_prefix("filetype="))
+		})
+		.filter(|v| !v.is_empty())
+}
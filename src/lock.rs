@@ -0,0 +1,78 @@
+use std::fs::{File, TryLockError};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use crate::log;
+
+/// How long to wait before trying again to lock a busy index.
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// An advisory lock on an index, released when dropped. Readers share the
+/// lock, while a writer holds it alone, so no one reads a partial rewrite.
+#[derive(Debug)]
+pub struct IndexLock {
+	_file: File,
+}
+
+impl IndexLock {
+	/// Locks the index at `index` for reading, waiting for any writer.
+	pub fn shared(index: &Path) -> std::io::Result<Self> {
+		Self::acquire(&path_for(index), false, true).map(|lock| lock.expect("waited for lock"))
+	}
+
+	/// Locks the index at `index` for writing, waiting for every reader
+	/// and writer.
+	pub fn exclusive(index: &Path) -> std::io::Result<Self> {
+		Self::acquire(&path_for(index), true, true).map(|lock| lock.expect("waited for lock"))
+	}
+
+	/// Marks the index at `index` as being built, without blocking its
+	/// readers. Returns `None` if another process is already building it.
+	pub fn building(index: &Path) -> std::io::Result<Option<Self>> {
+		let mut path = index.as_os_str().to_owned();
+		path.push(".build");
+		Self::acquire(Path::new(&path), true, false)
+	}
+
+	/// Locks the lock file at `path`, or returns `None` if it is busy and
+	/// `wait` isn't set.
+	fn acquire(path: &Path, exclusive: bool, wait: bool) -> std::io::Result<Option<Self>> {
+		let file = File::options()
+			.create(true)
+			.read(true)
+			.write(true)
+			.truncate(false)
+			.open(path)?;
+
+		let mut waiting = false;
+		loop {
+			let result = if exclusive {
+				file.try_lock()
+			} else {
+				file.try_lock_shared()
+			};
+
+			match result {
+				Ok(()) => return Ok(Some(Self { _file: file })),
+				Err(TryLockError::WouldBlock) if !wait => return Ok(None),
+				Err(TryLockError::WouldBlock) => {
+					if !waiting {
+						log::info("Index busy, retrying...");
+						waiting = true;
+					}
+
+					thread::sleep(RETRY_INTERVAL);
+				}
+				Err(TryLockError::Error(e)) => return Err(e),
+			}
+		}
+	}
+}
+
+/// Returns the path of the lock file for the index at `index`.
+pub fn path_for(index: &Path) -> PathBuf {
+	let mut path = index.as_os_str().to_owned();
+	path.push(".lock");
+	PathBuf::from(path)
+}
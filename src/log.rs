@@ -0,0 +1,53 @@
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// How much is written to stderr besides warnings and errors.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+	/// Nothing: no progress bars or notes.
+	Quiet,
+	/// Progress bars and notes about what codesearch is doing.
+	#[default]
+	Normal,
+	/// Files skipped or removed while indexing, and why.
+	Verbose,
+	/// Every file indexed.
+	Trace,
+}
+
+/// The current level, set once from the command line.
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Normal as u8);
+
+impl Level {
+	/// Returns the level with the given verbosity, e.g. the number of `-v`
+	/// flags given. Verbosities past `Trace` are `Trace`.
+	pub fn from_verbosity(verbosity: u8) -> Self {
+		match verbosity {
+			0 => Level::Normal,
+			1 => Level::Verbose,
+			_ => Level::Trace,
+		}
+	}
+
+	/// Returns the number of `-v` flags giving this level.
+	pub fn verbosity(self) -> u8 {
+		(self as u8).saturating_sub(Level::Normal as u8)
+	}
+}
+
+/// Sets the level of messages written from now on.
+pub fn set_level(level: Level) {
+	LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Returns whether messages of `level` are written.
+pub fn enabled(level: Level) -> bool {
+	level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+/// Writes a note to stderr unless `--quiet` was given.
+pub fn info<D: Display>(message: D) {
+	if enabled(Level::Normal) {
+		eprintln!("{message}");
+	}
+}
@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// A map holding at most a fixed number of entries, dropping the least
+/// recently used one to make room for a new one.
+#[derive(Debug)]
+pub struct Lru<K, V> {
+	capacity: usize,
+	/// Each value with the tick it was last used at.
+	entries: HashMap<K, (V, u64)>,
+	tick: u64,
+}
+
+impl<K: Eq + Hash + Clone, V> Lru<K, V> {
+	/// Returns an empty map holding at most `capacity` entries.
+	pub fn new(capacity: usize) -> Self {
+		Self {
+			capacity,
+			entries: HashMap::new(),
+			tick: 0,
+		}
+	}
+
+	/// Returns the value of `key`, marking it as the most recently used.
+	pub fn get(&mut self, key: &K) -> Option<&V> {
+		self.tick += 1;
+		let tick = self.tick;
+		self.entries.get_mut(key).map(|(value, used)| {
+			*used = tick;
+			&*value
+		})
+	}
+
+	/// Sets the value of `key`, dropping the least recently used entry if
+	/// the map is full.
+	pub fn insert(&mut self, key: K, value: V) {
+		if self.capacity == 0 {
+			return;
+		}
+
+		if self.entries.len() >= self.capacity && !self.entries.contains_key(&key) {
+			// Caches are small, so finding the oldest entry by scanning is cheap
+			let oldest = self
+				.entries
+				.iter()
+				.min_by_key(|(_, (_, used))| *used)
+				.map(|(key, _)| key.clone());
+
+			if let Some(oldest) = oldest {
+				self.entries.remove(&oldest);
+			}
+		}
+
+		self.tick += 1;
+		self.entries.insert(key, (value, self.tick));
+	}
+
+	/// Removes every entry.
+	pub fn clear(&mut self) {
+		self.entries.clear();
+	}
+}
@@ -1,153 +1,2737 @@
-use crate::index::Index;
+use crate::alerts::{Alert, AlertCommand};
+use crate::args::{ColorChoice, Command, Options};
+use crate::build_options::BuildOptions;
+use crate::cancel::CancelToken;
+use crate::cargo::Workspace;
+use crate::comments::{Region, Regions};
+use crate::config::Config;
+use crate::editor::EditorError;
+use crate::error::{Category, CodesearchError};
+use crate::extractor::Extractor;
+use crate::freshness::{open_index, open_shards, start_background_build, Freshness};
+use crate::index::{Index, IndexCache, IndexError, IndexOptions, Plan};
+use crate::language::Language;
+use crate::lock::IndexLock;
+use crate::log::Level;
+use crate::ngram::NGram;
+use crate::normalize::{EnglishStemmer, Normalizer};
+use crate::progress::ProgressFormat;
+use crate::protocol::{Framing, Method, Query};
 use bitmap::BitMap;
 use console::style;
-use search_rank::rank_file;
-use std::error::Error;
-use std::ffi::OsString;
-use std::path::PathBuf;
+use result_set::{Match, ResultSetError, SearchResult, SetOperation};
+use search_rank::{
+	boost_module_root, demote_path, rank_bm25, rank_file, rank_path, rank_short_terms,
+	rank_symbols, rank_tokens, weigh_regions, CollectionStats, CommentScoring, PathScoring,
+	TermFrequencies,
+};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 use std::{env, fs};
 
+mod alerts;
+mod archive;
+mod args;
+mod bench;
 mod bitmap;
+mod build_options;
+mod bundle;
+mod cancel;
+mod cargo;
+mod comments;
+mod completions;
+mod compression;
+mod config;
+mod delimited;
+mod editor;
 mod encoding;
+mod error;
+mod extractor;
+mod file_list;
+mod freshness;
+mod front_matter;
+mod generated;
+mod git;
+mod help;
+mod history;
+mod hooks;
 mod index;
+mod language;
+mod lock;
+mod log;
+mod lru;
+mod ngram;
+mod normalize;
+mod notebook;
+mod output;
+mod paths;
+mod pickaxe;
+mod progress;
+mod protocol;
+mod queries;
+mod remote;
+mod replace;
+mod result_cache;
+mod result_set;
+mod sarif;
+mod saved;
 mod search_rank;
+mod shard;
+mod stats;
+mod suppress;
+mod symbols;
+mod synonyms;
+mod template;
+mod tokenize;
+mod why_not;
 
 fn main() {
 	let mut args = env::args();
 	let name = args.next();
-	let search_term = args.collect::<Vec<String>>();
-	if search_term.len() == 0 {
-		show_help(name.as_deref());
+	// The arguments of the search, as replayed by `run` and `--last`
+	let mut query = args.collect::<Vec<String>>();
+	let mut options = parse_args(&query, name.as_deref());
+
+	match &options.command {
+		Command::Save(search, saved) => save_search(search, saved),
+		Command::Run(None, _) => list_searches(),
+		Command::Run(Some(search), extra) => {
+			query = load_search(search, extra);
+			options = parse_args(&query, name.as_deref());
+		}
+		_ => {}
+	}
+
+	if options.last {
+		query = load_last(&query, options.index_path.as_deref());
+		options = parse_args(&query, name.as_deref());
+	}
+
+	log::set_level(options.verbosity);
+	set_colors(options.color);
+
+	// Bars would garble piped output, or a pager reading it
+	let interactive = console::Term::stdout().is_term() && console::Term::stderr().is_term();
+	if (options.verbosity == Level::Quiet || !interactive)
+		&& options.progress == ProgressFormat::Bar
+	{
+		options.progress = ProgressFormat::Hidden;
+	}
+
+	if let Command::Set(operation, files) = &options.command {
+		run_set(*operation, files);
+	}
+
+	if let Command::Completions(shell) = options.command {
+		print!("{}", completions::generate(shell));
+		process::exit(0);
+	}
+
+	if let Command::Help(command) = options.command {
+		show_help(name.as_deref(), command);
+	}
+
+	if let Command::Man = options.command {
+		print!("{}", help::man_page());
+		process::exit(0);
+	}
+
+	if matches!(
+		options.command,
+		Command::Search | Command::WhyNot(_) | Command::Bench | Command::Log
+	) && options.terms.is_empty()
+	{
+		let program = name.as_deref().unwrap_or("codesearch");
+		eprintln!("Missing search terms");
+		eprintln!("Usage: {program} [flags] <search term>...");
+		eprintln!("Run `{program} --help` for more.");
+		process::exit(Category::Query.exit_code());
+	}
+
+	// Refined searches read the previous results from stdin
+	if let Some(previous) = &mut options.refine {
+		let results = std::io::read_to_string(std::io::stdin())
+			.map_err(ResultSetError::Io)
+			.and_then(|contents| result_set::parse(&contents))
+			.unwrap_or_else(|e| fail("Failed to read results to refine", e));
+
+		previous.extend(results.into_iter().map(|result| PathBuf::from(result.path)));
 	}
 
-	let save_path = match get_save_path() {
+	// Ephemeral searches keep nothing, so they need no data directory
+	let mut save_path = match paths::save_path(options.index_path.as_deref()) {
+		_ if options.ephemeral => PathBuf::new(),
 		Ok(v) => v,
-		Err(e) => {
-			eprintln!("Failed to get save location: {e}");
-			process::exit(1);
+		Err(e) => fail("Failed to get save location", e),
+	};
+
+	// Remote indexes are cached in the data directory
+	if let Some(url) = &options.remote {
+		let cache_dir = match paths::data_dir() {
+			Ok(v) => v.join(remote::CACHE_DIR),
+			Err(e) => fail("Failed to get save location", e),
+		};
+
+		save_path = match remote::fetch(url, &cache_dir) {
+			Ok(v) => v,
+			Err(e) => fail("Failed to fetch remote index", e),
+		};
+	}
+
+	// Each commit has an index of its own, and searches read its files
+	// from git by its full hash
+	if let Some(rev) = &options.rev {
+		let commit =
+			git::resolve_commit(rev).unwrap_or_else(|e| fail("Failed to find revision", e));
+		save_path = git::index_path(&save_path, &commit);
+		options.rev = Some(commit);
+	}
+
+	if let Command::History = options.command {
+		list_queries(&save_path);
+	}
+
+	let rebuild_for_options = load_build_options(&save_path, &mut options);
+
+	let alerts_path = alerts::path_for(&save_path);
+	match &options.command {
+		Command::Alert(AlertCommand::List) => list_alerts(&alerts_path),
+		Command::Alert(AlertCommand::Remove(number)) => remove_alert(&alerts_path, *number),
+		_ => {}
+	}
+
+	let config = match Config::load(config::CONFIG_FILE) {
+		Ok(v) => v,
+		Err(e) => fail(&format!("Failed to read {}", config::CONFIG_FILE), e),
+	};
+
+	let Config {
+		alert_command,
+		mut aliases,
+		comment_scoring,
+		editors,
+		extractors,
+		hooks,
+		path_scoring,
+		pinned,
+	} = config;
+
+	let mut index_options = IndexOptions {
+		extractors: extractor::with_builtins(extractors),
+		hooks,
+		ngram_size: options.ngram_size.unwrap_or(ngram::DEFAULT_NGRAM_SIZE),
+		progress: options.progress,
+		roots: Vec::new(),
+		follow_links: options.follow,
+		link_targets: options.link_targets,
+		binary: options.binary,
+		generated: options.include_generated,
+		files: None,
+		revision: options.rev.clone(),
+		shard: None,
+		normalizer: options
+			.stem
+			.then(|| Arc::new(EnglishStemmer) as Arc<dyn Normalizer>),
+	};
+
+	if options.rust {
+		let workspace = match Workspace::load() {
+			Ok(v) => v,
+			Err(e) => fail("Failed to read Cargo workspace", e),
+		};
+
+		index_options.roots = workspace.external_roots();
+		index_options.follow_links = true;
+		options.symbols = true;
+		resolve_rust_paths(&workspace, &mut options);
+	}
+
+	// Synonyms shared by every project are searched like its own aliases
+	if let Ok(dir) = paths::data_dir() {
+		match synonyms::load(&synonyms::path_for(&dir)) {
+			Ok(groups) => synonyms::add_to_aliases(groups, &mut aliases),
+			Err(e) => fail("Failed to read synonyms", e),
+		}
+	}
+
+	options.aliases = expand_aliases(&options.terms, &aliases);
+
+	// Normal forms are searched like aliases, so `parsing` also finds `parser`
+	if let Some(normalizer) = &index_options.normalizer {
+		for term in &options.terms {
+			let normal = normalizer.normalize(&term.to_lowercase());
+			if !options.terms.iter().any(|t| t.to_lowercase() == normal)
+				&& !options.aliases.contains(&normal)
+			{
+				options.aliases.push(normal);
+			}
+		}
+	}
+
+	if !options.aliases.is_empty() {
+		log::info(format_args!(
+			"Note: also searching for {}.",
+			options.aliases.join(", ")
+		));
+	}
+
+	let (files, rebuild) = if options.ephemeral {
+		(None, false)
+	} else {
+		load_file_list(&save_path, &options)
+	};
+
+	let rebuild = rebuild || rebuild_for_options;
+
+	index_options.files = files;
+	if options.git_tracked {
+		match git::tracked_files() {
+			Ok(v) => index_options.files = Some(v),
+			Err(e) => fail("Failed to list tracked files", e),
+		}
+	}
+
+	if let Some(commit) = &index_options.revision {
+		match git::revision_files(commit) {
+			Ok(v) => index_options.files = Some(v),
+			Err(e) => fail("Failed to list the revision's files", e),
 		}
+	}
+
+	// Indexes of file lists are never sharded
+	let shards = match shard::load(&shard::manifest_path(&save_path)) {
+		_ if options.ephemeral => None,
+		Ok(v) => v.filter(|_| index_options.files.is_none()),
+		Err(e) => fail("Failed to read shards", e),
 	};
 
-	let mut index = match Index::load(&save_path)
-		.and_then(|mut i| {
-			i.update()?;
-			Ok(i)
-		})
-		.or_else(|e| {
-			eprintln!("Failed to read index: {e}");
-			Index::create(&save_path)
-		}) {
-		Ok(i) => i,
-		Err(e) => {
-			eprintln!("Index creation failed: {e}");
-			process::exit(1);
+	if let Command::Bench = options.command {
+		run_bench(
+			&save_path,
+			&options,
+			&index_options,
+			&path_scoring,
+			&comment_scoring,
+			&pinned,
+		);
+	}
+
+	if let Command::Log = options.command {
+		run_log(&save_path, &options);
+	}
+
+	if let Command::Stats { history } = options.command {
+		run_stats(&save_path, shards.as_deref(), history);
+	}
+
+	if let Command::Export { path, zstd } = &options.command {
+		run_export(&save_path, shards.as_deref(), path, *zstd);
+	}
+
+	if let Command::Import(path) = &options.command {
+		run_import(&save_path, path);
+	}
+
+	if let Command::Verify { repair } = options.command {
+		run_verify(
+			&index_targets(&save_path, shards.as_deref(), &index_options),
+			repair,
+		);
+	}
+
+	if let Command::Index { dry_run: true } = options.command {
+		run_dry_run(
+			&index_targets(&save_path, shards.as_deref(), &index_options),
+			rebuild,
+		);
+	}
+
+	// Without an index, search files directly while one is built, unless
+	// they are a commit's, which aren't on disk, or --wait asks for the index
+	if matches!(options.command, Command::Search)
+		&& !options.wait
+		&& !options.read_compat
+		&& !options.ephemeral
+		&& options.rev.is_none()
+		&& shards.is_none()
+		&& !save_path.exists()
+		&& start_background_build(&save_path, &options)
+	{
+		log::info("Note: searching without an index while it is built in the background.");
+		let cancel = CancelToken::with_timeout(options.timeout);
+		match search_files(
+			&options,
+			&index_options,
+			&path_scoring,
+			&comment_scoring,
+			&pinned,
+			&cancel,
+		) {
+			Ok(results) => {
+				note_timeout(&cancel, &options);
+				record_query(&save_path, &query, &results);
+				report_results(&results, &options, &index_options.extractors, &editors)
+			}
+			Err(e) => fail("Search failed", e),
+		}
+	}
+
+	// Only one process builds the index at a time; searches don't wait for it
+	let _building = match options.command {
+		Command::Index { .. } => match IndexLock::building(&save_path) {
+			Ok(Some(v)) => Some(v),
+			Ok(None) => {
+				log::info("The index is already being built.");
+				process::exit(0);
+			}
+			Err(e) => fail("Failed to lock index", e),
+		},
+		_ => None,
+	};
+
+	// Searches answer from an outdated index while it is updated in the background
+	let defer = matches!(options.command, Command::Search) && !options.wait;
+	let started = Instant::now();
+	let (mut indexes, freshness) = match shards {
+		_ if options.ephemeral => match Index::create_in_memory(&index_options) {
+			Ok(index) => (vec![index], Freshness::Current),
+			Err(e) => fail("Index creation failed", e),
+		},
+		// Remote indexes are searched as they were published
+		_ if options.read_compat || options.remote.is_some() => (
+			open_read_only(&save_path, shards.as_deref()),
+			Freshness::Current,
+		),
+		Some(dirs) => open_shards(&save_path, &dirs, &index_options, options.ngram_size, defer),
+		None if index_options.files.is_none()
+			&& (options.shard || (!save_path.exists() && shard::is_large(&index_options))) =>
+		{
+			open_shards(&save_path, &[], &index_options, options.ngram_size, defer)
+		}
+		None => match open_index(&save_path, &index_options, options.ngram_size, defer) {
+			Ok((index, freshness)) => (vec![index], freshness),
+			Err(e) => fail("Index creation failed", e),
+		},
+	};
+
+	if freshness == Freshness::Outdated && start_background_build(&save_path, &options) {
+		log::info("Note: files changed; searching the previous index while it is updated in the background.");
+	}
+
+	if freshness == Freshness::Updated {
+		record_write(&save_path, &indexes, started.elapsed());
+	}
+
+	let mut context = SearchContext {
+		aliases: &aliases,
+		extractors: &index_options.extractors,
+		indexes: &mut indexes,
+		path_scoring: &path_scoring,
+		comment_scoring: &comment_scoring,
+		pinned: &pinned,
+	};
+
+	if let Command::Alert(command) = &options.command {
+		let result = match command {
+			AlertCommand::Add(query) => add_alert(&mut context, &alerts_path, query),
+			_ => check_alerts(&mut context, &alerts_path, alert_command.as_deref()),
+		};
+
+		if let Err(e) = result {
+			fail("Failed to update alerts", e);
+		}
+
+		return;
+	}
+
+	// Search runs update the index too, so they check alerts on any change
+	if freshness == Freshness::Updated {
+		if let Err(e) = check_alerts(&mut context, &alerts_path, alert_command.as_deref()) {
+			eprintln!("Warning: failed to check alerts: {e}");
+		}
+	}
+
+	if matches!(options.command, Command::Index { .. }) {
+		return;
+	}
+
+	// The server loads the index for each request, so it doesn't keep other
+	// processes from updating it
+	if let Command::Serve { lsp_proxy } = options.command {
+		drop(indexes);
+		let context = ServeContext {
+			aliases: &aliases,
+			caches: Mutex::default(),
+			extractors: &index_options.extractors,
+			path_scoring: &path_scoring,
+			comment_scoring: &comment_scoring,
+			index_options: &index_options,
+			pinned: &pinned,
+			save_path: &save_path,
+			sharded: index_options.files.is_none(),
+		};
+
+		run_serve(&context, lsp_proxy);
+	}
+
+	// Repeating a search of an unchanged index answers from the cache
+	let cache = (matches!(options.command, Command::Search)
+		&& freshness != Freshness::Outdated
+		&& !options.ephemeral
+		&& options.refine.is_none())
+	.then(|| {
+		(
+			result_cache::path_for(&save_path),
+			result_cache::generation(context.indexes),
+			result_cache::key(&query),
+		)
+	});
+
+	if let Some((path, generation, key)) = &cache {
+		if let Some(results) = result_cache::get(path, generation, key) {
+			if log::enabled(Level::Verbose) {
+				eprintln!("Answered from the result cache");
+			}
+
+			record_query(&save_path, &query, &results);
+			report_results(&results, &options, &index_options.extractors, &editors);
 		}
+	}
+
+	let cancel = CancelToken::with_timeout(options.timeout);
+	let results = match search_all(
+		context.indexes,
+		&options,
+		context.extractors,
+		context.path_scoring,
+		context.comment_scoring,
+		context.pinned,
+		&cancel,
+	) {
+		Ok(v) => v,
+		Err(e) => fail("Search failed", e),
 	};
 
-	let results = match search(&mut index, search_term) {
+	note_timeout(&cancel, &options);
+
+	// Results cut short by the timeout aren't the search's real results
+	if let (Some((path, generation, key)), false) = (&cache, cancel.is_expired()) {
+		if let Err(e) = result_cache::put(path, generation, key, &results) {
+			eprintln!("Warning: failed to cache results: {e}");
+		}
+	}
+
+	if let Command::WhyNot(path) = &options.command {
+		match why_not::explain(context.indexes, path, &options, &index_options, &results) {
+			Ok(findings) => findings.iter().for_each(|f| println!("{f}")),
+			Err(e) => fail("Failed to read index", e),
+		}
+
+		return;
+	}
+
+	if let Command::Replace {
+		pattern,
+		replacement,
+		write,
+		backup,
+	} = &options.command
+	{
+		run_replace(&results, pattern, replacement, *write, *backup, &options);
+	}
+
+	if !options.ephemeral {
+		record_query(&save_path, &query, &results);
+	}
+
+	report_results(&results, &options, &index_options.extractors, &editors);
+}
+
+/// Notes that only part of the document at `path` was ranked, if `cut` is
+/// set, because it is too large or has too long lines.
+fn note_cut(path: &Path, cut: bool) {
+	if cut && log::enabled(Level::Verbose) {
+		eprintln!(
+			"Ranked only part of {}: it is too large or has too long lines",
+			path.to_string_lossy()
+		);
+	}
+}
+
+/// Notes that the search with `cancel` ran out of time, if it did, so only
+/// the results ranked by then are shown.
+fn note_timeout(cancel: &CancelToken, options: &Options) {
+	if let (true, Some(timeout)) = (cancel.is_expired(), options.timeout) {
+		log::info(format_args!(
+			"Note: the search timed out after {}s; showing the best results ranked by then.",
+			timeout.as_secs_f64()
+		));
+	}
+}
+
+/// Parses the command line arguments `args`, exiting with where to find
+/// help if they are invalid.
+fn parse_args(args: &[String], name: Option<&str>) -> Options {
+	match Options::parse(args.iter().cloned()) {
 		Ok(v) => v,
 		Err(e) => {
-			eprintln!("Search failed: {e}");
-			process::exit(1);
+			let program = name.unwrap_or("codesearch");
+			let command = args.first().and_then(|arg| args::subcommand(arg));
+			eprintln!("{e}");
+			match command {
+				Some(command) => eprintln!("Run `{program} {} --help` for usage.", command.name),
+				None => eprintln!("Run `{program} --help` for usage."),
+			}
+
+			process::exit(Category::Query.exit_code());
+		}
+	}
+}
+
+/// Prints `results` unless `--quiet` was given, then exits like grep: with
+/// `0` if any file has a matching line, and `1` if none do. With `--open`,
+/// the result is opened in an editor with `editors`' templates instead.
+/// Results less relevant than `--min-score` are left out first, unless
+/// they're pinned.
+fn report_results(
+	results: &[SearchResult],
+	options: &Options,
+	extractors: &[Arc<dyn Extractor>],
+	editors: &HashMap<String, Vec<String>>,
+) -> ! {
+	let best = result_set::best_score(results);
+	let results = results
+		.iter()
+		.filter(|result| {
+			result.pinned
+				|| options
+					.min_score
+					.is_none_or(|min| result.relevance(best) >= min)
+		})
+		.cloned()
+		.collect::<Vec<SearchResult>>();
+
+	let results = results.as_slice();
+	if let Some(position) = options.open {
+		open_result(results, position, options, editors);
+	}
+
+	if options.verbosity != Level::Quiet {
+		if let Err(e) = output::print_results(results, options, extractors) {
+			fail("Failed to write results", e);
 		}
+	}
+
+	let matched = results.iter().any(SearchResult::is_match);
+	process::exit(if matched { 0 } else { 1 });
+}
+
+/// Opens the result at the 1-based `position` in `results` in an editor, at
+/// its first match, then exits once the editor does. Symbol definitions
+/// come first, so a definition is preferred over other matches.
+fn open_result(
+	results: &[SearchResult],
+	position: usize,
+	options: &Options,
+	editors: &HashMap<String, Vec<String>>,
+) -> ! {
+	let result = match results.get(position.wrapping_sub(1)) {
+		Some(v) => v,
+		None => fail("Failed to open result", EditorError::NoResult(position)),
 	};
 
-	results[..usize::min(5, results.len())]
-		.into_iter()
-		.for_each(|(file, rank, previews)| {
-			println!("{} ({})", style(file.to_string_lossy()).bold(), rank);
-			previews
-				.into_iter()
-				.for_each(|(line, prev)| println!("{}\t{prev}", style(line).bold()));
-		});
+	// Files in archives and commits have no path an editor can open
+	let path = Path::new(&result.path);
+	if options.rev.is_some() || archive::split(path).is_some() {
+		let path = path.to_string_lossy().into_owned();
+		fail("Failed to open result", EditorError::NotOnDisk(path));
+	}
+
+	// Editors count columns in characters, so the byte column is converted
+	// by the file's line
+	let (line, column, byte_column) = result.matches.first().map_or((1, 1, 1), |m| {
+		let contents = fs::read(path).map(encoding::decode).unwrap_or_default();
+		let column = editor::char_column(&contents, m.line, m.column);
+		(m.line, column, m.column)
+	});
+	match editor::open(path, line, column, byte_column, editors) {
+		Ok(()) => process::exit(0),
+		Err(e) => fail("Failed to open result", e),
+	}
+}
+
+/// Shows the substitutions of `replacement` for `pattern` in the files of
+/// `results` as a unified diff, applying them if `write` is set, and keeping
+/// copies of the files first if `backup` is set. Exits like grep: with `0`
+/// if any file contains the pattern, and `1` if none do.
+fn run_replace(
+	results: &[SearchResult],
+	pattern: &str,
+	replacement: &str,
+	write: bool,
+	backup: bool,
+	options: &Options,
+) -> ! {
+	let mut files = 0;
+	let mut count = 0;
+	for result in results.iter().filter(|result| result.is_match()) {
+		// Compressed files and archive members can't be edited in place
+		let path = Path::new(&result.path);
+		if compression::is_compressed(path) || archive::split(path).is_some() {
+			eprintln!(
+				"Warning: skipping {}, which can't be edited in place",
+				path.to_string_lossy()
+			);
+			continue;
+		}
+
+		let planned = match replace::plan(path, pattern, replacement) {
+			Ok(Some(v)) => v,
+			Ok(None) => continue,
+			Err(e) => {
+				eprintln!("Warning: failed to read {}: {e}", path.to_string_lossy());
+				continue;
+			}
+		};
+
+		if options.verbosity != Level::Quiet {
+			for line in planned.diff().lines() {
+				let styled = match line.as_bytes().first() {
+					_ if line.starts_with("---") || line.starts_with("+++") => style(line).bold(),
+					Some(b'-') => style(line).red(),
+					Some(b'+') => style(line).green(),
+					Some(b'@') => style(line).cyan(),
+					_ => style(line),
+				};
+
+				println!("{styled}");
+			}
+		}
+
+		if write {
+			if let Err(e) = planned.apply(backup) {
+				fail(&format!("Failed to write {}", path.to_string_lossy()), e);
+			}
+		}
+
+		files += 1;
+		count += planned.count;
+	}
+
+	if write {
+		log::info(format_args!(
+			"Replaced {count} occurrences in {files} files."
+		));
+	} else if files > 0 {
+		log::info(format_args!(
+			"{count} occurrences in {files} files; run again with --write to replace them."
+		));
+	}
+
+	process::exit(if files > 0 { 0 } else { 1 });
 }
 
-fn get_file_name() -> Result<String, std::io::Error> {
-	let cwd = env::current_dir()?;
-	let cwd = encoding::os_str_to_bytes(cwd.as_os_str());
-	let hash = hmac_sha256::Hash::hash(&cwd);
-	Ok(encoding::to_hex(&hash))
+/// Combines the result files at `files` with `operation` and writes the
+/// combined result file to stdout.
+fn run_set(operation: SetOperation, files: &[PathBuf]) -> ! {
+	let mut sets = Vec::with_capacity(files.len());
+	for file in files {
+		match result_set::read(file) {
+			Ok(v) => sets.push(v),
+			Err(e) => fail(&format!("Failed to read {}", file.to_string_lossy()), e),
+		}
+	}
+
+	let results = result_set::apply(operation, sets);
+	if let Err(e) = result_set::write(std::io::stdout().lock(), &results) {
+		fail("Failed to write results", e);
+	}
+
+	process::exit(0);
 }
 
-fn get_save_path() -> Result<PathBuf, String> {
-	#[cfg(target_family = "unix")]
-	let env_name = "HOME";
+/// Benchmarks building an index of the current directory and running each
+/// search term as a query, printing the latencies and throughput of each
+/// phase. The index at `save_path` is left as is.
+fn run_bench(
+	save_path: &Path,
+	options: &Options,
+	index_options: &IndexOptions,
+	path_scoring: &PathScoring,
+	comment_scoring: &CommentScoring,
+	pinned: &[PathBuf],
+) -> ! {
+	let runs = options.runs.unwrap_or(bench::DEFAULT_RUNS);
+	let path = save_path.with_extension("bench");
+	let report = bench::run(
+		&path,
+		index_options,
+		&options.terms,
+		runs,
+		|index, query| {
+			let query = Options {
+				candidates: options.candidates,
+				symbols: options.symbols,
+				terms: vec![String::from(query)],
+				types: options.types.clone(),
+				..Options::default()
+			};
+
+			let results = search(
+				index,
+				&query,
+				&index_options.extractors,
+				path_scoring,
+				comment_scoring,
+				pinned,
+				&CancelToken::default(),
+			)?;
+			Ok(results.len())
+		},
+	)
+	.unwrap_or_else(|e| fail("Benchmark failed", e));
+
+	let build = report.build.as_secs_f64();
+	println!(
+		"Indexed {} files in {build:.2}s ({:.0} files/s).",
+		report.documents,
+		report.documents as f64 / build.max(f64::EPSILON)
+	);
+
+	println!("Ran {} queries {runs} times each.\n", options.terms.len());
 
-	#[cfg(target_family = "windows")]
-	let env_name = "LOCALAPPDATA";
+	println!(
+		"{}",
+		style(format!(
+			"{:<8}  {:>10}  {:>10}  {:>20}",
+			"Phase", "p50", "p95", "Throughput"
+		))
+		.bold()
+	);
 
-	let appdata = env::var_os(env_name).ok_or(String::from("Could not get app data dir"))?;
-	let mut path = PathBuf::from(appdata);
-	path.push(".codesearch");
-	if !path.exists() {
-		fs::create_dir(&path).map_err(|e| e.to_string())?;
+	let ms = |d: Duration| format!("{:.3}ms", d.as_secs_f64() * 1000.0);
+	for samples in &report.samples {
+		let (rate, unit) = samples.throughput();
+		println!(
+			"{:<8}  {:>10}  {:>10}  {:>20}",
+			samples.name,
+			ms(samples.percentile(0.5)),
+			ms(samples.percentile(0.95)),
+			format!("{rate:.0} {unit}/s")
+		);
 	}
 
-	let file_name = get_file_name().map_err(|e| e.to_string())?;
-	path.push(file_name);
+	process::exit(0);
+}
+
+/// Finds the commits up to `--rev`, or `HEAD`, that added or removed lines
+/// containing the search terms, as one phrase, after updating the commit
+/// index alongside the index at `save_path`. Exits like a search.
+fn run_log(save_path: &Path, options: &Options) -> ! {
+	let tip = options.rev.as_deref().unwrap_or("HEAD");
+	let ngram_size = options.ngram_size.unwrap_or(ngram::DEFAULT_NGRAM_SIZE);
+	let mut segments = match pickaxe::update(save_path, tip, ngram_size, options.progress) {
+		Ok(v) => v,
+		Err(e) => fail("Failed to index commits", e),
+	};
+
+	let commits = match pickaxe::search(&mut segments, &options.terms.join(" ")) {
+		Ok(v) => v,
+		Err(e) => fail("Search failed", e),
+	};
+
+	if options.verbosity != Level::Quiet {
+		pickaxe::print(&commits);
+	}
 
-	Ok(path)
+	process::exit(if commits.is_empty() { 1 } else { 0 });
 }
 
-fn get_trigrams(bytes: &[u8], buf: &mut Vec<[u8; 3]>) {
-	if bytes.len() < 3 {
-		return;
+/// Writes the index at `save_path`, which is split into the shards of the
+/// `shards` directories if it is sharded, to a bundle at `out`.
+fn run_export(save_path: &Path, shards: Option<&[PathBuf]>, out: &Path, zstd: bool) -> ! {
+	if let Err(e) = bundle::export(save_path, shards, out, zstd) {
+		fail("Failed to export index", e);
 	}
 
-	let mut tri_buf = [0; 3];
-	'outer: for i in 0..=bytes.len() - 3 {
-		tri_buf.copy_from_slice(&bytes[i..i + 3]);
-		for b in tri_buf.iter_mut() {
-			if !b.is_ascii_alphanumeric() {
-				continue 'outer;
-			}
+	log::info(format_args!("Exported index to {}", out.display()));
+	process::exit(0);
+}
 
-			if b.is_ascii() {
-				*b = b.to_ascii_lowercase();
+/// Replaces the index at `save_path` with the one in the bundle at `input`.
+fn run_import(save_path: &Path, input: &Path) -> ! {
+	let metadata = match bundle::import(save_path, input) {
+		Ok(v) => v,
+		Err(e) => fail("Failed to import index", e),
+	};
+
+	let written = metadata
+		.written
+		.duration_since(std::time::UNIX_EPOCH)
+		.unwrap_or_default()
+		.as_secs();
+
+	log::info(format_args!(
+		"Imported index of {} written {} by codesearch {}",
+		metadata.root.display(),
+		history::format_time(written),
+		metadata.version
+	));
+	process::exit(0);
+}
+
+/// Prints the size of the index at `save_path`, which is split into the
+/// shards of the `shards` directories if it is sharded. With `show_history`
+/// set, also prints how it grew with each write.
+fn run_stats(save_path: &Path, shards: Option<&[PathBuf]>, show_history: bool) -> ! {
+	let mut indexes = Vec::new();
+	for path in index_paths(save_path, shards) {
+		match Index::load(&path) {
+			Ok(index) => indexes.push(index),
+			Err(e) => fail("Failed to read index", e),
+		}
+	}
+
+	match stats::collect(&mut indexes) {
+		Ok(v) => {
+			let out = std::io::stdout().lock();
+			match stats::print(out, &v, shards.map(<[PathBuf]>::len)) {
+				Ok(()) => {}
+				// The reader went away, e.g. `stats | head` got its lines
+				Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => process::exit(0),
+				Err(e) => fail("Failed to write stats", e),
 			}
 		}
+		Err(e) => fail("Failed to read index", e),
+	}
+
+	if !show_history {
+		process::exit(0);
+	}
+
+	let entries = match history::load(&history::path_for(save_path)) {
+		Ok(v) => v,
+		Err(e) => fail("Failed to read history", e),
+	};
+
+	println!();
+	if entries.is_empty() {
+		println!("No writes recorded yet.");
+		process::exit(0);
+	}
+
+	println!(
+		"{}",
+		style(format!(
+			"{:<16}  {:>9}  {:>8}  {:>10}  {:>10}  {:>8}",
+			"Written", "Documents", "Change", "Size", "Change", "Duration"
+		))
+		.bold()
+	);
+
+	let mut previous: Option<history::Entry> = None;
+	for entry in &entries {
+		let (document_change, byte_change) = match previous {
+			Some(p) => (
+				format_change(entry.documents as i64 - p.documents as i64, |n| {
+					n.to_string()
+				}),
+				format_change(entry.bytes as i64 - p.bytes as i64, history::format_bytes),
+			),
+			None => (String::new(), String::new()),
+		};
+
+		println!(
+			"{:<16}  {:>9}  {document_change:>8}  {:>10}  {byte_change:>10}  {:>7.1}s",
+			history::format_time(entry.time),
+			entry.documents,
+			history::format_bytes(entry.bytes),
+			entry.duration as f64 / 1000.0
+		);
+
+		previous = Some(*entry);
+	}
+
+	let (first, last) = (entries[0], entries[entries.len() - 1]);
+	if entries.len() > 1 {
+		let percent = |from: u64, to: u64| match from {
+			0 => String::new(),
+			from => format!(
+				" ({:+.1}%)",
+				(to as f64 - from as f64) / from as f64 * 100.0
+			),
+		};
+
+		println!();
+		println!(
+			"Over {:.1} days: {} documents{}, {}{}.",
+			last.time.saturating_sub(first.time) as f64 / 86_400.0,
+			format_change(last.documents as i64 - first.documents as i64, |n| {
+				n.to_string()
+			}),
+			percent(first.documents, last.documents),
+			format_change(
+				last.bytes as i64 - first.bytes as i64,
+				history::format_bytes
+			),
+			percent(first.bytes, last.bytes)
+		);
+
+		let (growth, time) = entries
+			.windows(2)
+			.map(|w| (w[1].documents as i64 - w[0].documents as i64, w[1].time))
+			.max_by_key(|(growth, _)| *growth)
+			.expect("history has two entries");
+
+		if growth > 0 {
+			println!(
+				"Largest growth: +{growth} documents at {}.",
+				history::format_time(time)
+			);
+		}
+	}
 
-		buf.push(tri_buf);
+	process::exit(0);
+}
+
+/// Returns the paths of the index files of the index at `save_path`, which
+/// is split into the shards of the `shards` directories if it is sharded.
+fn index_paths(save_path: &Path, shards: Option<&[PathBuf]>) -> Vec<PathBuf> {
+	match shards {
+		Some(dirs) => dirs
+			.iter()
+			.map(|dir| shard::index_path(save_path, dir))
+			.collect(),
+		None => vec![save_path.to_path_buf()],
 	}
 }
 
-fn search(
-	index: &mut Index,
-	terms: Vec<String>,
-) -> Result<Vec<(OsString, usize, Vec<(usize, String)>)>, Box<dyn Error>> {
-	let mut trigrams = Vec::new();
-	terms
+/// Loads every index file of the index at `save_path` without creating,
+/// updating, or migrating any of them, for `--read-compat`.
+fn open_read_only(save_path: &Path, shards: Option<&[PathBuf]>) -> Vec<Index> {
+	let indexes = index_paths(save_path, shards)
 		.iter()
-		.for_each(|t| get_trigrams(t.as_bytes(), &mut trigrams));
+		.map(Index::load)
+		.collect::<Result<Vec<Index>, IndexError>>()
+		.unwrap_or_else(|e| fail("Failed to read index", e));
 
-	let mut any = BitMap::new(index.bitmap_len() as usize);
-	for t in &trigrams {
-		if let Some(v) = index.find_trigram(*t)? {
-			any |= &v;
+	if indexes.iter().any(Index::is_legacy_format) {
+		log::info(
+			"Note: searching an index in an older format; run without --read-compat to migrate it.",
+		);
+	}
+
+	indexes
+}
+
+/// Formats `change` with its sign, formatting its magnitude with `format`.
+fn format_change(change: i64, format: impl Fn(u64) -> String) -> String {
+	let sign = if change < 0 { '-' } else { '+' };
+	format!("{sign}{}", format(change.unsigned_abs()))
+}
+
+/// Appends the size of `indexes`, the shards of the index at `save_path`,
+/// to its history after they were written in `duration`.
+fn record_write(save_path: &Path, indexes: &[Index], duration: Duration) {
+	let documents = indexes.iter().map(|i| i.document_count() as u64).sum();
+	let bytes = indexes.iter().map(Index::file_len).sum();
+	let entry = history::Entry::new(documents, bytes, duration);
+	if let Err(e) = history::append(&history::path_for(save_path), entry) {
+		eprintln!("Warning: failed to record index history: {e}");
+	}
+}
+
+/// Returns the files to index instead of walking the current directory, if
+/// any, and whether the index is rebuilt for them. `index --files-from`
+/// saves the list it reads alongside the index at `save_path`, rebuilding
+/// the index if the list changed, since listed files can be older than the
+/// index. Other commands use the saved list, except a plain `index`, which
+/// deletes it and rebuilds the index. Dry runs change nothing.
+fn load_file_list(save_path: &Path, options: &Options) -> (Option<Vec<PathBuf>>, bool) {
+	let path = file_list::path_for(save_path);
+	let saved = file_list::load(&path).unwrap_or_else(|e| fail("Failed to read file list", e));
+	let Command::Index { dry_run } = options.command else {
+		return (saved, false);
+	};
+
+	let files = options.files_from.as_ref().map(|source| {
+		file_list::read(source).unwrap_or_else(|e| fail("Failed to read file list", e))
+	});
+
+	let rebuild = files != saved;
+	if rebuild && !dry_run {
+		let _ = fs::remove_file(save_path);
+		let result = match &files {
+			Some(files) => file_list::save(&path, files),
+			None => fs::remove_file(&path),
+		};
+
+		if let Err(e) = result {
+			fail("Failed to save file list", e);
 		}
 	}
 
-	let mut documents = Vec::new();
-	for (doc, bit) in any.into_iter().enumerate() {
-		if !bit {
-			continue;
+	(files, rebuild)
+}
+
+/// Adds the build options saved alongside the index at `save_path` to
+/// `options`, and returns whether the index is rebuilt for them. `index`
+/// saves the flags it is given instead, rebuilding the index if they
+/// changed, since updates only reindex changed files. Other commands save
+/// their flags when there is no index yet, so that the index they build,
+/// even in the background, keeps them. Dry runs change nothing.
+fn load_build_options(save_path: &Path, options: &mut Options) -> bool {
+	// Ephemeral, remote, and commit indexes are never updated
+	if options.ephemeral || options.read_compat || options.remote.is_some() || options.rev.is_some()
+	{
+		return false;
+	}
+
+	let path = build_options::path_for(save_path);
+	let saved =
+		build_options::load(&path).unwrap_or_else(|e| fail("Failed to read build options", e));
+
+	let manifest = shard::manifest_path(save_path);
+	let exists = save_path.exists() || manifest.exists();
+	let Command::Index { dry_run } = options.command else {
+		if let Some(saved) = saved {
+			saved.apply(options);
 		}
 
-		let doc = index
-			.find_document(doc as u32)?
-			.expect("find_trigram returned invalid document index");
+		if !exists {
+			save_build_options(&path, saved, BuildOptions::from_options(options));
+		}
+
+		return false;
+	};
 
-		let mut preview_buf = Vec::new();
-		let rank = rank_file(&doc, &terms, &trigrams, &mut preview_buf)?;
-		documents.push((doc, rank, preview_buf));
+	let given = BuildOptions::from_options(options);
+	let rebuild = exists && saved.unwrap_or_default() != given;
+	if dry_run {
+		return rebuild;
 	}
 
-	documents.sort_by(|a, b| b.1.cmp(&a.1));
-	Ok(documents)
+	if rebuild {
+		let _ = fs::remove_file(save_path);
+		if let Ok(Some(dirs)) = shard::load(&manifest) {
+			for dir in dirs {
+				let _ = fs::remove_file(shard::index_path(save_path, &dir));
+			}
+		}
+	}
+
+	save_build_options(&path, saved, given);
+	rebuild
 }
 
-fn show_help(name: Option<&str>) {
-	println!("Usage: {} [search term]", name.unwrap_or("codesearch"));
-	process::exit(1);
+/// Saves `options` to `path` unless they are the `saved` build options.
+fn save_build_options(path: &Path, saved: Option<BuildOptions>, options: BuildOptions) {
+	if saved != Some(options) {
+		if let Err(e) = build_options::save(path, options) {
+			fail("Failed to save build options", e);
+		}
+	}
+}
+
+/// Pairs each index file of the index at `save_path` with the options it is
+/// built with, one per shard if it is sharded.
+fn index_targets(
+	save_path: &Path,
+	shards: Option<&[PathBuf]>,
+	options: &IndexOptions,
+) -> Vec<(PathBuf, IndexOptions)> {
+	match shards {
+		Some(dirs) => dirs
+			.iter()
+			.map(|dir| {
+				let shard_options = IndexOptions {
+					shard: Some(dir.clone()),
+					..options.clone()
+				};
+
+				(shard::index_path(save_path, dir), shard_options)
+			})
+			.collect(),
+		None => vec![(save_path.to_path_buf(), options.clone())],
+	}
+}
+
+/// Lists what creating or updating each index in `targets` would do with
+/// every file, without writing anything. If `rebuild` is set, the indexes
+/// would be created anew.
+fn run_dry_run(targets: &[(PathBuf, IndexOptions)], rebuild: bool) -> ! {
+	let mut plans = Vec::new();
+	for (path, options) in targets {
+		let result = if path.exists() && !rebuild {
+			Index::load(path).and_then(|mut index| index.plan_update(options))
+		} else {
+			index::plan_create(options)
+		};
+
+		match result {
+			Ok(v) => plans.extend(v),
+			Err(e) => fail("Failed to plan index", e),
+		}
+	}
+
+	let mut counts = [0; 5];
+	for (path, plan) in &plans {
+		let (i, label, reason) = match plan {
+			Plan::Index => (0, style("index").green(), String::new()),
+			Plan::Keep => (1, style("keep"), String::new()),
+			Plan::Skip(reason) => (2, style("skip").yellow(), format!(" ({reason})")),
+			Plan::Ignore => (3, style("ignore").dim(), String::new()),
+			Plan::Remove => (4, style("remove").red(), String::new()),
+		};
+
+		counts[i] += 1;
+		println!("{label:<6}  {}{reason}", path.to_string_lossy());
+	}
+
+	let [indexed, kept, skipped, ignored, removed] = counts;
+	println!(
+		"\n{indexed} to index, {kept} kept, {skipped} skipped, {ignored} ignored, {removed} removed."
+	);
+
+	process::exit(0);
+}
+
+/// Verifies each index in `targets`, which pairs an index path with the
+/// options it is built with, rebuilding corrupt indexes if `repair` is set.
+fn run_verify(targets: &[(PathBuf, IndexOptions)], repair: bool) -> ! {
+	let mut checksummed = true;
+	let mut corrupt = Vec::new();
+	for (path, options) in targets {
+		let verification = match Index::load(path) {
+			Ok(mut index) => index.verify(),
+			Err(e) => index::Verification {
+				checksummed: false,
+				problems: vec![e.to_string()],
+			},
+		};
+
+		checksummed &= verification.checksummed;
+		let shard = options
+			.shard
+			.as_ref()
+			.map(|dir| format!(" in shard {}", dir.to_string_lossy()))
+			.unwrap_or_default();
+
+		verification
+			.problems
+			.iter()
+			.for_each(|p| println!("{} {p}", style(format!("Problem{shard}:")).red()));
+
+		if !verification.problems.is_empty() {
+			corrupt.push((path, options));
+		}
+	}
+
+	if corrupt.is_empty() {
+		if !checksummed {
+			println!("Note: this index predates checksums, so only its structure was checked.");
+		}
+
+		println!("Index is valid.");
+		process::exit(0);
+	}
+
+	if !repair {
+		println!("Rerun with --repair to rebuild the index.");
+		process::exit(1);
+	}
+
+	for (path, options) in corrupt {
+		if let Err(e) = Index::repair(path, options) {
+			fail("Index creation failed", e);
+		}
+	}
+
+	println!("Index rebuilt.");
+	process::exit(0);
+}
+
+/// What searches run outside of the main search, e.g. for alerts, need.
+struct SearchContext<'a> {
+	aliases: &'a HashMap<String, Vec<String>>,
+	extractors: &'a [Arc<dyn Extractor>],
+	indexes: &'a mut [Index],
+	path_scoring: &'a PathScoring,
+	comment_scoring: &'a CommentScoring,
+	pinned: &'a [PathBuf],
+}
+
+/// Returns the paths of the files with lines matching `query`.
+fn find_matches(
+	context: &mut SearchContext,
+	query: &[String],
+) -> Result<Vec<String>, CodesearchError> {
+	let options = Options {
+		aliases: expand_aliases(query, context.aliases),
+		candidates: Some(0),
+		terms: query.to_vec(),
+		..Options::default()
+	};
+
+	let results = search_all(
+		context.indexes,
+		&options,
+		context.extractors,
+		context.path_scoring,
+		context.comment_scoring,
+		context.pinned,
+		&CancelToken::default(),
+	)?;
+
+	Ok(results
+		.into_iter()
+		.filter(SearchResult::is_match)
+		.map(|result| result.path.to_string_lossy().into_owned())
+		.collect())
+}
+
+/// Saves an alert for `query`. Files already matching it don't raise the
+/// alert; only files that start matching after later updates do.
+fn add_alert(
+	context: &mut SearchContext,
+	alerts_path: &Path,
+	query: &[String],
+) -> Result<(), CodesearchError> {
+	let mut alerts = alerts::load(alerts_path)?;
+	let alert = Alert {
+		query: query.to_vec(),
+		matches: find_matches(context, query)?,
+	};
+
+	println!(
+		"Added alert {} for {} ({} existing matches).",
+		alerts.len() + 1,
+		alert.query_string(),
+		alert.matches.len()
+	);
+
+	alerts.push(alert);
+	alerts::save(alerts_path, &alerts)?;
+	Ok(())
+}
+
+/// Checks every saved alert against the index, reporting files that match
+/// an alert's query for the first time.
+fn check_alerts(
+	context: &mut SearchContext,
+	alerts_path: &Path,
+	command: Option<&[String]>,
+) -> Result<(), CodesearchError> {
+	let mut alerts = alerts::load(alerts_path)?;
+	if alerts.is_empty() {
+		return Ok(());
+	}
+
+	for alert in &mut alerts {
+		let matches = find_matches(context, &alert.query)?;
+		let new_matches = matches
+			.iter()
+			.filter(|path| !alert.matches.contains(path))
+			.cloned()
+			.collect::<Vec<String>>();
+
+		if !new_matches.is_empty() {
+			alerts::notify(command, alert, &new_matches);
+		}
+
+		alert.matches = matches;
+	}
+
+	alerts::save(alerts_path, &alerts)?;
+	Ok(())
+}
+
+/// Prints the saved alerts, numbered for `alert remove`.
+fn list_alerts(alerts_path: &Path) -> ! {
+	let alerts = match alerts::load(alerts_path) {
+		Ok(v) => v,
+		Err(e) => fail("Failed to read alerts", e),
+	};
+
+	for (i, alert) in alerts.iter().enumerate() {
+		println!("{}\t{}", style(i + 1).bold(), alert.query_string());
+	}
+
+	process::exit(0);
+}
+
+/// Removes the alert numbered `number` by `alert list`.
+fn remove_alert(alerts_path: &Path, number: usize) -> ! {
+	let result = alerts::load(alerts_path).and_then(|mut alerts| {
+		if number > alerts.len() {
+			eprintln!("No alert numbered {number}.");
+			process::exit(Category::Query.exit_code());
+		}
+
+		let alert = alerts.remove(number - 1);
+		alerts::save(alerts_path, &alerts)?;
+		println!("Removed alert for {}.", alert.query_string());
+		Ok(())
+	});
+
+	if let Err(e) = result {
+		fail("Failed to update alerts", e);
+	}
+
+	process::exit(0);
+}
+
+/// Saves the search with the arguments `query` as `name`, replacing any
+/// search saved with that name.
+fn save_search(name: &str, query: &[String]) -> ! {
+	let result = paths::data_dir().and_then(|dir| {
+		let path = saved::path_for(&dir);
+		let mut searches = saved::load(&path)?;
+		searches.insert(String::from(name), query.to_vec());
+		saved::save(&path, &searches)?;
+		Ok(())
+	});
+
+	if let Err(e) = result {
+		fail("Failed to save search", e);
+	}
+
+	println!("Saved {name}: {}", saved::command_line(query));
+	process::exit(0);
+}
+
+/// Prints the saved searches with their arguments.
+fn list_searches() -> ! {
+	let searches = paths::data_dir()
+		.and_then(|dir| Ok(saved::load(&saved::path_for(&dir))?))
+		.unwrap_or_else(|e| fail("Failed to read saved searches", e));
+
+	for (name, query) in searches {
+		println!("{}\t{}", style(name).bold(), saved::command_line(&query));
+	}
+
+	process::exit(0);
+}
+
+/// Returns the arguments of the search saved as `name`, followed by the
+/// `extra` arguments given to `run`.
+fn load_search(name: &str, extra: &[String]) -> Vec<String> {
+	let searches = paths::data_dir()
+		.and_then(|dir| Ok(saved::load(&saved::path_for(&dir))?))
+		.unwrap_or_else(|e| fail("Failed to read saved searches", e));
+
+	let query = match searches.get(name) {
+		Some(v) => v,
+		None => {
+			eprintln!("No search saved as {name}.");
+			process::exit(Category::Query.exit_code());
+		}
+	};
+
+	query.iter().chain(extra).cloned().collect()
+}
+
+/// Returns the arguments of the previous search in the current directory,
+/// followed by the arguments in `args` other than `--last`. The history is
+/// read from beside the index at `index_path` if it is given.
+fn load_last(args: &[String], index_path: Option<&Path>) -> Vec<String> {
+	let queries = paths::save_path(index_path)
+		.and_then(|path| Ok(queries::load(&queries::path_for(&path))?))
+		.unwrap_or_else(|e| fail("Failed to read search history", e));
+
+	let last = match queries.last() {
+		Some(v) => v,
+		None => {
+			eprintln!("No previous search.");
+			process::exit(Category::Query.exit_code());
+		}
+	};
+
+	let extra = args.iter().filter(|arg| *arg != "--last");
+	last.args.iter().chain(extra).cloned().collect()
+}
+
+/// Adds the search with the arguments `query` and its top `results` to the
+/// search history of the index at `save_path`.
+fn record_query(save_path: &Path, query: &[String], results: &[SearchResult]) {
+	let results = results
+		.iter()
+		.take(output::SHOWN_RESULTS)
+		.map(|result| result.path.to_string_lossy().into_owned())
+		.collect();
+
+	let query = queries::Query::new(query.to_vec(), results);
+	if let Err(e) = queries::record(&queries::path_for(save_path), query) {
+		eprintln!("Warning: failed to record search history: {e}");
+	}
+}
+
+/// Prints the recent searches of the index at `save_path`, oldest first,
+/// with their top results.
+fn list_queries(save_path: &Path) -> ! {
+	let queries = queries::load(&queries::path_for(save_path))
+		.unwrap_or_else(|e| fail("Failed to read search history", e));
+
+	for query in queries {
+		println!(
+			"{}  {}",
+			style(history::format_time(query.time)).dim(),
+			style(saved::command_line(&query.args)).bold()
+		);
+
+		query.results.iter().for_each(|path| println!("\t{path}"));
+	}
+
+	process::exit(0);
+}
+
+/// What `serve` answers queries with.
+struct ServeContext<'a> {
+	aliases: &'a HashMap<String, Vec<String>>,
+	/// The cache of each index or shard, kept across queries.
+	caches: Mutex<HashMap<PathBuf, Arc<IndexCache>>>,
+	extractors: &'a [Arc<dyn Extractor>],
+	path_scoring: &'a PathScoring,
+	comment_scoring: &'a CommentScoring,
+	/// The options saved files are reindexed with.
+	index_options: &'a IndexOptions,
+	pinned: &'a [PathBuf],
+	save_path: &'a Path,
+	/// Whether the index may be split into shards, as indexes of file lists
+	/// never are.
+	sharded: bool,
+}
+
+/// A parsed request, or the error it couldn't be parsed with, and the token
+/// that cancels it.
+type Incoming = (
+	Result<protocol::Request, (Value, protocol::ProtocolError)>,
+	CancelToken,
+);
+
+/// Answers JSON-RPC requests read from stdin, with responses written to
+/// stdout, until stdin is closed or a `shutdown` request arrives. Each query
+/// loads the index afresh, so it sees updates made since. With `lsp_proxy`
+/// set, messages are framed with headers as in the Language Server
+/// Protocol, and each query cancels any still being answered.
+fn run_serve(context: &ServeContext, lsp_proxy: bool) -> ! {
+	let framing = if lsp_proxy {
+		Framing::Headers
+	} else {
+		Framing::Lines
+	};
+
+	// Requests are read while others are answered, so they can cancel them,
+	// and files saved meanwhile are reindexed before the queries waiting
+	let pending = Arc::new(Mutex::new(HashMap::new()));
+	let saved = Arc::new(Mutex::new(Vec::new()));
+	let (sender, receiver) = mpsc::channel();
+	let reader_pending = Arc::clone(&pending);
+	let reader_saved = Arc::clone(&saved);
+	thread::spawn(move || {
+		read_requests(framing, lsp_proxy, &reader_pending, &reader_saved, &sender)
+	});
+
+	let mut stdout = std::io::stdout().lock();
+	for (request, cancel) in receiver {
+		let (response, shutdown) = match request {
+			Ok(request) => {
+				let result = match &request.method {
+					Method::Version => Ok(protocol::version()),
+					Method::Shutdown | Method::Cancel(_) => Ok(Value::Null),
+					Method::Save(_) => {
+						reindex_saved(context, &saved);
+						Ok(Value::Null)
+					}
+					Method::Query(query) => {
+						reindex_saved(context, &saved);
+						serve_query(context, query, &cancel)
+							.map(|results| protocol::query_result(&results, &query.terms))
+					}
+				};
+
+				// Notifications get no response
+				let response = request.id.map(|id| {
+					pending
+						.lock()
+						.expect("pending requests poisoned")
+						.remove(&id.to_string());
+
+					match result {
+						Ok(value) => protocol::response(&id, value),
+						Err(CodesearchError::Cancelled) => protocol::error_response(
+							&id,
+							protocol::REQUEST_CANCELLED,
+							"query cancelled",
+						),
+						Err(e) => {
+							protocol::error_response(&id, protocol::SERVER_ERROR, &e.to_string())
+						}
+					}
+				});
+
+				(response, matches!(request.method, Method::Shutdown))
+			}
+			Err((id, e)) => (
+				Some(protocol::error_response(&id, e.code(), &e.to_string())),
+				false,
+			),
+		};
+
+		// The client closing stdout ends the session like closing stdin
+		if let Some(response) = response {
+			if protocol::write_message(&mut stdout, framing, &response).is_err() {
+				break;
+			}
+		}
+
+		if shutdown {
+			break;
+		}
+	}
+
+	process::exit(0);
+}
+
+/// Reads requests from stdin and sends them to `sender` until stdin is
+/// closed. The tokens of queries awaiting a response are kept in `pending`
+/// by id, and cancelled by `$/cancelRequest`, or by any newer query if
+/// `supersede` is set. Saved files are added to `saved` as soon as they are
+/// read, ahead of the requests before them.
+fn read_requests(
+	framing: Framing,
+	supersede: bool,
+	pending: &Mutex<HashMap<String, CancelToken>>,
+	saved: &Mutex<Vec<PathBuf>>,
+	sender: &mpsc::Sender<Incoming>,
+) {
+	let mut stdin = std::io::stdin().lock();
+	loop {
+		let message = match protocol::read_message(&mut stdin, framing) {
+			Ok(Some(v)) => v,
+			Ok(None) => break,
+			Err(e) => {
+				eprintln!("Failed to read request: {e}");
+				break;
+			}
+		};
+
+		if message.trim().is_empty() {
+			continue;
+		}
+
+		let request = protocol::parse_request(&message);
+		let cancel = CancelToken::default();
+		if let Ok(request) = &request {
+			let mut pending = pending.lock().expect("pending requests poisoned");
+			match (&request.method, &request.id) {
+				(Method::Cancel(id), _) => {
+					if let Some(token) = pending.get(&id.to_string()) {
+						token.cancel();
+					}
+				}
+				(Method::Save(path), _) => saved
+					.lock()
+					.expect("saved files poisoned")
+					.push(index::document_path(path)),
+				(Method::Query(_), id) => {
+					if supersede {
+						pending.values().for_each(CancelToken::cancel);
+					}
+
+					if let Some(id) = id {
+						pending.insert(id.to_string(), cancel.clone());
+					}
+				}
+				_ => {}
+			}
+		}
+
+		if sender.send((request, cancel)).is_err() {
+			break;
+		}
+	}
+}
+
+/// Reindexes the files saved in the editor since the last request, in
+/// `saved`, so that queries see the edits without waiting for the next
+/// update of every file.
+fn reindex_saved(context: &ServeContext, saved: &Mutex<Vec<PathBuf>>) {
+	let files = std::mem::take(&mut *saved.lock().expect("saved files poisoned"));
+	if files.is_empty() {
+		return;
+	}
+
+	let shards = match shard::load(&shard::manifest_path(context.save_path)) {
+		Ok(v) => v.filter(|_| context.sharded),
+		Err(e) => {
+			eprintln!("Warning: failed to reindex saved files: {e}");
+			return;
+		}
+	};
+
+	// Each file is only reindexed by the index or shard holding it
+	for (path, options) in
+		index_targets(context.save_path, shards.as_deref(), context.index_options)
+	{
+		if let Err(e) = Index::load(&path).and_then(|mut index| index.reindex(&files, &options)) {
+			eprintln!("Warning: failed to reindex saved files: {e}");
+		}
+	}
+}
+
+/// Runs `query` against the index, returning the files with matching lines,
+/// at most as many as its limit. Stops with `CodesearchError::Cancelled`
+/// once `cancel` is cancelled.
+fn serve_query(
+	context: &ServeContext,
+	query: &Query,
+	cancel: &CancelToken,
+) -> Result<Vec<SearchResult>, CodesearchError> {
+	// Queries superseded while they waited aren't started
+	if cancel.is_cancelled() {
+		return Err(CodesearchError::Cancelled);
+	}
+
+	let options = Options {
+		aliases: expand_aliases(&query.terms, context.aliases),
+		excluded: query.excluded.clone(),
+		paths: query.paths.clone(),
+		symbols: query.symbols,
+		terms: query.terms.clone(),
+		types: query.types.clone(),
+		..Options::default()
+	};
+
+	let shards = if context.sharded {
+		shard::load(&shard::manifest_path(context.save_path))?
+	} else {
+		None
+	};
+
+	let paths = index_paths(context.save_path, shards.as_deref());
+	let mut indexes = paths
+		.iter()
+		.map(Index::load)
+		.collect::<Result<Vec<Index>, IndexError>>()?;
+
+	// Repeated and refined queries look up the same postings, so they are
+	// kept across queries instead of read again
+	let mut caches = context.caches.lock().expect("cache lock poisoned");
+	caches.retain(|path, _| paths.contains(path));
+	for (path, index) in paths.iter().zip(&mut indexes) {
+		index.use_cache(Arc::clone(caches.entry(path.clone()).or_default()));
+	}
+
+	drop(caches);
+
+	let mut results = search_all(
+		&mut indexes,
+		&options,
+		context.extractors,
+		context.path_scoring,
+		context.comment_scoring,
+		context.pinned,
+		cancel,
+	)?;
+	results.retain(SearchResult::is_match);
+	results.truncate(query.limit.unwrap_or(usize::MAX));
+	Ok(results)
+}
+
+/// Searches each of `indexes`, which are the shards of a sharded index,
+/// concurrently, merging their ranked results.
+fn search_all(
+	indexes: &mut [Index],
+	options: &Options,
+	extractors: &[Arc<dyn Extractor>],
+	path_scoring: &PathScoring,
+	comment_scoring: &CommentScoring,
+	pinned: &[PathBuf],
+	cancel: &CancelToken,
+) -> Result<Vec<SearchResult>, CodesearchError> {
+	if let [index] = indexes {
+		return search(
+			index,
+			options,
+			extractors,
+			path_scoring,
+			comment_scoring,
+			pinned,
+			cancel,
+		);
+	}
+
+	let results = shard::parallel_map(indexes.iter_mut().collect(), |index| {
+		search(
+			index,
+			options,
+			extractors,
+			path_scoring,
+			comment_scoring,
+			pinned,
+			cancel,
+		)
+	});
+
+	let mut documents = Vec::new();
+	for result in results {
+		documents.append(&mut result?);
+	}
+
+	result_set::sort(&mut documents);
+	Ok(documents)
+}
+
+/// Searches `index`, returning the ranked candidates. Stops with
+/// `CodesearchError::Cancelled` once `cancel` is cancelled.
+fn search(
+	index: &mut Index,
+	options: &Options,
+	extractors: &[Arc<dyn Extractor>],
+	path_scoring: &PathScoring,
+	comment_scoring: &CommentScoring,
+	pinned: &[PathBuf],
+	cancel: &CancelToken,
+) -> Result<Vec<SearchResult>, CodesearchError> {
+	let terms = &options.terms;
+	let all_terms = terms
+		.iter()
+		.chain(&options.aliases)
+		.cloned()
+		.collect::<Vec<String>>();
+
+	// Expand the query into identifier subtokens
+	let mut subtokens = all_terms
+		.iter()
+		.flat_map(|t| tokenize::split_identifier(t))
+		.collect::<Vec<String>>();
+
+	subtokens.sort();
+	subtokens.dedup();
+
+	let mut token_bitmaps = Vec::with_capacity(subtokens.len());
+	for token in subtokens {
+		if let Some(v) = index.find_token(&token)? {
+			token_bitmaps.push((token, v));
+		}
+	}
+
+	let stop_ngrams = find_stop_ngrams(index, &all_terms)?;
+	let document_count = index.document_count() as usize;
+	let mut ngrams = Vec::new();
+	// The documents containing every term, or one of their aliases
+	let mut every_term: Option<BitMap> = None;
+	let mut alias_matches = BitMap::new(document_count);
+	// The documents containing any term, then any of the n-grams read, and
+	// the n-grams left unread, in case no document contains every term
+	let mut any_term = BitMap::new(document_count);
+	let mut partial = BitMap::new(document_count);
+	let mut unread = Vec::new();
+	let mut term_bitmaps = Vec::with_capacity(terms.len());
+	let mut term_frequencies = Vec::with_capacity(terms.len());
+	// How many of the query's n-grams each document contains
+	let mut ngram_matches = vec![0; document_count];
+	// The terms shorter than an n-gram, which any document may contain
+	let mut short_terms = Vec::new();
+	for (i, term) in all_terms.iter().enumerate() {
+		let mut term_ngrams = Vec::new();
+		ngram::get_ngrams(term, index.ngram_size(), &mut term_ngrams);
+		if i < terms.len() && term_ngrams.is_empty() {
+			short_terms.push(term.to_lowercase());
+		}
+
+		// A document can only contain a term if it contains all of its
+		// n-grams, so they are intersected from the rarest, which isn't
+		// indexed at all if it sorts first, and the rest aren't read once
+		// no document is left
+		let mut planned = Vec::with_capacity(term_ngrams.len());
+		for t in &term_ngrams {
+			planned.push((index.ngram_document_frequency(*t)?, *t));
+		}
+
+		planned.sort();
+		let mut all: Option<BitMap> = None;
+		let mut read = 0;
+		for (_, t) in &planned {
+			if all.as_ref().is_some_and(BitMap::is_empty) {
+				unread.push(*t);
+				continue;
+			}
+
+			read += 1;
+			let v = match index.find_ngram_ref(*t)? {
+				Some(v) => v,
+				None => {
+					all = Some(BitMap::new(document_count));
+					continue;
+				}
+			};
+
+			// Stop n-grams still narrow the term, but don't add candidates
+			if !stop_ngrams.contains(t) {
+				partial |= v;
+				v.iter_ones().for_each(|doc_id| ngram_matches[doc_id] += 1);
+			}
+
+			all = Some(match all {
+				Some(mut all) => {
+					all &= v;
+					all
+				}
+				None => v.to_bitmap(),
+			});
+		}
+
+		// The documents that may contain the term
+		let mut matches = None;
+		if let Some(all) = all {
+			if log::enabled(Level::Verbose) {
+				eprintln!(
+					"\"{term}\": read {read} of {} n-grams, rarest first, leaving {} candidates",
+					planned.len(),
+					all.count_ones()
+				);
+			}
+
+			// Terms made only of stop n-grams don't add candidates either
+			if term_ngrams.iter().any(|t| !stop_ngrams.contains(t)) {
+				any_term |= &all;
+			}
+
+			term_frequencies.push(TermFrequencies {
+				counts: term_counts(index, &term_ngrams, &all)?,
+				documents: all.count_ones(),
+			});
+
+			// Aliases are alternatives to the terms, so they don't narrow
+			if i >= terms.len() {
+				alias_matches |= &all;
+			} else {
+				matches = Some(all.clone());
+				term_bitmaps.push((term, all));
+			}
+		}
+
+		// A term is also found in the documents containing all of its
+		// identifier subtokens, e.g. `user_name` in `getUserName`
+		if i < terms.len() {
+			let mut all_tokens: Option<BitMap> = None;
+			for token in tokenize::split_identifier(term) {
+				let v = match token_bitmaps.iter().find(|(t, _)| *t == token) {
+					Some((_, v)) => v.clone(),
+					None => BitMap::new(document_count),
+				};
+
+				all_tokens = Some(match all_tokens {
+					Some(mut all_tokens) => {
+						all_tokens &= &v;
+						all_tokens
+					}
+					None => v,
+				});
+			}
+
+			let matches = match (matches, all_tokens) {
+				(Some(mut matches), Some(all_tokens)) => {
+					matches |= &all_tokens;
+					Some(matches)
+				}
+				(matches, all_tokens) => matches.or(all_tokens),
+			};
+
+			// Terms shorter than an n-gram can't be looked up, so they
+			// don't narrow the candidates
+			if let Some(matches) = matches.filter(|_| !term_ngrams.is_empty()) {
+				every_term = Some(match every_term {
+					Some(mut every) => {
+						every &= &matches;
+						every
+					}
+					None => matches,
+				});
+			}
+		}
+
+		ngrams.append(&mut term_ngrams);
+	}
+
+	// Candidates contain every term, or one of their aliases, unless --any
+	// asks for those containing any term or no document contains them all
+	let every_term = every_term.filter(|every| !options.any && !every.is_empty());
+	// Terms shorter than an n-gram can't narrow the candidates, so when
+	// they decide them, every document is checked for them instead
+	let only_short = !short_terms.is_empty() && short_terms.len() == terms.len();
+	let checks_everything = !short_terms.is_empty() && (only_short || every_term.is_none());
+	if checks_everything {
+		for term in terms
+			.iter()
+			.filter(|t| short_terms.contains(&t.to_lowercase()))
+		{
+			log::info(format_args!(
+				"Note: \"{term}\" is shorter than the index's {}-byte n-grams, so every file is checked for it.",
+				index.ngram_size()
+			));
+		}
+	}
+	let mut any = match every_term {
+		Some(mut every) => {
+			every |= &alias_matches;
+			every
+		}
+		None => {
+			if !options.any && terms.len() > 1 && !only_short {
+				log::info(format_args!(
+					"Note: no file contains every search term; showing files containing any of them."
+				));
+			}
+
+			// When no document contains a whole term, e.g. one misspelled,
+			// the candidates are those sharing any of the query's rarer
+			// n-grams
+			if any_term.is_empty() {
+				for t in unread.into_iter().filter(|t| !stop_ngrams.contains(t)) {
+					if let Some(v) = index.find_ngram_ref(t)? {
+						partial |= v;
+						v.iter_ones().for_each(|doc_id| ngram_matches[doc_id] += 1);
+					}
+				}
+
+				any_term = partial;
+			}
+
+			if let Some(narrowed) = narrow_by_selectivity(index, &term_bitmaps, options.auto_narrow)
+			{
+				any_term &= &narrowed;
+			}
+
+			token_bitmaps.iter().for_each(|(_, v)| any_term |= v);
+			any_term
+		}
+	};
+
+	// The candidates found by their n-grams, which need not be checked for
+	// the short terms
+	let indexed =
+		checks_everything.then(|| std::mem::replace(&mut any, !BitMap::new(document_count)));
+
+	// Restrict candidates to the requested paths, by the index's directory
+	// bitmaps where it has them and by each candidate's path otherwise
+	if !options.paths.is_empty() {
+		let mut within = BitMap::new(index.document_count() as usize);
+		let mut other_paths = Vec::new();
+		for path in &options.paths {
+			match index.directory_bitmap(path)? {
+				Some(v) => within |= &v,
+				None => other_paths.push(path.clone()),
+			}
+		}
+
+		if !other_paths.is_empty() {
+			for doc_id in any.iter_ones() {
+				if let Some(doc) = index.find_document(doc_id as u32)? {
+					if is_within(Path::new(&doc), &other_paths) {
+						within.set(doc_id, true);
+					}
+				}
+			}
+		}
+
+		any &= &within;
+	}
+
+	// Restrict candidates to the results being refined
+	if let Some(previous) = &options.refine {
+		for doc_id in any.clone().iter_ones() {
+			if let Some(doc) = index.find_document(doc_id as u32)? {
+				any.set(doc_id, previous.contains(Path::new(&doc)));
+			}
+		}
+	}
+
+	// Restrict candidates to the requested languages
+	let mut filter_paths = false;
+	if !options.types.is_empty() {
+		let mut types = BitMap::new(index.document_count() as usize);
+		for language in &options.types {
+			match index.language_bitmap(*language)? {
+				Some(v) => types |= &v,
+				None => filter_paths = true,
+			}
+		}
+
+		if !filter_paths {
+			any &= &types;
+		}
+	}
+
+	// Drop the candidates sure to contain an excluded term, those with it as
+	// an identifier subtoken, and check the rest that have all of its
+	// n-grams by their contents
+	let mut may_exclude = BitMap::new(index.document_count() as usize);
+	for term in &options.excluded {
+		let lowercase = term.to_lowercase();
+		if let [token] = &tokenize::split_identifier(term)[..] {
+			if *token == lowercase {
+				if let Some(v) = index.find_token(token)? {
+					any -= &v;
+				}
+			}
+		}
+
+		let mut term_ngrams = Vec::new();
+		ngram::get_ngrams(term, index.ngram_size(), &mut term_ngrams);
+		let mut all = any.clone();
+		for t in term_ngrams {
+			match index.find_ngram_ref(t)? {
+				Some(v) => all &= v,
+				None => all = BitMap::new(index.document_count() as usize),
+			}
+		}
+
+		may_exclude |= &all;
+	}
+
+	let excluded = options
+		.excluded
+		.iter()
+		.map(|t| t.to_lowercase())
+		.collect::<Vec<String>>();
+
+	let lengths = index.document_lengths()?.unwrap_or_default();
+	let stats = CollectionStats {
+		average_length: lengths.iter().map(|v| *v as f64).sum::<f64>()
+			/ f64::max(lengths.len() as f64, 1.0),
+		document_count: index.document_count() as usize,
+		lengths,
+	};
+
+	// Only rank the candidates sharing the most n-grams with the query,
+	// breaking ties by their BM25 scores, and keep any pinned candidates.
+	// Terms without n-grams leave nothing to tell candidates apart by, so
+	// every candidate is ranked for them
+	let candidates = options.candidates.unwrap_or(args::DEFAULT_CANDIDATES);
+	if candidates > 0 && !checks_everything && any.count_ones() > candidates {
+		let score = |doc_id| rank_bm25(&term_frequencies, &stats, doc_id);
+		let mut top = top_candidates(&any, &ngram_matches, candidates, score);
+		if !pinned.is_empty() {
+			let dropped = any.clone() - &top;
+			for doc_id in dropped.iter_ones() {
+				if let Some(doc) = index.find_document(doc_id as u32)? {
+					top.set(doc_id, is_within(Path::new(&doc), pinned));
+				}
+			}
+		}
+
+		any = top;
+	}
+
+	if any.is_empty() {
+		return Ok(Vec::new());
+	}
+
+	let compressed = index.compressed()?;
+	let languages = index.languages()?;
+	let mut symbols = if options.symbols {
+		index.symbols()?
+	} else {
+		Vec::new()
+	};
+
+	// With a deadline, the most promising candidates are ranked first, so
+	// those ranked in time are the best found
+	let mut order = any.iter_ones().collect::<Vec<usize>>();
+	if cancel.has_deadline() {
+		order.sort_by_cached_key(|doc_id| {
+			let score = rank_bm25(&term_frequencies, &stats, *doc_id);
+			std::cmp::Reverse((ngram_matches[*doc_id], score))
+		});
+	}
+
+	let mut documents = Vec::new();
+	for doc_id in order {
+		if cancel.is_cancelled() {
+			return Err(CodesearchError::Cancelled);
+		}
+
+		if cancel.is_expired() {
+			break;
+		}
+
+		let doc_symbols = symbols.get_mut(doc_id).map(std::mem::take);
+		let doc_tokens = token_bitmaps
+			.iter()
+			.filter(|(_, bitmap)| bitmap.get(doc_id))
+			.map(|(token, _)| token.as_str());
+
+		let token_rank = rank_tokens(doc_tokens);
+		let doc = index
+			.find_document(doc_id as u32)?
+			.expect("find_ngram_ref returned invalid document index");
+
+		// Indexes without language classes are filtered by path instead
+		if filter_paths
+			&& !Language::from_path(doc.as_ref()).is_some_and(|l| options.types.contains(&l))
+		{
+			continue;
+		}
+
+		let doc_path = PathBuf::from(&doc);
+		let doc_compressed = compressed.as_ref().is_some_and(|c| c.get(doc_id));
+		// Files deleted or changed since the index was updated, e.g. while
+		// updates are deferred, are dropped rather than failing the search
+		let (contents, cut) = match index::read_ranked_document(
+			&doc_path,
+			doc_compressed,
+			extractors,
+			options.rev.as_deref(),
+		) {
+			Ok(v) => v,
+			Err(e) => {
+				if log::enabled(Level::Verbose) {
+					eprintln!("Skipped {}: {e}", doc_path.to_string_lossy());
+				}
+
+				continue;
+			}
+		};
+		note_cut(&doc_path, cut);
+		let contents = encoding::decode(contents);
+		let contents = if options.no_suppressions {
+			Cow::Borrowed(contents.as_str())
+		} else {
+			match suppress::apply(&contents) {
+				Some(v) => v,
+				None => continue,
+			}
+		};
+
+		if may_exclude.get(doc_id) && contains_any(&contents.to_lowercase(), &excluded) {
+			continue;
+		}
+
+		// Documents that aren't candidates by their n-grams must contain the
+		// short terms: all of them when they're the whole query, else any
+		if indexed.as_ref().is_some_and(|v| !v.get(doc_id)) {
+			let lowercase = contents.to_lowercase();
+			let mut found = short_terms
+				.iter()
+				.filter(|t| lowercase.contains(t.as_str()));
+			let contains = if only_short && !options.any {
+				found.count() == short_terms.len()
+			} else {
+				found.next().is_some()
+			};
+
+			if !contains {
+				continue;
+			}
+		}
+
+		let mut matches = Vec::new();
+		let logical_path = compression::logical_path(&doc_path, doc_compressed);
+
+		// Indexes built before shebangs and modelines were read lack some
+		let language = languages[doc_id].or_else(|| Language::detect(&logical_path, &contents));
+		let mut rank = rank_file(
+			&logical_path,
+			language,
+			&contents,
+			terms,
+			&ngrams,
+			&mut matches,
+			cancel,
+		) + rank_aliases(
+			&logical_path,
+			language,
+			&contents,
+			&options.aliases,
+			&mut matches,
+			cancel,
+		) + rank_bm25(&term_frequencies, &stats, doc_id)
+			+ rank_short_terms(&contents, &short_terms)
+			+ rank_path(&logical_path, &all_terms, path_scoring)
+			+ token_rank;
+		if let Some(doc_symbols) = doc_symbols {
+			let symbol_rank = rank_symbols(&doc_symbols, &contents, &all_terms, &mut matches);
+			rank += if options.rust {
+				boost_module_root(&logical_path, symbol_rank)
+			} else {
+				symbol_rank
+			};
+		}
+
+		let Some(rank) = weigh_matches(
+			rank,
+			&contents,
+			language,
+			&mut matches,
+			options,
+			comment_scoring,
+		) else {
+			continue;
+		};
+
+		let rank = demote_path(&logical_path, rank, path_scoring);
+
+		// A document ranked only partly is left out
+		if cancel.should_stop() {
+			break;
+		}
+
+		let result = SearchResult {
+			path: doc,
+			score: rank,
+			matches,
+			pinned: rank > 0 && is_within(&doc_path, pinned),
+		};
+
+		let matched = result.is_match();
+		documents.push(result);
+
+		// With --quiet, nothing is printed, so the first match decides
+		if matched && options.verbosity == Level::Quiet {
+			break;
+		}
+	}
+
+	if cancel.is_cancelled() {
+		return Err(CodesearchError::Cancelled);
+	}
+
+	result_set::sort(&mut documents);
+	Ok(documents)
+}
+
+/// Searches the files in the current directory without an index, for when
+/// it hasn't been built yet. Every file is read, and symbols aren't ranked.
+fn search_files(
+	options: &Options,
+	index_options: &IndexOptions,
+	path_scoring: &PathScoring,
+	comment_scoring: &CommentScoring,
+	pinned: &[PathBuf],
+	cancel: &CancelToken,
+) -> Result<Vec<SearchResult>, CodesearchError> {
+	let terms = &options.terms;
+	let lowercase_terms = terms
+		.iter()
+		.chain(&options.aliases)
+		.map(|t| t.to_lowercase())
+		.collect::<Vec<String>>();
+
+	let excluded = options
+		.excluded
+		.iter()
+		.map(|t| t.to_lowercase())
+		.collect::<Vec<String>>();
+
+	let mut documents = Vec::new();
+	let walked = index::walk(index_options)
+		.map(|entry| entry.map(|e| index::expand_archive(e.into_path(), 0)))
+		.collect::<Result<Vec<_>, ignore::Error>>()?;
+
+	for (doc_path, _) in walked.into_iter().flatten() {
+		if cancel.should_stop() {
+			break;
+		}
+
+		if !options.paths.is_empty() && !is_within(&doc_path, &options.paths) {
+			continue;
+		}
+
+		if options
+			.refine
+			.as_ref()
+			.is_some_and(|previous| !previous.contains(&doc_path))
+		{
+			continue;
+		}
+
+		// Directories and unreadable, binary, or generated files are skipped
+		let compressed = compression::is_compressed(&doc_path);
+		let extractors = &index_options.extractors;
+		let logical_path = compression::logical_path(&doc_path, compressed);
+		let contents = match index::read_ranked_document(&doc_path, compressed, extractors, None)
+			.ok()
+			.map(|(contents, cut)| {
+				note_cut(&doc_path, cut);
+				contents
+			})
+			.filter(|v| options.binary || !encoding::is_binary(v))
+			.map(encoding::decode)
+			.filter(|v| options.include_generated || !generated::is_generated(&logical_path, v))
+		{
+			Some(v) => v,
+			None => continue,
+		};
+
+		let contents = if options.no_suppressions {
+			Cow::Borrowed(contents.as_str())
+		} else {
+			match suppress::apply(&contents) {
+				Some(v) => v,
+				None => continue,
+			}
+		};
+
+		let language = Language::detect(&logical_path, &contents);
+		if !options.types.is_empty() && !language.is_some_and(|l| options.types.contains(&l)) {
+			continue;
+		}
+
+		let lowercase = contents.to_lowercase();
+		if !contains_any(&lowercase, &lowercase_terms) || contains_any(&lowercase, &excluded) {
+			continue;
+		}
+
+		let mut matches = Vec::new();
+		let rank = rank_file(
+			&logical_path,
+			language,
+			&contents,
+			terms,
+			&[],
+			&mut matches,
+			cancel,
+		) + rank_aliases(
+			&logical_path,
+			language,
+			&contents,
+			&options.aliases,
+			&mut matches,
+			cancel,
+		) + rank_path(&logical_path, terms, path_scoring);
+
+		let Some(rank) = weigh_matches(
+			rank,
+			&contents,
+			language,
+			&mut matches,
+			options,
+			comment_scoring,
+		) else {
+			continue;
+		};
+
+		let rank = demote_path(&logical_path, rank, path_scoring);
+		if cancel.should_stop() {
+			break;
+		}
+
+		let result = SearchResult {
+			pinned: rank > 0 && is_within(&doc_path, pinned),
+			path: doc_path.into_os_string(),
+			score: rank,
+			matches,
+		};
+
+		let matched = result.is_match();
+		documents.push(result);
+		if matched && options.verbosity == Level::Quiet {
+			break;
+		}
+	}
+
+	result_set::sort(&mut documents);
+	Ok(documents)
+}
+
+/// Returns `true` if `text` contains any of `terms`.
+fn contains_any(text: &str, terms: &[String]) -> bool {
+	terms.iter().any(|t| text.contains(t.as_str()))
+}
+
+/// Returns the `limit` documents in `candidates` with the most n-gram
+/// `matches`, breaking ties by `score` and then by document ID.
+fn top_candidates<F>(candidates: &BitMap, matches: &[u32], limit: usize, score: F) -> BitMap
+where
+	F: Fn(usize) -> usize,
+{
+	let ids = candidates.iter_ones().collect::<Vec<usize>>();
+	let mut order = (0..ids.len()).collect::<Vec<usize>>();
+	order.sort_by_cached_key(|i| std::cmp::Reverse((matches[ids[*i]], score(ids[*i]))));
+
+	let mut top = BitMap::new(candidates.len());
+	order
+		.into_iter()
+		.take(limit)
+		.for_each(|i| top.set(ids[i], true));
+
+	top
+}
+
+/// Returns the aliases of `terms` that aren't already search terms.
+fn expand_aliases(terms: &[String], aliases: &HashMap<String, Vec<String>>) -> Vec<String> {
+	let mut expanded = Vec::new();
+	for alias in terms
+		.iter()
+		.filter_map(|term| aliases.get(&term.to_lowercase()))
+		.flatten()
+	{
+		if !terms.contains(alias) && !expanded.contains(alias) {
+			expanded.push(alias.clone());
+		}
+	}
+
+	expanded
+}
+
+/// Ranks `contents` by each of the `aliases` on its own, as if it were
+/// searched for alone.
+fn rank_aliases(
+	path: &Path,
+	language: Option<Language>,
+	contents: &str,
+	aliases: &[String],
+	matches: &mut Vec<Match>,
+	cancel: &CancelToken,
+) -> usize {
+	aliases
+		.iter()
+		.map(|alias| {
+			rank_file(
+				path,
+				language,
+				contents,
+				std::slice::from_ref(alias),
+				&[],
+				matches,
+				cancel,
+			)
+		})
+		.sum()
+}
+
+/// Returns `rank` weighed by whether `matches` are in code, comments, or
+/// string literals of `contents`, first dropping those outside code for
+/// `--code-only`. Returns `None` if none are left.
+fn weigh_matches(
+	rank: usize,
+	contents: &str,
+	language: Option<Language>,
+	matches: &mut Vec<Match>,
+	options: &Options,
+	scoring: &CommentScoring,
+) -> Option<usize> {
+	if !options.code_only && scoring.is_neutral() {
+		return Some(rank);
+	}
+
+	let regions = Regions::find(contents, language);
+	if options.code_only {
+		matches.retain(|m| regions.at(m.byte_range.start) == Region::Code);
+		if matches.is_empty() {
+			return None;
+		}
+	}
+
+	Some(weigh_regions(rank, matches, &regions, scoring))
+}
+
+/// Returns `true` if `path` is, or is in, one of `paths`.
+fn is_within(path: &Path, paths: &[PathBuf]) -> bool {
+	let path = path.strip_prefix(".").unwrap_or(path);
+	paths.iter().any(|p| {
+		let p = p.strip_prefix(".").unwrap_or(p);
+		encoding::strip_path_prefix(path, p).is_some()
+	})
+}
+
+/// Replaces search terms that are Rust paths into `workspace`, such as
+/// `crate::index::Index`, with the item they name, and restricts the search
+/// to the files of the modules they name.
+fn resolve_rust_paths(workspace: &Workspace, options: &mut Options) {
+	for term in &mut options.terms {
+		if let Some((files, item)) = workspace.resolve_path(term) {
+			options.paths.extend(files);
+			*term = item;
+		}
+	}
+}
+
+/// Estimates how many times a term occurs in each document from the counts
+/// of its `ngrams`, as a term can't occur more often than its rarest n-gram.
+/// Documents in `all` are counted once if the index has no frequencies.
+fn term_counts(index: &mut Index, ngrams: &[NGram], all: &BitMap) -> Result<Vec<u32>, IndexError> {
+	let mut counts = (0..index.document_count() as usize)
+		.map(|i| if all.get(i) { u32::MAX } else { 0 })
+		.collect::<Vec<u32>>();
+
+	for ngram in ngrams {
+		let frequencies = match index.ngram_frequencies(*ngram)? {
+			Some(v) => v,
+			None => continue,
+		};
+
+		for (count, frequency) in counts.iter_mut().zip(frequencies) {
+			*count = (*count).min(frequency);
+		}
+	}
+
+	counts
+		.iter_mut()
+		.filter(|count| **count == u32::MAX)
+		.for_each(|count| *count = 1);
+
+	Ok(counts)
+}
+
+/// Returns the n-grams of `terms` in so many documents of `index` that they
+/// would make nearly every document a candidate, like `the` or `con`.
+/// Candidates are found by the query's rarer n-grams instead, so if it has
+/// none, or the index is too small for frequencies to mean much, there are
+/// no stop n-grams.
+fn find_stop_ngrams(index: &mut Index, terms: &[String]) -> Result<HashSet<NGram>, IndexError> {
+	const STOP_RATIO: f64 = 0.5;
+	const MIN_DOCUMENTS: u32 = 100;
+
+	let mut stop_ngrams = HashSet::new();
+	let document_count = index.document_count();
+	if document_count < MIN_DOCUMENTS {
+		return Ok(stop_ngrams);
+	}
+
+	let mut ngrams = Vec::new();
+	for term in terms {
+		ngram::get_ngrams(term, index.ngram_size(), &mut ngrams);
+	}
+
+	let mut rare = false;
+	for t in ngrams {
+		match index.ngram_document_frequency(t)? {
+			Some(documents) if documents as f64 > STOP_RATIO * document_count as f64 => {
+				stop_ngrams.insert(t);
+			}
+			Some(_) => rare = true,
+			None => {}
+		}
+	}
+
+	if !rare {
+		stop_ngrams.clear();
+	}
+
+	if log::enabled(Level::Verbose) && !stop_ngrams.is_empty() {
+		let mut skipped = stop_ngrams
+			.iter()
+			.map(|t| format!("\"{}\"", String::from_utf8_lossy(t.as_bytes())))
+			.collect::<Vec<String>>();
+
+		skipped.sort();
+		eprintln!(
+			"Finding candidates without common n-grams {}",
+			skipped.join(", ")
+		);
+	}
+
+	Ok(stop_ngrams)
+}
+
+/// Looks for query terms that match nearly every document alongside terms
+/// that match very few. Common terms add candidates without adding any
+/// selectivity, so the user is told about them, and if `apply` is set,
+/// the intersection of the rare terms' documents is returned to narrow
+/// the candidates to.
+fn narrow_by_selectivity(
+	index: &Index,
+	term_bitmaps: &[(&String, BitMap)],
+	apply: bool,
+) -> Option<BitMap> {
+	const COMMON_RATIO: f64 = 0.5;
+	const RARE_RATIO: f64 = 0.05;
+
+	let document_count = index.document_count() as f64;
+	if term_bitmaps.len() < 2 || document_count == 0.0 {
+		return None;
+	}
+
+	let ratio = |bitmap: &BitMap| bitmap.count_ones() as f64 / document_count;
+	let common = term_bitmaps
+		.iter()
+		.filter(|(_, bitmap)| ratio(bitmap) >= COMMON_RATIO)
+		.collect::<Vec<_>>();
+
+	let rare = term_bitmaps
+		.iter()
+		.filter(|(_, bitmap)| !bitmap.is_empty() && ratio(bitmap) <= RARE_RATIO)
+		.collect::<Vec<_>>();
+
+	if common.is_empty() || rare.is_empty() {
+		return None;
+	}
+
+	let quote = |terms: &[&(&String, BitMap)]| {
+		terms
+			.iter()
+			.map(|(term, _)| format!("\"{term}\""))
+			.collect::<Vec<String>>()
+			.join(", ")
+	};
+
+	for (term, bitmap) in &common {
+		log::info(format_args!(
+			"Note: \"{term}\" appears in {:.0}% of files and carries no selectivity.",
+			ratio(bitmap) * 100.0
+		));
+	}
+
+	if !apply {
+		log::info(format_args!(
+			"Note: rerun with --auto-narrow to only verify files matching {}.",
+			quote(&rare)
+		));
+
+		return None;
+	}
+
+	let mut narrowed = rare[0].1.clone();
+	rare[1..].iter().for_each(|(_, bitmap)| narrowed &= bitmap);
+	log::info(format_args!(
+		"Narrowed search to {} files matching {}.",
+		narrowed.count_ones(),
+		quote(&rare)
+	));
+
+	Some(narrowed)
+}
+
+/// Turns styled output on or off as `choice` says. Automatically, output is
+/// styled if written to a terminal, as `console` detects, unless `NO_COLOR`
+/// is set to anything but an empty string.
+fn set_colors(choice: ColorChoice) {
+	let enabled = match choice {
+		ColorChoice::Always => true,
+		ColorChoice::Never => false,
+		ColorChoice::Auto if env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()) => false,
+		ColorChoice::Auto => return,
+	};
+
+	console::set_colors_enabled(enabled);
+	console::set_colors_enabled_stderr(enabled);
+}
+
+/// Prints `error` after `what` failed and exits with the error's exit code.
+fn fail(what: &str, error: impl Into<CodesearchError>) -> ! {
+	let error = error.into();
+	eprintln!("{what}: {error}");
+	process::exit(error.exit_code());
+}
+
+/// Prints the help of the subcommand named `command`, or of codesearch if
+/// none is, and exits.
+fn show_help(name: Option<&str>, command: Option<&str>) -> ! {
+	let program = name.unwrap_or("codesearch");
+	match command.and_then(args::subcommand) {
+		Some(command) => print!("{}", help::command(program, command)),
+		None => print!("{}", help::general(program)),
+	}
+
+	process::exit(0);
 }
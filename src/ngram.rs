@@ -0,0 +1,78 @@
+use std::fmt::Display;
+
+/// The n-gram size used when none is specified.
+pub const DEFAULT_NGRAM_SIZE: u8 = 3;
+
+/// The smallest supported n-gram size.
+pub const MIN_NGRAM_SIZE: u8 = 2;
+
+/// The largest supported n-gram size.
+pub const MAX_NGRAM_SIZE: u8 = 4;
+
+/// A sequence of 2 to 4 bytes from the lowercased text of a document.
+/// All n-grams in an index share the same length, so n-grams order the
+/// same way as their bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NGram {
+	bytes: [u8; MAX_NGRAM_SIZE as usize],
+	len: u8,
+}
+
+impl NGram {
+	/// Creates an n-gram from `bytes`.
+	/// Panics if `bytes` is longer than `MAX_NGRAM_SIZE`.
+	pub fn new(bytes: &[u8]) -> Self {
+		assert!(bytes.len() <= MAX_NGRAM_SIZE as usize);
+		let mut buf = [0; MAX_NGRAM_SIZE as usize];
+		buf[..bytes.len()].copy_from_slice(bytes);
+		Self {
+			bytes: buf,
+			len: bytes.len() as u8,
+		}
+	}
+
+	/// Returns the bytes of this n-gram.
+	pub fn as_bytes(&self) -> &[u8] {
+		&self.bytes[..self.len as usize]
+	}
+
+	/// Returns this n-gram as a string, or `None` if it splits a multi-byte character.
+	pub fn as_str(&self) -> Option<&str> {
+		std::str::from_utf8(self.as_bytes()).ok()
+	}
+}
+
+impl Display for NGram {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", String::from_utf8_lossy(self.as_bytes()))
+	}
+}
+
+/// Returns `true` if n-grams of `size` bytes can be indexed.
+pub fn is_supported_size(size: u8) -> bool {
+	(MIN_NGRAM_SIZE..=MAX_NGRAM_SIZE).contains(&size)
+}
+
+/// Collects the n-grams of `text` into `buf`.
+/// N-grams are every `size`-byte window of the UTF-8 encoding of each run
+/// of alphanumeric characters, after Unicode lowercasing. N-grams never
+/// span non-alphanumeric characters.
+pub fn get_ngrams(text: &str, size: u8, buf: &mut Vec<NGram>) {
+	let mut run = Vec::new();
+	let mut utf8 = [0; 4];
+	for c in text.chars().chain(std::iter::once(' ')) {
+		if c.is_alphanumeric() {
+			for lower in c.to_lowercase() {
+				run.extend_from_slice(lower.encode_utf8(&mut utf8).as_bytes());
+			}
+
+			continue;
+		}
+
+		for window in run.windows(size as usize) {
+			buf.push(NGram::new(window));
+		}
+
+		run.clear();
+	}
+}
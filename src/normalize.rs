@@ -0,0 +1,65 @@
+use std::fmt::Debug;
+
+/// The fewest characters `EnglishStemmer` leaves of a word.
+const MIN_STEM_LEN: usize = 3;
+
+/// Suffixes `EnglishStemmer` strips, longest first where one ends another.
+const SUFFIXES: &[&str] = &[
+	"ations", "ation", "ments", "ment", "ness", "ings", "ing", "ers", "er", "edly", "ed", "es",
+	"ly", "s",
+];
+
+/// Reduces words to a normal form, so that different forms of a word are
+/// found by searching for any of them. Applied to identifier subtokens when
+/// indexing and to search terms when searching.
+pub trait Normalizer: Debug + Send + Sync {
+	/// Returns the normal form of the lowercase `word`.
+	fn normalize(&self, word: &str) -> String;
+}
+
+/// A light English stemmer that strips common suffixes, so `parse`,
+/// `parser`, `parses`, and `parsing` all become `pars`. Stems are prefixes
+/// of the words they come from, so their n-grams find every form.
+#[derive(Debug, Default)]
+pub struct EnglishStemmer;
+
+impl Normalizer for EnglishStemmer {
+	fn normalize(&self, word: &str) -> String {
+		let is_stem = |stem: &str| stem.chars().count() >= MIN_STEM_LEN;
+
+		// `entries` and `entry` share `entr`
+		if let Some(stem) = word.strip_suffix("ies").filter(|v| is_stem(v)) {
+			return stem.to_string();
+		}
+
+		let mut stem = word;
+		for suffix in SUFFIXES {
+			let Some(rest) = stem.strip_suffix(suffix).filter(|v| is_stem(v)) else {
+				continue;
+			};
+
+			// `class` and `status` aren't plurals
+			if *suffix == "s" && rest.ends_with(['s', 'u', 'i']) {
+				break;
+			}
+
+			stem = rest;
+			break;
+		}
+
+		// `parse` becomes `pars`, like `parsing`, and `entry` `entr`
+		if let Some(rest) = stem.strip_suffix(['e', 'y']).filter(|v| is_stem(v)) {
+			stem = rest;
+		}
+
+		// `running` becomes `run`, like `run`
+		if let [.., a, b] = stem.as_bytes() {
+			let undoubled = &stem[..stem.len() - 1];
+			if a == b && a.is_ascii_alphabetic() && !b"aeiouls".contains(a) && is_stem(undoubled) {
+				stem = undoubled;
+			}
+		}
+
+		stem.to_string()
+	}
+}
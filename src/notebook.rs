@@ -0,0 +1,76 @@
+use serde_json::Value;
+use std::path::Path;
+
+use crate::extractor::{ExtractError, Extractor};
+
+/// Extracts the sources of the code and markdown cells of Jupyter
+/// notebooks, leaving out outputs and metadata. Cells are separated by a
+/// blank line, and lines are located by cell and line within it.
+#[derive(Debug)]
+pub struct NotebookExtractor;
+
+impl Extractor for NotebookExtractor {
+	fn extensions(&self) -> Vec<&str> {
+		vec!["ipynb"]
+	}
+
+	fn extract(&self, path: &Path) -> Result<String, ExtractError> {
+		let sources = read_cells(path)?
+			.into_iter()
+			.map(|(_, source)| source)
+			.collect::<Vec<String>>();
+
+		Ok(sources.join("\n\n"))
+	}
+
+	fn locate(&self, path: &Path, line: usize) -> Option<String> {
+		let mut start = 1;
+		for (cell, source) in read_cells(path).ok()? {
+			let lines = source.split('\n').count();
+			if line < start + lines {
+				return Some(format!("cell {cell}:{}", line.checked_sub(start)? + 1));
+			}
+
+			start += lines + 1;
+		}
+
+		None
+	}
+}
+
+/// Reads the code and markdown cells of the notebook at `path`, with the
+/// 1-based position of each among all cells.
+fn read_cells(path: &Path) -> Result<Vec<(usize, String)>, ExtractError> {
+	let contents = std::fs::read(path).map_err(ExtractError::Io)?;
+	let notebook = serde_json::from_slice::<Value>(&contents)
+		.map_err(|e| ExtractError::Invalid(e.to_string()))?;
+
+	let cells = notebook
+		.get("cells")
+		.and_then(Value::as_array)
+		.ok_or_else(|| ExtractError::Invalid(String::from("notebook has no cells")))?;
+
+	Ok(cells
+		.iter()
+		.enumerate()
+		.filter(|(_, cell)| {
+			matches!(
+				cell.get("cell_type").and_then(Value::as_str),
+				Some("code" | "markdown")
+			)
+		})
+		.map(|(i, cell)| (i + 1, cell_source(cell)))
+		.collect())
+}
+
+/// Returns the source of `cell`, which notebooks store either as one string
+/// or as a list of lines.
+fn cell_source(cell: &Value) -> String {
+	let source = match cell.get("source") {
+		Some(Value::String(s)) => s.clone(),
+		Some(Value::Array(lines)) => lines.iter().filter_map(Value::as_str).collect(),
+		_ => String::new(),
+	};
+
+	source.trim_end_matches('\n').to_string()
+}
@@ -0,0 +1,417 @@
+use console::style;
+use std::collections::HashMap;
+use std::env;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::archive;
+use crate::args::{Options, OutputFormat};
+use crate::comments::{Region, Regions};
+use crate::compression;
+use crate::delimited::{self, Delimiter};
+use crate::encoding;
+use crate::extractor::Extractor;
+use crate::git;
+use crate::history;
+use crate::index;
+use crate::language::Language;
+use crate::result_set::{self, Match, SearchResult};
+use crate::sarif;
+use crate::saved;
+use crate::search_rank;
+use crate::suppress;
+use crate::template::Template;
+
+/// How many results are shown in text output.
+pub const SHOWN_RESULTS: usize = 5;
+
+/// How many directories `--group-by-dir` shows.
+const SHOWN_DIRECTORIES: usize = 10;
+
+/// How many matching lines are previewed for each result in text output,
+/// unless `--max-previews` says otherwise.
+const DEFAULT_MAX_PREVIEWS: usize = 5;
+
+/// Writes `results` to stdout in the format of `options`. Text shows the top
+/// results, and previews identical to an earlier result's, e.g. of copies of
+/// a vendored file, are replaced with a reference to it. Lines of text
+/// extracted from a file are numbered by where they are in the file, e.g. by
+/// notebook cell, as its extractor in `extractors` locates them. With
+/// `--blame`, each preview line is annotated with who last changed it and
+/// when.
+pub fn print_results(
+	results: &[SearchResult],
+	options: &Options,
+	extractors: &[Arc<dyn Extractor>],
+) -> std::io::Result<()> {
+	if options.format == OutputFormat::Json {
+		return result_set::write(std::io::stdout().lock(), results);
+	}
+
+	if options.format == OutputFormat::Occurrences {
+		return write_occurrences(std::io::stdout().lock(), results, options, extractors);
+	}
+
+	if options.format == OutputFormat::Directories {
+		print_directories(results);
+		return Ok(());
+	}
+
+	let delimiter = match options.format {
+		OutputFormat::Csv => Some(Delimiter::Comma),
+		OutputFormat::Tsv => Some(Delimiter::Tab),
+		_ => None,
+	};
+
+	if options.format == OutputFormat::Sarif {
+		return write_sarif(std::io::stdout().lock(), results, options, extractors);
+	}
+
+	if let Some(delimiter) = delimiter {
+		return write_rows(
+			std::io::stdout().lock(),
+			results,
+			options,
+			extractors,
+			delimiter,
+		);
+	}
+
+	if let (OutputFormat::Template, Some(template)) = (options.format, &options.template) {
+		return write_template(std::io::stdout().lock(), results, template);
+	}
+
+	if options.format == OutputFormat::Paths {
+		let end = if options.print0 { b'\0' } else { b'\n' };
+		return write_paths(std::io::stdout().lock(), results, end);
+	}
+
+	// Previews are clipped to fit a terminal, but written whole to pipes
+	let width = console::Term::stdout()
+		.size_checked()
+		.map(|(_, columns)| columns as usize);
+
+	let best = result_set::best_score(results);
+	let shown = &results[..usize::min(SHOWN_RESULTS, results.len())];
+	for (i, result) in shown.iter().enumerate() {
+		let marker = if result.pinned { " [pinned]" } else { "" };
+		println!(
+			"{} ({}){}",
+			style(result.path.to_string_lossy()).bold(),
+			result.relevance(best),
+			style(marker).cyan()
+		);
+
+		let original = shown[..i]
+			.iter()
+			.find(|other| result.is_match() && other.matches == result.matches);
+
+		match original {
+			Some(original) => println!(
+				"\t{} {}",
+				style("same matches as").dim(),
+				original.path.to_string_lossy()
+			),
+			None => {
+				// Only files on disk are extracted
+				let path = Path::new(&result.path);
+				let extractor = extractors
+					.iter()
+					.find(|e| e.matches(path))
+					.filter(|_| options.rev.is_none() && archive::split(path).is_none());
+
+				let max = options.max_previews.unwrap_or(DEFAULT_MAX_PREVIEWS);
+				let (previews, omitted) = result.previews(max);
+
+				// Files git doesn't know are shown without blame
+				let blame = if options.blame {
+					let lines = previews.iter().map(|m| m.line).collect::<Vec<usize>>();
+					git::blame(path, &lines, options.rev.as_deref()).unwrap_or_default()
+				} else {
+					HashMap::new()
+				};
+
+				for (i, m) in previews.iter().enumerate() {
+					// Runs of adjacent lines are grouped, and gaps marked
+					if i > 0 && m.line > previews[i - 1].line + 1 {
+						println!("\t{}", style("…").dim());
+					}
+
+					// Blame is by line of the file, so located lines have none
+					let location = extractor.and_then(|e| e.locate(path, m.line));
+					let annotation = match blame.get(&m.line).filter(|_| location.is_none()) {
+						Some(b) => {
+							let date = history::format_time(b.time);
+							let date = date.split_once(' ').map_or(date.as_str(), |(d, _)| d);
+							format!("{}\t", style(format!("{} {date}", b.author)).dim())
+						}
+						None => String::new(),
+					};
+
+					// Invalid UTF-8 is shown replaced, so say the line isn't exact
+					let lossy = if encoding::is_lossy(&m.preview) {
+						format!(" {}", style("(lossy)").dim())
+					} else {
+						String::new()
+					};
+
+					let line = location.unwrap_or_else(|| m.line.to_string());
+					let prefix = format!("{}\t{annotation}", style(line).bold());
+					let prev = match width {
+						Some(width) => {
+							fit_preview(&m.preview, &prefix, &lossy, &options.terms, width)
+						}
+						None => m.preview.clone(),
+					};
+
+					println!("{prefix}{prev}{lossy}");
+				}
+
+				if omitted > 0 {
+					let lines = if omitted == 1 { "line" } else { "lines" };
+					let more = format!("({omitted} more matching {lines})");
+					println!("\t{}", style(more).dim());
+				}
+			}
+		}
+	}
+
+	Ok(())
+}
+
+/// Prints the directories with the best matching files among `results`,
+/// with their relevance relative to the best directory and their number of
+/// matching files.
+fn print_directories(results: &[SearchResult]) {
+	let directories = result_set::by_directory(results);
+	let best = directories.first().map_or(0, |(_, score, _)| *score);
+	for (directory, score, files) in directories.into_iter().take(SHOWN_DIRECTORIES) {
+		let directory = directory.strip_prefix(".").unwrap_or(&directory);
+		let name = if directory.as_os_str().is_empty() {
+			String::from(".")
+		} else {
+			directory.to_string_lossy().into_owned()
+		};
+
+		let relevance = result_set::relevance(score, best);
+		let noun = if files == 1 { "file" } else { "files" };
+		println!(
+			"{} ({relevance})\t{}",
+			style(name).bold(),
+			style(format!("{files} matching {noun}")).dim()
+		);
+	}
+}
+
+/// Writes `results` to `out` as a SARIF log, with the search terms as its
+/// rule and a result for each occurrence of them. Files are read again,
+/// with `extractors`, to locate every occurrence; files that can no longer
+/// be read get a result for each preview's line instead.
+fn write_sarif<W: Write>(
+	mut out: W,
+	results: &[SearchResult],
+	options: &Options,
+	extractors: &[Arc<dyn Extractor>],
+) -> std::io::Result<()> {
+	let root = env::current_dir().unwrap_or_default();
+	let mut log = sarif::Log::new(saved::command_line(&options.terms), &root);
+	let best = result_set::best_score(results);
+	for result in results.iter().filter(|result| result.is_match()) {
+		let relevance = result.relevance(best);
+		match read_occurrences(result, options, extractors) {
+			Some((contents, occurrences)) => {
+				log.add(result, &occurrences, relevance, Some(&contents));
+			}
+			None => {
+				let previews = result
+					.previews(0)
+					.0
+					.into_iter()
+					.cloned()
+					.collect::<Vec<Match>>();
+				log.add(result, &previews, relevance, None);
+			}
+		}
+	}
+
+	serde_json::to_writer_pretty(&mut out, &log.to_json())?;
+	writeln!(out)?;
+	out.flush()
+}
+
+/// Writes each matching line of `results` to `out` as a row of values
+/// separated by `delimiter`, after a header row if `--header` was given.
+/// Files are read again, with `extractors`, so that every line with an
+/// occurrence of a term gets a row and lines are written whole rather than
+/// clipped; files that can no longer be read are written with their
+/// previews instead.
+fn write_rows<W: Write>(
+	mut out: W,
+	results: &[SearchResult],
+	options: &Options,
+	extractors: &[Arc<dyn Extractor>],
+	delimiter: Delimiter,
+) -> std::io::Result<()> {
+	if options.header {
+		delimited::write_row(&mut out, delimiter, &delimited::HEADER)?;
+	}
+
+	let best = result_set::best_score(results);
+	for result in results.iter().filter(|result| result.is_match()) {
+		let name = result.path.to_string_lossy();
+		let score = result.relevance(best).to_string();
+		let Some((contents, mut occurrences)) = read_occurrences(result, options, extractors)
+		else {
+			for m in result.previews(0).0 {
+				let (line, column) = (m.line.to_string(), m.column.to_string());
+				let fields = [&*name, &line, &column, &score, &m.preview];
+				delimited::write_row(&mut out, delimiter, &fields)?;
+			}
+
+			continue;
+		};
+
+		// A line with several occurrences gets one row, at the first
+		occurrences.dedup_by_key(|m| m.line);
+		let lines = contents.lines().collect::<Vec<&str>>();
+		for m in occurrences {
+			let text = lines
+				.get(m.line - 1)
+				.map_or(m.preview.as_str(), |line| line.trim());
+			let (line, column) = (m.line.to_string(), m.column.to_string());
+			delimited::write_row(&mut out, delimiter, &[&name, &line, &column, &score, text])?;
+		}
+	}
+
+	out.flush()
+}
+
+/// Writes each matching line of `results` to `out`, in line order and laid
+/// out by `template`.
+fn write_template<W: Write>(
+	mut out: W,
+	results: &[SearchResult],
+	template: &Template,
+) -> std::io::Result<()> {
+	let best = result_set::best_score(results);
+	for result in results {
+		for m in result.previews(0).0 {
+			writeln!(out, "{}", template.render(result, m, best))?;
+		}
+	}
+
+	out.flush()
+}
+
+/// Writes the path of each of `results` with a matching line to `out`, as
+/// is rather than lossily converted, each followed by `end`.
+fn write_paths<W: Write>(mut out: W, results: &[SearchResult], end: u8) -> std::io::Result<()> {
+	for result in results.iter().filter(|result| result.is_match()) {
+		out.write_all(&encoding::os_str_to_bytes(&result.path))?;
+		out.write_all(&[end])?;
+	}
+
+	out.flush()
+}
+
+/// Writes every occurrence of the search terms of `options` in the files of
+/// `results` with a matching line to `out`, as `path:line:text`. The files
+/// are read again as they were ranked, with `extractors`, and files that
+/// can no longer be read are skipped.
+fn write_occurrences<W: Write>(
+	mut out: W,
+	results: &[SearchResult],
+	options: &Options,
+	extractors: &[Arc<dyn Extractor>],
+) -> std::io::Result<()> {
+	for result in results.iter().filter(|result| result.is_match()) {
+		let Some((contents, occurrences)) = read_occurrences(result, options, extractors) else {
+			continue;
+		};
+
+		let name = result.path.to_string_lossy();
+		for m in occurrences {
+			writeln!(out, "{name}:{}:{}", m.line, &contents[m.byte_range])?;
+		}
+	}
+
+	out.flush()
+}
+
+/// Reads the file of `result` again as it was ranked, with `extractors` and
+/// without suppressed lines, and finds every occurrence in it of the search
+/// terms of `options`, only in code with `--code-only`. Returns the text
+/// and the occurrences, or `None` if the file can no longer be read.
+fn read_occurrences(
+	result: &SearchResult,
+	options: &Options,
+	extractors: &[Arc<dyn Extractor>],
+) -> Option<(String, Vec<Match>)> {
+	let path = Path::new(&result.path);
+	let compressed = compression::is_compressed(path);
+	let (contents, _) =
+		index::read_ranked_document(path, compressed, extractors, options.rev.as_deref()).ok()?;
+
+	let contents = encoding::decode(contents);
+	let contents = if options.no_suppressions {
+		contents
+	} else {
+		suppress::apply(&contents)?.into_owned()
+	};
+
+	// Languages aren't stored with results, so they're detected again
+	let regions = if options.code_only {
+		let logical_path = compression::logical_path(path, compressed);
+		Regions::find(&contents, Language::detect(&logical_path, &contents))
+	} else {
+		Regions::default()
+	};
+
+	let terms = options
+		.terms
+		.iter()
+		.chain(&options.aliases)
+		.cloned()
+		.collect::<Vec<String>>();
+
+	let occurrences = search_rank::find_occurrences(&contents, &terms)
+		.into_iter()
+		.filter(|m| regions.at(m.byte_range.start) == Region::Code)
+		.collect();
+
+	Some((contents, occurrences))
+}
+
+/// Clips `preview` to fit a terminal `width` columns wide between `prefix`
+/// and `suffix`, which may hold tabs and styling, keeping the first match
+/// of `terms` in view.
+fn fit_preview(
+	preview: &str,
+	prefix: &str,
+	suffix: &str,
+	terms: &[String],
+	width: usize,
+) -> String {
+	const TAB_WIDTH: usize = 8;
+
+	let mut column = 0;
+	let mut segments = prefix.split('\t').peekable();
+	while let Some(segment) = segments.next() {
+		column += console::measure_text_width(segment);
+		if segments.peek().is_some() {
+			column = (column / TAB_WIDTH + 1) * TAB_WIDTH;
+		}
+	}
+
+	// ASCII lowercasing keeps byte offsets the same
+	let lowercase = preview.to_ascii_lowercase();
+	let start = terms
+		.iter()
+		.filter_map(|term| lowercase.find(&term.to_ascii_lowercase()))
+		.min()
+		.unwrap_or(0);
+
+	let room = width.saturating_sub(column + console::measure_text_width(suffix) + 1);
+	search_rank::clip_preview(preview, start, room.max(TAB_WIDTH))
+}
@@ -0,0 +1,126 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::encoding;
+use crate::error::CodesearchError;
+use crate::log;
+
+/// The name of the directory codesearch keeps its data in.
+const DIR_NAME: &str = "codesearch";
+
+/// The environment variable giving the path of the index, if `--index-path`
+/// isn't given.
+const INDEX_PATH_VAR: &str = "CODESEARCH_INDEX";
+
+/// The name of the directory older versions kept their data in, in the home
+/// directory, or `%LOCALAPPDATA%` on Windows.
+const LEGACY_DIR_NAME: &str = ".codesearch";
+
+/// Returns the directory codesearch keeps its data in, creating it if
+/// needed. Data kept where older versions put it is moved there first.
+pub fn data_dir() -> Result<PathBuf, CodesearchError> {
+	let (base, legacy) = platform_dirs()?;
+	let path = base.join(DIR_NAME);
+	if !path.exists() && legacy.is_dir() {
+		fs::create_dir_all(&base)?;
+		match fs::rename(&legacy, &path) {
+			Ok(()) => log::info(format_args!(
+				"Moved codesearch data from {} to {}",
+				legacy.display(),
+				path.display()
+			)),
+			// Another codesearch process may have just moved it
+			Err(_) if path.exists() => {}
+			Err(e) => {
+				eprintln!(
+					"Warning: failed to move codesearch data from {} to {}: {e}",
+					legacy.display(),
+					path.display()
+				);
+
+				return Ok(legacy);
+			}
+		}
+	}
+
+	if !path.exists() {
+		fs::create_dir_all(&path)?;
+	}
+
+	Ok(path)
+}
+
+/// Returns the path of the index of the current directory: `index_path`
+/// if it is given, then the path in `CODESEARCH_INDEX` if it is set, and
+/// otherwise a file in the data directory named for the current directory.
+/// The directory of a given path is created if needed.
+pub fn save_path(index_path: Option<&Path>) -> Result<PathBuf, CodesearchError> {
+	let path = match index_path {
+		Some(path) => path.to_path_buf(),
+		None => match env::var_os(INDEX_PATH_VAR).filter(|v| !v.is_empty()) {
+			Some(path) => PathBuf::from(path),
+			None => return Ok(data_dir()?.join(file_name()?)),
+		},
+	};
+
+	if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+		fs::create_dir_all(parent)?;
+	}
+
+	Ok(path)
+}
+
+/// Returns the name of the index of the current directory, a hash of its
+/// path.
+fn file_name() -> Result<String, std::io::Error> {
+	// The same directory written differently shares an index
+	let cwd = encoding::fold_case(&encoding::strip_verbatim(&env::current_dir()?));
+	let cwd = encoding::os_str_to_bytes(cwd.as_os_str());
+	let hash = hmac_sha256::Hash::hash(&cwd);
+	Ok(encoding::to_hex(&hash))
+}
+
+/// Returns the directory data directories go in, `$XDG_DATA_HOME` or
+/// `~/.local/share`, and the legacy data directory.
+#[cfg(all(target_family = "unix", not(target_os = "macos")))]
+fn platform_dirs() -> Result<(PathBuf, PathBuf), CodesearchError> {
+	let home = home_dir()?;
+
+	// Relative paths are invalid by the XDG spec, so they are ignored
+	let base = env::var_os("XDG_DATA_HOME")
+		.map(PathBuf::from)
+		.filter(|path| path.is_absolute())
+		.unwrap_or_else(|| home.join(".local").join("share"));
+
+	Ok((base, home.join(LEGACY_DIR_NAME)))
+}
+
+/// Returns the directory data directories go in, `~/Library/Application
+/// Support`, and the legacy data directory.
+#[cfg(target_os = "macos")]
+fn platform_dirs() -> Result<(PathBuf, PathBuf), CodesearchError> {
+	let home = home_dir()?;
+	let base = home.join("Library").join("Application Support");
+	Ok((base, home.join(LEGACY_DIR_NAME)))
+}
+
+/// Returns the directory data directories go in, `%LOCALAPPDATA%`, and the
+/// legacy data directory.
+#[cfg(target_family = "windows")]
+fn platform_dirs() -> Result<(PathBuf, PathBuf), CodesearchError> {
+	let base = PathBuf::from(
+		env::var_os("LOCALAPPDATA").ok_or(CodesearchError::NoDataDir("LOCALAPPDATA"))?,
+	);
+
+	let legacy = base.join(LEGACY_DIR_NAME);
+	Ok((base, legacy))
+}
+
+/// Returns the home directory of the current user.
+#[cfg(target_family = "unix")]
+fn home_dir() -> Result<PathBuf, CodesearchError> {
+	env::var_os("HOME")
+		.map(PathBuf::from)
+		.ok_or(CodesearchError::NoDataDir("HOME"))
+}
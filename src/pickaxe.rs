@@ -0,0 +1,196 @@
+use console::style;
+use std::path::{Path, PathBuf};
+
+use crate::bitmap::BitMap;
+use crate::error::CodesearchError;
+use crate::git::{self, Commit};
+use crate::index::Index;
+use crate::log;
+use crate::ngram;
+use crate::progress::ProgressFormat;
+
+/// How many segments the commit index grows to before it is rebuilt as one.
+const MAX_SEGMENTS: usize = 16;
+
+/// Returns the path of the manifest of the commit index kept alongside the
+/// index at `index`. It lists the newest commit of each segment, oldest
+/// segment first.
+pub fn manifest_path(index: &Path) -> PathBuf {
+	index.with_extension("commits")
+}
+
+/// Returns the path of the `n`th segment of the commit index.
+fn segment_path(index: &Path, n: usize) -> PathBuf {
+	index.with_extension(format!("commits-{n}"))
+}
+
+/// Indexes the changes of the commits up to `tip` not yet indexed into a
+/// new segment of the commit index alongside the index at `save_path`, and
+/// returns every segment. Each commit is a document named by its hash,
+/// made of the lines it changed. The index is rebuilt if history was
+/// rewritten or it has too many segments.
+pub fn update(
+	save_path: &Path,
+	tip: &str,
+	ngram_size: u8,
+	progress: ProgressFormat,
+) -> Result<Vec<Index>, CodesearchError> {
+	let manifest = manifest_path(save_path);
+	let mut tips = match std::fs::read_to_string(&manifest) {
+		Ok(v) => v.lines().map(String::from).collect::<Vec<String>>(),
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+		Err(e) => return Err(e.into()),
+	};
+
+	let head = git::resolve_commit(tip)?;
+	let rebuild = match tips.last() {
+		Some(last) => tips.len() >= MAX_SEGMENTS || !git::is_ancestor(last, &head)?,
+		None => false,
+	};
+
+	if rebuild {
+		for n in 0..tips.len() {
+			let _ = std::fs::remove_file(segment_path(save_path, n));
+		}
+
+		tips.clear();
+	}
+
+	if tips.last() != Some(&head) {
+		let range = match tips.last() {
+			Some(last) => format!("{last}..{head}"),
+			None => head.clone(),
+		};
+
+		let commits = git::commits(&[&range])?;
+		if !commits.is_empty() {
+			let noun = if commits.len() == 1 {
+				"commit"
+			} else {
+				"commits"
+			};
+			log::info(format_args!("Indexing {} new {noun}...", commits.len()));
+			let texts = commits
+				.into_iter()
+				.map(|commit| (PathBuf::from(commit.hash), changed_text(&commit.changes)))
+				.collect();
+
+			Index::create_from_texts(
+				segment_path(save_path, tips.len()),
+				ngram_size,
+				texts,
+				progress,
+			)?;
+
+			tips.push(head);
+		} else if let Some(last) = tips.last_mut() {
+			// The last segment covers commits that changed nothing here too
+			*last = head;
+		}
+
+		std::fs::write(&manifest, tips.join("\n") + "\n")?;
+	}
+
+	let mut segments = Vec::with_capacity(tips.len());
+	for n in 0..tips.len() {
+		segments.push(Index::load(segment_path(save_path, n))?);
+	}
+
+	Ok(segments)
+}
+
+/// Returns the text indexed for the lines a commit changed.
+fn changed_text(changes: &[git::Change]) -> String {
+	changes
+		.iter()
+		.map(|change| change.line.as_str())
+		.collect::<Vec<&str>>()
+		.join("\n")
+}
+
+/// Finds the commits in `segments` that added or removed lines containing
+/// `term`, ignoring case, newest first. Only the commits whose changes
+/// contain every n-gram of `term` are read from git.
+pub fn search(segments: &mut [Index], term: &str) -> Result<Vec<Commit>, CodesearchError> {
+	let mut candidates = Vec::new();
+	for segment in segments.iter_mut() {
+		let mut ngrams = Vec::new();
+		ngram::get_ngrams(term, segment.ngram_size(), &mut ngrams);
+
+		let count = segment.document_count() as usize;
+		let mut all: Option<BitMap> = None;
+		for gram in ngrams {
+			let v = match segment.find_ngram_ref(gram)? {
+				Some(v) => v,
+				None => {
+					all = Some(BitMap::new(count));
+					break;
+				}
+			};
+
+			all = Some(match all {
+				Some(mut all) => {
+					all &= v;
+					all
+				}
+				None => v.to_bitmap(),
+			});
+		}
+
+		// Terms too short for any n-gram can't narrow the commits
+		let ids = match all {
+			Some(all) => all.iter_ones().collect(),
+			None => (0..count).collect::<Vec<usize>>(),
+		};
+
+		for doc_id in ids {
+			if let Some(hash) = segment.find_document(doc_id as u32)? {
+				candidates.push(hash.to_string_lossy().into_owned());
+			}
+		}
+	}
+
+	if candidates.is_empty() {
+		return Ok(Vec::new());
+	}
+
+	let term = term.to_lowercase();
+	let mut revs = vec!["--no-walk"];
+	revs.extend(candidates.iter().map(String::as_str));
+	let mut commits = git::commits(&revs)?;
+	for commit in &mut commits {
+		commit
+			.changes
+			.retain(|change| change.line.to_lowercase().contains(&term));
+	}
+
+	commits.retain(|commit| !commit.changes.is_empty());
+	Ok(commits)
+}
+
+/// Prints each of `commits` with the matching lines it changed, grouped by
+/// file.
+pub fn print(commits: &[Commit]) {
+	for commit in commits {
+		println!(
+			"{} {} {}",
+			style(&commit.hash[..commit.hash.len().min(12)]).yellow(),
+			style(&commit.date).dim(),
+			commit.subject
+		);
+
+		let mut path = None;
+		for change in &commit.changes {
+			if path != Some(&change.path) {
+				println!("  {}", style(change.path.to_string_lossy()).bold());
+				path = Some(&change.path);
+			}
+
+			if change.added {
+				println!("    {}", style(format!("+{}", change.line)).green());
+			} else {
+				println!("    {}", style(format!("-{}", change.line)).red());
+			}
+		}
+	}
+}
@@ -0,0 +1,127 @@
+use indicatif::ProgressBar;
+use serde_json::{json, Value};
+use std::path::Path;
+
+use crate::log::{self, Level};
+
+/// How progress is reported while indexing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ProgressFormat {
+	/// An interactive progress bar.
+	#[default]
+	Bar,
+	/// One JSON object per line on stderr, for other programs to parse.
+	Json,
+	/// Nothing, for work running alongside other work.
+	Hidden,
+}
+
+/// Reports progress through a phase of work, such as indexing files.
+pub struct Progress {
+	bar: Option<ProgressBar>,
+	done: u64,
+	phase: &'static str,
+	total: u64,
+}
+
+impl ProgressFormat {
+	/// Returns the progress format with the given name.
+	pub fn from_name(name: &str) -> Option<Self> {
+		match name {
+			"bar" => Some(ProgressFormat::Bar),
+			"json" => Some(ProgressFormat::Json),
+			_ => None,
+		}
+	}
+}
+
+impl Progress {
+	/// Starts reporting progress through `total` items of `phase`.
+	pub fn new(format: ProgressFormat, phase: &'static str, total: u64) -> Self {
+		let bar = match format {
+			ProgressFormat::Bar => Some(ProgressBar::new(total)),
+			ProgressFormat::Json => None,
+			ProgressFormat::Hidden => Some(ProgressBar::hidden()),
+		};
+
+		let progress = Self {
+			bar,
+			done: 0,
+			phase,
+			total,
+		};
+
+		progress.emit("start", None);
+		progress
+	}
+
+	/// Records that work has started on the next item, which may be a file.
+	/// JSON events for items without a file are only written when the
+	/// percentage done changes, to keep the output small.
+	pub fn inc(&mut self, file: Option<&Path>) {
+		let percent = self.percent();
+		self.done += 1;
+		match (&self.bar, file) {
+			(Some(bar), _) => bar.inc(1),
+			(None, Some(file)) => self.emit("progress", Some(("file", &file.to_string_lossy()))),
+			(None, None) if self.percent() != percent => self.emit("progress", None),
+			(None, None) => (),
+		}
+	}
+
+	/// Prints a message without disrupting the progress output.
+	pub fn println<S: AsRef<str>>(&self, message: S) {
+		match &self.bar {
+			Some(bar) => bar.println(message),
+			None => self.emit("message", Some(("message", message.as_ref()))),
+		}
+	}
+
+	/// Prints a message of `level`, such as a file skipped while indexing,
+	/// if that level is enabled. Unlike `println`, messages are written even
+	/// when no progress bar is drawn.
+	pub fn log<S: AsRef<str>>(&self, level: Level, message: S) {
+		if !log::enabled(level) {
+			return;
+		}
+
+		match &self.bar {
+			Some(bar) if !bar.is_hidden() => bar.println(message),
+			Some(_) => eprintln!("{}", message.as_ref()),
+			None => self.emit("message", Some(("message", message.as_ref()))),
+		}
+	}
+
+	/// Finishes reporting progress.
+	pub fn finish(self) {
+		match &self.bar {
+			Some(bar) => bar.finish(),
+			None => self.emit("finish", None),
+		}
+	}
+
+	/// Returns the percentage of items done.
+	fn percent(&self) -> u64 {
+		(self.done * 100).checked_div(self.total).unwrap_or(100)
+	}
+
+	/// Writes a JSON event line to stderr if no progress bar is shown.
+	fn emit(&self, event: &str, field: Option<(&str, &str)>) {
+		if self.bar.is_some() {
+			return;
+		}
+
+		let mut line = json!({
+			"event": event,
+			"phase": self.phase,
+			"done": self.done,
+			"total": self.total,
+		});
+
+		if let Some((key, value)) = field {
+			line[key] = Value::from(value);
+		}
+
+		eprintln!("{line}");
+	}
+}
@@ -0,0 +1,370 @@
+use serde_json::{json, Value};
+use std::fmt::Display;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+use crate::encoding::Folded;
+use crate::language::Language;
+use crate::result_set::{self, SearchResult};
+
+/// The version of the messages `serve` understands, raised when they change
+/// incompatibly. Clients can check it with the `version` method.
+pub const VERSION: u64 = 1;
+
+/// The JSON-RPC error code of a request that was understood but failed.
+pub const SERVER_ERROR: i64 = -32000;
+
+/// The error code of a query cancelled before it finished, as in the
+/// Language Server Protocol.
+pub const REQUEST_CANCELLED: i64 = -32800;
+
+/// How messages are separated on stdin and stdout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Framing {
+	/// One message per line.
+	Lines,
+	/// Each message follows a `Content-Length` header and a blank line, as
+	/// in the Language Server Protocol.
+	Headers,
+}
+
+/// Represents a request that couldn't be understood, with its JSON-RPC error.
+#[derive(Debug)]
+pub enum ProtocolError {
+	InvalidParams(String),
+	InvalidRequest(String),
+	MethodNotFound(String),
+	Parse(serde_json::Error),
+}
+
+impl ProtocolError {
+	/// Returns the JSON-RPC error code of this error.
+	pub fn code(&self) -> i64 {
+		match self {
+			ProtocolError::InvalidParams(_) => -32602,
+			ProtocolError::InvalidRequest(_) => -32600,
+			ProtocolError::MethodNotFound(_) => -32601,
+			ProtocolError::Parse(_) => -32700,
+		}
+	}
+}
+
+impl Display for ProtocolError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ProtocolError::InvalidParams(msg) => write!(f, "invalid params: {msg}"),
+			ProtocolError::InvalidRequest(msg) => write!(f, "invalid request: {msg}"),
+			ProtocolError::MethodNotFound(method) => write!(f, "unknown method {method}"),
+			ProtocolError::Parse(e) => write!(f, "invalid JSON: {e}"),
+		}
+	}
+}
+
+impl std::error::Error for ProtocolError {}
+
+/// A search, given as the params of a `query` request.
+#[derive(Debug, Default)]
+pub struct Query {
+	/// The terms to search for, as given on the command line.
+	pub terms: Vec<String>,
+	/// The most results to return. `None` returns every matching file.
+	pub limit: Option<usize>,
+	/// Only search files in these paths. Empty means all files.
+	pub paths: Vec<PathBuf>,
+	/// Only search documents in these languages. Empty means all languages.
+	pub types: Vec<Language>,
+	/// Terms that results must not contain.
+	pub excluded: Vec<String>,
+	/// Rank symbol definitions above plain text matches.
+	pub symbols: bool,
+}
+
+/// A method called by a request.
+#[derive(Debug)]
+pub enum Method {
+	/// Return the protocol and codesearch versions.
+	Version,
+	/// Search the index.
+	Query(Query),
+	/// Cancel the query with the given id, sent as `$/cancelRequest`.
+	Cancel(Value),
+	/// Reindex a file the editor saved before answering further queries,
+	/// sent as `didSave` or `textDocument/didSave`.
+	Save(PathBuf),
+	/// Stop serving.
+	Shutdown,
+}
+
+/// A JSON-RPC request. Requests without an `id` are notifications, which
+/// get no response.
+#[derive(Debug)]
+pub struct Request {
+	pub id: Option<Value>,
+	pub method: Method,
+}
+
+/// Parses a request from one line of JSON. Errors come with the id of the
+/// request, if it could be read.
+pub fn parse_request(line: &str) -> Result<Request, (Value, ProtocolError)> {
+	let value =
+		serde_json::from_str::<Value>(line).map_err(|e| (Value::Null, ProtocolError::Parse(e)))?;
+	let id = value.get("id").cloned();
+	let fail = |e| (id.clone().unwrap_or(Value::Null), e);
+	if value.get("jsonrpc").and_then(Value::as_str) != Some("2.0") {
+		return Err(fail(ProtocolError::InvalidRequest(String::from(
+			"jsonrpc must be \"2.0\"",
+		))));
+	}
+
+	let method = match value.get("method").and_then(Value::as_str) {
+		Some("version") => Method::Version,
+		Some("query") => Method::Query(parse_query(value.get("params")).map_err(fail)?),
+		Some("$/cancelRequest") => Method::Cancel(
+			value
+				.get("params")
+				.and_then(|params| params.get("id"))
+				.cloned()
+				.ok_or_else(|| fail(ProtocolError::InvalidParams(String::from("missing id"))))?,
+		),
+		Some("didSave") => Method::Save(
+			value
+				.get("params")
+				.and_then(|params| params.get("path"))
+				.and_then(Value::as_str)
+				.map(PathBuf::from)
+				.ok_or_else(|| fail(ProtocolError::InvalidParams(String::from("missing path"))))?,
+		),
+		Some("textDocument/didSave") => Method::Save(
+			value
+				.get("params")
+				.and_then(|params| params.pointer("/textDocument/uri"))
+				.and_then(Value::as_str)
+				.and_then(uri_path)
+				.ok_or_else(|| {
+					fail(ProtocolError::InvalidParams(String::from(
+						"missing file textDocument.uri",
+					)))
+				})?,
+		),
+		Some("shutdown") => Method::Shutdown,
+		Some(method) => return Err(fail(ProtocolError::MethodNotFound(method.to_string()))),
+		None => {
+			return Err(fail(ProtocolError::InvalidRequest(String::from(
+				"missing method",
+			))))
+		}
+	};
+
+	Ok(Request { id, method })
+}
+
+/// Returns the path of the file a `file:` URI names, or `None` for other
+/// URIs.
+fn uri_path(uri: &str) -> Option<PathBuf> {
+	let path = uri.strip_prefix("file://")?;
+	let mut bytes = Vec::with_capacity(path.len());
+	let mut rest = path.as_bytes();
+	while let Some((&byte, tail)) = rest.split_first() {
+		let escaped = tail
+			.get(..2)
+			.and_then(|hex| std::str::from_utf8(hex).ok())
+			.and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+		match escaped {
+			Some(v) if byte == b'%' => {
+				bytes.push(v);
+				rest = &tail[2..];
+			}
+			_ => {
+				bytes.push(byte);
+				rest = tail;
+			}
+		}
+	}
+
+	// Windows paths come after a slash, e.g. `file:///C:/src/main.rs`
+	let path = String::from_utf8_lossy(&bytes).into_owned();
+	let drive = cfg!(windows) && path.get(1..3).is_some_and(|v| v.ends_with(':'));
+	Some(PathBuf::from(match drive {
+		true => &path[1..],
+		false => &path[..],
+	}))
+}
+
+/// Parses the params of a `query` request.
+fn parse_query(params: Option<&Value>) -> Result<Query, ProtocolError> {
+	let invalid = |msg: String| ProtocolError::InvalidParams(msg);
+	let params = params
+		.and_then(Value::as_object)
+		.ok_or_else(|| invalid(String::from("params must be an object")))?;
+
+	let strings = |key: &str| -> Result<Vec<String>, ProtocolError> {
+		match params.get(key) {
+			None => Ok(Vec::new()),
+			Some(Value::Array(items)) => items
+				.iter()
+				.map(|item| item.as_str().map(String::from))
+				.collect::<Option<Vec<String>>>()
+				.ok_or_else(|| invalid(format!("{key} must be an array of strings"))),
+			Some(_) => Err(invalid(format!("{key} must be an array of strings"))),
+		}
+	};
+
+	let terms = strings("terms")?;
+	if terms.is_empty() {
+		return Err(invalid(String::from("terms must not be empty")));
+	}
+
+	let limit = match params.get("limit") {
+		None | Some(Value::Null) => None,
+		Some(value) => Some(
+			value
+				.as_u64()
+				.ok_or_else(|| invalid(String::from("limit must be a number")))? as usize,
+		),
+	};
+
+	let types = strings("types")?
+		.iter()
+		.map(|name| {
+			Language::from_name(name).ok_or_else(|| invalid(format!("unknown type {name}")))
+		})
+		.collect::<Result<Vec<Language>, ProtocolError>>()?;
+
+	Ok(Query {
+		terms,
+		limit,
+		paths: strings("paths")?.into_iter().map(PathBuf::from).collect(),
+		types,
+		excluded: strings("exclude")?,
+		symbols: params.get("symbols").and_then(Value::as_bool) == Some(true),
+	})
+}
+
+/// Reads the next message from `reader`, or `None` at the end of the input.
+pub fn read_message<R: BufRead>(reader: &mut R, framing: Framing) -> io::Result<Option<String>> {
+	let mut line = String::new();
+	if framing == Framing::Lines {
+		return Ok((reader.read_line(&mut line)? > 0).then_some(line));
+	}
+
+	// Headers end at a blank line, and only the content length matters
+	let mut length = None;
+	loop {
+		line.clear();
+		if reader.read_line(&mut line)? == 0 {
+			return Ok(None);
+		}
+
+		let header = line.trim_end();
+		if header.is_empty() {
+			break;
+		}
+
+		if let Some((name, value)) = header.split_once(':') {
+			if name.trim().eq_ignore_ascii_case("content-length") {
+				length = value.trim().parse::<usize>().ok();
+			}
+		}
+	}
+
+	let length = length.ok_or_else(|| {
+		io::Error::new(io::ErrorKind::InvalidData, "message has no Content-Length")
+	})?;
+
+	let mut content = vec![0; length];
+	reader.read_exact(&mut content)?;
+	String::from_utf8(content)
+		.map(Some)
+		.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Writes `message` to `out`, then flushes it.
+pub fn write_message<W: Write>(out: &mut W, framing: Framing, message: &str) -> io::Result<()> {
+	match framing {
+		Framing::Lines => writeln!(out, "{message}")?,
+		Framing::Headers => write!(out, "Content-Length: {}\r\n\r\n{message}", message.len())?,
+	}
+
+	out.flush()
+}
+
+/// Returns the result of a `version` request.
+pub fn version() -> Value {
+	json!({
+		"protocol": VERSION,
+		"codesearch": env!("CARGO_PKG_VERSION"),
+	})
+}
+
+/// Returns the result of a `query` request for `terms` that found `results`.
+/// Each matching line comes with the `text_spans` of the terms in it, as
+/// byte offsets into its preview `text`, not the file.
+pub fn query_result(results: &[SearchResult], terms: &[String]) -> Value {
+	let best = result_set::best_score(results);
+	let results = results
+		.iter()
+		.map(|result| {
+			let lines = result
+				.matches
+				.iter()
+				.map(|m| {
+					let mut line = m.to_json();
+					line["text_spans"] = json!(spans(&m.preview, terms));
+					line
+				})
+				.collect::<Vec<Value>>();
+
+			json!({
+				"path": result.path.to_string_lossy(),
+				"rank": result.score,
+				"score": result.relevance(best),
+				"pinned": result.pinned,
+				"lines": lines,
+			})
+		})
+		.collect::<Vec<Value>>();
+
+	json!({ "results": results })
+}
+
+/// Returns the spans of every occurrence of `terms` in `text`, ignoring
+/// case, ordered by where they start.
+fn spans(text: &str, terms: &[String]) -> Vec<Value> {
+	let folded = Folded::new(text);
+	let mut spans = terms
+		.iter()
+		.map(|term| term.to_lowercase())
+		.filter(|term| !term.is_empty())
+		.flat_map(|term| {
+			folded
+				.text
+				.match_indices(&term)
+				.map(|(start, _)| folded.original_range(start..start + term.len()))
+				.map(|range| (range.start, range.end))
+				.collect::<Vec<(usize, usize)>>()
+		})
+		.collect::<Vec<(usize, usize)>>();
+
+	spans.sort();
+	spans
+		.into_iter()
+		.map(|(start, end)| json!({ "start": start, "end": end }))
+		.collect()
+}
+
+/// Returns the response to the request `id` that succeeded with `result`.
+pub fn response(id: &Value, result: Value) -> String {
+	json!({ "jsonrpc": "2.0", "id": id, "result": result }).to_string()
+}
+
+/// Returns the response to the request `id` that failed with `code` and
+/// `message`.
+pub fn error_response(id: &Value, code: i64, message: &str) -> String {
+	json!({
+		"jsonrpc": "2.0",
+		"id": id,
+		"error": { "code": code, "message": message },
+	})
+	.to_string()
+}
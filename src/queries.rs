@@ -0,0 +1,115 @@
+use serde_json::{json, Value};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// The most searches kept in the history. Older searches are dropped.
+const MAX_QUERIES: usize = 100;
+
+/// A search run in a project, recorded for `history` and `--last`.
+#[derive(Clone, Debug)]
+pub struct Query {
+	/// When the search was run, in seconds since the Unix epoch.
+	pub time: u64,
+	/// The arguments of the search, flags included.
+	pub args: Vec<String>,
+	/// The paths of the top results.
+	pub results: Vec<String>,
+}
+
+/// Represents an error reading or writing the search history file.
+#[derive(Debug)]
+pub enum QueryError {
+	Invalid(String),
+	Io(std::io::Error),
+	Parse(serde_json::Error),
+}
+
+impl Display for QueryError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			QueryError::Invalid(msg) => write!(f, "invalid search history file: {msg}"),
+			QueryError::Io(e) => write!(f, "search history file error: {e}"),
+			QueryError::Parse(e) => write!(f, "invalid search history file: {e}"),
+		}
+	}
+}
+
+impl std::error::Error for QueryError {}
+
+impl Query {
+	/// Records a search with `args` run now, returning `results`.
+	pub fn new(args: Vec<String>, results: Vec<String>) -> Self {
+		let time = SystemTime::now()
+			.duration_since(SystemTime::UNIX_EPOCH)
+			.unwrap_or_default();
+
+		Self {
+			time: time.as_secs(),
+			args,
+			results,
+		}
+	}
+}
+
+/// Returns the path of the search history kept alongside the index at `index`.
+pub fn path_for(index: &Path) -> PathBuf {
+	index.with_extension("queries")
+}
+
+/// Reads the searches recorded at `path`, oldest first. A missing file has
+/// no searches.
+pub fn load(path: &Path) -> Result<Vec<Query>, QueryError> {
+	let contents = match std::fs::read_to_string(path) {
+		Ok(v) => v,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+		Err(e) => return Err(QueryError::Io(e)),
+	};
+
+	let value = serde_json::from_str::<Value>(&contents).map_err(QueryError::Parse)?;
+	let invalid = |msg: &str| QueryError::Invalid(String::from(msg));
+	let strings = |value: Option<&Value>| -> Result<Vec<String>, QueryError> {
+		value
+			.and_then(Value::as_array)
+			.ok_or_else(|| invalid("expected an array of strings"))?
+			.iter()
+			.map(|v| v.as_str().map(String::from))
+			.collect::<Option<Vec<String>>>()
+			.ok_or_else(|| invalid("expected an array of strings"))
+	};
+
+	let mut queries = Vec::new();
+	for query in value
+		.get("queries")
+		.and_then(Value::as_array)
+		.ok_or_else(|| invalid("missing queries array"))?
+	{
+		queries.push(Query {
+			time: query.get("time").and_then(Value::as_u64).unwrap_or(0),
+			args: strings(query.get("args"))?,
+			results: strings(query.get("results"))?,
+		});
+	}
+
+	Ok(queries)
+}
+
+/// Adds `query` to the history at `path`, dropping the oldest searches once
+/// there are too many.
+pub fn record(path: &Path, query: Query) -> Result<(), QueryError> {
+	let mut queries = load(path)?;
+	queries.push(query);
+	if queries.len() > MAX_QUERIES {
+		queries.drain(..queries.len() - MAX_QUERIES);
+	}
+
+	let queries = queries
+		.iter()
+		.map(|q| json!({ "time": q.time, "args": q.args, "results": q.results }))
+		.collect::<Vec<Value>>();
+
+	let contents =
+		serde_json::to_string_pretty(&json!({ "queries": queries })).map_err(QueryError::Parse)?;
+
+	std::fs::write(path, contents).map_err(QueryError::Io)
+}
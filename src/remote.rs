@@ -0,0 +1,113 @@
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::encoding;
+use crate::lock::IndexLock;
+use crate::log::{self, Level};
+
+/// The name of the directory in the data directory that remote indexes are
+/// cached in.
+pub const CACHE_DIR: &str = "remote";
+
+/// How long to wait to connect to the server, or for it to send more data.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Represents an error fetching a remote index.
+#[derive(Debug)]
+pub enum RemoteError {
+	Http(Box<ureq::Error>),
+	Io(io::Error),
+	UnsupportedUrl(String),
+}
+
+impl Display for RemoteError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			RemoteError::Http(e) => write!(f, "remote index error: {e}"),
+			RemoteError::Io(e) => write!(f, "remote index error: {e}"),
+			RemoteError::UnsupportedUrl(url) => {
+				write!(
+					f,
+					"remote indexes must be http:// or https:// URLs, not {url}"
+				)
+			}
+		}
+	}
+}
+
+impl std::error::Error for RemoteError {}
+
+impl From<io::Error> for RemoteError {
+	fn from(value: io::Error) -> Self {
+		RemoteError::Io(value)
+	}
+}
+
+/// Returns the path of a copy of the index published at `url`, cached in
+/// `cache_dir`. A cached copy is revalidated with the ETag it was served
+/// with, and only downloaded again if it changed. If the server can't be
+/// reached, the cached copy is used as is.
+pub fn fetch(url: &str, cache_dir: &Path) -> Result<PathBuf, RemoteError> {
+	if !url.starts_with("http://") && !url.starts_with("https://") {
+		return Err(RemoteError::UnsupportedUrl(url.to_string()));
+	}
+
+	fs::create_dir_all(cache_dir)?;
+	let path = cache_dir.join(encoding::to_hex(&hmac_sha256::Hash::hash(url.as_bytes())));
+	let etag_path = path.with_extension("etag");
+
+	// An ETag is only useful with the copy it was served with
+	let etag = fs::read_to_string(&etag_path)
+		.ok()
+		.filter(|_| path.exists());
+
+	let agent = ureq::AgentBuilder::new()
+		.timeout_connect(TIMEOUT)
+		.timeout_read(TIMEOUT)
+		.build();
+
+	let mut request = agent.get(url);
+	if let Some(etag) = &etag {
+		request = request.set("If-None-Match", etag.trim());
+	}
+
+	let response = match request.call() {
+		Ok(v) => v,
+		Err(e) if path.exists() => {
+			eprintln!("Warning: failed to check remote index, using the cached copy: {e}");
+			return Ok(path);
+		}
+		Err(e) => return Err(RemoteError::Http(Box::new(e))),
+	};
+
+	if response.status() == 304 {
+		if log::enabled(Level::Verbose) {
+			eprintln!("Remote index {url} is unchanged");
+		}
+
+		return Ok(path);
+	}
+
+	log::info(format_args!("Downloading index from {url}"));
+	let etag = response.header("ETag").map(String::from);
+	let download = path.with_extension("download");
+	let mut writer = BufWriter::new(File::create(&download)?);
+	io::copy(&mut response.into_reader(), &mut writer)?;
+	writer.flush()?;
+	drop(writer);
+
+	// Searches of the previous copy finish before it is replaced
+	let _lock = IndexLock::exclusive(&path)?;
+	fs::rename(&download, &path)?;
+	match etag {
+		Some(etag) => fs::write(&etag_path, etag)?,
+		None => {
+			let _ = fs::remove_file(&etag_path);
+		}
+	}
+
+	Ok(path)
+}
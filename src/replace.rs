@@ -0,0 +1,114 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The number of unchanged lines shown around each change in a diff.
+const CONTEXT_LINES: usize = 3;
+
+/// The proposed substitutions in one file.
+#[derive(Debug)]
+pub struct Replacement {
+	pub path: PathBuf,
+	/// The file's contents as they are.
+	pub original: String,
+	/// The file's contents with every occurrence of the pattern replaced.
+	pub replaced: String,
+	/// The number of occurrences replaced.
+	pub count: usize,
+}
+
+/// Returns the substitutions of `replacement` for every occurrence of
+/// `pattern` in the file at `path`, matching case exactly, or `None` if the
+/// file has no occurrence or isn't valid UTF-8.
+pub fn plan(path: &Path, pattern: &str, replacement: &str) -> io::Result<Option<Replacement>> {
+	let original = match String::from_utf8(fs::read(path)?) {
+		Ok(v) => v,
+		Err(_) => return Ok(None),
+	};
+
+	let count = original.matches(pattern).count();
+	if count == 0 {
+		return Ok(None);
+	}
+
+	Ok(Some(Replacement {
+		path: path.to_path_buf(),
+		replaced: original.replace(pattern, replacement),
+		original,
+		count,
+	}))
+}
+
+impl Replacement {
+	/// Returns the substitutions as a unified diff. Patterns and
+	/// replacements never span lines, so lines correspond one to one.
+	pub fn diff(&self) -> String {
+		let old = self.original.split_inclusive('\n').collect::<Vec<&str>>();
+		let new = self.replaced.split_inclusive('\n').collect::<Vec<&str>>();
+		let changed = (0..old.len())
+			.filter(|i| old.get(*i) != new.get(*i))
+			.collect::<Vec<usize>>();
+
+		// Changes whose context would touch or overlap share a hunk
+		let mut hunks: Vec<(usize, usize)> = Vec::new();
+		for &i in &changed {
+			let start = i.saturating_sub(CONTEXT_LINES);
+			let end = usize::min(i + CONTEXT_LINES + 1, old.len());
+			match hunks.last_mut() {
+				Some(last) if start <= last.1 => last.1 = end,
+				_ => hunks.push((start, end)),
+			}
+		}
+
+		let name = self.path.strip_prefix(".").unwrap_or(&self.path);
+		let name = name.to_string_lossy();
+		let mut diff = format!("--- a/{name}\n+++ b/{name}\n");
+		for (start, end) in hunks {
+			let len = end - start;
+			diff.push_str(&format!("@@ -{0},{len} +{0},{len} @@\n", start + 1));
+
+			let mut i = start;
+			while i < end {
+				if !changed.contains(&i) {
+					push_line(&mut diff, ' ', old[i]);
+					i += 1;
+					continue;
+				}
+
+				// A run of changed lines is shown removed, then added
+				let run_end = (i..end).find(|j| !changed.contains(j)).unwrap_or(end);
+				old[i..run_end]
+					.iter()
+					.for_each(|line| push_line(&mut diff, '-', line));
+				new[i..run_end]
+					.iter()
+					.for_each(|line| push_line(&mut diff, '+', line));
+				i = run_end;
+			}
+		}
+
+		diff
+	}
+
+	/// Writes the replaced contents to the file, first copying it to
+	/// `<path>.orig` if `backup` is set.
+	pub fn apply(&self, backup: bool) -> io::Result<()> {
+		if backup {
+			let mut backup_path = self.path.clone().into_os_string();
+			backup_path.push(".orig");
+			fs::copy(&self.path, backup_path)?;
+		}
+
+		fs::write(&self.path, &self.replaced)
+	}
+}
+
+/// Appends `line` to `diff`, prefixed by `marker`, noting if it doesn't end
+/// with a newline.
+fn push_line(diff: &mut String, marker: char, line: &str) {
+	diff.push(marker);
+	diff.push_str(line);
+	if !line.ends_with('\n') {
+		diff.push_str("\n\\ No newline at end of file\n");
+	}
+}
@@ -0,0 +1,123 @@
+use serde_json::{json, Value};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config;
+use crate::encoding;
+use crate::index::Index;
+use crate::paths;
+use crate::result_set::{self, SearchResult};
+use crate::synonyms;
+
+/// The most searches whose results are kept. The oldest are dropped.
+const MAX_ENTRIES: usize = 50;
+
+/// The most results a search may have to be kept, so the cache stays small.
+const MAX_RESULTS: usize = 200;
+
+/// Returns the path of the result cache kept alongside the index at `index`.
+pub fn path_for(index: &Path) -> PathBuf {
+	index.with_extension("results")
+}
+
+/// Returns the key of the search with the arguments `query` from everything
+/// that decides its results: its arguments, the current directory, the
+/// configuration, and the synonyms.
+pub fn key(query: &[String]) -> String {
+	let cwd = env::current_dir().unwrap_or_default();
+	let cwd = encoding::os_str_to_bytes(cwd.as_os_str());
+	let config = fs::read(config::CONFIG_FILE).unwrap_or_default();
+	// Synonyms are searched as aliases, so editing them changes results
+	let synonyms = paths::data_dir()
+		.ok()
+		.and_then(|dir| fs::read(synonyms::path_for(&dir)).ok())
+		.unwrap_or_default();
+
+	let mut parts = vec![&*cwd, config.as_slice(), synonyms.as_slice()];
+	parts.extend(query.iter().map(String::as_bytes));
+
+	let mut hash = hmac_sha256::Hash::new();
+	for part in parts {
+		// Lengths keep different splits of the same bytes apart
+		hash.update((part.len() as u64).to_le_bytes());
+		hash.update(part);
+	}
+
+	encoding::to_hex(&hash.finalize())
+}
+
+/// Returns the generation of `indexes`, the shards of an index, which
+/// changes whenever any of them is rewritten.
+pub fn generation(indexes: &[Index]) -> String {
+	indexes
+		.iter()
+		.map(|index| {
+			let modified = index
+				.modified()
+				.duration_since(std::time::UNIX_EPOCH)
+				.unwrap_or_default();
+
+			format!(
+				"{}.{:09}:{}",
+				modified.as_secs(),
+				modified.subsec_nanos(),
+				index.document_count()
+			)
+		})
+		.collect::<Vec<String>>()
+		.join(",")
+}
+
+/// Returns the results cached at `path` for the search with `key`, if they
+/// were cached while the index was at `generation`. An unreadable cache has
+/// no results.
+pub fn get(path: &Path, generation: &str, key: &str) -> Option<Vec<SearchResult>> {
+	let entries = load(path, generation)?;
+	let entry = entries
+		.iter()
+		.find(|entry| entry.get("key").and_then(Value::as_str) == Some(key))?;
+
+	result_set::from_json(entry).ok()
+}
+
+/// Caches `results` at `path` for the search with `key` of the index at
+/// `generation`. Results cached at an older generation are dropped, as are
+/// the oldest searches once there are too many, and searches with too many
+/// results aren't cached.
+pub fn put(
+	path: &Path,
+	generation: &str,
+	key: &str,
+	results: &[SearchResult],
+) -> std::io::Result<()> {
+	if results.len() > MAX_RESULTS {
+		return Ok(());
+	}
+
+	let mut entries = load(path, generation).unwrap_or_default();
+	entries.retain(|entry| entry.get("key").and_then(Value::as_str) != Some(key));
+
+	let mut entry = result_set::to_json(results);
+	entry["key"] = json!(key);
+	entries.insert(0, entry);
+	entries.truncate(MAX_ENTRIES);
+
+	let contents = json!({ "generation": generation, "entries": entries });
+	std::fs::write(path, contents.to_string())
+}
+
+/// Reads the entries cached at `path`, most recent first, if they were
+/// cached while the index was at `generation`.
+fn load(path: &Path, generation: &str) -> Option<Vec<Value>> {
+	let contents = std::fs::read_to_string(path).ok()?;
+	let mut value = serde_json::from_str::<Value>(&contents).ok()?;
+	if value.get("generation").and_then(Value::as_str) != Some(generation) {
+		return None;
+	}
+
+	match value.get_mut("entries")?.take() {
+		Value::Array(entries) => Some(entries),
+		_ => None,
+	}
+}
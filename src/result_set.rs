@@ -0,0 +1,319 @@
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::io::Write;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::encoding;
+
+/// A document found by a search.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchResult {
+	pub path: OsString,
+	/// How well the document matches the search; higher is better.
+	pub score: usize,
+	/// The matching lines, with symbol definitions first.
+	pub matches: Vec<Match>,
+	/// Whether the document is pinned to the top of the results.
+	pub pinned: bool,
+}
+
+/// A matching line of a document.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Match {
+	/// The 1-based line number.
+	pub line: usize,
+	/// The 1-based byte column the match starts at in its line.
+	pub column: usize,
+	/// The byte offsets of the match in the content searched, not in its
+	/// `preview`: the document decoded, decompressed or extracted where it
+	/// needs to be, with long lines cut. These are only offsets into the
+	/// file for plain UTF-8 files without lines cut before the match.
+	pub byte_range: Range<usize>,
+	/// The line, trimmed and clipped around the match.
+	pub preview: String,
+	/// Whether only n-grams of the terms were found, e.g. for a misspelled
+	/// term, rather than a term itself.
+	pub partial: bool,
+}
+
+impl SearchResult {
+	/// Returns `true` if the document has a line matching a term, rather
+	/// than only some of its n-grams.
+	pub fn is_match(&self) -> bool {
+		self.matches.iter().any(|m| !m.partial)
+	}
+
+	/// Returns how relevant the document is from 0 to 100: its score as a
+	/// percentage of `best`, the best score of the results it's among.
+	pub fn relevance(&self, best: usize) -> usize {
+		relevance(self.score, best)
+	}
+
+	/// Returns the matches to preview, one per line and at most `max` of
+	/// them (all if `max` is `0`), ordered by line, with the number of other
+	/// matching lines left out. Earlier matches are kept first, so symbol
+	/// definitions are.
+	pub fn previews(&self, max: usize) -> (Vec<&Match>, usize) {
+		let mut previews = Vec::<&Match>::new();
+		for m in &self.matches {
+			if !previews.iter().any(|other| other.line == m.line) {
+				previews.push(m);
+			}
+		}
+
+		let omitted = match max {
+			0 => 0,
+			max => previews.len().saturating_sub(max),
+		};
+
+		previews.truncate(previews.len() - omitted);
+		previews.sort_by_key(|m| m.line);
+		(previews, omitted)
+	}
+}
+
+impl Match {
+	/// Returns the JSON form of the match.
+	pub fn to_json(&self) -> Value {
+		json!({
+			"line": self.line,
+			"column": self.column,
+			"byte_range": { "start": self.byte_range.start, "end": self.byte_range.end },
+			"text": self.preview,
+			"lossy": encoding::is_lossy(&self.preview),
+			"partial": self.partial,
+		})
+	}
+}
+
+/// A set operation over saved result files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetOperation {
+	/// Documents found in every set.
+	And,
+	/// Documents found in any set.
+	Or,
+	/// Documents in the first set that are not in any other set.
+	Subtract,
+	/// Documents found in exactly one set.
+	Xor,
+}
+
+/// Represents an error reading a result file.
+#[derive(Debug)]
+pub enum ResultSetError {
+	Invalid(String),
+	Io(std::io::Error),
+	Parse(serde_json::Error),
+}
+
+impl SetOperation {
+	/// Returns the set operation with the given name.
+	pub fn from_name(name: &str) -> Option<Self> {
+		match name {
+			"and" => Some(SetOperation::And),
+			"or" => Some(SetOperation::Or),
+			"subtract" => Some(SetOperation::Subtract),
+			"xor" => Some(SetOperation::Xor),
+			_ => None,
+		}
+	}
+}
+
+impl Display for ResultSetError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ResultSetError::Invalid(msg) => write!(f, "invalid result file: {msg}"),
+			ResultSetError::Io(e) => write!(f, "result file error: {e}"),
+			ResultSetError::Parse(e) => write!(f, "invalid result file: {e}"),
+		}
+	}
+}
+
+impl std::error::Error for ResultSetError {}
+
+/// Converts results into the JSON form of a result file.
+pub fn to_json(results: &[SearchResult]) -> Value {
+	let best = best_score(results);
+	let results = results
+		.iter()
+		.map(|result| {
+			json!({
+				"path": result.path.to_string_lossy(),
+				"rank": result.score,
+				"score": result.relevance(best),
+				"previews": result.matches.iter().map(Match::to_json).collect::<Vec<Value>>(),
+				"pinned": result.pinned,
+			})
+		})
+		.collect::<Vec<Value>>();
+
+	json!({ "results": results })
+}
+
+/// Writes results to `out` as a result file.
+pub fn write<W: Write>(mut out: W, results: &[SearchResult]) -> std::io::Result<()> {
+	serde_json::to_writer_pretty(&mut out, &to_json(results))?;
+	writeln!(out)
+}
+
+/// Reads the results saved in the result file at `path`.
+pub fn read<P: AsRef<Path>>(path: P) -> Result<Vec<SearchResult>, ResultSetError> {
+	let contents = std::fs::read_to_string(path).map_err(ResultSetError::Io)?;
+	parse(&contents)
+}
+
+/// Parses the contents of a result file.
+pub fn parse(contents: &str) -> Result<Vec<SearchResult>, ResultSetError> {
+	let value = serde_json::from_str::<Value>(contents).map_err(ResultSetError::Parse)?;
+	from_json(&value)
+}
+
+/// Reads results from the JSON form of a result file. Matches saved without
+/// a column or byte range start their line with an empty range.
+pub fn from_json(value: &Value) -> Result<Vec<SearchResult>, ResultSetError> {
+	let invalid = |msg: &str| ResultSetError::Invalid(String::from(msg));
+	let results = value
+		.get("results")
+		.and_then(Value::as_array)
+		.ok_or_else(|| invalid("missing results array"))?;
+
+	let mut set = Vec::with_capacity(results.len());
+	for result in results {
+		let path = result
+			.get("path")
+			.and_then(Value::as_str)
+			.ok_or_else(|| invalid("result is missing a path"))?;
+
+		let rank = result.get("rank").and_then(Value::as_u64).unwrap_or(0);
+		let mut matches = Vec::new();
+		for preview in result
+			.get("previews")
+			.and_then(Value::as_array)
+			.into_iter()
+			.flatten()
+		{
+			let line = preview.get("line").and_then(Value::as_u64);
+			let text = preview.get("text").and_then(Value::as_str);
+			let (Some(line), Some(text)) = (line, text) else {
+				continue;
+			};
+
+			let column = preview.get("column").and_then(Value::as_u64).unwrap_or(1);
+			let offset = |key| {
+				preview
+					.get("byte_range")
+					.and_then(|range| range.get(key))
+					.and_then(Value::as_u64)
+					.unwrap_or(0) as usize
+			};
+
+			matches.push(Match {
+				line: line as usize,
+				column: column as usize,
+				byte_range: offset("start")..offset("end"),
+				preview: String::from(text),
+				partial: preview.get("partial").and_then(Value::as_bool) == Some(true),
+			});
+		}
+
+		set.push(SearchResult {
+			path: OsString::from(path),
+			score: rank as usize,
+			matches,
+			pinned: result.get("pinned").and_then(Value::as_bool) == Some(true),
+		});
+	}
+
+	Ok(set)
+}
+
+/// Combines result sets with `operation`. Documents found in more than one
+/// set keep the matches from the first set they appear in, their scores are
+/// summed, and they are pinned if pinned in any set. The combined results
+/// are ordered by score, with pinned results first.
+pub fn apply(operation: SetOperation, sets: Vec<Vec<SearchResult>>) -> Vec<SearchResult> {
+	let set_count = sets.len();
+	let mut order = Vec::new();
+	let mut documents: HashMap<OsString, (SearchResult, Vec<usize>)> = HashMap::new();
+	for (i, set) in sets.into_iter().enumerate() {
+		for result in set {
+			match documents.get_mut(&result.path) {
+				Some((existing, found_in)) => {
+					if !found_in.contains(&i) {
+						existing.score += result.score;
+						existing.pinned |= result.pinned;
+						found_in.push(i);
+					}
+				}
+				None => {
+					order.push(result.path.clone());
+					documents.insert(result.path.clone(), (result, vec![i]));
+				}
+			}
+		}
+	}
+
+	let mut results = order
+		.into_iter()
+		.filter_map(|path| documents.remove(&path))
+		.filter(|(_, found_in)| match operation {
+			SetOperation::And => found_in.len() == set_count,
+			SetOperation::Or => true,
+			SetOperation::Subtract => found_in == &[0],
+			SetOperation::Xor => found_in.len() == 1,
+		})
+		.map(|(result, _)| result)
+		.collect::<Vec<SearchResult>>();
+
+	sort(&mut results);
+	results
+}
+
+/// Returns the directories of the `results` with a matching line, with
+/// their files' combined scores and how many there are, best first.
+pub fn by_directory(results: &[SearchResult]) -> Vec<(PathBuf, usize, usize)> {
+	let mut directories = HashMap::<PathBuf, (usize, usize)>::new();
+	for result in results.iter().filter(|result| result.is_match()) {
+		let path = Path::new(&result.path);
+		let directory = path.parent().unwrap_or(Path::new("")).to_path_buf();
+		let entry = directories.entry(directory).or_default();
+		entry.0 += result.score;
+		entry.1 += 1;
+	}
+
+	let mut directories = directories
+		.into_iter()
+		.map(|(directory, (score, files))| (directory, score, files))
+		.collect::<Vec<(PathBuf, usize, usize)>>();
+
+	directories.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+	directories
+}
+
+/// Returns `score` as a percentage of `best` from 0 to 100, rounded.
+pub fn relevance(score: usize, best: usize) -> usize {
+	if best == 0 {
+		return 0;
+	}
+
+	(score as f64 * 100.0 / best as f64).round() as usize
+}
+
+/// Returns the best score of `results`, which their relevance is relative to.
+pub fn best_score(results: &[SearchResult]) -> usize {
+	results.iter().map(|result| result.score).max().unwrap_or(0)
+}
+
+/// Orders `results` pinned first, then by score, breaking ties by path so
+/// that the same results always come out in the same order.
+pub fn sort(results: &mut [SearchResult]) {
+	results.sort_by(|a, b| {
+		(b.pinned, b.score)
+			.cmp(&(a.pinned, a.score))
+			.then_with(|| a.path.cmp(&b.path))
+	});
+}
@@ -0,0 +1,164 @@
+use serde_json::{json, Value};
+use std::path::{Component, Path};
+
+use crate::result_set::{Match, SearchResult};
+
+/// The version of SARIF written.
+const VERSION: &str = "2.1.0";
+
+/// The schema of the SARIF version written.
+const SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+/// The base that relative paths are resolved against: the directory searched.
+const SOURCE_ROOT: &str = "%SRCROOT%";
+
+/// A SARIF log of a search, with its query as the one rule that each
+/// matching line is a result of.
+pub struct Log {
+	rule: String,
+	root: String,
+	results: Vec<Value>,
+}
+
+impl Log {
+	/// Starts a log of the search for `query` in the directory `root`.
+	pub fn new(query: String, root: &Path) -> Self {
+		let mut root = file_uri(root);
+		if !root.ends_with('/') {
+			root.push('/');
+		}
+
+		Self {
+			rule: query,
+			root,
+			results: Vec::new(),
+		}
+	}
+
+	/// Adds a result for each of `matches` in the file of `result`, whose
+	/// relevance is `relevance`. Match spans are located in `contents`, the
+	/// text of its file, if it could be read; otherwise only their lines are
+	/// known.
+	pub fn add(
+		&mut self,
+		result: &SearchResult,
+		matches: &[Match],
+		relevance: usize,
+		contents: Option<&str>,
+	) {
+		let path = Path::new(&result.path);
+		let artifact = match path.is_absolute() {
+			true => json!({ "uri": file_uri(path) }),
+			false => json!({ "uri": relative_uri(path), "uriBaseId": SOURCE_ROOT }),
+		};
+
+		for m in matches {
+			self.results.push(json!({
+				"ruleId": self.rule,
+				"ruleIndex": 0,
+				"level": "note",
+				"message": { "text": format!("Matches {}: {}", self.rule, m.preview) },
+				"locations": [{
+					"physicalLocation": {
+						"artifactLocation": artifact,
+						"region": region(m, contents),
+					},
+				}],
+				"properties": { "score": relevance },
+			}));
+		}
+	}
+
+	/// Returns the log as JSON.
+	pub fn to_json(&self) -> Value {
+		json!({
+			"$schema": SCHEMA,
+			"version": VERSION,
+			"runs": [{
+				"tool": {
+					"driver": {
+						"name": "codesearch",
+						"version": env!("CARGO_PKG_VERSION"),
+						"rules": [{
+							"id": self.rule,
+							"shortDescription": { "text": format!("Matches of {}", self.rule) },
+						}],
+					},
+				},
+				"originalUriBaseIds": { SOURCE_ROOT: { "uri": self.root } },
+				"columnKind": "unicodeCodePoints",
+				"results": self.results,
+			}],
+		})
+	}
+}
+
+/// Returns the region of the match `m`: its span in `contents` from its
+/// first to last character, or just its line if the span isn't in them.
+fn region(m: &Match, contents: Option<&str>) -> Value {
+	let span = contents.filter(|contents| {
+		!m.byte_range.is_empty() && contents.get(m.byte_range.clone()).is_some()
+	});
+
+	let Some(contents) = span else {
+		return json!({ "startLine": m.line, "snippet": { "text": m.preview } });
+	};
+
+	let (start_line, start_column) = position(contents, m.byte_range.start);
+	let (end_line, end_column) = position(contents, m.byte_range.end);
+	json!({
+		"startLine": start_line,
+		"startColumn": start_column,
+		"endLine": end_line,
+		"endColumn": end_column,
+		"snippet": { "text": &contents[m.byte_range.clone()] },
+	})
+}
+
+/// Returns the 1-based line and character column of the byte `offset` of
+/// `text`.
+fn position(text: &str, offset: usize) -> (usize, usize) {
+	let before = &text[..offset];
+	let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+	let line = before.matches('\n').count() + 1;
+	(line, before[line_start..].chars().count() + 1)
+}
+
+/// Returns the URI of the relative `path`, with `/` separators and without
+/// a leading `./`.
+fn relative_uri(path: &Path) -> String {
+	path.components()
+		.filter_map(|component| match component {
+			Component::CurDir => None,
+			component => Some(encode(&component.as_os_str().to_string_lossy())),
+		})
+		.collect::<Vec<String>>()
+		.join("/")
+}
+
+/// Returns the `file:` URI of the absolute `path`.
+fn file_uri(path: &Path) -> String {
+	let path = path.to_string_lossy().replace('\\', "/");
+	let path = encode(&path).replace("%2F", "/");
+	match path.starts_with('/') {
+		true => format!("file://{path}"),
+		// Windows paths start with a drive letter, e.g. `C:/src`
+		false => format!("file:///{}", path.replace("%3A", ":")),
+	}
+}
+
+/// Percent-encodes the bytes of `text` that can't appear as they are in a
+/// URI path segment.
+fn encode(text: &str) -> String {
+	let mut encoded = String::with_capacity(text.len());
+	for byte in text.bytes() {
+		match byte {
+			b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+				encoded.push(byte as char)
+			}
+			_ => encoded.push_str(&format!("%{byte:02X}")),
+		}
+	}
+
+	encoded
+}
@@ -0,0 +1,90 @@
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+/// Saved searches by name: the arguments of each, flags included.
+pub type SavedSearches = BTreeMap<String, Vec<String>>;
+
+/// Represents an error reading or writing the saved searches file.
+#[derive(Debug)]
+pub enum SavedError {
+	Invalid(String),
+	Io(std::io::Error),
+	Parse(serde_json::Error),
+}
+
+impl Display for SavedError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			SavedError::Invalid(msg) => write!(f, "invalid saved searches file: {msg}"),
+			SavedError::Io(e) => write!(f, "saved searches file error: {e}"),
+			SavedError::Parse(e) => write!(f, "invalid saved searches file: {e}"),
+		}
+	}
+}
+
+impl std::error::Error for SavedError {}
+
+/// Returns `args` as typed, with arguments containing spaces quoted.
+pub fn command_line(args: &[String]) -> String {
+	args.iter()
+		.map(|arg| {
+			if arg.contains(' ') {
+				format!("\"{arg}\"")
+			} else {
+				arg.clone()
+			}
+		})
+		.collect::<Vec<String>>()
+		.join(" ")
+}
+
+/// Returns the path of the saved searches file in the data directory `dir`,
+/// shared by every project.
+pub fn path_for(dir: &Path) -> PathBuf {
+	dir.join("searches.json")
+}
+
+/// Reads the searches saved at `path`. A missing file has no searches.
+pub fn load(path: &Path) -> Result<SavedSearches, SavedError> {
+	let contents = match std::fs::read_to_string(path) {
+		Ok(v) => v,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(SavedSearches::new()),
+		Err(e) => return Err(SavedError::Io(e)),
+	};
+
+	let value = serde_json::from_str::<Value>(&contents).map_err(SavedError::Parse)?;
+	let invalid = |msg: &str| SavedError::Invalid(String::from(msg));
+	let mut searches = SavedSearches::new();
+	for (name, args) in value
+		.get("searches")
+		.and_then(Value::as_object)
+		.ok_or_else(|| invalid("missing searches object"))?
+	{
+		let args = args
+			.as_array()
+			.ok_or_else(|| invalid("expected an array of strings"))?
+			.iter()
+			.map(|v| v.as_str().map(String::from))
+			.collect::<Option<Vec<String>>>()
+			.ok_or_else(|| invalid("expected an array of strings"))?;
+
+		searches.insert(name.clone(), args);
+	}
+
+	Ok(searches)
+}
+
+/// Writes `searches` to `path`, replacing any saved searches.
+pub fn save(path: &Path, searches: &SavedSearches) -> Result<(), SavedError> {
+	let searches = searches
+		.iter()
+		.map(|(name, args)| (name.clone(), json!(args)))
+		.collect::<Map<String, Value>>();
+
+	let contents = serde_json::to_string_pretty(&json!({ "searches": searches }))
+		.map_err(SavedError::Parse)?;
+
+	std::fs::write(path, contents).map_err(SavedError::Io)
+}
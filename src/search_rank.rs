@@ -1,69 +1,630 @@
-use std::{fs, path::Path};
+use regex::Regex;
+use std::ops::Range;
+use std::path::Path;
 
-pub fn rank_file<P: AsRef<Path> + std::fmt::Debug>(
+use crate::cancel::CancelToken;
+use crate::comments::{Region, Regions};
+use crate::encoding::Folded;
+use crate::language::Language;
+use crate::ngram::NGram;
+use crate::result_set::Match;
+use crate::symbols::Symbol;
+use crate::tokenize;
+
+/// Rank added per byte of a search term found on a definition line,
+/// e.g. `fn term` or `class term`.
+const DEFINITION_RANK: usize = 1000;
+
+/// Rank added for a definition whose name exactly matches a search term.
+/// Large enough that definitions always outrank plain text matches.
+const SYMBOL_EXACT_RANK: usize = 1_000_000;
+
+/// Rank added for a definition whose name contains a search term.
+const SYMBOL_PARTIAL_RANK: usize = 100_000;
+
+/// Multiplies the symbol rank of Rust module roots, `lib.rs` and `mod.rs`,
+/// where a crate's or module's public items are usually defined or
+/// re-exported.
+const MODULE_ROOT_FACTOR: usize = 2;
+
+/// The most characters of a line kept in a preview. Printed previews are
+/// clipped further to fit the terminal.
+const PREVIEW_CHARS: usize = 120;
+
+/// Controls how quickly repeated occurrences of a term stop adding rank.
+const BM25_K1: f64 = 1.2;
+
+/// Controls how much longer documents are penalized.
+const BM25_B: f64 = 0.75;
+
+/// Scales BM25 scores to the magnitude of the other rank signals.
+const BM25_SCALE: f64 = 100.0;
+
+/// Configures how file paths affect ranking.
+#[derive(Clone, Debug)]
+pub struct PathScoring {
+	/// Rank added per byte of a search term found in a file's name.
+	/// Doubled when the name without its extension is the search term.
+	pub basename_rank: usize,
+	/// Rank added per byte of a search term found in a file's directories.
+	pub directory_rank: usize,
+	/// Patterns of files to demote. Patterns containing a `.` match file
+	/// names containing them, e.g. `.min.`, and others match directory
+	/// names, e.g. `vendor`.
+	pub demote: Vec<String>,
+	/// Demoted files have their rank divided by this.
+	pub demote_factor: usize,
+}
+
+/// Configures how matches in comments and string literals affect ranking.
+#[derive(Clone, Debug)]
+pub struct CommentScoring {
+	/// The percentage of its rank kept by a document whose matches are all
+	/// in comments.
+	pub comment: usize,
+	/// The percentage of its rank kept by a document whose matches are all
+	/// in string literals, or in comments and string literals.
+	pub string: usize,
+}
+
+impl CommentScoring {
+	/// Returns `true` if matches rank the same wherever they are.
+	pub fn is_neutral(&self) -> bool {
+		self.comment == 100 && self.string == 100
+	}
+}
+
+/// Occurrence counts of a search term across every indexed document.
+pub struct TermFrequencies {
+	/// How many times the term occurs in each document, in document order.
+	pub counts: Vec<u32>,
+	/// The number of documents containing the term.
+	pub documents: usize,
+}
+
+/// Document statistics used to score terms with BM25.
+pub struct CollectionStats {
+	/// The average number of n-grams in a document.
+	pub average_length: f64,
+	/// The number of indexed documents.
+	pub document_count: usize,
+	/// The number of n-grams in each document, in document order.
+	pub lengths: Vec<u32>,
+}
+
+/// Ranks a document by how well `contents` match the search terms.
+/// Definitions are found with the patterns of `language`, or of the
+/// extension of `path` for languages without an ID, so compressed documents
+/// should be given their decompressed path. Stops early with the rank so far
+/// once `cancel` is cancelled or expires.
+pub fn rank_file<P: AsRef<Path>>(
 	path: P,
+	language: Option<Language>,
+	contents: &str,
 	search_terms: &[String],
-	trigrams: &[[u8; 3]],
-	previews: &mut Vec<(usize, String)>,
-) -> std::io::Result<usize> {
-	let contents = fs::read_to_string(&path)?.to_lowercase();
+	ngrams: &[NGram],
+	matches: &mut Vec<Match>,
+	cancel: &CancelToken,
+) -> usize {
+	let folded = Folded::new(contents);
+	let contents = folded.text.as_str();
+	let found = |start: usize, len: usize| original_match(&folded, start..start + len);
+
+	let search_terms = search_terms
+		.iter()
+		.map(|term| term.to_lowercase())
+		.collect::<Vec<String>>();
+
+	let search_terms = search_terms.as_slice();
 	let mut rank = 0;
-	let mut preview_buf = Vec::new();
+	let mut buf = Vec::new();
 
 	// Check if the file contains our exact phrase
-	let mut terms = search_terms.iter();
-	if let Some(start) = contents.find(terms.next().expect("search_terms cannot be empty")) {
-		let mut search_str = contents[start..].trim();
-		if terms.all(|term| {
-			if search_str.starts_with(term) {
-				search_str = search_str[term.len()..].trim();
-				true
-			} else {
-				false
+	if let Some(phrase) = find_phrase(contents, search_terms) {
+		let len = search_terms.iter().fold(0, |v, term| v + term.len());
+		rank += len * 100;
+		buf.push(original_match(&folded, phrase));
+	}
+
+	// Check for identifier spellings of the search terms
+	for variant in tokenize::variants(search_terms) {
+		if cancel.should_stop() {
+			return rank;
+		}
+
+		if let Some(start) = contents.find(&variant) {
+			rank += variant.len() * 100;
+			buf.push(found(start, variant.len()));
+		}
+	}
+
+	// Check for definitions of individual terms
+	let extension = path
+		.as_ref()
+		.extension()
+		.and_then(|e| e.to_str())
+		.unwrap_or("");
+
+	for term in search_terms {
+		if cancel.should_stop() {
+			return rank;
+		}
+
+		for range in find_definitions(contents, language, extension, term) {
+			rank += term.len() * DEFINITION_RANK;
+			buf.push(original_match(&folded, range));
+		}
+	}
+
+	// Preview every line with a term, which are ranked by `rank_bm25`, so
+	// the lines previewed and the count of those left out are the lines
+	// `find_occurrences` reports
+	buf.extend(occurrences(&folded, search_terms));
+
+	// N-grams are only previewed when nothing else matched, e.g. for a
+	// misspelled term, as they'd otherwise add a line each. Those that split
+	// a multi-byte character are skipped.
+	if buf.is_empty() {
+		for gram in ngrams.iter().filter_map(|gram| gram.as_str()) {
+			if let Some(start) = contents.find(gram) {
+				buf.push(Match {
+					partial: true,
+					..found(start, gram.len())
+				});
 			}
-		}) {
-			let len = search_terms.iter().fold(0, |v, term| v + term.len());
-			rank += len * 100;
-			preview_buf.push(get_preview(&contents, &contents[start..start + len]));
 		}
 	}
 
-	// Check for individual terms
-	search_terms.iter().for_each(|term| {
-		if contents.contains(term) {
-			rank += term.len() * 10;
-			preview_buf.push(get_preview(&contents, term));
+	// Previews are centered on different matches, so a line keeps its first
+	buf.sort_by_key(|m| m.line);
+	buf.into_iter().for_each(|m| {
+		if !matches.iter().any(|other| other.line == m.line) {
+			matches.push(m);
 		}
 	});
 
-	// Check for individual trigrams
-	trigrams
-		.iter()
-		.map(|tri| std::str::from_utf8(tri).unwrap())
-		.for_each(|tri| {
-			if contents.contains(tri) {
-				rank += 1;
-				preview_buf.push(get_preview(&contents, tri));
+	rank
+}
+
+/// Returns the bytes of the first occurrence in `contents` of `terms` in
+/// order, separated only by whitespace, line breaks included.
+fn find_phrase(contents: &str, terms: &[String]) -> Option<Range<usize>> {
+	let (first, rest) = terms.split_first()?;
+	contents
+		.match_indices(first.as_str())
+		.find_map(|(start, _)| {
+			let mut end = start + first.len();
+			for term in rest {
+				let after = contents[end..].trim_start();
+				if !after.starts_with(term.as_str()) {
+					return None;
+				}
+
+				end = contents.len() - after.len() + term.len();
 			}
-		});
 
-	preview_buf.sort_by(|a, b| a.0.cmp(&b.0));
-	preview_buf.into_iter().for_each(|prev| {
-		if !previews.contains(&prev) {
-			previews.push(prev);
+			Some(start..end)
+		})
+}
+
+/// Returns every occurrence in `contents` of the search terms and their
+/// identifier spellings, ignoring case, in the order they appear. Where
+/// occurrences overlap, only the one starting first, or the longest of
+/// those, is kept.
+pub fn find_occurrences(contents: &str, search_terms: &[String]) -> Vec<Match> {
+	let terms = search_terms
+		.iter()
+		.map(|term| term.to_lowercase())
+		.collect::<Vec<String>>();
+
+	occurrences(&Folded::new(contents), &terms)
+}
+
+/// Returns every occurrence in the `folded` text of `terms`, which are
+/// lowercase, like `find_occurrences`.
+fn occurrences(folded: &Folded, terms: &[String]) -> Vec<Match> {
+	let mut found = terms
+		.iter()
+		.cloned()
+		.chain(tokenize::variants(terms))
+		.filter(|term| !term.is_empty())
+		.flat_map(|term| {
+			folded
+				.text
+				.match_indices(&term)
+				.map(|(start, _)| start..start + term.len())
+				.collect::<Vec<Range<usize>>>()
+		})
+		.collect::<Vec<Range<usize>>>();
+
+	found.sort_by_key(|range| (range.start, std::cmp::Reverse(range.end)));
+	let mut end = 0;
+	found
+		.into_iter()
+		.filter(|range| {
+			let keep = range.start >= end;
+			end = end.max(range.end);
+			keep
+		})
+		.map(|range| original_match(folded, range))
+		.collect()
+}
+
+/// Ranks the document `doc_id` by the BM25 score of the search terms, so
+/// documents mentioning a term often outrank those mentioning it once, and
+/// rare terms count for more than common ones.
+pub fn rank_bm25(terms: &[TermFrequencies], stats: &CollectionStats, doc_id: usize) -> usize {
+	let document_count = stats.document_count as f64;
+	let length = stats.lengths.get(doc_id).copied().unwrap_or(0) as f64;
+	let normalization = if stats.average_length > 0.0 {
+		1.0 - BM25_B + BM25_B * length / stats.average_length
+	} else {
+		1.0
+	};
+
+	let score = terms
+		.iter()
+		.map(|term| {
+			let frequency = term.counts.get(doc_id).copied().unwrap_or(0) as f64;
+			let documents = term.documents as f64;
+			let idf = ((document_count - documents + 0.5) / (documents + 0.5) + 1.0).ln();
+			idf * frequency * (BM25_K1 + 1.0) / (frequency + BM25_K1 * normalization)
+		})
+		.sum::<f64>();
+
+	(score * BM25_SCALE) as usize
+}
+
+/// Ranks a document by how often it mentions `terms`, lowercase terms
+/// shorter than an n-gram, which have no frequencies for `rank_bm25`.
+pub fn rank_short_terms(contents: &str, terms: &[String]) -> usize {
+	if terms.is_empty() {
+		return 0;
+	}
+
+	let contents = contents.to_lowercase();
+	let score = terms
+		.iter()
+		.map(|term| {
+			let frequency = contents.matches(term.as_str()).count() as f64;
+			frequency * (BM25_K1 + 1.0) / (frequency + BM25_K1)
+		})
+		.sum::<f64>();
+
+	(score * BM25_SCALE) as usize
+}
+
+/// Ranks a document by the search terms found in its `path`, so that a
+/// query for `parser` ranks `src/parser.rs` above files that only mention it.
+pub fn rank_path(path: &Path, search_terms: &[String], scoring: &PathScoring) -> usize {
+	let name = path
+		.file_name()
+		.map(|v| v.to_string_lossy().to_lowercase())
+		.unwrap_or_default();
+
+	let stem = path
+		.file_stem()
+		.map(|v| v.to_string_lossy().to_lowercase())
+		.unwrap_or_default();
+
+	let directories = path
+		.parent()
+		.map(|v| v.to_string_lossy().to_lowercase())
+		.unwrap_or_default();
+
+	let mut rank = 0;
+	for term in search_terms.iter().map(|term| term.to_lowercase()) {
+		if stem == term {
+			rank += term.len() * scoring.basename_rank * 2;
+		} else if name.contains(&term) {
+			rank += term.len() * scoring.basename_rank;
+		}
+
+		if directories.contains(&term) {
+			rank += term.len() * scoring.directory_rank;
 		}
+	}
+
+	rank
+}
+
+/// Returns `rank` scaled by where `matches` are in their document, whose
+/// comments and string literals are `regions`, according to `scoring`.
+/// Documents get the largest percentage of the places they match in, code
+/// being 100%, so those matching in code are never down-weighted.
+pub fn weigh_regions(
+	rank: usize,
+	matches: &[Match],
+	regions: &Regions,
+	scoring: &CommentScoring,
+) -> usize {
+	let percent = matches
+		.iter()
+		.map(|m| match regions.at(m.byte_range.start) {
+			Region::Code => 100,
+			Region::Comment => scoring.comment,
+			Region::String => scoring.string,
+		})
+		.max()
+		.unwrap_or(100);
+
+	rank * percent / 100
+}
+
+/// Returns `rank` reduced if `path` is a vendored, test, or generated file
+/// according to `scoring`.
+pub fn demote_path(path: &Path, rank: usize, scoring: &PathScoring) -> usize {
+	let name = path
+		.file_name()
+		.map(|v| v.to_string_lossy().to_lowercase())
+		.unwrap_or_default();
+
+	let demoted = scoring.demote.iter().any(|pattern| {
+		if pattern.contains('.') {
+			return name.contains(&pattern.to_lowercase());
+		}
+
+		path.parent()
+			.into_iter()
+			.flat_map(|p| p.components())
+			.any(|c| {
+				c.as_os_str()
+					.to_string_lossy()
+					.eq_ignore_ascii_case(pattern)
+			})
 	});
 
-	Ok(rank)
+	if demoted {
+		rank / scoring.demote_factor.max(1)
+	} else {
+		rank
+	}
+}
+
+/// Ranks a document by the query subtokens found in its compound identifiers.
+pub fn rank_tokens<'a, I: Iterator<Item = &'a str>>(matched: I) -> usize {
+	matched.map(|token| token.len() * 10).sum()
+}
+
+/// Ranks a document by how well its symbol definitions match the search terms.
+/// Matches of definitions, located in the document's `contents`, are placed
+/// ahead of any existing matches.
+pub fn rank_symbols(
+	symbols: &[Symbol],
+	contents: &str,
+	search_terms: &[String],
+	matches: &mut Vec<Match>,
+) -> usize {
+	let line_starts = std::iter::once(0)
+		.chain(contents.match_indices('\n').map(|(i, _)| i + 1))
+		.collect::<Vec<usize>>();
+
+	let mut rank = 0;
+	let mut definitions = Vec::new();
+	for symbol in symbols {
+		let name = symbol.name.to_lowercase();
+		let symbol_rank = search_terms
+			.iter()
+			.map(|term| term.to_lowercase())
+			.map(|term| {
+				if name == term {
+					SYMBOL_EXACT_RANK
+				} else if name.contains(term.as_str()) {
+					SYMBOL_PARTIAL_RANK
+				} else {
+					0
+				}
+			})
+			.max()
+			.unwrap_or(0);
+
+		if symbol_rank > 0 {
+			rank += symbol_rank;
+
+			// The name is found on the line the definition starts on, if it's
+			// there, or else the match is the start of the line
+			let line = symbol.line as usize;
+			let line_start = line_starts
+				.get(line.saturating_sub(1))
+				.copied()
+				.unwrap_or(contents.len());
+
+			let line_end = contents[line_start..]
+				.find('\n')
+				.map_or(contents.len(), |i| line_start + i);
+
+			let (column, len) = match contents[line_start..line_end].find(&symbol.name) {
+				Some(column) => (column, symbol.name.len()),
+				None => (0, 0),
+			};
+
+			let start = line_start + column;
+			definitions.push(Match {
+				line,
+				column: column + 1,
+				byte_range: start..start + len,
+				preview: format!("{} {}", symbol.kind, symbol.name),
+				partial: false,
+			});
+		}
+	}
+
+	matches.retain(|m| !definitions.iter().any(|def| def.line == m.line));
+	definitions.append(matches);
+	*matches = definitions;
+	rank
+}
+
+/// Boosts `symbol_rank` if `path` is a Rust module root.
+pub fn boost_module_root(path: &Path, symbol_rank: usize) -> usize {
+	let is_root = path
+		.file_name()
+		.is_some_and(|name| name == "lib.rs" || name == "mod.rs");
+
+	if is_root {
+		symbol_rank * MODULE_ROOT_FACTOR
+	} else {
+		symbol_rank
+	}
 }
 
-fn get_preview(source: &str, search: &str) -> (usize, String) {
-	for (i, line) in source.lines().enumerate() {
-		if line.contains(search) {
-			let trimmed = line.trim();
-			return (i + 1, trimmed[..50.min(trimmed.len())].to_string());
+impl Default for PathScoring {
+	fn default() -> Self {
+		let demote = [
+			"vendor",
+			"third_party",
+			"node_modules",
+			"test",
+			"tests",
+			"__tests__",
+			"testdata",
+			"generated",
+			"dist",
+			"_test.",
+			".test.",
+			".spec.",
+			".min.",
+			".generated.",
+			".pb.",
+		];
+
+		Self {
+			basename_rank: 200,
+			directory_rank: 20,
+			demote: demote.into_iter().map(String::from).collect(),
+			demote_factor: 4,
+		}
+	}
+}
+
+impl Default for CommentScoring {
+	fn default() -> Self {
+		Self {
+			comment: 50,
+			string: 100,
+		}
+	}
+}
+
+/// Returns the definition patterns for documents in `language`, or with
+/// the given extension if their language has no ID. Each pattern contains a
+/// `{}` placeholder for the (escaped) identifier and is matched against
+/// lowercased file contents.
+fn definition_patterns(language: Option<Language>, extension: &str) -> &'static [&'static str] {
+	const C_LIKE: &[&str] = &[r"\b(?:struct|class|enum|union|interface|typedef|fun|func)\s+{}\b"];
+	match language {
+		Some(Language::Rust) => {
+			&[r"\b(?:fn|struct|enum|trait|union|mod|type|const|static|macro_rules!)\s+{}\b"]
+		}
+		Some(Language::Python) => &[r"\b(?:def|class)\s+{}\b"],
+		Some(Language::JavaScript | Language::TypeScript) => &[
+			r"\b(?:function\*?|class|interface|type|enum)\s+{}\b",
+			r"\b(?:const|let|var)\s+{}\s*=",
+		],
+		Some(Language::Go) => &[r"\bfunc\s+(?:\([^)]*\)\s*)?{}\b", r"\btype\s+{}\b"],
+		Some(Language::Ruby) => &[r"\b(?:def|class|module)\s+{}\b"],
+		Some(Language::C | Language::Cpp | Language::Java | Language::CSharp) => C_LIKE,
+		Some(Language::Shell) => &[r"\bfunction\s+{}\b", r"(?m)^\s*{}\s*\(\s*\)"],
+		Some(Language::Markdown) => &[r"(?m)^#+\s+{}\b"],
+		None => match extension {
+			"kt" | "swift" => C_LIKE,
+			_ => &[],
+		},
+	}
+}
+
+/// Finds the bytes of the defined name in every definition of `term` in
+/// `contents`, using the patterns for `language` or `extension`.
+fn find_definitions(
+	contents: &str,
+	language: Option<Language>,
+	extension: &str,
+	term: &str,
+) -> Vec<Range<usize>> {
+	let term = format!("({})", regex::escape(&term.to_lowercase()));
+	let mut names = Vec::new();
+	for pattern in definition_patterns(language, extension) {
+		let re = match Regex::new(&pattern.replace("{}", &term)) {
+			Ok(v) => v,
+			Err(_) => continue,
+		};
+
+		names.extend(
+			re.captures_iter(contents)
+				.filter_map(|captures| captures.get(1))
+				.map(|name| name.range()),
+		);
+	}
+
+	names
+}
+
+/// Returns the match of the bytes `range` of the lowercased text of
+/// `folded`, with its offsets into the original text.
+fn original_match(folded: &Folded, range: Range<usize>) -> Match {
+	line_match(folded.original, folded.original_range(range))
+}
+
+/// Returns the match of the bytes `range` of `source`, previewing the line
+/// it starts on, or the last line if it starts past the end.
+fn line_match(source: &str, range: Range<usize>) -> Match {
+	let mut offset = range.start.min(source.len());
+	while !source.is_char_boundary(offset) {
+		offset -= 1;
+	}
+
+	let line_start = source[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+	let line_end = source[offset..]
+		.find('\n')
+		.map(|i| offset + i)
+		.unwrap_or(source.len());
+
+	let column = offset - line_start;
+	Match {
+		line: source[..offset].matches('\n').count() + 1,
+		column: column + 1,
+		byte_range: range,
+		preview: truncate_preview(&source[line_start..line_end], column),
+		partial: false,
+	}
+}
+
+/// Returns the preview of `line`, trimmed and clipped to `PREVIEW_CHARS`
+/// around the match at the byte offset `column`.
+fn truncate_preview(line: &str, column: usize) -> String {
+	let trimmed = line.trim_start();
+	let column = column.saturating_sub(line.len() - trimmed.len());
+	clip_preview(trimmed.trim_end(), column, PREVIEW_CHARS)
+}
+
+/// Clips `text` to at most `width` characters, keeping the window centered
+/// on the byte offset `column` where possible. Clipped ends are marked with
+/// `…`, and characters are never split.
+pub fn clip_preview(text: &str, column: usize, width: usize) -> String {
+	let len = text.chars().count();
+	if len <= width {
+		return text.to_string();
+	}
+
+	let mut column = column.min(text.len());
+	while !text.is_char_boundary(column) {
+		column -= 1;
+	}
+
+	let center = text[..column].chars().count();
+	let start = center.saturating_sub(width / 2).min(len - width);
+	let mut window = text.chars().skip(start).take(width).collect::<Vec<char>>();
+	if start > 0 {
+		if let Some(first) = window.first_mut() {
+			*first = '…';
+		}
+	}
+
+	if start + width < len {
+		if let Some(last) = window.last_mut() {
+			*last = '…';
 		}
 	}
 
-	unreachable!()
+	window.into_iter().collect()
 }
@@ -0,0 +1,144 @@
+use serde_json::{json, Value};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::encoding;
+use crate::index::{self, IndexOptions};
+
+/// The shard holding the files directly in the current directory.
+pub const ROOT_SHARD: &str = ".";
+
+/// New indexes of trees with more files than this are sharded.
+const LARGE_TREE_FILES: usize = 100_000;
+
+/// Represents an error reading or writing the shard manifest.
+#[derive(Debug)]
+pub enum ShardError {
+	Invalid(String),
+	Io(std::io::Error),
+	Parse(serde_json::Error),
+	Walk(ignore::Error),
+}
+
+impl Display for ShardError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ShardError::Invalid(msg) => write!(f, "invalid shard manifest: {msg}"),
+			ShardError::Io(e) => write!(f, "shard manifest error: {e}"),
+			ShardError::Parse(e) => write!(f, "invalid shard manifest: {e}"),
+			ShardError::Walk(e) => write!(f, "failed to list shards: {e}"),
+		}
+	}
+}
+
+impl std::error::Error for ShardError {}
+
+/// Returns the path of the shard manifest kept alongside the index at `index`.
+pub fn manifest_path(index: &Path) -> PathBuf {
+	index.with_extension("shards")
+}
+
+/// Returns the path of the index file for the shard of `dir`.
+pub fn index_path(index: &Path, dir: &Path) -> PathBuf {
+	let hash = hmac_sha256::Hash::hash(&encoding::os_str_to_bytes(dir.as_os_str()));
+	index.with_extension(format!("shard-{}", &encoding::to_hex(&hash)[..16]))
+}
+
+/// Reads the shard directories listed in the manifest at `path`, or `None`
+/// if the index isn't sharded.
+pub fn load(path: &Path) -> Result<Option<Vec<PathBuf>>, ShardError> {
+	let contents = match std::fs::read_to_string(path) {
+		Ok(v) => v,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+		Err(e) => return Err(ShardError::Io(e)),
+	};
+
+	let value = serde_json::from_str::<Value>(&contents).map_err(ShardError::Parse)?;
+	value
+		.get("shards")
+		.and_then(Value::as_array)
+		.ok_or_else(|| ShardError::Invalid(String::from("missing shards array")))?
+		.iter()
+		.map(|v| v.as_str().map(PathBuf::from))
+		.collect::<Option<Vec<PathBuf>>>()
+		.map(Some)
+		.ok_or_else(|| ShardError::Invalid(String::from("expected an array of strings")))
+}
+
+/// Writes the manifest listing `dirs` to `path`.
+pub fn save(path: &Path, dirs: &[PathBuf]) -> Result<(), ShardError> {
+	let dirs = dirs
+		.iter()
+		.map(|dir| dir.to_string_lossy())
+		.collect::<Vec<_>>();
+
+	let contents =
+		serde_json::to_string_pretty(&json!({ "shards": dirs })).map_err(ShardError::Parse)?;
+
+	std::fs::write(path, contents).map_err(ShardError::Io)
+}
+
+/// Lists the shards of the current directory: one per top-level directory
+/// that isn't ignored, one for the files directly in it, and one for each
+/// extra root.
+pub fn discover(roots: &[PathBuf]) -> Result<Vec<PathBuf>, ShardError> {
+	let mut dirs = vec![PathBuf::from(ROOT_SHARD)];
+	for res in ignore::WalkBuilder::new(".").max_depth(Some(1)).build() {
+		let entry = res.map_err(ShardError::Walk)?;
+		if entry.depth() == 1 && entry.file_type().is_some_and(|t| t.is_dir()) {
+			dirs.push(entry.into_path());
+		}
+	}
+
+	dirs.extend(roots.iter().cloned());
+	dirs.sort();
+	dirs.dedup();
+	Ok(dirs)
+}
+
+/// Returns whether the tree indexed with `options` is large enough to shard
+/// its index. Stops walking it once it is.
+pub fn is_large(options: &IndexOptions) -> bool {
+	index::walk(options).take(LARGE_TREE_FILES + 1).count() > LARGE_TREE_FILES
+}
+
+/// Applies `f` to each of `items` on a pool of threads, one per available
+/// core, returning the results in the order of `items`.
+pub fn parallel_map<T, R, F>(items: Vec<T>, f: F) -> Vec<R>
+where
+	T: Send,
+	R: Send,
+	F: Fn(T) -> R + Sync,
+{
+	let threads = thread::available_parallelism()
+		.map(|n| n.get())
+		.unwrap_or(1)
+		.min(items.len());
+
+	let len = items.len();
+	let queue = Mutex::new(items.into_iter().enumerate());
+	let results = Mutex::new((0..len).map(|_| None).collect::<Vec<Option<R>>>());
+	thread::scope(|scope| {
+		for _ in 0..threads {
+			scope.spawn(|| loop {
+				let next = queue.lock().expect("shard queue poisoned").next();
+				let (i, item) = match next {
+					Some(v) => v,
+					None => break,
+				};
+
+				let result = f(item);
+				results.lock().expect("shard results poisoned")[i] = Some(result);
+			});
+		}
+	});
+
+	results
+		.into_inner()
+		.expect("shard results poisoned")
+		.into_iter()
+		.map(|r| r.expect("shard worker exited early"))
+		.collect()
+}
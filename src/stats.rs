@@ -0,0 +1,167 @@
+use console::style;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use crate::history;
+use crate::index::{Index, IndexError};
+use crate::ngram::NGram;
+
+/// How many n-grams are listed as the most and the least common.
+const LISTED_NGRAMS: usize = 10;
+
+/// How many of the largest documents are listed.
+const LISTED_DOCUMENTS: usize = 10;
+
+/// The widest bar of the documents per n-gram histogram.
+const HISTOGRAM_WIDTH: usize = 40;
+
+/// What an index holds and how much space each part takes, across all of
+/// its shards.
+pub struct IndexStats {
+	/// The number of documents.
+	documents: u64,
+	/// The size of the index files, in bytes.
+	bytes: u64,
+	/// The number of documents containing each n-gram, most common first.
+	ngrams: Vec<(NGram, usize)>,
+	/// The length in bytes of each part of the index files.
+	parts: Vec<(String, u64)>,
+	/// The largest documents and their sizes, largest first.
+	largest: Vec<(PathBuf, u64)>,
+}
+
+/// Gathers the statistics of `indexes`, the shards of one index.
+pub fn collect(indexes: &mut [Index]) -> Result<IndexStats, IndexError> {
+	let mut documents = 0;
+	let mut bytes = 0;
+	let mut ngrams = HashMap::new();
+	let mut parts: Vec<(String, u64)> = Vec::new();
+	let mut largest = Vec::new();
+	for index in indexes.iter_mut() {
+		documents += index.document_count() as u64;
+		bytes += index.file_len();
+		for (ngram, count) in index.ngram_document_counts()? {
+			*ngrams.entry(ngram).or_insert(0) += count;
+		}
+
+		for (name, len) in index.part_lengths() {
+			match parts.iter_mut().find(|(n, _)| n == &name) {
+				Some((_, total)) => *total += len,
+				None => parts.push((name, len)),
+			}
+		}
+
+		if let Some(sizes) = index.document_sizes()? {
+			largest.extend(index.document_paths()?.into_iter().zip(sizes));
+		}
+	}
+
+	let mut ngrams = ngrams.into_iter().collect::<Vec<(NGram, usize)>>();
+	ngrams.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+	largest.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+	largest.truncate(LISTED_DOCUMENTS);
+	Ok(IndexStats {
+		documents,
+		bytes,
+		ngrams,
+		parts,
+		largest,
+	})
+}
+
+/// Writes `stats` to `out`, noting the number of `shards` if the index is
+/// sharded.
+pub fn print<W: Write>(mut out: W, stats: &IndexStats, shards: Option<usize>) -> io::Result<()> {
+	writeln!(out, "Documents: {}", stats.documents)?;
+	writeln!(out, "Size: {}", history::format_bytes(stats.bytes))?;
+	if let Some(shards) = shards {
+		writeln!(out, "Shards: {shards}")?;
+	}
+
+	writeln!(out, "N-grams: {}", stats.ngrams.len())?;
+	if stats.ngrams.is_empty() || stats.documents == 0 {
+		return Ok(());
+	}
+
+	// The share of documents the average n-gram's posting marks
+	let postings = stats
+		.ngrams
+		.iter()
+		.map(|(_, count)| *count as f64)
+		.sum::<f64>();
+	let density = postings / stats.ngrams.len() as f64 / stats.documents as f64;
+	writeln!(out, "Average postings density: {:.2}%", density * 100.0)?;
+
+	let heading = |text: &str| format!("\n{}", style(text).bold());
+	let most = &stats.ngrams[..LISTED_NGRAMS.min(stats.ngrams.len())];
+	let least = &stats.ngrams[stats.ngrams.len().saturating_sub(LISTED_NGRAMS)..];
+	for (title, ngrams) in [
+		("Most common n-grams", most),
+		("Least common n-grams", least),
+	] {
+		writeln!(out, "{}", heading(title))?;
+		for (ngram, count) in ngrams {
+			let quoted = format!("\"{}\"", escape(ngram));
+			writeln!(out, "  {quoted:<8}  {count} documents")?;
+		}
+	}
+
+	// Bucket n-grams by powers of two of their document counts
+	let mut buckets = Vec::<usize>::new();
+	for (_, count) in &stats.ngrams {
+		let bucket = count.max(&1).ilog2() as usize;
+		if buckets.len() <= bucket {
+			buckets.resize(bucket + 1, 0);
+		}
+
+		buckets[bucket] += 1;
+	}
+
+	writeln!(out, "{}", heading("Documents per n-gram"))?;
+	let widest = buckets.iter().copied().max().unwrap_or(1).max(1);
+	for (i, n) in buckets.iter().enumerate() {
+		let (low, high) = (1usize << i, (1usize << (i + 1)) - 1);
+		let range = if low == high {
+			low.to_string()
+		} else {
+			format!("{low}-{high}")
+		};
+
+		let bar = "#".repeat((n * HISTOGRAM_WIDTH).div_ceil(widest));
+		writeln!(out, "  {range:>11}  {n:>8}  {bar}")?;
+	}
+
+	if !stats.largest.is_empty() {
+		writeln!(out, "{}", heading("Largest documents"))?;
+		for (path, size) in &stats.largest {
+			writeln!(
+				out,
+				"  {:>10}  {}",
+				history::format_bytes(*size),
+				path.to_string_lossy()
+			)?;
+		}
+	}
+
+	writeln!(out, "{}", heading("Size on disk"))?;
+	for (name, len) in &stats.parts {
+		writeln!(out, "  {name:<9}  {:>10}", history::format_bytes(*len))?;
+	}
+
+	Ok(())
+}
+
+/// Returns `ngram` as text, with the bytes of any character it splits
+/// escaped, e.g. `\xe2c\x80`.
+fn escape(ngram: &NGram) -> String {
+	let mut text = String::new();
+	for chunk in ngram.as_bytes().utf8_chunks() {
+		text.push_str(chunk.valid());
+		for byte in chunk.invalid() {
+			text.push_str(&format!("\\x{byte:02x}"));
+		}
+	}
+
+	text
+}
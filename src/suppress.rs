@@ -0,0 +1,36 @@
+use std::borrow::Cow;
+
+/// Excludes a whole file from results when found in its first lines.
+/// The annotations are split so this file doesn't suppress itself.
+const IGNORE_FILE: &str = concat!("codesearch", ":ignore-file");
+
+/// Excludes the line it is found on from results.
+const IGNORE_LINE: &str = concat!("codesearch", ":ignore");
+
+/// How many lines from the start of a file are checked for `IGNORE_FILE`.
+const IGNORE_FILE_LINES: usize = 10;
+
+/// Applies the suppression annotations in `contents`. Returns `None` if the
+/// whole file is suppressed. Otherwise, suppressed lines are blanked so that
+/// line numbers are unchanged.
+pub fn apply(contents: &str) -> Option<Cow<'_, str>> {
+	if contents
+		.lines()
+		.take(IGNORE_FILE_LINES)
+		.any(|line| line.contains(IGNORE_FILE))
+	{
+		return None;
+	}
+
+	if !contents.contains(IGNORE_LINE) {
+		return Some(Cow::Borrowed(contents));
+	}
+
+	let text = contents
+		.split('\n')
+		.map(|line| if line.contains(IGNORE_LINE) { "" } else { line })
+		.collect::<Vec<&str>>()
+		.join("\n");
+
+	Some(Cow::Owned(text))
+}
@@ -0,0 +1,197 @@
+use std::fmt::Display;
+use std::path::Path;
+
+/// The kind of definition a symbol refers to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SymbolKind {
+	Function,
+	Struct,
+	Enum,
+	Trait,
+	Class,
+	Module,
+	Constant,
+	Type,
+	Macro,
+}
+
+/// A named definition extracted from a document.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Symbol {
+	pub kind: SymbolKind,
+	/// The 1-based line the definition starts on.
+	pub line: u32,
+	pub name: String,
+}
+
+impl SymbolKind {
+	fn from_byte(b: u8) -> Option<Self> {
+		Some(match b {
+			0 => SymbolKind::Function,
+			1 => SymbolKind::Struct,
+			2 => SymbolKind::Enum,
+			3 => SymbolKind::Trait,
+			4 => SymbolKind::Class,
+			5 => SymbolKind::Module,
+			6 => SymbolKind::Constant,
+			7 => SymbolKind::Type,
+			8 => SymbolKind::Macro,
+			_ => return None,
+		})
+	}
+
+	fn to_byte(self) -> u8 {
+		match self {
+			SymbolKind::Function => 0,
+			SymbolKind::Struct => 1,
+			SymbolKind::Enum => 2,
+			SymbolKind::Trait => 3,
+			SymbolKind::Class => 4,
+			SymbolKind::Module => 5,
+			SymbolKind::Constant => 6,
+			SymbolKind::Type => 7,
+			SymbolKind::Macro => 8,
+		}
+	}
+}
+
+impl Display for SymbolKind {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let s = match self {
+			SymbolKind::Function => "fn",
+			SymbolKind::Struct => "struct",
+			SymbolKind::Enum => "enum",
+			SymbolKind::Trait => "trait",
+			SymbolKind::Class => "class",
+			SymbolKind::Module => "mod",
+			SymbolKind::Constant => "const",
+			SymbolKind::Type => "type",
+			SymbolKind::Macro => "macro",
+		};
+
+		write!(f, "{s}")
+	}
+}
+
+impl Symbol {
+	/// Appends the binary representation of this symbol to `out`.
+	/// Symbols are stored as a kind byte, a 4-byte line number
+	/// and a length-prefixed name.
+	pub fn write_to(&self, out: &mut Vec<u8>) {
+		let name = &self.name.as_bytes()[..self.name.len().min(u16::MAX as usize)];
+		out.push(self.kind.to_byte());
+		out.extend_from_slice(&self.line.to_be_bytes());
+		out.extend_from_slice(&(name.len() as u16).to_be_bytes());
+		out.extend_from_slice(name);
+	}
+
+	/// Reads a symbol from the start of `bytes`, returning it along
+	/// with the number of bytes consumed.
+	pub fn read_from(bytes: &[u8]) -> Option<(Self, usize)> {
+		if bytes.len() < 7 {
+			return None;
+		}
+
+		let kind = SymbolKind::from_byte(bytes[0])?;
+		let line = u32::from_be_bytes(bytes[1..5].try_into().unwrap());
+		let len = u16::from_be_bytes(bytes[5..7].try_into().unwrap()) as usize;
+		let name = bytes.get(7..7 + len)?;
+		let name = String::from_utf8_lossy(name).into_owned();
+		Some((Self { kind, line, name }, 7 + len))
+	}
+}
+
+/// Parses `source`, the contents of the file at `path`, and returns all
+/// of the definitions found in it. Returns an empty list for unsupported languages.
+#[cfg(feature = "symbols")]
+pub fn extract(path: &Path, source: &str) -> Vec<Symbol> {
+	use tree_sitter::{Language, Parser};
+
+	let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+	let (language, kinds): (Language, &[(&str, SymbolKind)]) = match extension {
+		"rs" => (
+			tree_sitter_rust::LANGUAGE.into(),
+			&[
+				("function_item", SymbolKind::Function),
+				("struct_item", SymbolKind::Struct),
+				("enum_item", SymbolKind::Enum),
+				("trait_item", SymbolKind::Trait),
+				("mod_item", SymbolKind::Module),
+				("const_item", SymbolKind::Constant),
+				("static_item", SymbolKind::Constant),
+				("type_item", SymbolKind::Type),
+				("macro_definition", SymbolKind::Macro),
+			],
+		),
+		"py" => (
+			tree_sitter_python::LANGUAGE.into(),
+			&[
+				("function_definition", SymbolKind::Function),
+				("class_definition", SymbolKind::Class),
+			],
+		),
+		"js" | "jsx" | "mjs" | "cjs" => (
+			tree_sitter_javascript::LANGUAGE.into(),
+			&[
+				("function_declaration", SymbolKind::Function),
+				("generator_function_declaration", SymbolKind::Function),
+				("method_definition", SymbolKind::Function),
+				("class_declaration", SymbolKind::Class),
+			],
+		),
+		_ => return Vec::new(),
+	};
+
+	let mut parser = Parser::new();
+	if parser.set_language(&language).is_err() {
+		return Vec::new();
+	}
+
+	let tree = match parser.parse(source, None) {
+		Some(t) => t,
+		None => return Vec::new(),
+	};
+
+	// Walk the whole tree depth-first, picking out definition nodes
+	let mut symbols = Vec::new();
+	let mut cursor = tree.walk();
+	'walk: loop {
+		let node = cursor.node();
+		if let Some((_, kind)) = kinds.iter().find(|(k, _)| *k == node.kind()) {
+			let name = node
+				.child_by_field_name("name")
+				.and_then(|n| n.utf8_text(source.as_bytes()).ok());
+
+			if let Some(name) = name {
+				symbols.push(Symbol {
+					kind: *kind,
+					line: node.start_position().row as u32 + 1,
+					name: name.to_string(),
+				});
+			}
+		}
+
+		if cursor.goto_first_child() || cursor.goto_next_sibling() {
+			continue;
+		}
+
+		loop {
+			if !cursor.goto_parent() {
+				break 'walk;
+			}
+
+			if cursor.goto_next_sibling() {
+				break;
+			}
+		}
+	}
+
+	symbols
+}
+
+/// Parses `source`, the contents of the file at `path`, and returns all
+/// of the definitions found in it. Always empty when built without symbol support.
+#[cfg(not(feature = "symbols"))]
+pub fn extract(_path: &Path, _source: &str) -> Vec<Symbol> {
+	Vec::new()
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+/// Represents an error reading a synonyms file.
+#[derive(Debug)]
+pub enum SynonymsError {
+	Invalid(usize, String),
+	Io(std::io::Error),
+}
+
+impl Display for SynonymsError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			SynonymsError::Invalid(line, msg) => {
+				write!(f, "invalid synonyms file: line {line}: {msg}")
+			}
+			SynonymsError::Io(e) => write!(f, "synonyms file error: {e}"),
+		}
+	}
+}
+
+impl std::error::Error for SynonymsError {}
+
+/// Returns the path of the synonyms file in the data directory `dir`,
+/// shared by every project.
+pub fn path_for(dir: &Path) -> PathBuf {
+	dir.join("synonyms.txt")
+}
+
+/// Reads the groups of synonyms at `path`. A missing file has none.
+pub fn load(path: &Path) -> Result<Vec<Vec<String>>, SynonymsError> {
+	match std::fs::read_to_string(path) {
+		Ok(contents) => parse(&contents),
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+		Err(e) => Err(SynonymsError::Io(e)),
+	}
+}
+
+/// Parses a synonyms file: one group of words meaning the same per line,
+/// as `term = synonym`, with either side a comma-separated list, e.g.
+/// `cfg = config, configuration`. Blank lines and lines starting with `#`
+/// are skipped.
+pub fn parse(contents: &str) -> Result<Vec<Vec<String>>, SynonymsError> {
+	let mut groups = Vec::new();
+	for (i, line) in contents.lines().enumerate() {
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') {
+			continue;
+		}
+
+		let invalid = |msg: &str| SynonymsError::Invalid(i + 1, String::from(msg));
+		let (terms, synonyms) = line
+			.split_once('=')
+			.ok_or_else(|| invalid("expected `term = synonym`"))?;
+
+		let group = terms
+			.split(',')
+			.chain(synonyms.split(','))
+			.map(str::trim)
+			.map(String::from)
+			.collect::<Vec<String>>();
+
+		if group.iter().any(String::is_empty) {
+			return Err(invalid("synonyms must not be empty"));
+		}
+
+		groups.push(group);
+	}
+
+	Ok(groups)
+}
+
+/// Adds each word of `groups` to `aliases`, keyed by the word lowercased,
+/// with the other words of its group as its aliases.
+pub fn add_to_aliases(groups: Vec<Vec<String>>, aliases: &mut HashMap<String, Vec<String>>) {
+	for group in groups {
+		for word in &group {
+			let entry = aliases.entry(word.to_lowercase()).or_default();
+			for other in group
+				.iter()
+				.filter(|other| !other.eq_ignore_ascii_case(word))
+			{
+				if !entry.contains(other) {
+					entry.push(other.clone());
+				}
+			}
+		}
+	}
+}
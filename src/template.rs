@@ -0,0 +1,134 @@
+use std::fmt::Display;
+
+use crate::result_set::{Match, SearchResult};
+
+/// A value of a matching line that a template can show.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+	Path,
+	Line,
+	Column,
+	/// The relevance of the line's file, from 0 to 100.
+	Score,
+	Preview,
+}
+
+/// A literal piece of a template or a placeholder in it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+	Text(String),
+	Placeholder(Field),
+}
+
+/// A user-defined layout for each matching line, with placeholders in braces
+/// like `{path}:{line}: {preview}`. `{{` and `}}` stand for literal braces.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Template(Vec<Part>);
+
+/// Represents an error parsing a template.
+#[derive(Debug)]
+pub enum TemplateError {
+	Unclosed,
+	UnknownPlaceholder(String),
+}
+
+impl Field {
+	/// Every field, in the order they're documented.
+	pub const ALL: [Field; 5] = [
+		Field::Path,
+		Field::Line,
+		Field::Column,
+		Field::Score,
+		Field::Preview,
+	];
+
+	/// Looks up a field by its placeholder name.
+	pub fn from_name(name: &str) -> Option<Self> {
+		Self::ALL.into_iter().find(|field| field.name() == name)
+	}
+
+	/// Returns the placeholder name of this field.
+	pub fn name(self) -> &'static str {
+		match self {
+			Field::Path => "path",
+			Field::Line => "line",
+			Field::Column => "column",
+			Field::Score => "score",
+			Field::Preview => "preview",
+		}
+	}
+}
+
+impl Template {
+	/// Parses a template.
+	pub fn parse(template: &str) -> Result<Self, TemplateError> {
+		let mut parts = Vec::new();
+		let mut text = String::new();
+		let mut chars = template.chars().peekable();
+		while let Some(c) = chars.next() {
+			match c {
+				'{' if chars.next_if_eq(&'{').is_some() => text.push('{'),
+				'}' if chars.next_if_eq(&'}').is_some() => text.push('}'),
+				'{' => {
+					let mut name = String::new();
+					loop {
+						match chars.next() {
+							Some('}') => break,
+							Some(c) => name.push(c),
+							None => return Err(TemplateError::Unclosed),
+						}
+					}
+
+					let field =
+						Field::from_name(&name).ok_or(TemplateError::UnknownPlaceholder(name))?;
+					if !text.is_empty() {
+						parts.push(Part::Text(std::mem::take(&mut text)));
+					}
+
+					parts.push(Part::Placeholder(field));
+				}
+				c => text.push(c),
+			}
+		}
+
+		if !text.is_empty() {
+			parts.push(Part::Text(text));
+		}
+
+		Ok(Self(parts))
+	}
+
+	/// Fills in the template for the matching line `m` of `result`, whose
+	/// relevance is relative to `best`.
+	pub fn render(&self, result: &SearchResult, m: &Match, best: usize) -> String {
+		let mut rendered = String::new();
+		for part in &self.0 {
+			match part {
+				Part::Text(text) => rendered.push_str(text),
+				Part::Placeholder(field) => rendered.push_str(&match field {
+					Field::Path => result.path.to_string_lossy().into_owned(),
+					Field::Line => m.line.to_string(),
+					Field::Column => m.column.to_string(),
+					Field::Score => result.relevance(best).to_string(),
+					Field::Preview => m.preview.clone(),
+				}),
+			}
+		}
+
+		rendered
+	}
+}
+
+impl Display for TemplateError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			TemplateError::Unclosed => write!(f, "unclosed {{ in template"),
+			TemplateError::UnknownPlaceholder(name) => {
+				let names = Field::ALL.map(Field::name).join(", ");
+				write!(f, "unknown placeholder {{{name}}}; expected one of {names}")
+			}
+		}
+	}
+}
+
+impl std::error::Error for TemplateError {}
@@ -0,0 +1,98 @@
+use std::collections::HashSet;
+
+use crate::normalize::Normalizer;
+
+/// Tokens longer than this are not stored in the index.
+pub const MAX_TOKEN_LEN: usize = u8::MAX as usize;
+
+/// Splits an identifier like `getUserName`, `get_user_name` or
+/// `HTTPServer` into its lowercased subtokens.
+pub fn split_identifier(ident: &str) -> Vec<String> {
+	let chars = ident.chars().collect::<Vec<char>>();
+	let mut tokens = Vec::new();
+	let mut current = String::new();
+	for (i, c) in chars.iter().enumerate() {
+		if !c.is_alphanumeric() {
+			if !current.is_empty() {
+				tokens.push(std::mem::take(&mut current));
+			}
+
+			continue;
+		}
+
+		let prev = i.checked_sub(1).map(|p| chars[p]);
+		let next = chars.get(i + 1);
+		let boundary = match prev {
+			// fooBar
+			Some(p) if p.is_lowercase() && c.is_uppercase() => true,
+			// HTTPServer
+			Some(p) if p.is_uppercase() && c.is_uppercase() => {
+				next.is_some_and(|n| n.is_lowercase())
+			}
+			_ => false,
+		};
+
+		if boundary && !current.is_empty() {
+			tokens.push(std::mem::take(&mut current));
+		}
+
+		current.extend(c.to_lowercase());
+	}
+
+	if !current.is_empty() {
+		tokens.push(current);
+	}
+
+	tokens
+}
+
+/// Collects the unique subtokens of every compound identifier in `text`,
+/// and their normal forms if given a `normalizer`. Identifiers made of a
+/// single word are skipped, since their trigrams already cover them.
+pub fn tokens(text: &str, normalizer: Option<&dyn Normalizer>) -> Vec<String> {
+	let mut tokens = HashSet::new();
+	for ident in text.split(|c: char| !c.is_alphanumeric() && c != '_' && c != '-') {
+		let mut parts = split_identifier(ident);
+		if parts.len() < 2 {
+			continue;
+		}
+
+		if let Some(normalizer) = normalizer {
+			let normal = parts
+				.iter()
+				.map(|part| normalizer.normalize(part))
+				.collect::<Vec<String>>();
+
+			parts.extend(normal);
+		}
+
+		tokens.extend(
+			parts
+				.into_iter()
+				.filter(|t| t.len() > 1 && t.len() <= MAX_TOKEN_LEN),
+		);
+	}
+
+	let mut tokens = tokens.into_iter().collect::<Vec<String>>();
+	tokens.sort();
+	tokens
+}
+
+/// Returns the identifier spellings a set of search terms could take in
+/// code, e.g. `user name` expands to `username`, `user_name` and `user-name`.
+/// Returns nothing if the terms make up a single subtoken.
+pub fn variants(terms: &[String]) -> Vec<String> {
+	let parts = terms
+		.iter()
+		.flat_map(|t| split_identifier(t))
+		.collect::<Vec<String>>();
+
+	if parts.len() < 2 {
+		return Vec::new();
+	}
+
+	["", "_", "-"]
+		.iter()
+		.map(|joiner| parts.join(joiner))
+		.collect()
+}
@@ -0,0 +1,231 @@
+use std::path::Path;
+
+use crate::archive;
+use crate::args::{self, Options};
+use crate::compression;
+use crate::encoding;
+use crate::index::{self, Index, IndexError, IndexOptions};
+use crate::language::Language;
+use crate::ngram::{self, NGram};
+use crate::output::SHOWN_RESULTS;
+use crate::result_set::SearchResult;
+use crate::suppress;
+
+/// Returns whether any directory `path` is in is a symbolic link.
+fn is_linked(path: &Path) -> bool {
+	path.ancestors().skip(1).any(|dir| {
+		dir.symlink_metadata()
+			.is_ok_and(|m| m.file_type().is_symlink())
+	})
+}
+
+/// Explains why the file at `path` was or wasn't among the `results` of the
+/// search described by `options`, returning one finding per line.
+pub fn explain(
+	indexes: &mut [Index],
+	path: &Path,
+	options: &Options,
+	index_options: &IndexOptions,
+	results: &[SearchResult],
+) -> Result<Vec<String>, IndexError> {
+	let path = index::document_path(path);
+	if let Some(i) = results.iter().position(|r| Path::new(&r.path) == path) {
+		let mut findings = vec![format!(
+			"It is returned at position {} with rank {}.",
+			i + 1,
+			results[i].score
+		)];
+
+		if i >= SHOWN_RESULTS {
+			findings.push(format!(
+				"Only the top {SHOWN_RESULTS} results are shown; use --format json to list them all."
+			));
+		}
+
+		return Ok(findings);
+	}
+
+	let exists = match &index_options.revision {
+		Some(_) => index_options
+			.files
+			.as_ref()
+			.is_some_and(|f| f.contains(&path)),
+		None => match archive::split(&path) {
+			Some((archive, _)) => archive.is_file(),
+			None => path.is_file(),
+		},
+	};
+
+	if !exists {
+		return Ok(vec![String::from("It doesn't exist or isn't a file.")]);
+	}
+
+	let mut findings = Vec::new();
+	let language = Language::from_path(&compression::logical_path(
+		&path,
+		compression::is_compressed(&path),
+	));
+
+	if !options.types.is_empty() && !language.is_some_and(|l| options.types.contains(&l)) {
+		let language = language.map(Language::name).unwrap_or("no known language");
+		findings.push(format!(
+			"It is filtered out by --type: it is in {language}."
+		));
+	}
+
+	if !options.paths.is_empty() && !crate::is_within(&path, &options.paths) {
+		findings.push(String::from(
+			"It is outside the modules named by the query's crate paths.",
+		));
+	}
+
+	if options
+		.refine
+		.as_ref()
+		.is_some_and(|previous| !previous.contains(&path))
+	{
+		findings.push(String::from(
+			"It isn't among the previous results being refined.",
+		));
+	}
+
+	let mut found = None;
+	for index in indexes.iter_mut() {
+		if let Some(i) = index.document_paths()?.iter().position(|p| p == &path) {
+			found = Some((index, i));
+			break;
+		}
+	}
+
+	let (index, doc_id) = match found {
+		Some(v) => v,
+		None => {
+			let walked = index_options.revision.is_some()
+				|| index::walk(index_options)
+					.filter_map(Result::ok)
+					.flat_map(|entry| index::expand_archive(entry.into_path(), 0))
+					.any(|(walked, _)| walked == path);
+
+			findings.push(String::from(if walked {
+				"It isn't indexed: it is binary, generated, empty, too large, or couldn't be read."
+			} else if !index_options.follow_links && is_linked(&path) {
+				"It isn't indexed: it is reached through a symbolic link; use --follow to index it."
+			} else if is_linked(&path) {
+				"It isn't indexed under this path: it is reached through a symbolic link to a \
+				directory indexed under another path."
+			} else {
+				"It isn't indexed: it is ignored by a .gitignore or .ignore file, or hidden."
+			}));
+
+			return Ok(findings);
+		}
+	};
+
+	if let Some(target) = index.link_targets()?.swap_remove(doc_id) {
+		findings.push(format!(
+			"It is reached through a symbolic link to {}.",
+			target.to_string_lossy()
+		));
+	}
+
+	let compressed = index.compressed()?.is_some_and(|c| c.get(doc_id));
+	let contents = index::read_document(
+		&path,
+		compressed,
+		&index_options.extractors,
+		index_options.revision.as_deref(),
+	)
+	.ok()
+	.map(encoding::decode);
+
+	// Candidates contain every term, or with --any or when no file does,
+	// every n-gram of a term, or at least one of the query's n-grams. Terms
+	// shorter than an n-gram are looked for in every file instead
+	let lowercase = contents.as_deref().map(str::to_lowercase);
+	let mut has_any = false;
+	let mut has_a_term = false;
+	let mut has_all_terms = true;
+	let mut has_ngrams = false;
+	for (i, term) in options.terms.iter().chain(&options.aliases).enumerate() {
+		let mut ngrams = Vec::new();
+		ngram::get_ngrams(term, index.ngram_size(), &mut ngrams);
+		if i < options.terms.len() && ngrams.is_empty() {
+			if lowercase
+				.as_ref()
+				.is_some_and(|v| v.contains(&term.to_lowercase()))
+			{
+				has_any = true;
+				has_a_term = true;
+			} else {
+				has_all_terms = false;
+				findings.push(format!(
+					"It doesn't contain \"{term}\", which is shorter than the index's {}-byte n-grams, so every file is checked for it.",
+					index.ngram_size()
+				));
+			}
+
+			continue;
+		}
+
+		has_ngrams |= !ngrams.is_empty();
+		let mut missing = Vec::new();
+		for gram in ngrams {
+			match index.find_ngram_ref(gram)? {
+				Some(v) if v.iter_ones().any(|id| id == doc_id) => has_any = true,
+				_ => missing.push(gram),
+			}
+		}
+
+		if missing.is_empty() {
+			has_a_term = true;
+		} else {
+			has_all_terms = false;
+			findings.push(format!(
+				"It lacks {} of the n-grams of \"{term}\": {}.",
+				missing.len(),
+				quote(&missing)
+			));
+		}
+	}
+
+	// Without n-grams, only the short terms' findings apply
+	if has_ngrams && !has_any {
+		findings.push(String::from(
+			"It contains none of the query's n-grams, so it isn't a candidate.",
+		));
+	} else if has_ngrams && !has_a_term {
+		findings.push(String::from(
+			"It lacks n-grams of every term, so it is only a candidate when no file contains every n-gram of a term.",
+		));
+	} else if has_ngrams && !has_all_terms && !options.any {
+		findings.push(String::from(
+			"It doesn't contain every term, so it is only a candidate with --any or when no file contains them all.",
+		));
+	}
+
+	let suppressed = contents.is_some_and(|contents| suppress::apply(&contents).is_none());
+
+	if suppressed && !options.no_suppressions {
+		findings.push(String::from(
+			"It is suppressed by an ignore-file annotation; use --no-suppressions to include it.",
+		));
+	}
+
+	if findings.is_empty() && has_all_terms {
+		let candidates = options.candidates.unwrap_or(args::DEFAULT_CANDIDATES);
+		findings.push(format!(
+			"It contains every n-gram of the query, but wasn't among the {candidates} candidates sharing the most n-grams with it; use --candidates 0 to rank every candidate."
+		));
+	}
+
+	Ok(findings)
+}
+
+/// Quotes each of `ngrams` for display.
+fn quote(ngrams: &[NGram]) -> String {
+	ngrams
+		.iter()
+		.map(|gram| format!("\"{gram}\""))
+		.collect::<Vec<String>>()
+		.join(", ")
+}